@@ -0,0 +1,78 @@
+//! Detects horizontal and vertical lines in 2x2 pixel images, searching over hyper-parameters.
+//!
+//! Instead of a fixed net config and fixed backprop options, the trainer is given factories which
+//! sample them from the `ParamFactory` of an `Optimizer`. Every task trains a net with freshly
+//! sampled parameters on one of the executor's workers, and the best net is kept. Ctrl-C cancels
+//! the search through the trainer's `TrainingControl`, which still returns the best net so far.
+//!
+//! Run with `cargo run --example lines_2x2`.
+
+use std::error::Error;
+
+use rust_neural_net::data::PreparedDataSet;
+use rust_neural_net::func::{ActivationFn, CompletionFn, ErrorFn, LearningRateFn, MiniBatchSize};
+use rust_neural_net::initializer::RandomNetInitializer;
+use rust_neural_net::net::NetConfig;
+use rust_neural_net::train::{
+    BackpropOptions, Executor, NetTrainerBuilder, ParamFactory, RandomOptimizer, TrainingEvent,
+};
+
+fn main() -> Result<(), Box<dyn Error>> {
+
+    let data_set = PreparedDataSet::from_csv(
+        "data/2x2_lines_binary.csv",
+        ["0_0", "0_1", "1_0", "1_1"],
+        ["has_horizontal", "has_vertical"]
+    )?;
+
+    let mut trainer = NetTrainerBuilder::default()
+        .data_set(data_set)
+        .executor(Executor::Local(2))
+        .optimizer_factory(Box::new(|| Box::new(RandomOptimizer::from_seed("lines"))))
+        .net_config_factory(Box::new(|params: &mut dyn ParamFactory| {
+            let hidden_size = params.range_usize("hidden_size".to_string(), 3, 6);
+            NetConfig::new_fully_connected(4, 2, [hidden_size], ActivationFn::standard_logistic_sigmoid())
+        }))
+        .backprop_options_factory(Box::new(|params: &mut dyn ParamFactory| {
+            BackpropOptions {
+                completion_fn: CompletionFn::stop_after_epoch(2000),
+                mini_batch_size_fn: MiniBatchSize::Full,
+                learning_rate_fn: LearningRateFn::Constant(params.range_float(1.0, 3.0)),
+                error_fn: ErrorFn::SquaredError,
+                gradient_noise_fn: None,
+                gradient_noise_layer_scales: Vec::new(),
+                shuffle: false,
+                multi_threading: None,
+                validation_set: None,
+                update_interval: None,
+                check_divergence: false,
+                seed: None,
+                track_norms: false,
+                ema_decay: None,
+                mixed_precision: None,
+                batch_threads: None,
+            }
+        }))
+        // stop once 4 tasks reported a result
+        .global_completion_fn(CompletionFn::stop_after_epoch(4))
+        .initializer(RandomNetInitializer::new_standard_with_seed("lines"))
+        .observer(Box::new(|event| {
+            match event {
+                TrainingEvent::TaskSubmit(task) => println!("submitted {}", task.task_id),
+                TrainingEvent::TaskAccepted { task_id, executor_id } => println!("{} accepted by {}", task_id, executor_id),
+                TrainingEvent::TaskResult(result) => println!("{}: mean error {:.6}", result.task_id, result.error_stats.mean()),
+                TrainingEvent::TaskUpdate(_) => {},
+            }
+        }))
+        .build()?;
+
+    let control = trainer.control();
+    ctrlc::set_handler(move || control.cancel())?;
+
+    let result = trainer.execute()?;
+
+    println!("best mean error {:.6} after {} trials in {:.1}s",
+        result.error_stats.mean(), result.trials.len(), result.duration.as_secs_f32());
+
+    Ok(())
+}
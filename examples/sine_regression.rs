@@ -0,0 +1,56 @@
+//! Fits one period of a sine wave, scaled into the output range of the logistic sigmoid, using
+//! mini-batches of 4 rows.
+//!
+//! Run with `cargo run --example sine_regression`.
+
+use std::error::Error;
+use std::num::NonZeroU32;
+
+use rust_neural_net::Float;
+use rust_neural_net::data::PreparedDataSet;
+use rust_neural_net::func::{ActivationFn, CompletionFn, ErrorFn, LearningRateFn, MiniBatchSize};
+use rust_neural_net::initializer::RandomNetInitializer;
+use rust_neural_net::net::NetConfig;
+use rust_neural_net::train::{BackpropOptions, NetTrainerBuilder};
+
+fn main() -> Result<(), Box<dyn Error>> {
+
+    // y = 0.5 + 0.4 * sin(2 * pi * x) for x in [0, 1]
+    let data_set = PreparedDataSet::from_csv("data/sine_regression.csv", ["x"], ["y"])?;
+
+    let mut trainer = NetTrainerBuilder::default()
+        .data_set(data_set)
+        .net_config(NetConfig::new_fully_connected(1, 1, [8], ActivationFn::standard_logistic_sigmoid()))
+        .backprop_options(BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(5000),
+            mini_batch_size_fn: MiniBatchSize::Constant(NonZeroU32::new(4).unwrap()),
+            learning_rate_fn: LearningRateFn::Constant(1.0),
+            error_fn: ErrorFn::SquaredError,
+            gradient_noise_fn: None,
+            gradient_noise_layer_scales: Vec::new(),
+            shuffle: false,
+            multi_threading: None,
+            validation_set: None,
+            update_interval: None,
+            check_divergence: false,
+            seed: None,
+            track_norms: false,
+            ema_decay: None,
+            mixed_precision: None,
+            batch_threads: None,
+        })
+        .initializer(RandomNetInitializer::new_standard_with_seed("sine"))
+        .observer(Box::new(|_| {}))
+        .build()?;
+
+    let result = trainer.execute()?;
+
+    println!("mean error {:.6}", result.error_stats.mean());
+    for i in 0..=8 {
+        let x = i as Float / 8.0;
+        let expected = 0.5 + 0.4 * (2.0 * std::f64::consts::PI as Float * x).sin();
+        println!("x = {:.3}: predicted {:.3}, expected {:.3}", x, result.net.predict(&[x])[0], expected);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Trains a net with a single hidden layer to compute XOR, the classic example of a function
+//! which is not linearly separable.
+//!
+//! Run with `cargo run --example xor`.
+
+use std::error::Error;
+
+use rust_neural_net::data::PreparedDataSet;
+use rust_neural_net::func::{ActivationFn, CompletionFn, ErrorFn, LearningRateFn, MiniBatchSize};
+use rust_neural_net::initializer::RandomNetInitializer;
+use rust_neural_net::net::NetConfig;
+use rust_neural_net::train::{BackpropOptions, NetTrainerBuilder, TrainingEvent};
+
+fn main() -> Result<(), Box<dyn Error>> {
+
+    let data_set = PreparedDataSet::from_csv("data/xor.csv", ["x_0", "x_1"], ["xor"])?;
+
+    let mut trainer = NetTrainerBuilder::default()
+        .data_set(data_set)
+        // 2 inputs, 1 output and a hidden layer of 3 nodes
+        .net_config(NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid()))
+        .backprop_options(BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(5000),
+            mini_batch_size_fn: MiniBatchSize::Full,
+            learning_rate_fn: LearningRateFn::Constant(5.0),
+            error_fn: ErrorFn::SquaredError,
+            gradient_noise_fn: None,
+            gradient_noise_layer_scales: Vec::new(),
+            shuffle: false,
+            multi_threading: None,
+            validation_set: None,
+            update_interval: None,
+            check_divergence: false,
+            seed: None,
+            track_norms: false,
+            ema_decay: None,
+            mixed_precision: None,
+            batch_threads: None,
+        })
+        // a seeded initializer makes the run reproducible
+        .initializer(RandomNetInitializer::new_standard_with_seed("xor"))
+        .observer(Box::new(|event| {
+            if let TrainingEvent::TaskResult(result) = event {
+                println!("{} finished after {} epochs with mean error {:.6}",
+                    result.task_id, result.epoch, result.error_stats.mean());
+            }
+        }))
+        .build()?;
+
+    let result = trainer.execute()?;
+
+    for input in &[[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]] {
+        println!("{:?} -> {:.3}", input, result.net.predict(input)[0]);
+    }
+
+    Ok(())
+}
@@ -1,5 +1,9 @@
+mod pool;
 mod row;
+mod shared;
 
 pub use self::{
+    pool::*,
     row::*,
+    shared::*,
 };
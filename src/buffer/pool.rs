@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::buffer::RowBuffer;
+
+/// The most spare buffers kept for each row layout, more are dropped
+const MAX_SPARE_BUFFERS: usize = 32;
+
+/// Spare `RowBuffer`s kept for reuse by buffers of the same row layout, e.g. the scratch buffers
+/// of training tasks of the same net architecture run one after another. Clones share the same
+/// buffers, so the threads of a task can return theirs.
+#[derive(Clone, Default)]
+pub struct BufferPool {
+    state: Arc<Mutex<PoolState>>,
+}
+
+/// The buffer length and row layout shared by interchangeable buffers
+type LayoutKey = (usize, Box<[(usize, usize)]>);
+
+#[derive(Default)]
+struct PoolState {
+    buffers: HashMap<LayoutKey, Vec<RowBuffer>>,
+    reused: usize,
+}
+
+impl BufferPool {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A zeroed buffer with the layout of `template`, which is a spare one if there is any
+    pub fn take_zeroed_like(&self, template: &RowBuffer) -> RowBuffer {
+        self.take_zeroed(template.buffer_len(), template.row_layout())
+            .unwrap_or_else(|| {
+                let mut buffer = template.clone();
+                buffer.reset_to(0.0);
+                buffer
+            })
+    }
+
+    /// Like `RowBuffer::new_with_row_sizes` with an initial value of zero, but reuses a spare
+    /// buffer if there is any
+    pub fn take_zeroed_with_row_sizes(&self, row_sizes: &[usize]) -> RowBuffer {
+        let layout: Vec<(usize, usize)> = row_sizes.iter()
+            .scan(0, |offset, &size| {
+                let row = (*offset, size);
+                *offset += size;
+                Some(row)
+            })
+            .collect();
+        self.take_zeroed(row_sizes.iter().sum(), &layout)
+            .unwrap_or_else(|| RowBuffer::new_with_row_sizes(0.0, row_sizes))
+    }
+
+    fn take_zeroed(&self, buffer_len: usize, layout: &[(usize, usize)]) -> Option<RowBuffer> {
+        let mut state = self.state.lock().unwrap();
+        let mut buffer = state.buffers.get_mut(&(buffer_len, layout.into()))?.pop()?;
+        state.reused += 1;
+        drop(state);
+        buffer.reset_to(0.0);
+        Some(buffer)
+    }
+
+    /// Keeps the buffer for a later `take_zeroed_like` or `take_zeroed_with_row_sizes`
+    pub fn put(&self, buffer: RowBuffer) {
+        let key: LayoutKey = (buffer.buffer_len(), buffer.row_layout().into());
+        let mut state = self.state.lock().unwrap();
+        let spare = state.buffers.entry(key).or_default();
+        if spare.len() < MAX_SPARE_BUFFERS {
+            spare.push(buffer);
+        }
+    }
+
+    /// The number of buffers waiting to be reused
+    pub fn num_spare(&self) -> usize {
+        self.state.lock().unwrap().buffers.values().map(Vec::len).sum()
+    }
+
+    /// The number of buffers handed out which were reused rather than allocated
+    pub fn num_reused(&self) -> usize {
+        self.state.lock().unwrap().reused
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_buffer_pool() {
+
+        let pool = BufferPool::new();
+        let mut buffer = pool.take_zeroed_with_row_sizes(&[2, 3]);
+        buffer.reset_to(1.0);
+        let address = buffer.get_buffer().as_ptr();
+        pool.put(buffer);
+        assert_eq!((pool.num_spare(), pool.num_reused()), (1, 0));
+
+        // a different layout of the same length isn't reused
+        assert_eq!(pool.take_zeroed_with_row_sizes(&[3, 2]).get_row(0), &[0.0; 3]);
+        let buffer = pool.take_zeroed_like(&RowBuffer::new_with_row_sizes(4.0, [2, 3]));
+        assert_eq!((buffer.get_buffer().as_ptr(), buffer.get_buffer()), (address, &[0.0; 5][..]));
+        assert_eq!((pool.num_spare(), pool.num_reused()), (0, 1));
+
+    }
+
+}
@@ -1,13 +1,44 @@
 use std::ptr;
 use std::slice;
 use std::fmt;
+use std::convert::TryFrom;
+use serde::{Serialize, Deserialize};
+use crate::Float;
+use crate::simd;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedRowBuffer")]
 pub struct RowBuffer {
-    buffer: Box<[f32]>,
+    #[serde(with = "crate::utils::serde_non_finite")]
+    buffer: Box<[Float]>,
     row_offsets_and_sizes: Box<[(usize, usize)]>,
 }
 
+/// A deserialized `RowBuffer` whose rows haven't been checked to lie within the buffer yet
+#[derive(Deserialize)]
+struct UncheckedRowBuffer {
+    #[serde(with = "crate::utils::serde_non_finite")]
+    buffer: Box<[Float]>,
+    row_offsets_and_sizes: Box<[(usize, usize)]>,
+}
+
+impl TryFrom<UncheckedRowBuffer> for RowBuffer {
+    type Error = String;
+
+    fn try_from(unchecked: UncheckedRowBuffer) -> Result<Self, Self::Error> {
+        let len = unchecked.buffer.len();
+        for &(offset, size) in unchecked.row_offsets_and_sizes.iter() {
+            if offset.checked_add(size).is_none_or(|end| end > len) {
+                return Err(format!("row of {} values at {} is outside of the buffer of {} values", size, offset, len));
+            }
+        }
+        Ok(RowBuffer {
+            buffer: unchecked.buffer,
+            row_offsets_and_sizes: unchecked.row_offsets_and_sizes,
+        })
+    }
+}
+
 impl fmt::Debug for RowBuffer {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         let mut s = &mut f.debug_struct("RowBuffer");
@@ -21,14 +52,13 @@ impl fmt::Debug for RowBuffer {
     }
 }
 
-#[allow(dead_code)]
 impl RowBuffer {
 
-    pub fn new_with_row_sizes(initial_value: f32, row_sizes: impl AsRef<[usize]>) -> Self {
+    pub fn new_with_row_sizes(initial_value: Float, row_sizes: impl AsRef<[usize]>) -> Self {
         let row_sizes = row_sizes.as_ref();
         assert!(row_sizes.len() > 0);
         let total_size: usize = row_sizes.iter().sum();
-        let buffer: Vec<f32> = vec![initial_value; total_size];
+        let buffer: Vec<Float> = vec![initial_value; total_size];
         let mut row_offsets_and_sizes: Vec<(usize, usize)> = Vec::with_capacity(row_sizes.len());
         let mut offset: usize = 0;
         for i in 0..row_sizes.len() {
@@ -41,24 +71,51 @@ impl RowBuffer {
         }
     }
 
+    /// Like `new_with_row_sizes`, but each row with a source row shares the storage of that
+    /// earlier row, which must be the same size
+    pub fn new_with_shared_rows(initial_value: Float, row_sizes: impl AsRef<[usize]>, row_sources: &[Option<usize>]) -> Self {
+        let row_sizes = row_sizes.as_ref();
+        assert_eq!(row_sizes.len(), row_sources.len());
+        let unique_sizes: Vec<usize> = row_sizes.iter().zip(row_sources)
+            .map(|(&size, source)| if source.is_some() { 0 } else { size })
+            .collect();
+        let mut buffer = RowBuffer::new_with_row_sizes(initial_value, unique_sizes);
+        for (row, source) in row_sources.iter().enumerate() {
+            if let Some(source) = *source {
+                assert!(source < row && row_sources[source].is_none(), "row {} can't share row {}", row, source);
+                assert_eq!(row_sizes[row], row_sizes[source], "shared rows differ in size");
+                buffer.row_offsets_and_sizes[row] = buffer.row_offsets_and_sizes[source];
+            }
+        }
+        buffer
+    }
+
+    /// Whether the row shares its storage with an earlier row
+    pub fn is_aliased_row(&self, row: usize) -> bool {
+        let (offset, size) = self.row_offsets_and_sizes[row];
+        size > 0 && self.row_offsets_and_sizes[..row].iter().any(|&(o, s)| o == offset && s == size)
+    }
+
     #[inline]
-    pub fn get_row(&self, row: usize) -> &[f32] {
+    pub fn get_row(&self, row: usize) -> &[Float] {
         let (offset, size) = self.row_offsets_and_sizes[row];
         //&self.buffer[offset...offset+size]
         unsafe { slice::from_raw_parts(self.buffer.as_ptr().add(offset), size) }
     }
 
     #[inline]
-    pub fn get_row_mut(&mut self, row: usize) -> &mut [f32] {
+    pub fn get_row_mut(&mut self, row: usize) -> &mut [Float] {
         let (offset, size) = self.row_offsets_and_sizes[row];
         unsafe { slice::from_raw_parts_mut(self.buffer.as_mut_ptr().add(offset), size) }
     }
 
     #[inline]
-    pub fn split_rows(&mut self, row_first: usize, row_second: usize) -> (&mut [f32], &mut [f32]) {
+    pub fn split_rows(&mut self, row_first: usize, row_second: usize) -> (&mut [Float], &mut [Float]) {
         assert_ne!(row_first, row_second);
         let (offset_first, size_first) = self.row_offsets_and_sizes[row_first];
         let (offset_second, size_second) = self.row_offsets_and_sizes[row_second];
+        assert!(offset_first + size_first <= offset_second || offset_second + size_second <= offset_first,
+            "rows {} and {} overlap", row_first, row_second);
         let ptr = self.buffer.as_mut_ptr();
         unsafe {
             (slice::from_raw_parts_mut(ptr.add(offset_first), size_first),
@@ -71,8 +128,14 @@ impl RowBuffer {
         self.row_offsets_and_sizes.len()
     }
 
+    /// The offset and size of each row in the buffer
+    #[inline]
+    pub fn row_layout(&self) -> &[(usize, usize)] {
+        &self.row_offsets_and_sizes
+    }
+
     #[inline]
-    pub fn get_last_row(&self) -> &[f32] {
+    pub fn get_last_row(&self) -> &[Float] {
         unsafe {
             let (offset, size) = *(self.row_offsets_and_sizes.as_ptr()
                 .add(self.row_offsets_and_sizes.len() - 1));
@@ -81,7 +144,7 @@ impl RowBuffer {
     }
 
     #[inline]
-    pub fn get_last_row_mut(&mut self) -> &mut [f32] {
+    pub fn get_last_row_mut(&mut self) -> &mut [Float] {
         unsafe {
             let (offset, size) = *(self.row_offsets_and_sizes.as_ptr()
                 .add(self.row_offsets_and_sizes.len() - 1));
@@ -90,7 +153,7 @@ impl RowBuffer {
     }
 
     #[inline]
-    pub fn get_first_row(&self) -> &[f32] {
+    pub fn get_first_row(&self) -> &[Float] {
         unsafe {
             let (_offset, size) = *self.row_offsets_and_sizes.as_ptr();
             slice::from_raw_parts(self.buffer.as_ptr(), size)
@@ -98,14 +161,14 @@ impl RowBuffer {
     }
 
     #[inline]
-    pub fn get_first_row_mut(&mut self) -> &mut [f32] {
+    pub fn get_first_row_mut(&mut self) -> &mut [Float] {
         unsafe {
             let (_offset, size) = *self.row_offsets_and_sizes.as_ptr();
             slice::from_raw_parts_mut(self.buffer.as_mut_ptr(), size)
         }
     }
 
-    pub fn reset_to(&mut self, value: f32) {
+    pub fn reset_to(&mut self, value: Float) {
         let mut ptr = self.buffer.as_mut_ptr();
         unsafe {
             let end = ptr.add(self.buffer.len());
@@ -130,59 +193,41 @@ impl RowBuffer {
     }
 
     #[inline]
-    pub fn get_buffer(&self) -> &[f32] {
+    pub fn get_buffer(&self) -> &[Float] {
         &self.buffer
     }
 
     #[inline]
-    pub fn get_buffer_mut(&mut self) -> &mut [f32] {
+    pub fn get_buffer_mut(&mut self) -> &mut [Float] {
         &mut self.buffer
     }
 
-    #[allow(dead_code)]
     pub fn add(&mut self, other: &RowBuffer) {
-        let size = self.buffer.len();
-        assert_eq!(size, other.buffer.len());
-        let mut ptr_self = self.buffer.as_mut_ptr();
-        let mut ptr_other = other.buffer.as_ptr();
-        unsafe {
-            let end = ptr_self.add(size);
-            while ptr_self < end {
-                *ptr_self += *ptr_other;
-                ptr_self = ptr_self.add(1);
-                ptr_other = ptr_other.add(1);
-            }
-        }
+        simd::add_assign(&mut self.buffer, &other.buffer);
     }
 
-    pub fn add_with_multiplier(&mut self, other: &RowBuffer, multiplier: f32) {
-        let size = self.buffer.len();
-        assert_eq!(size, other.buffer.len());
-        let mut ptr_self = self.buffer.as_mut_ptr();
-        let mut ptr_other = other.buffer.as_ptr();
-        unsafe {
-            let end = ptr_self.add(size);
-            while ptr_self < end {
-                *ptr_self += *ptr_other * multiplier;
-                ptr_self = ptr_self.add(1);
-                ptr_other = ptr_other.add(1);
-            }
+    pub fn add_with_multiplier(&mut self, other: &RowBuffer, multiplier: Float) {
+        simd::axpy(multiplier, &other.buffer, &mut self.buffer);
+    }
+
+    /// Moves each value the given fraction of the way towards the value of the other buffer
+    pub fn blend_towards(&mut self, other: &RowBuffer, fraction: Float) {
+        assert_eq!(self.buffer.len(), other.buffer.len());
+        for (value, &target) in self.buffer.iter_mut().zip(other.buffer.iter()) {
+            *value += fraction * (target - *value);
         }
     }
 
+    /// Whether both buffers have the same rows and none of their values differ by more than
+    /// `tolerance`. NaN values are never equal.
+    pub fn approx_eq(&self, other: &RowBuffer, tolerance: Float) -> bool {
+        self.row_offsets_and_sizes == other.row_offsets_and_sizes
+            && self.buffer.iter().zip(other.buffer.iter())
+                .all(|(&a, &b)| a == b || (a - b).abs() <= tolerance)
+    }
+
     pub fn subtract(&mut self, subtract: &RowBuffer) {
-        let size = self.buffer.len();
-        assert_eq!(size, subtract.buffer.len());
-        let mut ptr_self = self.buffer.as_mut_ptr();
-        let mut ptr_other = subtract.buffer.as_ptr();
-        unsafe {
-            let end = ptr_self.add(size);
-            while ptr_self < end {
-                *ptr_self -= *ptr_other;
-                ptr_self = ptr_self.add(1);
-                ptr_other = ptr_other.add(1);
-            }
-        }
+        simd::sub_assign(&mut self.buffer, &subtract.buffer);
     }
 
 }
@@ -194,16 +239,37 @@ mod test {
 
     #[test]
     fn test_row_sizes_types() {
-        RowBuffer::new_with_row_sizes(0f32, vec![1, 2, 3]);
-        RowBuffer::new_with_row_sizes(0f32, &vec![1, 2, 3]);
-        RowBuffer::new_with_row_sizes(0f32, [1, 2, 3]);
-        RowBuffer::new_with_row_sizes(0f32, &[1, 2, 3]);
+        RowBuffer::new_with_row_sizes(0.0, vec![1, 2, 3]);
+        RowBuffer::new_with_row_sizes(0.0, &vec![1, 2, 3]);
+        RowBuffer::new_with_row_sizes(0.0, [1, 2, 3]);
+        RowBuffer::new_with_row_sizes(0.0, &[1, 2, 3]);
     }
 
     #[test]
     #[should_panic(expected = "assertion failed: row_sizes.len() > 0")]
     fn test_empty_not_allowed() {
-        RowBuffer::new_with_row_sizes(0f32, Vec::new());
+        RowBuffer::new_with_row_sizes(0.0, Vec::new());
+    }
+
+    #[test]
+    fn test_deserialize_checks_rows() {
+        let buffer: RowBuffer = serde_json::from_str(r#"{"buffer":[1.0,2.0,3.0],"row_offsets_and_sizes":[[0,1],[1,2]]}"#).unwrap();
+        assert_eq!(buffer.get_row(1), &[2.0, 3.0]);
+        assert!(serde_json::from_str::<RowBuffer>(r#"{"buffer":[1.0,2.0,3.0],"row_offsets_and_sizes":[[0,1],[2,2]]}"#).is_err());
+        assert!(serde_json::from_str::<RowBuffer>(r#"{"buffer":[],"row_offsets_and_sizes":[[18446744073709551615,2]]}"#).is_err());
+    }
+
+    #[test]
+    fn test_serialize_non_finite() {
+        let mut buffer = RowBuffer::new_with_row_sizes(0.5, [4]);
+        buffer.get_buffer_mut()[1..].copy_from_slice(&[Float::NAN, Float::INFINITY, Float::NEG_INFINITY]);
+        let json = serde_json::to_string(&buffer).unwrap();
+        assert!(json.contains(r#"[0.5,"NaN","inf","-inf"]"#), "{}", json);
+        let loaded: RowBuffer = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.get_buffer()[0], 0.5);
+        assert!(loaded.get_buffer()[1].is_nan());
+        assert_eq!(&loaded.get_buffer()[2..], &[Float::INFINITY, Float::NEG_INFINITY]);
+        assert!(serde_json::from_str::<RowBuffer>(r#"{"buffer":["1"],"row_offsets_and_sizes":[[0,1]]}"#).is_err());
     }
 
     #[test]
@@ -234,7 +300,7 @@ mod test {
         for i in 0..buf.num_rows() {
             let row = buf.get_row_mut(i);
             for j in 0..row.len() {
-                row[j] = (i * 10 + j) as f32;
+                row[j] = (i * 10 + j) as Float;
             }
         }
 
@@ -242,7 +308,7 @@ mod test {
         for i in 0..buf.num_rows() {
             let row = buf.get_row(i);
             for j in 0..row.len() {
-                assert_eq!(row[j], (i * 10 + j) as f32);
+                assert_eq!(row[j], (i * 10 + j) as Float);
             }
         }
 
@@ -253,7 +319,7 @@ mod test {
         for i in 0..buf.num_rows() {
             let row = buf.get_row(i);
             for j in 0..row.len() {
-                assert_eq!(row[j], ((i * 10 + j) * 2) as f32);
+                assert_eq!(row[j], ((i * 10 + j) * 2) as Float);
             }
         }
 
@@ -263,7 +329,7 @@ mod test {
         for i in 0..buf.num_rows() {
             let row = buf.get_row(i);
             for j in 0..row.len() {
-                assert_eq!(row[j], (i * 10 + j) as f32);
+                assert_eq!(row[j], (i * 10 + j) as Float);
             }
         }
 
@@ -273,7 +339,7 @@ mod test {
         for i in 0..buf.num_rows() {
             let row = buf.get_row(i);
             for j in 0..row.len() {
-                assert_eq!(row[j], ((i * 10 + j) * 3) as f32);
+                assert_eq!(row[j], ((i * 10 + j) * 3) as Float);
             }
         }
 
@@ -283,7 +349,7 @@ mod test {
         for i in 0..buf.num_rows() {
             let row = buf.get_row(i);
             for j in 0..row.len() {
-                assert_eq!(row[j], ((i * 10 + j) * 3) as f32);
+                assert_eq!(row[j], ((i * 10 + j) * 3) as Float);
             }
         }
 
@@ -293,7 +359,7 @@ mod test {
     fn test_get_first_last_rows() {
         let mut buf = RowBuffer::new_with_row_sizes(0.0, vec![15, 0, 8]);
         for i in 0..buf.buffer_len() {
-            buf.get_buffer_mut()[i] = i as f32;
+            buf.get_buffer_mut()[i] = i as Float;
         }
         let first = buf.get_first_row();
         let last = buf.get_last_row();
@@ -303,4 +369,35 @@ mod test {
         assert_eq!(last, &[15., 16., 17., 18., 19., 20., 21., 22.]);
     }
 
+    #[test]
+    fn test_shared_rows() {
+        let mut buf = RowBuffer::new_with_shared_rows(0.0, [2, 3, 2], &[None, None, Some(0)]);
+        assert_eq!(buf.buffer_len(), 5);
+        buf.get_row_mut(2)[1] = 4.0;
+        assert_eq!(buf.get_row(0), &[0.0, 4.0]);
+        assert!(buf.is_aliased_row(2));
+        assert!(!buf.is_aliased_row(1));
+        let (a, b) = buf.split_rows(1, 2);
+        assert_eq!((a.len(), b.len()), (3, 2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_split_shared_rows() {
+        let mut buf = RowBuffer::new_with_shared_rows(0.0, [2, 2], &[None, Some(0)]);
+        buf.split_rows(0, 1);
+    }
+
+    #[test]
+    fn test_approx_eq() {
+        let a = RowBuffer::new_with_row_sizes(1.0, [2, 3]);
+        let mut b = a.clone();
+        b.get_row_mut(1)[2] += 0.001;
+        assert!(a.approx_eq(&b, 0.002));
+        assert!(!a.approx_eq(&b, 0.0005));
+        assert!(!a.approx_eq(&RowBuffer::new_with_row_sizes(1.0, [3, 2]), 1.0));
+        b.get_row_mut(0)[0] = Float::NAN;
+        assert!(!b.approx_eq(&b, 1.0));
+    }
+
 }
@@ -0,0 +1,83 @@
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+#[cfg(not(feature = "f64"))]
+use std::sync::atomic::AtomicU32 as AtomicBits;
+#[cfg(feature = "f64")]
+use std::sync::atomic::AtomicU64 as AtomicBits;
+
+use crate::Float;
+use crate::buffer::RowBuffer;
+
+/// The values of a `RowBuffer` which many threads can read and update without a lock, e.g. the
+/// weights of hogwild training. Each value is read and written atomically, but an update is a
+/// separate read and write, so concurrent updates of the same value can overwrite each other.
+pub struct SharedRowBuffer {
+    values: Box<[AtomicBits]>,
+}
+
+impl SharedRowBuffer {
+
+    pub fn from_row_buffer(buffer: &RowBuffer) -> Self {
+        SharedRowBuffer {
+            values: buffer.get_buffer().iter().map(|value| AtomicBits::new(value.to_bits())).collect(),
+        }
+    }
+
+    #[inline]
+    pub fn buffer_len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Copies the current values into a buffer of the same layout
+    pub fn load_into(&self, target: &mut RowBuffer) {
+        assert_eq!(self.values.len(), target.buffer_len());
+        for (value, target) in self.values.iter().zip(target.get_buffer_mut()) {
+            *target = Float::from_bits(value.load(Ordering::Relaxed));
+        }
+    }
+
+    /// Overwrites the values in the range with those of a buffer of the same layout
+    pub fn store_range(&self, source: &RowBuffer, range: Range<usize>) {
+        assert_eq!(self.values.len(), source.buffer_len());
+        for (value, &source) in self.values[range.clone()].iter().zip(&source.get_buffer()[range]) {
+            value.store(source.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Adds the non-zero values of the deltas, leaving the other values untouched
+    pub fn add_sparse(&self, deltas: &RowBuffer) {
+        assert_eq!(self.values.len(), deltas.buffer_len());
+        for (value, &delta) in self.values.iter().zip(deltas.get_buffer()) {
+            if delta != 0.0 {
+                let current = Float::from_bits(value.load(Ordering::Relaxed));
+                value.store((current + delta).to_bits(), Ordering::Relaxed);
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shared_row_buffer() {
+
+        let mut buffer = RowBuffer::new_with_row_sizes(1.0, [2, 3]);
+        let shared = SharedRowBuffer::from_row_buffer(&buffer);
+        let mut deltas = RowBuffer::new_with_row_sizes(0.0, [2, 3]);
+        deltas.get_row_mut(1)[2] = 0.5;
+        shared.add_sparse(&deltas);
+        shared.add_sparse(&deltas);
+
+        shared.load_into(&mut buffer);
+        assert_eq!(buffer.get_buffer(), &[1.0, 1.0, 1.0, 1.0, 2.0]);
+
+        shared.store_range(&RowBuffer::new_with_row_sizes(3.0, [2, 3]), 1..3);
+        shared.load_into(&mut buffer);
+        assert_eq!(buffer.get_buffer(), &[1.0, 3.0, 3.0, 1.0, 2.0]);
+
+    }
+
+}
@@ -0,0 +1,279 @@
+use std::fmt;
+
+use half::f16;
+
+use crate::Float;
+use crate::net::Net;
+use crate::layer::NetLayerBase;
+use crate::data::PreparedDataSet;
+use crate::func::ErrorFn;
+use crate::stats::Stats;
+
+/// The storage format of the weights of a deployed net
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WeightFormat {
+    F32,
+    F16,
+    /// Affine quantization with a scale and zero point per layer
+    Int8,
+}
+
+impl WeightFormat {
+    pub fn bytes_per_weight(&self) -> usize {
+        match self {
+            WeightFormat::F32 => 4,
+            WeightFormat::F16 => 2,
+            WeightFormat::Int8 => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompressionOption {
+    pub format: WeightFormat,
+    /// The fraction of weights of each layer to prune, smallest magnitude first
+    pub sparsity: Float,
+}
+
+impl CompressionOption {
+
+    /// The f32, f16 and int8 formats, each at 0%, 50% and 90% sparsity
+    pub fn standard_options() -> Vec<CompressionOption> {
+        let mut options = Vec::new();
+        for &format in &[WeightFormat::F32, WeightFormat::F16, WeightFormat::Int8] {
+            for &sparsity in &[0.0, 0.5, 0.9] {
+                options.push(CompressionOption { format, sparsity });
+            }
+        }
+        options
+    }
+
+}
+
+#[derive(Clone, Debug)]
+pub struct CompressionReportEntry {
+    pub option: CompressionOption,
+    pub size_bytes: usize,
+    pub error_stats: Stats,
+    pub accuracy_stats: Option<Stats>,
+}
+
+/// The size and accuracy of a net under different compression options, relative to the
+/// uncompressed f32 net.
+#[derive(Clone, Debug)]
+pub struct CompressionReport {
+    pub baseline: CompressionReportEntry,
+    pub entries: Vec<CompressionReportEntry>,
+}
+
+impl CompressionReport {
+
+    /// Measures the size of the net under each compression option along with its error (and
+    /// accuracy, if a threshold is given) on the data set, by simulating the precision loss of
+    /// pruning and quantizing the weights.
+    pub fn compute(
+        net: &Net,
+        data_set: &PreparedDataSet,
+        error_fn: &ErrorFn,
+        accuracy_threshold: Option<Float>,
+        options: &[CompressionOption],
+    ) -> Self {
+        let baseline_option = CompressionOption { format: WeightFormat::F32, sparsity: 0.0 };
+        CompressionReport {
+            baseline: evaluate_option(net, data_set, error_fn, accuracy_threshold, baseline_option),
+            entries: options.iter()
+                .map(|&option| evaluate_option(net, data_set, error_fn, accuracy_threshold, option))
+                .collect(),
+        }
+    }
+
+    /// The change in mean error of each entry compared to the baseline
+    pub fn error_deltas(&self) -> Vec<f64> {
+        self.entries.iter()
+            .map(|e| e.error_stats.mean() - self.baseline.error_stats.mean())
+            .collect()
+    }
+
+    /// The change in accuracy of each entry compared to the baseline, if accuracy was computed
+    pub fn accuracy_deltas(&self) -> Vec<Option<f64>> {
+        self.entries.iter()
+            .map(|e| match (&e.accuracy_stats, &self.baseline.accuracy_stats) {
+                (Some(accuracy), Some(baseline)) => Some(accuracy.mean() - baseline.mean()),
+                _ => None,
+            })
+            .collect()
+    }
+
+}
+
+impl fmt::Display for CompressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        writeln!(f, "{:<8} {:>8} {:>12} {:>12} {:>12} {:>12}",
+            "format", "sparsity", "size (bytes)", "mean error", "error delta", "acc. delta")?;
+        let rows = std::iter::once((&self.baseline, 0.0, None))
+            .chain(self.entries.iter()
+                .zip(self.error_deltas())
+                .zip(self.accuracy_deltas())
+                .map(|((e, error_delta), accuracy_delta)| (e, error_delta, accuracy_delta)));
+        for (entry, error_delta, accuracy_delta) in rows {
+            writeln!(f, "{:<8} {:>8.2} {:>12} {:>12.6} {:>+12.6} {:>12}",
+                format!("{:?}", entry.option.format),
+                entry.option.sparsity,
+                entry.size_bytes,
+                entry.error_stats.mean(),
+                error_delta,
+                accuracy_delta.map_or("-".to_string(), |d| format!("{:+.4}", d)))?;
+        }
+        Ok(())
+    }
+}
+
+fn evaluate_option(
+    net: &Net,
+    data_set: &PreparedDataSet,
+    error_fn: &ErrorFn,
+    accuracy_threshold: Option<Float>,
+    option: CompressionOption,
+) -> CompressionReportEntry {
+    let mut net = net.clone();
+    let mut size_bytes = 0;
+    for row_index in 0..net.get_weights().num_rows() {
+        if net.get_weights().is_aliased_row(row_index) {
+            continue;
+        }
+        // non-trainable state such as running statistics is not pruned
+        let num_trainable_weights = net.layer(row_index).num_trainable_weights();
+        let row = net.get_weights_mut().get_row_mut(row_index);
+        prune(&mut row[..num_trainable_weights], option.sparsity);
+        match option.format {
+            WeightFormat::F32 => {},
+            WeightFormat::F16 => {
+                for value in row.iter_mut() {
+                    *value = f16::from_f64(*value as f64).to_f32() as Float;
+                }
+            },
+            WeightFormat::Int8 => {
+                let (scale, zero_point) = int8_quantization_params(row);
+                for value in row.iter_mut() {
+                    *value = dequantize_int8(quantize_int8(*value, scale, zero_point), scale, zero_point);
+                }
+                // scale (f32) and zero point (i8) per layer
+                size_bytes += 5;
+            },
+        }
+        size_bytes += storage_size(row, option.format);
+    }
+    let (error_stats, accuracy_stats) = net.get_training_context()
+        .compute_error_and_accuracy_for_batch(data_set, error_fn, accuracy_threshold);
+    CompressionReportEntry {
+        option,
+        size_bytes,
+        error_stats,
+        accuracy_stats,
+    }
+}
+
+/// The size of the values stored either densely, or as a presence bitmap plus the non-zero
+/// values, whichever is smaller.
+fn storage_size(values: &[Float], format: WeightFormat) -> usize {
+    let dense = values.len() * format.bytes_per_weight();
+    let non_zero = values.iter().filter(|&&v| v != 0.0).count();
+    let sparse = values.len().div_ceil(8) + non_zero * format.bytes_per_weight();
+    usize::min(dense, sparse)
+}
+
+/// Sets the given fraction of values with the smallest magnitude to zero
+fn prune(values: &mut [Float], sparsity: Float) {
+    debug_assert!((0.0..=1.0).contains(&sparsity));
+    let num_pruned = (sparsity * values.len() as Float).round() as usize;
+    if num_pruned == 0 {
+        return;
+    }
+    let mut indices: Vec<usize> = (0..values.len()).collect();
+    indices.sort_by(|&a, &b| values[a].abs().partial_cmp(&values[b].abs()).unwrap_or(std::cmp::Ordering::Equal));
+    for &i in &indices[..num_pruned] {
+        values[i] = 0.0;
+    }
+}
+
+/// The scale and zero point of an affine int8 quantization covering the range of the values
+/// (always including zero, so that zero, and thus pruned weights, are represented exactly).
+pub(crate) fn int8_quantization_params(values: &[Float]) -> (Float, i8) {
+    let min = values.iter().cloned().fold(0.0, Float::min);
+    let max = values.iter().cloned().fold(0.0, Float::max);
+    if max == min {
+        return (1.0, 0);
+    }
+    let scale = (max - min) / 255.0;
+    let zero_point = (-128.0 - min / scale).round().clamp(-128.0, 127.0) as i8;
+    (scale, zero_point)
+}
+
+#[inline]
+pub(crate) fn quantize_int8(value: Float, scale: Float, zero_point: i8) -> i8 {
+    (value / scale + zero_point as Float).round().clamp(-128.0, 127.0) as i8
+}
+
+#[inline]
+pub(crate) fn dequantize_int8(value: i8, scale: Float, zero_point: i8) -> Float {
+    (value as Float - zero_point as Float) * scale
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::lines_2x2;
+    use crate::net::NetConfig;
+    use crate::func::ActivationFn;
+    use crate::initializer::RandomNetInitializer;
+
+    #[test]
+    fn test_prune() {
+        let mut values = [0.1, -0.5, 0.2, -0.05];
+        prune(&mut values, 0.5);
+        assert_eq!(values, [0.0, -0.5, 0.2, 0.0]);
+    }
+
+    #[test]
+    fn test_int8_round_trip() {
+        let values = [-1.0, -0.25, 0.0, 0.5, 2.0];
+        let (scale, zero_point) = int8_quantization_params(&values);
+        for &value in &values {
+            let round_trip = dequantize_int8(quantize_int8(value, scale, zero_point), scale, zero_point);
+            assert!((round_trip - value).abs() <= scale / 2.0 + 0.00001);
+        }
+        assert_eq!(dequantize_int8(quantize_int8(0.0, scale, zero_point), scale, zero_point), 0.0);
+    }
+
+    #[test]
+    fn test_compression_report() {
+
+        let data_set = lines_2x2();
+
+        let mut net = NetConfig::new_fully_connected(4, 2, [4], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("compression"));
+        {
+            let mut context = net.get_training_context();
+            for _ in 0..500 {
+                context.train_backprop_single_batch(&data_set, 2.0, &ErrorFn::SquaredError, None, None);
+            }
+        }
+
+        let report = CompressionReport::compute(&net, &data_set, &ErrorFn::SquaredError, Some(0.5), &CompressionOption::standard_options());
+        let num_weights = net.get_weights().buffer_len();
+        let num_layers = net.get_weights().num_rows();
+
+        assert_eq!(report.baseline.size_bytes, num_weights * 4);
+        assert_eq!(report.entries.len(), 9);
+        assert_eq!(report.entries[0].size_bytes, num_weights * 4);
+        assert_eq!(report.error_deltas()[0], 0.0);
+        assert_eq!(report.accuracy_deltas()[0], Some(0.0));
+        assert_eq!(report.entries[3].size_bytes, num_weights * 2);
+        assert!(report.entries[6].size_bytes <= num_weights + 5 * num_layers);
+        assert!(report.entries[8].size_bytes < report.entries[6].size_bytes);
+        assert!(report.error_deltas()[3].abs() < 0.001);
+        assert_eq!(report.to_string().lines().count(), 11);
+
+    }
+
+}
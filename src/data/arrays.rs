@@ -0,0 +1,88 @@
+use std::convert::TryFrom;
+
+use ndarray::{Array2, ArrayView2};
+
+use crate::Float;
+use crate::data::PreparedDataSet;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ArrayConversionError {
+        ZeroColumns {
+            description("Inputs or targets have no columns")
+            display("Inputs and targets need at least one column each")
+        }
+        RowCountMismatch(input_rows: usize, target_rows: usize) {
+            description("Inputs and targets have different numbers of rows")
+            display("There are {} rows of inputs, but {} rows of targets", input_rows, target_rows)
+        }
+    }
+}
+
+/// A data set of a matrix of inputs and a matrix of targets, one row per sample. The matrices
+/// may have any memory layout.
+impl<'a> TryFrom<(ArrayView2<'a, Float>, ArrayView2<'a, Float>)> for PreparedDataSet {
+    type Error = ArrayConversionError;
+
+    fn try_from((inputs, targets): (ArrayView2<'a, Float>, ArrayView2<'a, Float>)) -> Result<Self, Self::Error> {
+        let (num_rows, independent_cols) = inputs.dim();
+        let (target_rows, dependent_cols) = targets.dim();
+        if independent_cols == 0 || dependent_cols == 0 {
+            return Err(ArrayConversionError::ZeroColumns);
+        }
+        if num_rows != target_rows {
+            return Err(ArrayConversionError::RowCountMismatch(num_rows, target_rows));
+        }
+        let mut data = Vec::with_capacity(num_rows * (independent_cols + dependent_cols));
+        for (row_inputs, row_targets) in inputs.outer_iter().zip(targets.outer_iter()) {
+            data.extend(row_inputs.iter());
+            data.extend(row_targets.iter());
+        }
+        Ok(PreparedDataSet::from_vec(data, independent_cols, dependent_cols, num_rows))
+    }
+}
+
+impl TryFrom<(Array2<Float>, Array2<Float>)> for PreparedDataSet {
+    type Error = ArrayConversionError;
+
+    fn try_from((inputs, targets): (Array2<Float>, Array2<Float>)) -> Result<Self, Self::Error> {
+        PreparedDataSet::try_from((inputs.view(), targets.view()))
+    }
+}
+
+/// The inputs and the targets of the data set as matrices of one row per sample
+impl From<&PreparedDataSet> for (Array2<Float>, Array2<Float>) {
+    fn from(data_set: &PreparedDataSet) -> Self {
+        let shape = |cols: usize| (data_set.num_rows(), cols);
+        (
+            Array2::from_shape_vec(shape(data_set.independent_cols()), data_set.inputs_row_major()).unwrap(),
+            Array2::from_shape_vec(shape(data_set.dependent_cols()), data_set.targets_row_major()).unwrap(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_array_conversions() {
+
+        let inputs = array![[0., 0.], [0., 1.], [1., 0.]];
+        let targets = array![[0.], [1.], [1.]];
+        let data_set = PreparedDataSet::try_from((inputs.clone(), targets.clone())).unwrap();
+        assert_eq!(data_set.row(1), (&[0., 1.][..], &[1.][..]));
+        assert_eq!(<(Array2<Float>, Array2<Float>)>::from(&data_set), (inputs.clone(), targets.clone()));
+
+        // a transposed view isn't in row-major order
+        let transposed = array![[0., 0., 1.], [0., 1., 0.]];
+        let data_set = PreparedDataSet::try_from((transposed.t(), targets.view())).unwrap();
+        assert_eq!(data_set.row(2), (&[1., 0.][..], &[1.][..]));
+
+        assert!(matches!(PreparedDataSet::try_from((inputs.clone(), array![[0.], [1.]])), Err(ArrayConversionError::RowCountMismatch(3, 2))));
+        assert!(matches!(PreparedDataSet::try_from((Array2::zeros((3, 0)), targets)), Err(ArrayConversionError::ZeroColumns)));
+
+    }
+
+}
@@ -0,0 +1,1404 @@
+use std::path::Path;
+use std::fs;
+use std::io::{self, Read, Seek};
+use std::slice;
+use std::error::Error;
+use std::boxed::Box;
+use std::sync::Arc;
+use std::fmt::{self, Debug};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::thread;
+use std::borrow::Borrow;
+use crate::Float;
+use rand::{Rng, SeedableRng};
+use rand::seq::{SliceRandom, index};
+use rand_xorshift::XorShiftRng;
+use crate::utils::{into_string_vec, first_duplicate, stable_hash_seed};
+use crate::data::{CsvDialect, DataSetSchema, LabelEncoding, LabelVocabulary, SchemaError};
+use crate::net::NetConfig;
+use crate::stats::Stats;
+use itertools::chain;
+
+quick_error! {
+    #[derive(Debug)]
+    enum CsvParseError {
+        ZeroColumns {
+            description("Zero columns in dataset")
+        }
+        ZeroColumnsSelected {
+            description("Zero columns selected")
+        }
+        NoHeaders {
+            description("Columns can only be selected by name in files with headers")
+        }
+        ZeroRows {
+            description("Zero rows in dataset")
+        }
+        ColumnCountMismatch(count: usize, previous: usize) {
+            description("Invalid number of columns, did not match previous columns")
+            display("Invalid number of columns {}, previous was {}", count, previous)
+        }
+        ColumnIndexOutOfRange(index: usize, count: usize) {
+            description("Column index out of range")
+            display("Column index {} out of range for {} columns", index, count)
+        }
+        ColumnNotFound(name: String) {
+            description("Column with specified name not found")
+            display("Column with name {} not found", name)
+        }
+        DuplicateColumns(name: String) {
+            description("Duplicate columns found in file")
+            display("Duplicate columns found in file: {}", name)
+        }
+        DuplicateColumnsSpecified(name: String) {
+            description("Duplicate columns specified")
+            display("Duplicate columns specified: {}", name)
+        }
+        ColumnGroupMissing(name: String) {
+            description("No columns given for an input group or output head")
+            display("No columns given for input group or output head {}", name)
+        }
+        ColumnGroupSizeMismatch(name: String, expected: usize, actual: usize) {
+            description("Wrong number of columns for an input group or output head")
+            display("Input group or output head {} takes {} columns, but {} were given", name, expected, actual)
+        }
+        Compressed(compression: &'static str) {
+            description("File is compressed")
+            display("File is {0} compressed, but the {0} feature is disabled", compression)
+        }
+        UnknownLabel(label: String) {
+            description("Label is not part of the vocabulary")
+            display("Label {} is not part of the vocabulary", label)
+        }
+    }
+}
+
+/// How the rows of a data set are assigned to partitions, e.g. of training workers
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PartitionStrategy {
+    /// Consecutive rows, without copying. The partitions of a sorted file are biased.
+    #[default]
+    Contiguous,
+    /// Every `n`th row, so each partition samples the whole file
+    RoundRobin,
+    /// Like `RoundRobin`, with the rows of each value of the dependent column spread evenly
+    /// across the partitions
+    Stratified { dependent_col: usize },
+}
+
+/// The number of records each thread parses at a time when loading a whole csv file
+const CSV_RECORDS_PER_THREAD: usize = 4096;
+
+#[derive(Clone)]
+pub struct PreparedDataSet {
+    data: Arc<Box<[Float]>>,
+    offset: usize,
+    end: usize,
+    num_cols: usize,
+    num_rows: usize,
+    dependent_cols: usize,
+    independent_cols: usize,
+    schema: Option<Arc<DataSetSchema>>,
+    labels: Option<Arc<LabelVocabulary>>,
+}
+
+impl Debug for PreparedDataSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("PreparedDataSet")
+            .field("num_rows", &self.num_rows)
+            .field("independent_cols", &self.independent_cols)
+            .field("dependent_cols", &self.dependent_cols)
+            .finish()
+    }
+}
+
+impl PreparedDataSet {
+
+    /// Loads the columns described by the schema from a csv file, validating every value.
+    pub fn from_csv_with_schema(
+        path: impl AsRef<Path>,
+        schema: DataSetSchema
+    ) -> Result<PreparedDataSet, Box<dyn Error>> {
+        let data_set = Self::from_csv(
+            path,
+            schema.independent_column_names(),
+            schema.dependent_column_names(),
+        )?;
+        Ok(data_set.with_schema(schema)?)
+    }
+
+    /// Attaches a schema to the data set, after validating that every row conforms to it.
+    pub fn with_schema(self, schema: DataSetSchema) -> Result<PreparedDataSet, SchemaError> {
+        schema.validate_counts(self.independent_cols, self.dependent_cols)?;
+        for (inputs, outputs) in &self {
+            schema.validate_row(inputs, outputs)?;
+        }
+        let mut new = self;
+        new.schema = Some(Arc::new(schema));
+        Ok(new)
+    }
+
+    #[inline]
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    #[inline]
+    pub fn independent_cols(&self) -> usize {
+        self.independent_cols
+    }
+
+    #[inline]
+    pub fn dependent_cols(&self) -> usize {
+        self.dependent_cols
+    }
+
+    #[inline]
+    pub fn schema(&self) -> Option<&DataSetSchema> {
+        self.schema.as_deref()
+    }
+
+    /// The vocabulary of the label column, if the dependent values are encoded class names
+    #[inline]
+    pub fn labels(&self) -> Option<&LabelVocabulary> {
+        self.labels.as_deref()
+    }
+
+    /// The min, max, mean and standard deviation of every column, the independent columns
+    /// first, e.g. to check the data for outliers or constant columns before training
+    pub fn column_stats(&self) -> Vec<Stats> {
+        Stats::of_columns(self.data[self.offset..self.end].chunks_exact(self.num_cols))
+    }
+
+    /// Loads the named columns of a csv file. Gzip and zstd compressed files are decompressed as
+    /// they're read, so they needn't be expanded on disk first.
+    pub fn from_csv<T1, I1, T2, I2>(
+        path: impl AsRef<Path>,
+        independent_cols: T1,
+        dependent_cols: T2
+    ) -> Result<PreparedDataSet, Box<dyn Error>>
+        where T1: AsRef<[I1]>, I1: ToString,
+              T2: AsRef<[I2]>, I2: ToString
+    {
+        Self::from_csv_with_dialect(path, &CsvDialect::default(), independent_cols, dependent_cols)
+    }
+
+    /// Like `from_csv`, for files in another format, e.g. semicolon-delimited with decimal commas.
+    /// The columns are selected by name, so the dialect must have headers.
+    pub fn from_csv_with_dialect<T1, I1, T2, I2>(
+        path: impl AsRef<Path>,
+        dialect: &CsvDialect,
+        independent_cols: T1,
+        dependent_cols: T2
+    ) -> Result<PreparedDataSet, Box<dyn Error>>
+        where T1: AsRef<[I1]>, I1: ToString,
+              T2: AsRef<[I2]>, I2: ToString
+    {
+        Self::from_csv_reader(Self::open_decompressed(path)?, dialect, independent_cols, dependent_cols)
+    }
+
+    /// Loads csv data from any reader, e.g. a network stream or a file in a compression format
+    /// other than gzip and zstd
+    pub fn from_csv_reader<T1, I1, T2, I2>(
+        reader: impl io::Read,
+        dialect: &CsvDialect,
+        independent_cols: T1,
+        dependent_cols: T2
+    ) -> Result<PreparedDataSet, Box<dyn Error>>
+        where T1: AsRef<[I1]>, I1: ToString,
+              T2: AsRef<[I2]>, I2: ToString
+    {
+        let (reader, column_names) = Self::csv_reader(reader, dialect)?;
+        let (independent_indices, dependent_indices) = Self::select_csv_columns(
+            &column_names,
+            dialect,
+            &into_string_vec(independent_cols),
+            &into_string_vec(dependent_cols),
+        )?;
+        Self::read_csv_columns(reader, dialect, column_names.len(), &independent_indices, &dependent_indices, num_cpus::get())
+    }
+
+    /// The indices of the independent and dependent columns selected by name
+    pub(crate) fn select_csv_columns(
+        column_names: &[String],
+        dialect: &CsvDialect,
+        independent_cols: &[String],
+        dependent_cols: &[String],
+    ) -> Result<(Vec<usize>, Vec<usize>), Box<dyn Error>> {
+
+        if !dialect.has_headers {
+            return Err(Box::new(CsvParseError::NoHeaders));
+        }
+
+        if independent_cols.is_empty() || dependent_cols.is_empty() {
+            return Err(Box::new(CsvParseError::ZeroColumnsSelected));
+        }
+
+        let independent_indices = Self::column_indices(column_names, independent_cols)?;
+        let dependent_indices = Self::column_indices(column_names, dependent_cols)?;
+
+        if let Some(dupe) = first_duplicate(chain(independent_cols.iter(), dependent_cols.iter())) {
+            return Err(Box::new(CsvParseError::DuplicateColumnsSpecified(dupe.clone())));
+        }
+
+        Ok((independent_indices, dependent_indices))
+
+    }
+
+    /// Loads the columns at the indices from a csv file, e.g. one without a header row. If
+    /// `has_headers` is true, the first row is skipped.
+    pub fn from_csv_by_index(
+        path: impl AsRef<Path>,
+        independent_cols: &[usize],
+        dependent_cols: &[usize],
+        has_headers: bool,
+    ) -> Result<PreparedDataSet, Box<dyn Error>> {
+        Self::from_csv_by_index_with_dialect(path, &CsvDialect::default().with_headers(has_headers), independent_cols, dependent_cols)
+    }
+
+    /// Like `from_csv_by_index`, for files in another format. The header row is skipped if the
+    /// dialect has one.
+    pub fn from_csv_by_index_with_dialect(
+        path: impl AsRef<Path>,
+        dialect: &CsvDialect,
+        independent_cols: &[usize],
+        dependent_cols: &[usize],
+    ) -> Result<PreparedDataSet, Box<dyn Error>> {
+
+        if independent_cols.is_empty() || dependent_cols.is_empty() {
+            return Err(Box::new(CsvParseError::ZeroColumnsSelected));
+        }
+
+        let (reader, column_names) = Self::csv_reader(Self::open_decompressed(path)?, dialect)?;
+        let n_cols = column_names.len();
+
+        if let Some(&index) = chain(independent_cols, dependent_cols).find(|&&index| index >= n_cols) {
+            return Err(Box::new(CsvParseError::ColumnIndexOutOfRange(index, n_cols)));
+        }
+
+        if let Some(dupe) = first_duplicate(chain(independent_cols, dependent_cols)) {
+            return Err(Box::new(CsvParseError::DuplicateColumnsSpecified(dupe.to_string())));
+        }
+
+        Self::read_csv_columns(reader, dialect, n_cols, independent_cols, dependent_cols, num_cpus::get())
+
+    }
+
+    /// Reads every row, parsing batches of records on `num_threads` threads. The records are read
+    /// from the file on this thread, and the parsed rows are appended in order.
+    fn read_csv_columns<R: io::Read>(
+        mut reader: csv::Reader<R>,
+        dialect: &CsvDialect,
+        n_cols: usize,
+        independent_indices: &[usize],
+        dependent_indices: &[usize],
+        num_threads: usize,
+    ) -> Result<PreparedDataSet, Box<dyn Error>> {
+
+        let row_cols = independent_indices.len() + dependent_indices.len();
+        let mut records = vec![csv::StringRecord::new(); num_threads.max(1) * CSV_RECORDS_PER_THREAD];
+        let mut data = Vec::new();
+        let mut num_rows = 0;
+        let parse_records = |records: &[csv::StringRecord], data: &mut Vec<Float>| {
+            let mut row_vals = Vec::with_capacity(n_cols);
+            let mut parse_buffer = String::new();
+            for record in records {
+                Self::parse_csv_record(record, dialect, n_cols, independent_indices, dependent_indices, &mut row_vals, &mut parse_buffer, data)?;
+            }
+            Ok::<_, Box<dyn Error + Send + Sync>>(())
+        };
+
+        loop {
+            let mut num_records = 0;
+            let mut read_error = None;
+            while num_records < records.len() {
+                match reader.read_record(&mut records[num_records]) {
+                    Ok(true) => num_records += 1,
+                    Ok(false) => break,
+                    // parse the rows before the invalid record first, in case one of them is invalid too
+                    Err(err) => {
+                        read_error = Some(err);
+                        break;
+                    },
+                }
+            }
+            let batch = &records[..num_records];
+            if num_records <= CSV_RECORDS_PER_THREAD {
+                parse_records(batch, &mut data).map_err(|err| err as Box<dyn Error>)?;
+            } else {
+                let parsed: Vec<Result<Vec<Float>, _>> = thread::scope(|scope| {
+                    let workers: Vec<_> = batch.chunks(CSV_RECORDS_PER_THREAD)
+                        .map(|chunk| scope.spawn(move || {
+                            let mut chunk_data = Vec::with_capacity(chunk.len() * row_cols);
+                            parse_records(chunk, &mut chunk_data).map(|_| chunk_data)
+                        }))
+                        .collect();
+                    workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+                });
+                // the error of the earliest row is returned, as when parsing on a single thread
+                for chunk_data in parsed {
+                    data.extend(chunk_data.map_err(|err| err as Box<dyn Error>)?);
+                }
+            }
+            if let Some(err) = read_error {
+                return Err(Box::new(err));
+            }
+            num_rows += num_records;
+            if num_records < records.len() {
+                break;
+            }
+        }
+
+        Ok(Self::from_vec(data, independent_indices.len(), dependent_indices.len(), num_rows))
+
+    }
+
+    /// Appends the selected columns of up to `max_rows` rows to `data`, returning the number of
+    /// rows read, which is less than `max_rows` only at the end of the file
+    pub(crate) fn read_csv_rows<R: io::Read>(
+        reader: &mut csv::Reader<R>,
+        dialect: &CsvDialect,
+        n_cols: usize,
+        independent_indices: &[usize],
+        dependent_indices: &[usize],
+        max_rows: usize,
+        data: &mut Vec<Float>,
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+
+        let mut record = csv::StringRecord::new();
+        let mut row_vals = Vec::with_capacity(n_cols);
+        let mut num_rows = 0usize;
+        let mut parse_buffer = String::new();
+
+        while num_rows < max_rows && reader.read_record(&mut record)? {
+            Self::parse_csv_record(&record, dialect, n_cols, independent_indices, dependent_indices, &mut row_vals, &mut parse_buffer, data)?;
+            num_rows += 1;
+        }
+
+        Ok(num_rows)
+
+    }
+
+    /// Appends the selected columns of a record to `data`, using `row_vals` and `parse_buffer`
+    /// as scratch space
+    #[allow(clippy::too_many_arguments)]
+    fn parse_csv_record(
+        record: &csv::StringRecord,
+        dialect: &CsvDialect,
+        n_cols: usize,
+        independent_indices: &[usize],
+        dependent_indices: &[usize],
+        row_vals: &mut Vec<Float>,
+        parse_buffer: &mut String,
+        data: &mut Vec<Float>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+
+        row_vals.clear();
+        for datum in record.iter() {
+            row_vals.push(dialect.parse(datum, parse_buffer)?);
+        }
+        if n_cols != row_vals.len() {
+            return Err(Box::new(CsvParseError::ColumnCountMismatch(row_vals.len(), n_cols)));
+        }
+
+        for &i in independent_indices {
+            data.push(row_vals[i]);
+        }
+
+        for &i in dependent_indices {
+            data.push(row_vals[i]);
+        }
+
+        Ok(())
+
+    }
+
+    /// Loads a data set whose dependent column holds class names, which are encoded into targets
+    /// with a vocabulary of the distinct names. Decode predictions with `labels`.
+    pub fn from_csv_with_labels<T, I>(
+        path: impl AsRef<Path>,
+        independent_cols: T,
+        label_col: impl ToString,
+        encoding: LabelEncoding,
+    ) -> Result<PreparedDataSet, Box<dyn Error>>
+        where T: AsRef<[I]>, I: ToString
+    {
+        Self::read_csv_with_labels(path, into_string_vec(independent_cols), label_col.to_string(), None, encoding)
+    }
+
+    /// Like `from_csv_with_labels`, but encodes the labels with an existing vocabulary, e.g. that of
+    /// the training set when loading a test set. Labels outside of the vocabulary are an error.
+    pub fn from_csv_with_vocabulary<T, I>(
+        path: impl AsRef<Path>,
+        independent_cols: T,
+        label_col: impl ToString,
+        vocabulary: &LabelVocabulary,
+    ) -> Result<PreparedDataSet, Box<dyn Error>>
+        where T: AsRef<[I]>, I: ToString
+    {
+        Self::read_csv_with_labels(path, into_string_vec(independent_cols), label_col.to_string(), Some(vocabulary), vocabulary.encoding())
+    }
+
+    fn read_csv_with_labels(
+        path: impl AsRef<Path>,
+        independent_cols: Vec<String>,
+        label_col: String,
+        vocabulary: Option<&LabelVocabulary>,
+        encoding: LabelEncoding,
+    ) -> Result<PreparedDataSet, Box<dyn Error>> {
+
+        if independent_cols.is_empty() {
+            return Err(Box::new(CsvParseError::ZeroColumnsSelected));
+        }
+
+        let (mut reader, column_names) = Self::csv_reader(Self::open_decompressed(path)?, &CsvDialect::default())?;
+        let independent_indices = Self::column_indices(&column_names, &independent_cols)?;
+        let label_index = Self::column_indices(&column_names, slice::from_ref(&label_col))?[0];
+
+        if let Some(dupe) = first_duplicate(chain(independent_cols.iter(), Some(&label_col))) {
+            return Err(Box::new(CsvParseError::DuplicateColumnsSpecified(dupe.clone())));
+        }
+
+        let mut inputs = Vec::new();
+        let mut labels = Vec::new();
+        for row in reader.records() {
+            let row = row?;
+            if column_names.len() != row.len() {
+                return Err(Box::new(CsvParseError::ColumnCountMismatch(row.len(), column_names.len())));
+            }
+            for &i in &independent_indices {
+                inputs.push(row[i].parse::<Float>()?);
+            }
+            labels.push(row[label_index].to_owned());
+        }
+
+        let vocabulary = match vocabulary {
+            Some(vocabulary) => vocabulary.clone(),
+            None if labels.is_empty() => return Err(Box::new(CsvParseError::ZeroRows)),
+            None => LabelVocabulary::fit(labels.iter().map(String::as_str), encoding),
+        };
+        let mut data = Vec::with_capacity(labels.len() * (independent_cols.len() + vocabulary.num_cols()));
+        for (row_inputs, label) in inputs.chunks_exact(independent_cols.len()).zip(&labels) {
+            data.extend_from_slice(row_inputs);
+            if !vocabulary.encode_into(label, &mut data) {
+                return Err(Box::new(CsvParseError::UnknownLabel(label.clone())));
+            }
+        }
+
+        Ok(Self::from_vec(data, independent_cols.len(), vocabulary.num_cols(), labels.len()).with_labels(vocabulary))
+
+    }
+
+    /// Opens a file for reading, decompressing it as it's read if it is gzip or zstd compressed,
+    /// which is told by its first bytes rather than its name
+    pub(crate) fn open_decompressed(path: impl AsRef<Path>) -> io::Result<Box<dyn io::Read + Send>> {
+        let mut file = fs::File::open(path)?;
+        let mut magic = [0u8; 4];
+        let len = file.read(&mut magic)?;
+        file.seek(io::SeekFrom::Start(0))?;
+        match &magic[..len] {
+            #[cfg(feature = "gzip")]
+            [0x1f, 0x8b, ..] => Ok(Box::new(flate2::read::MultiGzDecoder::new(io::BufReader::new(file)))),
+            #[cfg(not(feature = "gzip"))]
+            [0x1f, 0x8b, ..] => Err(io::Error::new(io::ErrorKind::InvalidData, CsvParseError::Compressed("gzip"))),
+            #[cfg(feature = "zstd")]
+            [0x28, 0xb5, 0x2f, 0xfd] => Ok(Box::new(zstd::stream::read::Decoder::new(file)?)),
+            #[cfg(not(feature = "zstd"))]
+            [0x28, 0xb5, 0x2f, 0xfd] => Err(io::Error::new(io::ErrorKind::InvalidData, CsvParseError::Compressed("zstd"))),
+            _ => Ok(Box::new(file)),
+        }
+    }
+
+    /// Returns a csv reader of the data and the names of its columns. Without headers, the values
+    /// of the first row stand in for the names, which still determine the column count.
+    pub(crate) fn csv_reader<R: io::Read>(reader: R, dialect: &CsvDialect) -> Result<(csv::Reader<R>, Vec<String>), Box<dyn Error>> {
+
+        let mut reader = dialect.reader_builder().from_reader(reader);
+
+        let column_names = reader.headers()?
+            .iter()
+            .map(str::to_owned)
+            .collect::<Vec<String>>();
+
+        if column_names.is_empty() {
+            return Err(Box::new(CsvParseError::ZeroColumns));
+        }
+
+        if dialect.has_headers {
+            if let Some(dupe) = first_duplicate(column_names.iter()) {
+                return Err(Box::new(CsvParseError::DuplicateColumns(dupe.clone())));
+            }
+        }
+
+        Ok((reader, column_names))
+
+    }
+
+    fn column_indices(column_names: &[String], names: &[String]) -> Result<Vec<usize>, CsvParseError> {
+        names.iter()
+            .map(|name| column_names.iter().position(|n| n == name).ok_or_else(|| CsvParseError::ColumnNotFound(name.clone())))
+            .collect()
+    }
+
+    /// Loads a data set for a net with named input groups and output heads, e.g. for multi-task
+    /// learning. `columns` gives the columns of each group and head by name, which are laid out
+    /// in the order the net expects. The input of a net without groups is taken to be a single
+    /// group named `input`, and the output of a net without heads a single head named `output`.
+    pub fn from_csv_for_net<K, V>(
+        path: impl AsRef<Path>,
+        net_config: &NetConfig,
+        columns: &HashMap<K, Vec<V>>,
+    ) -> Result<PreparedDataSet, Box<dyn Error>>
+        where K: Borrow<str> + Hash + Eq, V: ToString
+    {
+        let output_sizes: Vec<usize> = net_config.layer_shapes()?.iter().map(|shape| shape.size()).collect();
+        let input_groups: Vec<(String, usize)> = if net_config.input_groups().is_empty() {
+            vec![("input".to_string(), net_config.input_shape().size())]
+        } else {
+            net_config.input_groups().iter().map(|group| (group.name.clone(), group.size)).collect()
+        };
+        let heads: Vec<(String, usize)> = if net_config.output_heads().is_empty() {
+            vec![("output".to_string(), *output_sizes.last().unwrap())]
+        } else {
+            net_config.output_heads().iter().map(|head| (head.name.clone(), output_sizes[head.layer_index])).collect()
+        };
+        let group_columns = |groups: &[(String, usize)]| -> Result<Vec<String>, CsvParseError> {
+            let mut names = Vec::new();
+            for (name, size) in groups {
+                let group = columns.get(name.as_str()).ok_or_else(|| CsvParseError::ColumnGroupMissing(name.clone()))?;
+                if group.len() != *size {
+                    return Err(CsvParseError::ColumnGroupSizeMismatch(name.clone(), *size, group.len()));
+                }
+                names.extend(group.iter().map(ToString::to_string));
+            }
+            Ok(names)
+        };
+        Self::from_csv(path, group_columns(&input_groups)?, group_columns(&heads)?)
+    }
+
+    /// A data set of in-memory rows, e.g. generated data. Every row needs the same number of
+    /// values, and there must be a row of targets for each row of inputs.
+    pub fn from_rows<R1, R2>(inputs: &[R1], targets: &[R2]) -> PreparedDataSet
+        where R1: AsRef<[Float]>, R2: AsRef<[Float]>
+    {
+        assert_eq!(inputs.len(), targets.len(), "inputs and targets have different numbers of rows");
+        assert!(!inputs.is_empty(), "can't create a data set without any rows");
+        let independent_cols = inputs[0].as_ref().len();
+        let dependent_cols = targets[0].as_ref().len();
+        assert!(independent_cols > 0 && dependent_cols > 0, "rows must have inputs and targets");
+        let mut data = Vec::with_capacity(inputs.len() * (independent_cols + dependent_cols));
+        for (row_inputs, row_targets) in inputs.iter().zip(targets) {
+            assert_eq!(row_inputs.as_ref().len(), independent_cols, "rows have different numbers of inputs");
+            assert_eq!(row_targets.as_ref().len(), dependent_cols, "rows have different numbers of targets");
+            data.extend_from_slice(row_inputs.as_ref());
+            data.extend_from_slice(row_targets.as_ref());
+        }
+        Self::from_vec(data, independent_cols, dependent_cols, inputs.len())
+    }
+
+    /// A data set of rows laid out one after another in `data`, each holding its inputs followed
+    /// by its targets
+    pub fn from_flat(data: Vec<Float>, independent_cols: usize, dependent_cols: usize) -> PreparedDataSet {
+        let num_cols = independent_cols + dependent_cols;
+        assert!(independent_cols > 0 && dependent_cols > 0, "rows must have inputs and targets");
+        assert_eq!(data.len() % num_cols, 0, "data is not a whole number of rows");
+        let num_rows = data.len() / num_cols;
+        Self::from_vec(data, independent_cols, dependent_cols, num_rows)
+    }
+
+    /// A data set of separate row-major matrices of inputs and targets. With the `ndarray`
+    /// feature, data sets also convert from and to pairs of `Array2`s.
+    pub fn from_row_major(inputs: &[Float], targets: &[Float], independent_cols: usize, dependent_cols: usize) -> PreparedDataSet {
+        assert!(independent_cols > 0 && dependent_cols > 0, "rows must have inputs and targets");
+        assert_eq!(inputs.len() % independent_cols, 0, "inputs are not a whole number of rows");
+        let num_rows = inputs.len() / independent_cols;
+        assert_eq!(targets.len(), num_rows * dependent_cols, "inputs and targets have different numbers of rows");
+        let mut data = Vec::with_capacity(inputs.len() + targets.len());
+        for (row_inputs, row_targets) in inputs.chunks_exact(independent_cols).zip(targets.chunks_exact(dependent_cols)) {
+            data.extend_from_slice(row_inputs);
+            data.extend_from_slice(row_targets);
+        }
+        Self::from_vec(data, independent_cols, dependent_cols, num_rows)
+    }
+
+    /// The independent values as a `num_rows` x `independent_cols` row-major matrix
+    pub fn inputs_row_major(&self) -> Vec<Float> {
+        self.iter().flat_map(|(inputs, _)| inputs.iter().cloned()).collect()
+    }
+
+    /// The dependent values as a `num_rows` x `dependent_cols` row-major matrix
+    pub fn targets_row_major(&self) -> Vec<Float> {
+        self.iter().flat_map(|(_, targets)| targets.iter().cloned()).collect()
+    }
+
+    pub(crate) fn with_labels(self, labels: LabelVocabulary) -> PreparedDataSet {
+        assert_eq!(labels.num_cols(), self.dependent_cols, "labels do not match the dependent columns");
+        let mut new = self;
+        new.labels = Some(Arc::new(labels));
+        new
+    }
+
+    /// Attaches a schema without validating the rows, for schemas derived from the data set's own
+    pub(crate) fn with_derived_schema(self, schema: DataSetSchema) -> PreparedDataSet {
+        assert!(schema.validate_counts(self.independent_cols, self.dependent_cols).is_ok(), "schema does not match the columns");
+        let mut new = self;
+        new.schema = Some(Arc::new(schema));
+        new
+    }
+
+    pub(crate) fn from_vec(data: Vec<Float>, independent_cols: usize, dependent_cols: usize, num_rows: usize) -> Self {
+        let num_cols = dependent_cols + independent_cols;
+        assert_eq!(data.len(), num_rows * num_cols, "data length mismatch");
+        PreparedDataSet {
+            data: Arc::new(data.into_boxed_slice()),
+            offset: 0,
+            end: num_rows * num_cols,
+            num_cols,
+            num_rows,
+            independent_cols,
+            dependent_cols,
+            schema: None,
+            labels: None,
+        }
+    }
+
+    fn make_partition(&self, row_offset: usize, num_rows: usize) -> PreparedDataSet {
+        let offset = self.offset + row_offset * self.num_cols;
+        let end = offset + num_rows * self.num_cols;
+        assert!(offset <= end && end <= self.end);
+        PreparedDataSet {
+            data: Arc::clone(&self.data),
+            offset,
+            end,
+            num_cols: self.num_cols,
+            num_rows,
+            independent_cols: self.independent_cols,
+            dependent_cols: self.dependent_cols,
+            schema: self.schema.clone(),
+            labels: self.labels.clone(),
+        }
+    }
+
+    /// Splits the rows into `n` contiguous partitions, the last of which holds the leftover rows.
+    /// There's at most one partition per row, or a single empty one if there are no rows.
+    pub fn partition(&self, n: usize) -> Vec<PreparedDataSet> {
+        self.partition_with(n, PartitionStrategy::Contiguous)
+    }
+
+    /// Splits the rows into at most `n` partitions by the strategy, see `PartitionStrategy`. The
+    /// partitions of strategies other than `Contiguous` share a new buffer holding the rows in
+    /// partition order.
+    pub fn partition_with(&self, n: usize, strategy: PartitionStrategy) -> Vec<PreparedDataSet> {
+        let (ordered, windows) = self.partition_windows(n, strategy);
+        windows.into_iter()
+            .map(|(first_row, num_rows)| ordered.make_partition(first_row, num_rows))
+            .collect()
+    }
+
+    /// The partitions of `partition_with` as windows of (first row, number of rows) over the rows
+    /// in partition order, which is the data set itself for `Contiguous` partitions. Windows can
+    /// be shifted with `iter_window` to move the partition boundaries.
+    pub fn partition_windows(&self, n: usize, strategy: PartitionStrategy) -> (PreparedDataSet, Vec<(usize, usize)>) {
+        assert!(n > 0, "can't make zero partitions");
+        let n = n.min(self.num_rows.max(1));
+        let mut partition_rows: Vec<Vec<usize>> = vec![Vec::with_capacity(self.num_rows / n + 1); n];
+        match strategy {
+            PartitionStrategy::Contiguous => {
+                // the last partition holds the leftover rows, at the start of the data set
+                let target_rows = self.num_rows / n;
+                let mut windows = Vec::with_capacity(n);
+                let mut end_row = self.num_rows;
+                for _ in 0..n-1 {
+                    end_row -= target_rows;
+                    windows.push((end_row, target_rows));
+                }
+                windows.push((0, end_row));
+                return (self.clone(), windows);
+            },
+            PartitionStrategy::RoundRobin => {
+                for index in 0..self.num_rows {
+                    partition_rows[index % n].push(index);
+                }
+            },
+            PartitionStrategy::Stratified { dependent_col } => {
+                assert!(dependent_col < self.dependent_cols);
+                let mut rows_by_label: BTreeMap<_, Vec<usize>> = BTreeMap::new();
+                for (index, (_, outputs)) in self.iter().enumerate() {
+                    rows_by_label.entry(outputs[dependent_col].to_bits()).or_default().push(index);
+                }
+                // dealing the rows of every label in turn keeps the partition sizes balanced
+                for (position, index) in rows_by_label.into_values().flatten().enumerate() {
+                    partition_rows[position % n].push(index);
+                }
+            },
+        }
+        let ordered = self.select_rows(&partition_rows.concat());
+        let mut first_row = 0;
+        let windows = partition_rows.iter()
+            .map(|rows| {
+                let window = (first_row, rows.len());
+                first_row += rows.len();
+                window
+            })
+            .collect();
+        (ordered, windows)
+    }
+
+    /// A copy of the data set holding the rows at the indices, in order
+    pub fn select_rows(&self, indices: &[usize]) -> PreparedDataSet {
+        let mut data = Vec::with_capacity(indices.len() * self.num_cols);
+        for &index in indices {
+            assert!(index < self.num_rows);
+            let offset = self.offset + index * self.num_cols;
+            data.extend_from_slice(&self.data[offset..offset + self.num_cols]);
+        }
+        let mut selected = Self::from_vec(data, self.independent_cols, self.dependent_cols, indices.len());
+        selected.schema = self.schema.clone();
+        selected.labels = self.labels.clone();
+        selected
+    }
+
+    /// A copy of the data set without rows equal to an earlier row, e.g. to keep duplicates from
+    /// leaking between training and validation sets before splitting
+    pub fn dedup(&self) -> PreparedDataSet {
+        let mut seen = HashSet::with_capacity(self.num_rows);
+        let unique: Vec<usize> = self.data[self.offset..self.end].chunks_exact(self.num_cols)
+            .enumerate()
+            .filter(|(_, row)| {
+                // -0.0 and 0.0 are equal, but have different bits
+                seen.insert(row.iter().map(|&value| (value + 0.0).to_bits()).collect::<Vec<_>>())
+            })
+            .map(|(index, _)| index)
+            .collect();
+        self.select_rows(&unique)
+    }
+
+    /// Like `dedup`, but rows are duplicates if none of their values differ by more than
+    /// `tolerance`. Each row is compared with every kept row, so this is quadratic in the number of
+    /// rows.
+    pub fn dedup_with_tolerance(&self, tolerance: Float) -> PreparedDataSet {
+        assert!(tolerance >= 0.0, "tolerance must not be negative");
+        let rows: Vec<&[Float]> = self.data[self.offset..self.end].chunks_exact(self.num_cols).collect();
+        let mut unique: Vec<usize> = Vec::new();
+        for (index, row) in rows.iter().enumerate() {
+            let is_duplicate = unique.iter().any(|&kept| {
+                rows[kept].iter().zip(row.iter()).all(|(a, b)| (a - b).abs() <= tolerance)
+            });
+            if !is_duplicate {
+                unique.push(index);
+            }
+        }
+        self.select_rows(&unique)
+    }
+
+    /// A copy of the data set with the independent and dependent values of every row transformed
+    pub(crate) fn map_rows(&self, mut transform: impl FnMut(&mut [Float], &mut [Float])) -> PreparedDataSet {
+        let mut data = self.data[self.offset..self.end].to_vec();
+        for row in data.chunks_exact_mut(self.num_cols) {
+            let (inputs, outputs) = row.split_at_mut(self.independent_cols);
+            transform(inputs, outputs);
+        }
+        let mut mapped = Self::from_vec(data, self.independent_cols, self.dependent_cols, self.num_rows);
+        mapped.schema = self.schema.clone();
+        mapped.labels = self.labels.clone();
+        mapped
+    }
+
+    /// Randomly splits the rows into two data sets, e.g. to hold out a test set, with `fraction`
+    /// of the rows in the first. The rows are shuffled into a single new buffer which both data
+    /// sets share.
+    pub fn split(&self, fraction: Float, seed: &str) -> (PreparedDataSet, PreparedDataSet) {
+        assert!((0.0..=1.0).contains(&fraction), "split fraction must be between 0 and 1");
+        let mut permutation: Vec<usize> = (0..self.num_rows).collect();
+        permutation.shuffle(&mut XorShiftRng::from_seed(stable_hash_seed(seed)));
+        let shuffled = self.select_rows(&permutation);
+        let first_rows = (fraction * self.num_rows as Float).round() as usize;
+        (shuffled.make_partition(0, first_rows), shuffled.make_partition(first_rows, self.num_rows - first_rows))
+    }
+
+    /// Randomly partitions the rows into `k` folds, returning the training and validation data
+    /// sets of each fold for cross-validation. Every row is in exactly one validation set. All
+    /// of the data sets share a buffer holding the shuffled rows twice over, so the training rows
+    /// of each fold are contiguous.
+    pub fn k_folds(&self, k: usize, seed: &str) -> Vec<(PreparedDataSet, PreparedDataSet)> {
+        let mut permutation: Vec<usize> = (0..self.num_rows).collect();
+        permutation.shuffle(&mut XorShiftRng::from_seed(stable_hash_seed(seed)));
+        let doubled: Vec<usize> = permutation.iter().chain(permutation.iter()).cloned().collect();
+        let shuffled = self.select_rows(&doubled);
+        let mut folds = shuffled.make_partition(0, self.num_rows).partition(k);
+        folds.reverse();
+        folds.into_iter()
+            .map(|validation| {
+                let first_row = (validation.offset - shuffled.offset) / self.num_cols;
+                let train = shuffled.make_partition(first_row + validation.num_rows, self.num_rows - validation.num_rows);
+                (train, validation)
+            })
+            .collect()
+    }
+
+    /// Like `split`, but splits the rows of each value of the dependent column separately, so
+    /// both data sets keep the label distribution of the column, e.g. for imbalanced classes
+    pub fn split_stratified(&self, fraction: Float, seed: &str, dependent_col: usize) -> (PreparedDataSet, PreparedDataSet) {
+        assert!((0.0..=1.0).contains(&fraction), "split fraction must be between 0 and 1");
+        assert!(dependent_col < self.dependent_cols);
+        let mut rows_by_label: BTreeMap<_, Vec<usize>> = BTreeMap::new();
+        for (index, (_, outputs)) in self.iter().enumerate() {
+            rows_by_label.entry(outputs[dependent_col].to_bits()).or_default().push(index);
+        }
+        let mut rng = XorShiftRng::from_seed(stable_hash_seed(seed));
+        let (mut first, mut second) = (Vec::with_capacity(self.num_rows), Vec::new());
+        for rows in rows_by_label.values_mut() {
+            rows.shuffle(&mut rng);
+            let first_rows = (fraction * rows.len() as Float).round() as usize;
+            first.extend_from_slice(&rows[..first_rows]);
+            second.extend_from_slice(&rows[first_rows..]);
+        }
+        // mix the labels, which would otherwise follow one another
+        first.shuffle(&mut rng);
+        second.shuffle(&mut rng);
+        let first_rows = first.len();
+        first.append(&mut second);
+        let shuffled = self.select_rows(&first);
+        (shuffled.make_partition(0, first_rows), shuffled.make_partition(first_rows, self.num_rows - first_rows))
+    }
+
+    /// A copy of `n` randomly chosen rows, e.g. for a quick smoke test of a training setup or
+    /// a hyperparameter search on a subsample. The rows keep their original order.
+    pub fn take_n(&self, n: usize, seed: &str) -> PreparedDataSet {
+        assert!(n <= self.num_rows, "can't take {} of {} rows", n, self.num_rows);
+        let mut indices = index::sample(&mut XorShiftRng::from_seed(stable_hash_seed(seed)), self.num_rows, n).into_vec();
+        indices.sort_unstable();
+        self.select_rows(&indices)
+    }
+
+    /// Like `take_n`, with `fraction` of the rows
+    pub fn sample_fraction(&self, fraction: Float, seed: &str) -> PreparedDataSet {
+        assert!((0.0..=1.0).contains(&fraction), "sample fraction must be between 0 and 1");
+        self.take_n((fraction * self.num_rows as Float).round() as usize, seed)
+    }
+
+    /// A bootstrap sample of `fraction` times as many rows, drawn with replacement, e.g. to
+    /// train the members of a bagged ensemble
+    pub fn bootstrap_sample(&self, fraction: Float, seed: &str) -> PreparedDataSet {
+        assert!(fraction > 0.0, "sample fraction must be positive");
+        assert!(self.num_rows > 0, "can't sample an empty data set");
+        let mut rng = XorShiftRng::from_seed(stable_hash_seed(seed));
+        let num_rows = ((fraction * self.num_rows as Float).round() as usize).max(1);
+        let indices: Vec<usize> = (0..num_rows).map(|_| rng.gen_range(0, self.num_rows)).collect();
+        self.select_rows(&indices)
+    }
+
+}
+
+impl<'a> PreparedDataSet {
+
+    /// The independent and dependent values of the row at the index
+    pub fn row(&'a self, index: usize) -> (&'a [Float], &'a [Float]) {
+        assert!(index < self.num_rows);
+        let offset = self.offset + index * self.num_cols;
+        let dependent_offset = offset + self.independent_cols;
+        (&self.data[offset..dependent_offset], &self.data[dependent_offset..offset + self.num_cols])
+    }
+
+    /// Iterates the rows in the order of `permutation`, which should hold each row index once
+    pub fn iter_permuted(&'a self, permutation: &'a [usize]) -> PreparedDataSetPermutedIterator<'a> {
+        debug_assert_eq!(permutation.len(), self.num_rows);
+        PreparedDataSetPermutedIterator {
+            data_set: self,
+            permutation: permutation.iter(),
+        }
+    }
+
+    pub fn iter(&'a self) -> PreparedDataSetIterator<'a> {
+        PreparedDataSetIterator {
+            data: self.data.as_ref(),
+            offset: self.offset,
+            end: self.end,
+            num_cols: self.num_cols,
+            independent_cols: self.independent_cols
+        }
+    }
+
+    /// Iterates `num_rows` rows from `first_row`, wrapping around to the first row
+    pub fn iter_window(&'a self, first_row: usize, num_rows: usize) -> std::iter::Chain<PreparedDataSetIterator<'a>, PreparedDataSetIterator<'a>> {
+        assert!(num_rows <= self.num_rows && (first_row < self.num_rows || num_rows == 0), "window out of range");
+        let wrapped_rows = (first_row + num_rows).saturating_sub(self.num_rows);
+        let offset = self.offset + first_row * self.num_cols;
+        let window = PreparedDataSetIterator {
+            offset,
+            end: offset + (num_rows - wrapped_rows) * self.num_cols,
+            ..self.iter()
+        };
+        let wrapped = PreparedDataSetIterator {
+            end: self.offset + wrapped_rows * self.num_cols,
+            ..self.iter()
+        };
+        window.chain(wrapped)
+    }
+
+    /// The row at the index within the window of `iter_window` starting at `first_row`
+    pub fn window_row(&'a self, first_row: usize, index: usize) -> (&'a [Float], &'a [Float]) {
+        self.row((first_row + index) % self.num_rows)
+    }
+}
+
+pub struct PreparedDataSetIterator<'a> {
+    data: &'a [Float],
+    offset: usize,
+    end: usize,
+    num_cols: usize,
+    independent_cols: usize,
+}
+
+impl<'a> PreparedDataSetIterator<'a> {
+
+    #[inline]
+    pub fn has_next(&self) -> bool {
+        self.offset != self.end
+    }
+
+    pub fn next_unchecked(&mut self) -> (&'a [Float], &'a [Float]) {
+        let offset = self.offset;
+        let dependent_offset = offset + self.independent_cols;
+        let row_end = offset + self.num_cols;
+        self.offset = row_end;
+        (&self.data[offset..dependent_offset], &self.data[dependent_offset..row_end])
+    }
+
+}
+
+impl<'a> Iterator for PreparedDataSetIterator<'a> {
+    type Item = (&'a [Float], &'a [Float]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.has_next() {
+            Some(self.next_unchecked())
+        } else {
+            None
+        }
+    }
+
+}
+
+/// Iterates the rows of a data set in the order given by a permutation of the row indices
+pub struct PreparedDataSetPermutedIterator<'a> {
+    data_set: &'a PreparedDataSet,
+    permutation: std::slice::Iter<'a, usize>,
+}
+
+impl<'a> Iterator for PreparedDataSetPermutedIterator<'a> {
+    type Item = (&'a [Float], &'a [Float]);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.permutation.next().map(|&row| self.data_set.row(row))
+    }
+
+}
+
+impl<'a> IntoIterator for &'a PreparedDataSet {
+    type Item = (&'a [Float], &'a [Float]);
+    type IntoIter = PreparedDataSetIterator<'a>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::lines_2x2;
+    use std::error::Error;
+    use crate::data::{ColumnSchema, ColumnType};
+
+    #[test]
+    fn test_parse_csv() -> Result<(), Box<dyn Error>> {
+
+        /*
+        0_0, 0_1, 1_0, 1_1, has_horizontal, has_vertical
+        1, 1,    0, 0,   1, 0
+        1, 0,    1, 0,   0, 1
+        0, 0,    0, 0,   0, 0
+        1, 1,    0, 1,   1, 1
+        0, 1,    0, 1,   0, 1
+        0, 1,    0, 0,   0, 0
+        1, 1,    1, 1,   1, 1
+        1, 0,    0, 0,   0, 0
+        0, 1,    0, 1,   0, 1
+        1, 0,    1, 0,   0, 1
+        0, 1,    1, 0,   0, 0
+        */
+
+        let data = PreparedDataSet::from_csv(
+            "data/2x2_lines_binary.csv",
+            ["1_0", "1_1", "0_0", "0_1"], // purposely out of order
+            ["has_horizontal", "has_vertical"]
+        )?;
+        let expected: Vec<(&[Float], &[Float])> = vec![
+            (&[0., 0., 1., 1.], &[1., 0.]),
+            (&[1., 0., 1., 0.], &[0., 1.]),
+            (&[0., 0., 0., 0.], &[0., 0.]),
+            (&[0., 1., 1., 1.], &[1., 1.]),
+            (&[0., 1., 0., 1.], &[0., 1.]),
+            (&[0., 0., 0., 1.], &[0., 0.]),
+            (&[1., 1., 1., 1.], &[1., 1.]),
+            (&[0., 0., 1., 0.], &[0., 0.]),
+            (&[0., 1., 0., 1.], &[0., 1.]),
+            (&[1., 0., 1., 0.], &[0., 1.]),
+            (&[1., 0., 0., 1.], &[0., 0.]),
+        ];
+        assert_eq!(data.iter().collect::<Vec<(&[Float], &[Float])>>(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_with_schema() -> Result<(), Box<dyn Error>> {
+        let schema = DataSetSchema::new(
+            vec![
+                ColumnSchema::new("0_0", ColumnType::Binary).with_description("top left pixel"),
+                ColumnSchema::new("0_1", ColumnType::Binary),
+            ],
+            vec![ColumnSchema::new("has_horizontal", ColumnType::Binary)],
+        );
+        let data = PreparedDataSet::from_csv_with_schema("data/2x2_lines_binary.csv", schema.clone())?;
+        assert_eq!(data.schema(), Some(&schema));
+        assert_eq!(data.partition(2)[0].schema(), Some(&schema));
+
+        let schema = DataSetSchema::new(
+            vec![ColumnSchema::new("0_0", ColumnType::Continuous).with_range(0.0, 0.5)],
+            vec![ColumnSchema::new("has_horizontal", ColumnType::Binary)],
+        );
+        assert!(PreparedDataSet::from_csv_with_schema("data/2x2_lines_binary.csv", schema).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_rows() -> Result<(), Box<dyn Error>> {
+        let xor = PreparedDataSet::from_csv("data/xor.csv", ["x_0", "x_1"], ["xor"])?;
+        let inputs = vec![vec![0., 0.], vec![0., 1.], vec![1., 0.], vec![1., 1.]];
+        let targets: [&[Float]; 4] = [&[0.], &[1.], &[1.], &[0.]];
+        let data = PreparedDataSet::from_rows(&inputs, &targets);
+        assert_eq!(data.iter().collect::<Vec<_>>(), xor.iter().collect::<Vec<_>>());
+
+        let flat = PreparedDataSet::from_flat(vec![0., 0., 0., 0., 1., 1., 1., 0., 1., 1., 1., 0.], 2, 1);
+        assert_eq!(flat.iter().collect::<Vec<_>>(), xor.iter().collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_major() -> Result<(), Box<dyn Error>> {
+        let xor = PreparedDataSet::from_csv("data/xor.csv", ["x_0", "x_1"], ["xor"])?;
+        let inputs = xor.partition(2)[0].inputs_row_major();
+        assert_eq!(inputs, [1., 0., 1., 1.]);
+        assert_eq!(xor.targets_row_major(), [0., 1., 1., 0.]);
+        let data = PreparedDataSet::from_row_major(&xor.inputs_row_major(), &xor.targets_row_major(), 2, 1);
+        assert_eq!(data.iter().collect::<Vec<_>>(), xor.iter().collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_by_index() -> Result<(), Box<dyn Error>> {
+        let by_name = PreparedDataSet::from_csv("data/2x2_lines_binary.csv", ["1_0", "0_1"], ["has_vertical"])?;
+        let by_index = PreparedDataSet::from_csv_by_index("data/2x2_lines_binary.csv", &[2, 1], &[5], true)?;
+        assert_eq!(by_index.iter().collect::<Vec<_>>(), by_name.iter().collect::<Vec<_>>());
+
+        let headerless = PreparedDataSet::from_csv_by_index("data/xor_headerless.csv", &[0, 1], &[2], false)?;
+        let xor = PreparedDataSet::from_csv("data/xor.csv", ["x_0", "x_1"], ["xor"])?;
+        assert_eq!(headerless.iter().collect::<Vec<_>>(), xor.iter().collect::<Vec<_>>());
+
+        assert!(PreparedDataSet::from_csv_by_index("data/xor_headerless.csv", &[0, 3], &[2], false).is_err());
+        assert!(PreparedDataSet::from_csv_by_index("data/xor_headerless.csv", &[0, 1], &[1], false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_with_dialect() -> Result<(), Box<dyn Error>> {
+        let dialect = CsvDialect::new()
+            .with_delimiter(b';')
+            .with_decimal_separator(',')
+            .with_comment(Some(b'#'));
+        let data = PreparedDataSet::from_csv_with_dialect("data/sine_regression_eu.csv", &dialect, ["x"], ["y"])?;
+        assert_eq!(data.num_rows(), 3);
+        assert_eq!(data.row(1), (&[0.5][..], &[0.479425][..]));
+        assert_eq!(data.row(2).1, &[-1.5]);
+
+        let by_index = PreparedDataSet::from_csv_by_index_with_dialect("data/sine_regression_eu.csv", &dialect, &[0], &[1])?;
+        assert_eq!(by_index.iter().collect::<Vec<_>>(), data.iter().collect::<Vec<_>>());
+
+        assert!(PreparedDataSet::from_csv_with_dialect("data/sine_regression_eu.csv", &dialect.clone().with_headers(false), ["x"], ["y"]).is_err());
+        assert!(PreparedDataSet::from_csv_with_dialect("data/sine_regression_eu.csv", &CsvDialect::new(), ["x"], ["y"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_reader() -> Result<(), Box<dyn Error>> {
+        let csv = "x_0, x_1, xor\n0, 1, 1\n1, 1, 0\n";
+        let data = PreparedDataSet::from_csv_reader(csv.as_bytes(), &CsvDialect::default(), ["x_1", "x_0"], ["xor"])?;
+        assert_eq!(data.row(1), (&[1., 1.][..], &[0.][..]));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    fn test_parse_compressed_csv() -> Result<(), Box<dyn Error>> {
+        use std::io::Write;
+        let csv = "x_0, x_1, xor\n0, 1, 1\n1, 1, 0\n";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(csv.as_bytes())?;
+        let files = [
+            ("rust_neural_net_compressed.csv.gz", gzip.finish()?),
+            ("rust_neural_net_compressed.csv.zst", zstd::encode_all(csv.as_bytes(), 0)?),
+        ];
+        for (name, bytes) in &files {
+            let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+            fs::write(&path, bytes)?;
+            let data = PreparedDataSet::from_csv(&path, ["x_1", "x_0"], ["xor"]);
+            fs::remove_file(&path)?;
+            assert_eq!(data?.row(1), (&[1., 1.][..], &[0.][..]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_in_parallel() -> Result<(), Box<dyn Error>> {
+        let num_rows = 3 * CSV_RECORDS_PER_THREAD + 7;
+        let mut csv = String::from("a,b,c\n");
+        for row in 0..num_rows {
+            csv.push_str(&format!("{},{},{}\n", row, row % 3, -(row as i64)));
+        }
+        let read = |csv: &str, num_threads| {
+            let (reader, _) = PreparedDataSet::csv_reader(csv.as_bytes(), &CsvDialect::default())?;
+            PreparedDataSet::read_csv_columns(reader, &CsvDialect::default(), 3, &[2, 0], &[1], num_threads)
+        };
+        for num_threads in [1, 2, 4] {
+            let data = read(&csv, num_threads)?;
+            assert_eq!(data.num_rows(), num_rows);
+            assert!(data.iter().enumerate().all(|(row, (inputs, outputs))| {
+                inputs == [-(row as Float), row as Float] && outputs == [(row % 3) as Float]
+            }));
+        }
+
+        // the error of the earliest invalid row is returned
+        let invalid = csv.replacen("\n5000,", "\nx,", 1).replacen("\n9000,", "\n1,2,", 1);
+        let err = read(&invalid, 4).err().unwrap();
+        assert!(err.to_string().contains("invalid float"), "{}", err);
+        let err = read(&invalid.replacen("\nx,", "\n5000,", 1), 4).err().unwrap();
+        assert!(!err.to_string().contains("invalid float"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_with_labels() -> Result<(), Box<dyn Error>> {
+        let pixels = ["0_0", "0_1", "1_0", "1_1"];
+        let data = PreparedDataSet::from_csv_with_labels("data/2x2_lines_labeled.csv", pixels, "lines", LabelEncoding::OneHot)?;
+        let labels = data.labels().unwrap();
+        assert_eq!(labels.labels(), ["both", "horizontal", "none", "vertical"]);
+        assert_eq!(data.dependent_cols(), 4);
+        assert_eq!(data.row(0), (&[1., 1., 0., 0.][..], &[0., 1., 0., 0.][..]));
+        assert_eq!(labels.decode(data.row(1).1), "vertical");
+        assert_eq!(data.split(0.5, "labels").0.labels(), Some(labels));
+
+        let index = LabelVocabulary::new(labels.labels().to_vec(), LabelEncoding::Index);
+        let data = PreparedDataSet::from_csv_with_vocabulary("data/2x2_lines_labeled.csv", pixels, "lines", &index)?;
+        assert_eq!(data.row(2).1, &[2.]);
+
+        let partial = LabelVocabulary::new(vec!["none".to_string()], LabelEncoding::Index);
+        assert!(PreparedDataSet::from_csv_with_vocabulary("data/2x2_lines_labeled.csv", pixels, "lines", &partial).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_permuted() -> Result<(), Box<dyn Error>> {
+        let data = lines_2x2();
+        let rows: Vec<(&[Float], &[Float])> = data.iter().collect();
+        let permutation: Vec<usize> = (0..data.num_rows()).rev().collect();
+        let permuted: Vec<(&[Float], &[Float])> = data.iter_permuted(&permutation).collect();
+        assert_eq!(permuted, rows.iter().rev().cloned().collect::<Vec<_>>());
+        // rows are relative to the start of a partition
+        let partition = data.partition(2).remove(0);
+        assert_eq!(partition.row(0), rows[data.num_rows() - partition.num_rows()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_csv_for_net() -> Result<(), Box<dyn Error>> {
+        use crate::func::ActivationFn;
+        use crate::graph::{InputGroup, LayerInput, LayerSource};
+        use crate::layer::NetLayerConfig;
+
+        let sigmoid = ActivationFn::standard_logistic_sigmoid();
+        let config = NetConfig::new(4, vec![
+            NetLayerConfig::FullyConnected(3, sigmoid),
+            NetLayerConfig::FullyConnected(1, sigmoid),
+            NetLayerConfig::FullyConnected(1, sigmoid),
+        ])
+            .with_input_groups(vec![InputGroup::new("top", 2), InputGroup::new("bottom", 2)])
+            .with_input(2, LayerInput::Concat(vec![LayerSource::Layer(0)]))
+            .with_output_head("vertical", 2, None)
+            .with_output_head("horizontal", 1, None);
+
+        let mut columns = HashMap::new();
+        columns.insert("bottom", vec!["1_0", "1_1"]);
+        columns.insert("top", vec!["0_0", "0_1"]);
+        columns.insert("horizontal", vec!["has_horizontal"]);
+        columns.insert("vertical", vec!["has_vertical"]);
+        let data = PreparedDataSet::from_csv_for_net("data/2x2_lines_binary.csv", &config, &columns)?;
+        let expected = PreparedDataSet::from_csv(
+            "data/2x2_lines_binary.csv",
+            ["0_0", "0_1", "1_0", "1_1"],
+            ["has_vertical", "has_horizontal"]
+        )?;
+        assert_eq!(data.iter().collect::<Vec<_>>(), expected.iter().collect::<Vec<_>>());
+
+        columns.insert("top", vec!["0_0"]);
+        let error = PreparedDataSet::from_csv_for_net("data/2x2_lines_binary.csv", &config, &columns).err().unwrap();
+        assert!(matches!(error.downcast_ref(), Some(CsvParseError::ColumnGroupSizeMismatch(name, 2, 1)) if name == "top"));
+        columns.remove("vertical");
+        columns.insert("top", vec!["0_0", "0_1"]);
+        let error = PreparedDataSet::from_csv_for_net("data/2x2_lines_binary.csv", &config, &columns).err().unwrap();
+        assert!(matches!(error.downcast_ref(), Some(CsvParseError::ColumnGroupMissing(name)) if name == "vertical"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_split() -> Result<(), Box<dyn Error>> {
+        let data = lines_2x2();
+        let (train, test) = data.split(0.7, "split");
+        assert_eq!((train.num_rows(), test.num_rows()), (8, 3));
+        assert!(Arc::ptr_eq(&train.data, &test.data));
+
+        let mut rows: Vec<(&[Float], &[Float])> = train.iter().chain(test.iter()).collect();
+        let mut expected: Vec<(&[Float], &[Float])> = data.iter().collect();
+        let by_values = |a: &(&[Float], &[Float]), b: &(&[Float], &[Float])| a.partial_cmp(b).unwrap();
+        rows.sort_by(by_values);
+        expected.sort_by(by_values);
+        assert_eq!(rows, expected);
+
+        let (same_train, _) = data.split(0.7, "split");
+        assert_eq!(same_train.iter().collect::<Vec<_>>(), train.iter().collect::<Vec<_>>());
+        assert_eq!(data.split(1.0, "split").1.num_rows(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_n() -> Result<(), Box<dyn Error>> {
+        let data = lines_2x2();
+        let sample = data.take_n(5, "sample");
+        assert_eq!(sample.num_rows(), 5);
+        let mut rows = data.iter();
+        assert!(sample.iter().all(|row| rows.any(|other| other == row)), "rows are not a subset in order");
+        assert_eq!(data.take_n(5, "sample").iter().collect::<Vec<_>>(), sample.iter().collect::<Vec<_>>());
+        assert_eq!(data.sample_fraction(0.5, "sample").num_rows(), 6);
+        assert_eq!(data.take_n(data.num_rows(), "all").iter().collect::<Vec<_>>(), data.iter().collect::<Vec<_>>());
+
+        let bootstrap = data.bootstrap_sample(2.0, "bootstrap");
+        assert_eq!(bootstrap.num_rows(), 2 * data.num_rows());
+        assert!(bootstrap.iter().all(|row| data.iter().any(|other| other == row)));
+        assert!(bootstrap.iter().enumerate().any(|(i, row)| bootstrap.iter().skip(i + 1).any(|other| other == row)), "no row was drawn twice");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup() {
+        let data = PreparedDataSet::from_rows(
+            &[[1.0, 2.0], [1.0, 2.0], [-0.0, 1.0], [0.0, 1.0], [1.0, 2.0], [1.05, 2.0]],
+            &[[0.0], [0.0], [1.0], [1.0], [1.0], [0.0]],
+        );
+        let expected: Vec<(&[Float], &[Float])> = vec![
+            (&[1.0, 2.0], &[0.0]),
+            (&[-0.0, 1.0], &[1.0]),
+            (&[1.0, 2.0], &[1.0]),
+            (&[1.05, 2.0], &[0.0]),
+        ];
+        assert_eq!(data.dedup().iter().collect::<Vec<_>>(), expected);
+        let within = data.dedup_with_tolerance(0.1);
+        assert_eq!(within.iter().collect::<Vec<_>>(), &expected[..3]);
+        assert_eq!(data.dedup_with_tolerance(0.0).num_rows(), 4);
+    }
+
+    #[test]
+    fn test_column_stats() {
+        let data = PreparedDataSet::from_rows(&[[1.0, 5.0], [3.0, 5.0], [2.0, 5.0]], &[[-1.0], [1.0], [0.0]]);
+        let stats = data.column_stats();
+        assert_eq!(stats.len(), 3);
+        assert_eq!((stats[0].min(), stats[0].max(), stats[0].mean()), (1.0, 3.0, 2.0));
+        assert_eq!(stats[1].std_dev(), 0.0);
+        assert!((stats[2].std_dev() - (2.0f64 / 3.0).sqrt()).abs() < 1e-6);
+        assert_eq!(data.make_partition(1, 2).column_stats()[0].min(), 2.0);
+        assert!(data.make_partition(0, 0).column_stats().is_empty());
+    }
+
+    #[test]
+    fn test_split_stratified() -> Result<(), Box<dyn Error>> {
+        let data = lines_2x2();
+        let positives = |data: &PreparedDataSet| data.iter().filter(|(_, outputs)| outputs[1] == 1.0).count();
+        assert_eq!(positives(&data), 6);
+
+        let (train, test) = data.split_stratified(0.5, "stratified", 1);
+        assert_eq!(train.num_rows() + test.num_rows(), data.num_rows());
+        assert_eq!((positives(&train), positives(&test)), (3, 3));
+        assert!(Arc::ptr_eq(&train.data, &test.data));
+        Ok(())
+    }
+
+    #[test]
+    fn test_k_folds() -> Result<(), Box<dyn Error>> {
+        let data = lines_2x2();
+        let by_values = |a: &(&[Float], &[Float]), b: &(&[Float], &[Float])| a.partial_cmp(b).unwrap();
+        let mut expected: Vec<(&[Float], &[Float])> = data.iter().collect();
+        expected.sort_by(by_values);
+
+        let folds = data.k_folds(3, "folds");
+        assert_eq!(folds.len(), 3);
+        let mut validation_rows = Vec::new();
+        for (train, validation) in &folds {
+            assert_eq!(train.num_rows() + validation.num_rows(), data.num_rows());
+            let mut rows: Vec<(&[Float], &[Float])> = train.iter().chain(validation.iter()).collect();
+            rows.sort_by(by_values);
+            assert_eq!(rows, expected);
+            validation_rows.extend(validation.iter());
+        }
+        validation_rows.sort_by(by_values);
+        assert_eq!(validation_rows, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partition() -> Result<(), Box<dyn Error>> {
+        let data = PreparedDataSet::from_rows(
+            &[[0.0], [1.0], [2.0], [3.0], [4.0], [5.0], [6.0]],
+            &[[0.0], [0.0], [0.0], [0.0], [1.0], [1.0], [1.0]],
+        );
+        let inputs = |partitions: Vec<PreparedDataSet>| -> Vec<Vec<Float>> {
+            partitions.iter().map(|partition| partition.iter().map(|(inputs, _)| inputs[0]).collect()).collect()
+        };
+
+        assert_eq!(inputs(data.partition(3)), [vec![5.0, 6.0], vec![3.0, 4.0], vec![0.0, 1.0, 2.0]]);
+        assert_eq!(inputs(data.partition(1)), [vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]]);
+        assert_eq!(data.partition(7).len(), 7);
+        // there are no empty partitions
+        assert_eq!(data.partition(10).len(), 7);
+        assert_eq!(data.make_partition(0, 0).partition(4).len(), 1);
+
+        let round_robin = data.partition_with(3, PartitionStrategy::RoundRobin);
+        assert_eq!(inputs(round_robin), [vec![0.0, 3.0, 6.0], vec![1.0, 4.0], vec![2.0, 5.0]]);
+        let stratified = data.partition_with(2, PartitionStrategy::Stratified { dependent_col: 0 });
+        assert!(stratified.iter().all(|partition| partition.iter().any(|(_, outputs)| outputs[0] == 1.0)));
+        assert_eq!(inputs(stratified), [vec![0.0, 2.0, 4.0, 6.0], vec![1.0, 3.0, 5.0]]);
+        assert_eq!(data.partition_with(10, PartitionStrategy::RoundRobin).len(), 7);
+
+        let (ordered, windows) = data.partition_windows(3, PartitionStrategy::RoundRobin);
+        assert_eq!(windows, [(0, 3), (3, 2), (5, 2)]);
+        let shifted: Vec<Float> = ordered.iter_window(5, 3).map(|(inputs, _)| inputs[0]).collect();
+        assert_eq!(shifted, [2.0, 5.0, 0.0]);
+        assert_eq!(ordered.window_row(5, 2), ordered.row(0));
+        assert_eq!(data.iter_window(3, 0).count(), 0);
+        Ok(())
+    }
+
+}
+
+
@@ -0,0 +1,89 @@
+use std::num::ParseFloatError;
+
+use crate::Float;
+
+/// The format of a csv file. The default is comma-delimited with double quotes, no comments,
+/// a decimal point and a header row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    /// The quote character, or `None` to read quotes as part of the values
+    pub quote: Option<u8>,
+    /// Lines starting with this character are skipped
+    pub comment: Option<u8>,
+    /// The decimal separator of the values, e.g. `,` for data exported with a European locale
+    pub decimal_separator: char,
+    pub has_headers: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: b',',
+            quote: Some(b'"'),
+            comment: None,
+            decimal_separator: '.',
+            has_headers: true,
+        }
+    }
+}
+
+impl CsvDialect {
+
+    pub fn new() -> Self {
+        CsvDialect::default()
+    }
+
+    pub fn with_delimiter(self, delimiter: u8) -> Self {
+        let mut new = self;
+        new.delimiter = delimiter;
+        new
+    }
+
+    pub fn with_quote(self, quote: Option<u8>) -> Self {
+        let mut new = self;
+        new.quote = quote;
+        new
+    }
+
+    pub fn with_comment(self, comment: Option<u8>) -> Self {
+        let mut new = self;
+        new.comment = comment;
+        new
+    }
+
+    pub fn with_decimal_separator(self, decimal_separator: char) -> Self {
+        let mut new = self;
+        new.decimal_separator = decimal_separator;
+        new
+    }
+
+    pub fn with_headers(self, has_headers: bool) -> Self {
+        let mut new = self;
+        new.has_headers = has_headers;
+        new
+    }
+
+    pub(crate) fn reader_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .trim(csv::Trim::All)
+            .delimiter(self.delimiter)
+            .quoting(self.quote.is_some())
+            .quote(self.quote.unwrap_or(b'"'))
+            .comment(self.comment)
+            .has_headers(self.has_headers);
+        builder
+    }
+
+    /// Parses a value, using `buffer` as scratch space to replace the decimal separator
+    pub(crate) fn parse(&self, datum: &str, buffer: &mut String) -> Result<Float, ParseFloatError> {
+        if self.decimal_separator == '.' {
+            return datum.parse();
+        }
+        buffer.clear();
+        buffer.extend(datum.chars().map(|c| if c == self.decimal_separator { '.' } else { c }));
+        buffer.parse()
+    }
+
+}
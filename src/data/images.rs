@@ -0,0 +1,239 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::Float;
+use crate::data::{LabelEncoding, LabelVocabulary, PreparedDataSet};
+use crate::shape::Shape;
+
+quick_error! {
+    #[derive(Debug)]
+    enum ImageLoadError {
+        NoImages {
+            description("No images found in directory")
+        }
+        UnsupportedFormat(path: PathBuf) {
+            description("Image format is not supported")
+            display("Image {} can't be decoded, png and jpeg images need the image feature", path.display())
+        }
+        InvalidImage(path: PathBuf, reason: &'static str) {
+            description("Image is not valid")
+            display("Image {} is not valid: {}", path.display(), reason)
+        }
+        ShapeMismatch(path: PathBuf, shape: Shape, expected: Shape) {
+            description("Images have different shapes")
+            display("Image {} has shape {}, but previous images had shape {}", path.display(), shape, expected)
+        }
+    }
+}
+
+/// The decoded pixels of an image, row by row with the channels of each pixel together
+struct Image {
+    shape: Shape,
+    pixels: Vec<Float>,
+}
+
+/// Decodes a netpbm image, scaling the values to [0, 1]
+fn decode_netpbm(path: &Path, bytes: &[u8]) -> Result<Image, ImageLoadError> {
+    let invalid = |reason| ImageLoadError::InvalidImage(path.to_owned(), reason);
+    let (channels, binary) = match bytes.get(..2) {
+        Some(b"P2") => (1, false),
+        Some(b"P3") => (3, false),
+        Some(b"P5") => (1, true),
+        Some(b"P6") => (3, true),
+        _ => return Err(invalid("unknown netpbm magic number")),
+    };
+
+    // the header is the width, height and maximum value, separated by whitespace and comments
+    let mut position = 2;
+    let mut next_number = |bytes: &[u8]| -> Option<usize> {
+        loop {
+            match bytes.get(position)? {
+                b'#' => while *bytes.get(position)? != b'\n' { position += 1 },
+                byte if byte.is_ascii_whitespace() => position += 1,
+                _ => break,
+            }
+        }
+        let start = position;
+        while bytes.get(position).is_some_and(u8::is_ascii_digit) {
+            position += 1;
+        }
+        std::str::from_utf8(&bytes[start..position]).ok()?.parse().ok()
+    };
+    let width = next_number(bytes).ok_or_else(|| invalid("missing width"))?;
+    let height = next_number(bytes).ok_or_else(|| invalid("missing height"))?;
+    let max_value = next_number(bytes).ok_or_else(|| invalid("missing maximum value"))?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid("maximum value out of range"));
+    }
+
+    let num_values = width * height * channels;
+    let mut pixels = Vec::with_capacity(num_values);
+    if binary {
+        // a single whitespace byte separates the header from the values
+        let data = &bytes[(position + 1).min(bytes.len())..];
+        let value_bytes = if max_value < 256 { 1 } else { 2 };
+        if data.len() < num_values * value_bytes {
+            return Err(invalid("too few pixel values"));
+        }
+        for value in data.chunks_exact(value_bytes).take(num_values) {
+            let value = value.iter().fold(0usize, |acc, &byte| (acc << 8) | byte as usize);
+            pixels.push(value as Float / max_value as Float);
+        }
+    } else {
+        for _ in 0..num_values {
+            let value = next_number(bytes).ok_or_else(|| invalid("too few pixel values"))?;
+            pixels.push(value as Float / max_value as Float);
+        }
+    }
+
+    Ok(Image { shape: Shape::new([height, width, channels]), pixels })
+}
+
+/// Decodes a png or jpeg image, keeping its channels and scaling the values to [0, 1]
+#[cfg(feature = "image")]
+fn decode_image(path: &Path, bytes: &[u8]) -> Result<Image, Box<dyn Error>> {
+    let image = image::load_from_memory(bytes).map_err(|err| format!("Image {} is not valid: {}", path.display(), err))?;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let channels = image.color().channel_count() as usize;
+    let values = match channels {
+        1 => image.to_luma32f().into_raw(),
+        2 => image.to_luma_alpha32f().into_raw(),
+        3 => image.to_rgb32f().into_raw(),
+        _ => image.to_rgba32f().into_raw(),
+    };
+    Ok(Image {
+        shape: Shape::new([height, width, channels.min(4)]),
+        pixels: values.into_iter().map(|value| value as Float).collect(),
+    })
+}
+
+#[cfg(not(feature = "image"))]
+fn decode_image(path: &Path, _bytes: &[u8]) -> Result<Image, Box<dyn Error>> {
+    Err(Box::new(ImageLoadError::UnsupportedFormat(path.to_owned())))
+}
+
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+impl PreparedDataSet {
+
+    /// Loads a directory with a subdirectory of images for each label, e.g. `cats/1.png` and
+    /// `dogs/1.png`. The pixels of each image are scaled to [0, 1] and flattened row by row with
+    /// the channels of each pixel together, and the labels are encoded as in
+    /// `from_csv_with_labels`. All images must have the same shape, which is returned as the
+    /// input shape for the net.
+    ///
+    /// Png and jpeg images are decoded with the `image` feature, netpbm (pgm/ppm) images always.
+    /// Files with other extensions are skipped.
+    pub fn from_image_dir(path: impl AsRef<Path>, encoding: LabelEncoding) -> Result<(PreparedDataSet, Shape), Box<dyn Error>> {
+
+        let mut shape: Option<Shape> = None;
+        let mut images = Vec::new();
+        let mut labels = Vec::new();
+
+        for label_dir in sorted_entries(path.as_ref())? {
+            if !label_dir.is_dir() {
+                continue;
+            }
+            let label = label_dir.file_name().unwrap().to_string_lossy().into_owned();
+            for image_path in sorted_entries(&label_dir)? {
+                let extension = image_path.extension().map(|e| e.to_string_lossy().to_lowercase());
+                let image = match extension.as_deref() {
+                    Some("pgm") | Some("ppm") | Some("pnm") => decode_netpbm(&image_path, &fs::read(&image_path)?)?,
+                    Some("png") | Some("jpg") | Some("jpeg") => decode_image(&image_path, &fs::read(&image_path)?)?,
+                    _ => continue,
+                };
+                match &shape {
+                    Some(shape) if *shape != image.shape => {
+                        return Err(Box::new(ImageLoadError::ShapeMismatch(image_path, image.shape, shape.clone())));
+                    },
+                    Some(_) => {},
+                    None => shape = Some(image.shape.clone()),
+                }
+                images.push(image.pixels);
+                labels.push(label.clone());
+            }
+        }
+
+        let shape = shape.ok_or(ImageLoadError::NoImages)?;
+        let vocabulary = LabelVocabulary::fit(labels.iter().map(String::as_str), encoding);
+        let mut data = Vec::with_capacity(images.len() * (shape.size() + vocabulary.num_cols()));
+        for (pixels, label) in images.iter().zip(&labels) {
+            data.extend_from_slice(pixels);
+            vocabulary.encode_into(label, &mut data);
+        }
+        let data_set = Self::from_vec(data, shape.size(), vocabulary.num_cols(), images.len()).with_labels(vocabulary);
+        Ok((data_set, shape))
+
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_image_dir() -> Result<(), Box<dyn Error>> {
+
+        let (data_set, shape) = PreparedDataSet::from_image_dir("data/images", LabelEncoding::OneHot)?;
+        assert_eq!(shape, Shape::new([2, 2, 1]));
+        assert_eq!(data_set.labels().unwrap().labels(), ["dark", "light"]);
+        assert_eq!(data_set.num_rows(), 3);
+        let expected: Vec<(&[Float], &[Float])> = vec![
+            (&[0.0, 10.0 / 255.0, 20.0 / 255.0, 1.0], &[1.0, 0.0]),
+            (&[0.0, 0.0, 0.2, 0.0], &[1.0, 0.0]),
+            (&[1.0, 1.0, 1.0, 0.0], &[0.0, 1.0]),
+        ];
+        assert_eq!(data_set.iter().collect::<Vec<_>>(), expected);
+
+        assert!(decode_netpbm(Path::new("x.pgm"), b"P5\n2 2\n255\n\x00").is_err());
+        let color = decode_netpbm(Path::new("x.ppm"), b"P6 1 1 65535\n\xff\xff\x00\x00\x80\x00")?;
+        assert_eq!(color.shape, Shape::new([1, 1, 3]));
+        assert_eq!(color.pixels, [1.0, 0.0, 128.0 * 256.0 / 65535.0]);
+        Ok(())
+
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn test_decode_png_and_jpeg() -> Result<(), Box<dyn Error>> {
+
+        let dir = std::env::temp_dir().join(format!("rust_neural_net_images_{}", std::process::id()));
+        for label in &["dark", "light"] {
+            fs::create_dir_all(dir.join(label))?;
+        }
+        let dark = image::GrayImage::from_raw(2, 2, vec![0, 10, 20, 255]).unwrap();
+        dark.save(dir.join("dark/1.png"))?;
+        image::GrayImage::from_pixel(2, 2, image::Luma([250])).save(dir.join("light/1.jpg"))?;
+        let loaded = PreparedDataSet::from_image_dir(&dir, LabelEncoding::OneHot);
+        fs::remove_dir_all(&dir)?;
+
+        let (data_set, shape) = loaded?;
+        assert_eq!(shape, Shape::new([2, 2, 1]));
+        // the image crate decodes to f32
+        let dark: Vec<Float> = [0u8, 10, 20, 255].iter().map(|&value| (value as f32 / 255.0) as Float).collect();
+        assert_eq!(data_set.row(0), (&dark[..], &[1.0, 0.0][..]));
+        // jpeg is lossy
+        let (light, label) = data_set.row(1);
+        assert!(light.iter().all(|&value| (value - 250.0 / 255.0).abs() < 0.02), "{:?}", light);
+        assert_eq!(label, &[0.0, 1.0]);
+
+        let rgb = image::RgbImage::from_pixel(3, 1, image::Rgb([255, 0, 51]));
+        let mut png = Vec::new();
+        rgb.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+        let decoded = decode_image(Path::new("x.png"), &png)?;
+        assert_eq!(decoded.shape, Shape::new([1, 3, 3]));
+        assert_eq!(&decoded.pixels[..3], &[1.0, 0.0, 0.2f32 as Float]);
+        assert!(decode_image(Path::new("x.png"), b"not a png").is_err());
+        Ok(())
+
+    }
+
+}
@@ -0,0 +1,115 @@
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::Float;
+use crate::data::PreparedDataSet;
+use crate::utils::{into_string_vec, first_duplicate};
+use itertools::chain;
+
+quick_error! {
+    #[derive(Debug)]
+    enum JsonlParseError {
+        ZeroFieldsSelected {
+            description("Zero fields selected")
+        }
+        DuplicateFieldsSpecified(name: String) {
+            description("Duplicate fields specified")
+            display("Duplicate fields specified: {}", name)
+        }
+        NotAnObject(line: usize) {
+            description("Record is not a JSON object")
+            display("Record on line {} is not a JSON object", line)
+        }
+        FieldNotFound(line: usize, name: String) {
+            description("Field not found in record")
+            display("Field {} not found in the record on line {}", name, line)
+        }
+        NotANumber(line: usize, name: String) {
+            description("Field is not a number")
+            display("Field {} of the record on line {} is not a number or boolean", name, line)
+        }
+    }
+}
+
+fn field_value(value: &Value) -> Option<Float> {
+    match value {
+        Value::Number(number) => number.as_f64().map(|n| n as Float),
+        Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
+
+impl PreparedDataSet {
+
+    /// Loads newline-delimited JSON records, taking the values of the fields of each record.
+    /// Fields must hold numbers or booleans, which are loaded as 0 or 1. Other fields of the
+    /// records are ignored, as are blank lines.
+    pub fn from_jsonl<T1, I1, T2, I2>(
+        path: impl AsRef<Path>,
+        independent_fields: T1,
+        dependent_fields: T2
+    ) -> Result<PreparedDataSet, Box<dyn Error>>
+        where T1: AsRef<[I1]>, I1: ToString,
+              T2: AsRef<[I2]>, I2: ToString
+    {
+
+        let independent_fields = into_string_vec(independent_fields);
+        let dependent_fields = into_string_vec(dependent_fields);
+
+        if independent_fields.is_empty() || dependent_fields.is_empty() {
+            return Err(Box::new(JsonlParseError::ZeroFieldsSelected));
+        }
+
+        if let Some(dupe) = first_duplicate(chain(independent_fields.iter(), dependent_fields.iter())) {
+            return Err(Box::new(JsonlParseError::DuplicateFieldsSpecified(dupe.clone())));
+        }
+
+        let reader = BufReader::new(fs::File::open(path)?);
+        let mut data = Vec::new();
+        let mut num_rows = 0usize;
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line_number = index + 1;
+            let record: Value = serde_json::from_str(&line)?;
+            let record = record.as_object().ok_or(JsonlParseError::NotAnObject(line_number))?;
+            for name in chain(&independent_fields, &dependent_fields) {
+                let value = record.get(name).ok_or_else(|| JsonlParseError::FieldNotFound(line_number, name.clone()))?;
+                data.push(field_value(value).ok_or_else(|| JsonlParseError::NotANumber(line_number, name.clone()))?);
+            }
+            num_rows += 1;
+        }
+
+        Ok(Self::from_vec(data, independent_fields.len(), dependent_fields.len(), num_rows))
+
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_jsonl() -> Result<(), Box<dyn Error>> {
+
+        let data = PreparedDataSet::from_jsonl("data/xor.jsonl", ["x_1", "x_0"], ["xor"])?;
+        let csv = PreparedDataSet::from_csv("data/xor.csv", ["x_1", "x_0"], ["xor"])?;
+        assert_eq!(data.iter().collect::<Vec<_>>(), csv.iter().collect::<Vec<_>>());
+
+        let err = PreparedDataSet::from_jsonl("data/xor.jsonl", ["x_0"], ["source"]).err().unwrap();
+        assert_eq!(err.to_string(), "Field source of the record on line 1 is not a number or boolean");
+        let err = PreparedDataSet::from_jsonl("data/xor.jsonl", ["x_0"], ["y"]).err().unwrap();
+        assert_eq!(err.to_string(), "Field y not found in the record on line 1");
+        Ok(())
+
+    }
+
+}
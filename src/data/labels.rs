@@ -0,0 +1,126 @@
+use serde::{Serialize, Deserialize};
+
+use crate::Float;
+
+/// How the class of each row is encoded into dependent values
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LabelEncoding {
+    /// One dependent column per class, which is 1 for the class of the row and 0 otherwise
+    OneHot,
+    /// A single dependent column holding the index of the class
+    Index,
+}
+
+/// The class names of a label column, in the order of their indices, for encoding labels into
+/// targets and decoding predictions back into names
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelVocabulary {
+    labels: Vec<String>,
+    encoding: LabelEncoding,
+}
+
+impl LabelVocabulary {
+
+    pub fn new(labels: Vec<String>, encoding: LabelEncoding) -> Self {
+        assert!(!labels.is_empty(), "a vocabulary needs at least one label");
+        LabelVocabulary { labels, encoding }
+    }
+
+    /// A vocabulary of the distinct labels, sorted so that it doesn't depend on the order of the rows
+    pub fn fit<'a>(labels: impl IntoIterator<Item = &'a str>, encoding: LabelEncoding) -> Self {
+        let mut labels: Vec<String> = labels.into_iter().map(str::to_owned).collect();
+        labels.sort();
+        labels.dedup();
+        LabelVocabulary::new(labels, encoding)
+    }
+
+    #[inline]
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    #[inline]
+    pub fn encoding(&self) -> LabelEncoding {
+        self.encoding
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// The number of dependent columns of the encoded labels
+    pub fn num_cols(&self) -> usize {
+        match self.encoding {
+            LabelEncoding::OneHot => self.labels.len(),
+            LabelEncoding::Index => 1,
+        }
+    }
+
+    pub fn index_of(&self, label: &str) -> Option<usize> {
+        self.labels.iter().position(|l| l == label)
+    }
+
+    /// Appends the encoding of the label, returning false if it isn't part of the vocabulary
+    pub fn encode_into(&self, label: &str, target: &mut Vec<Float>) -> bool {
+        let index = match self.index_of(label) {
+            Some(index) => index,
+            None => return false,
+        };
+        match self.encoding {
+            LabelEncoding::OneHot => target.extend((0..self.labels.len()).map(|i| if i == index { 1.0 } else { 0.0 })),
+            LabelEncoding::Index => target.push(index as Float),
+        }
+        true
+    }
+
+    /// The class of the outputs of a net: the largest output if one-hot encoded, or the
+    /// nearest index otherwise
+    pub fn decode_index(&self, outputs: &[Float]) -> usize {
+        assert_eq!(outputs.len(), self.num_cols());
+        match self.encoding {
+            LabelEncoding::OneHot => {
+                let mut class = 0;
+                for (index, &output) in outputs.iter().enumerate() {
+                    if output > outputs[class] {
+                        class = index;
+                    }
+                }
+                class
+            },
+            LabelEncoding::Index => (outputs[0].round().max(0.0) as usize).min(self.labels.len() - 1),
+        }
+    }
+
+    pub fn decode(&self, outputs: &[Float]) -> &str {
+        &self.labels[self.decode_index(outputs)]
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_label_vocabulary() {
+
+        let one_hot = LabelVocabulary::fit(vec!["dog", "cat", "dog", "bird"], LabelEncoding::OneHot);
+        assert_eq!(one_hot.labels(), ["bird", "cat", "dog"]);
+        let mut target = Vec::new();
+        assert!(one_hot.encode_into("dog", &mut target));
+        assert!(!one_hot.encode_into("fish", &mut target));
+        assert_eq!(target, [0.0, 0.0, 1.0]);
+        assert_eq!(one_hot.decode(&[0.2, 0.7, 0.1]), "cat");
+
+        let index = LabelVocabulary::new(one_hot.labels().to_vec(), LabelEncoding::Index);
+        assert_eq!(index.num_cols(), 1);
+        target.clear();
+        assert!(index.encode_into("dog", &mut target));
+        assert_eq!(target, [2.0]);
+        assert_eq!(index.decode(&[0.8]), "cat");
+        assert_eq!(index.decode(&[-3.0]), "bird");
+        assert_eq!(index.decode(&[7.0]), "dog");
+
+    }
+
+}
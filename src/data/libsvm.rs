@@ -0,0 +1,136 @@
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::Float;
+use crate::data::{PreparedDataSet, SparseDataSet};
+
+quick_error! {
+    #[derive(Debug)]
+    enum LibsvmParseError {
+        MissingLabel(line: usize) {
+            description("Record has no label")
+            display("Record on line {} has no label", line)
+        }
+        InvalidFeature(line: usize, feature: String) {
+            description("Feature is not of the form index:value")
+            display("Feature {} on line {} is not of the form index:value", feature, line)
+        }
+        DuplicateFeature(line: usize, index: usize) {
+            description("Feature index appears twice in a record")
+            display("Feature index {} appears twice on line {}", index, line)
+        }
+        FeatureOutOfRange(line: usize, index: usize, num_features: usize) {
+            description("Feature index out of range")
+            display("Feature index {} on line {} is out of range for {} features", index, line, num_features)
+        }
+    }
+}
+
+/// The labels, 0-based features and number of features of the records of a libsvm file
+type LibsvmRecords = (Vec<Float>, Vec<Vec<(usize, Float)>>, usize);
+
+fn read_libsvm(path: &Path, num_features: Option<usize>) -> Result<LibsvmRecords, Box<dyn Error>> {
+
+    let reader = BufReader::new(fs::File::open(path)?);
+    let mut labels: Vec<Float> = Vec::new();
+    let mut features: Vec<Vec<(usize, Float)>> = Vec::new();
+    let mut max_index = 0;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = index + 1;
+        let record = line.split('#').next().unwrap().trim();
+        if record.is_empty() {
+            continue;
+        }
+        let mut tokens = record.split_whitespace();
+        let label = tokens.next().ok_or(LibsvmParseError::MissingLabel(line_number))?;
+        labels.push(label.parse()?);
+        let mut row = Vec::new();
+        for token in tokens.filter(|token| !token.starts_with("qid:")) {
+            let invalid = || LibsvmParseError::InvalidFeature(line_number, token.to_string());
+            let mut parts = token.splitn(2, ':');
+            let feature_index: usize = parts.next().unwrap().parse().map_err(|_| invalid())?;
+            let value: Float = parts.next().ok_or_else(invalid)?.parse()?;
+            if feature_index == 0 {
+                return Err(Box::new(invalid()));
+            }
+            if let Some(num_features) = num_features {
+                if feature_index > num_features {
+                    return Err(Box::new(LibsvmParseError::FeatureOutOfRange(line_number, feature_index, num_features)));
+                }
+            }
+            max_index = max_index.max(feature_index);
+            row.push((feature_index - 1, value));
+        }
+        row.sort_unstable_by_key(|&(index, _)| index);
+        if let Some(w) = row.windows(2).find(|w| w[0].0 == w[1].0) {
+            return Err(Box::new(LibsvmParseError::DuplicateFeature(line_number, w[0].0 + 1)));
+        }
+        features.push(row);
+    }
+
+    Ok((labels, features, num_features.unwrap_or(max_index).max(1)))
+
+}
+
+impl PreparedDataSet {
+
+    /// Loads a data set in the sparse libsvm (svmlight) format, with one `label index:value ...`
+    /// record per line and 1-based feature indices. The label becomes the single dependent
+    /// column, and features left out of a record are 0. Without `num_features`, the number of
+    /// features is the largest index of any record. `qid` tokens and comments are ignored.
+    pub fn from_libsvm(path: impl AsRef<Path>, num_features: Option<usize>) -> Result<PreparedDataSet, Box<dyn Error>> {
+
+        let (labels, features, num_features) = read_libsvm(path.as_ref(), num_features)?;
+        let mut data = vec![0.0; labels.len() * (num_features + 1)];
+        for ((row, label), row_features) in data.chunks_exact_mut(num_features + 1).zip(&labels).zip(&features) {
+            for &(index, value) in row_features {
+                row[index] = value;
+            }
+            row[num_features] = *label;
+        }
+
+        Ok(Self::from_vec(data, num_features, 1, labels.len()))
+
+    }
+
+}
+
+impl SparseDataSet {
+
+    /// Loads a libsvm file as in `PreparedDataSet::from_libsvm`, without expanding the features
+    pub fn from_libsvm(path: impl AsRef<Path>, num_features: Option<usize>) -> Result<SparseDataSet, Box<dyn Error>> {
+        let (labels, features, num_features) = read_libsvm(path.as_ref(), num_features)?;
+        let mut data_set = SparseDataSet::new(num_features, 1);
+        for (row, &label) in features.iter().zip(&labels) {
+            data_set.push(row, &[label]);
+        }
+        Ok(data_set)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_libsvm() -> Result<(), Box<dyn Error>> {
+
+        let data = PreparedDataSet::from_libsvm("data/xor.libsvm", None)?;
+        let csv = PreparedDataSet::from_csv("data/xor.csv", ["x_0", "x_1"], ["xor"])?;
+        assert_eq!(data.iter().collect::<Vec<_>>(), csv.iter().collect::<Vec<_>>());
+
+        let padded = PreparedDataSet::from_libsvm("data/xor.libsvm", Some(3))?;
+        assert_eq!(padded.row(3), (&[1., 1., 0.][..], &[0.][..]));
+        assert!(PreparedDataSet::from_libsvm("data/xor.libsvm", Some(1)).is_err());
+        let sparse = SparseDataSet::from_libsvm("data/xor.libsvm", Some(3))?;
+        assert_eq!(sparse.to_dense().iter().collect::<Vec<_>>(), padded.iter().collect::<Vec<_>>());
+        Ok(())
+
+    }
+
+}
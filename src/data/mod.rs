@@ -0,0 +1,32 @@
+mod dataset;
+mod schema;
+mod normalize;
+mod labels;
+mod dialect;
+mod jsonl;
+mod libsvm;
+mod stream;
+mod synthetic;
+mod images;
+mod timeseries;
+mod sequence;
+mod pipeline;
+mod pca;
+mod sparse;
+#[cfg(feature = "ndarray")]
+mod arrays;
+
+pub use self::{
+    dataset::*,
+    schema::*,
+    normalize::*,
+    labels::*,
+    dialect::*,
+    stream::*,
+    sequence::*,
+    pipeline::*,
+    pca::*,
+    sparse::*,
+};
+#[cfg(feature = "ndarray")]
+pub use self::arrays::*;
@@ -0,0 +1,204 @@
+use serde::{Serialize, Deserialize};
+
+use crate::Float;
+use crate::data::PreparedDataSet;
+use crate::stats::Stats;
+
+/// The scaling of each of a set of columns, fitted on a training set. The same normalizer must
+/// be applied to every data set and prediction input, so attach it to the trained net with
+/// `Net::set_input_normalizer`, which applies it within `Net::predict`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Normalizer {
+    /// Each value is shifted by the mean of its column and divided by its standard deviation.
+    /// Constant columns have a standard deviation of 1, so they are only shifted.
+    ZScore { means: Vec<Float>, std_devs: Vec<Float> },
+    /// Each value is scaled linearly from the range of its column to `range`. The values of
+    /// constant columns become the start of `range`.
+    MinMax { mins: Vec<Float>, maxs: Vec<Float>, range: (Float, Float) },
+}
+
+impl Normalizer {
+
+    /// Fits a z-score normalizer to the rows, which must all have the same number of columns
+    pub fn fit_z_score<'a>(rows: impl IntoIterator<Item = &'a [Float]>) -> Normalizer {
+        Self::z_score_from_stats(&Stats::of_columns(rows))
+    }
+
+    /// A z-score normalizer of columns with the stats, e.g. from `PreparedDataSet::column_stats`
+    pub fn z_score_from_stats(columns: &[Stats]) -> Normalizer {
+        assert!(columns.first().is_some_and(|stats| stats.count() > 0), "can't fit a normalizer without any rows");
+        Normalizer::ZScore {
+            means: columns.iter().map(|stats| stats.mean() as Float).collect(),
+            std_devs: columns.iter()
+                .map(|stats| {
+                    let std_dev = stats.std_dev() as Float;
+                    if std_dev > 0.0 { std_dev } else { 1.0 }
+                })
+                .collect(),
+        }
+    }
+
+    /// Fits a min-max normalizer scaling the rows to `range`
+    pub fn fit_min_max<'a>(rows: impl IntoIterator<Item = &'a [Float]>, range: (Float, Float)) -> Normalizer {
+        Self::min_max_from_stats(&Stats::of_columns(rows), range)
+    }
+
+    pub fn min_max_from_stats(columns: &[Stats], range: (Float, Float)) -> Normalizer {
+        assert!(range.0 < range.1, "invalid range {:?}", range);
+        assert!(columns.first().is_some_and(|stats| stats.count() > 0), "can't fit a normalizer without any rows");
+        Normalizer::MinMax {
+            mins: columns.iter().map(Stats::min).collect(),
+            maxs: columns.iter().map(Stats::max).collect(),
+            range,
+        }
+    }
+
+    pub fn num_cols(&self) -> usize {
+        match self {
+            Normalizer::ZScore { means, .. } => means.len(),
+            Normalizer::MinMax { mins, .. } => mins.len(),
+        }
+    }
+
+    /// The scale of the values of a min-max normalized column
+    fn min_max_scale(min: Float, max: Float, range: (Float, Float)) -> Float {
+        if max > min { (range.1 - range.0) / (max - min) } else { 1.0 }
+    }
+
+    pub fn normalize(&self, values: &mut [Float]) {
+        assert_eq!(values.len(), self.num_cols());
+        match self {
+            Normalizer::ZScore { means, std_devs } => {
+                for ((value, mean), std_dev) in values.iter_mut().zip(means).zip(std_devs) {
+                    *value = (*value - mean) / std_dev;
+                }
+            },
+            Normalizer::MinMax { mins, maxs, range } => {
+                for ((value, &min), &max) in values.iter_mut().zip(mins).zip(maxs) {
+                    *value = range.0 + (*value - min) * Self::min_max_scale(min, max, *range);
+                }
+            },
+        }
+    }
+
+    /// The inverse of `normalize`
+    pub fn denormalize(&self, values: &mut [Float]) {
+        assert_eq!(values.len(), self.num_cols());
+        match self {
+            Normalizer::ZScore { means, std_devs } => {
+                for ((value, mean), std_dev) in values.iter_mut().zip(means).zip(std_devs) {
+                    *value = *value * std_dev + mean;
+                }
+            },
+            Normalizer::MinMax { mins, maxs, range } => {
+                for ((value, &min), &max) in values.iter_mut().zip(mins).zip(maxs) {
+                    *value = min + (*value - range.0) / Self::min_max_scale(min, max, *range);
+                }
+            },
+        }
+    }
+
+}
+
+impl PreparedDataSet {
+
+    /// Fits a z-score normalizer to the independent columns and normalizes them, returning the
+    /// normalizer to apply to other data sets and to the inputs of the trained net
+    pub fn normalize_inputs_z_score(&self) -> (PreparedDataSet, Normalizer) {
+        let normalizer = Normalizer::fit_z_score(self.iter().map(|(inputs, _)| inputs));
+        (self.normalize_inputs(&normalizer), normalizer)
+    }
+
+    /// Fits a min-max normalizer scaling the independent columns to `range` and normalizes them,
+    /// e.g. to the output range of the activation function
+    pub fn normalize_inputs_min_max(&self, range: (Float, Float)) -> (PreparedDataSet, Normalizer) {
+        let normalizer = Normalizer::fit_min_max(self.iter().map(|(inputs, _)| inputs), range);
+        (self.normalize_inputs(&normalizer), normalizer)
+    }
+
+    /// A copy of the data set with the independent columns normalized by a fitted normalizer
+    pub fn normalize_inputs(&self, normalizer: &Normalizer) -> PreparedDataSet {
+        assert_eq!(normalizer.num_cols(), self.independent_cols(), "normalizer does not match the independent columns");
+        self.map_rows(|inputs, _| normalizer.normalize(inputs))
+    }
+
+    /// Fits a z-score normalizer to the dependent columns and normalizes them, e.g. for
+    /// regression targets with large or mixed scales. Pass the normalizer to
+    /// `Net::set_output_normalizer` so the trained net predicts in the original units.
+    pub fn normalize_outputs_z_score(&self) -> (PreparedDataSet, Normalizer) {
+        let normalizer = Normalizer::fit_z_score(self.iter().map(|(_, outputs)| outputs));
+        (self.normalize_outputs(&normalizer), normalizer)
+    }
+
+    /// Fits a min-max normalizer scaling the dependent columns to `range`, which should lie
+    /// within the output range of the last activation function
+    pub fn normalize_outputs_min_max(&self, range: (Float, Float)) -> (PreparedDataSet, Normalizer) {
+        let normalizer = Normalizer::fit_min_max(self.iter().map(|(_, outputs)| outputs), range);
+        (self.normalize_outputs(&normalizer), normalizer)
+    }
+
+    pub fn normalize_outputs(&self, normalizer: &Normalizer) -> PreparedDataSet {
+        assert_eq!(normalizer.num_cols(), self.dependent_cols(), "normalizer does not match the dependent columns");
+        self.map_rows(|_, outputs| normalizer.normalize(outputs))
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::lines_2x2;
+
+    #[test]
+    fn test_z_score() {
+
+        let rows: Vec<&[Float]> = vec![&[1.0, 5.0], &[3.0, 5.0]];
+        let normalizer = Normalizer::fit_z_score(rows);
+        assert_eq!(normalizer, Normalizer::ZScore { means: vec![2.0, 5.0], std_devs: vec![1.0, 1.0] });
+
+        let data_set = lines_2x2();
+        let (normalized, normalizer) = data_set.normalize_inputs_z_score();
+        let refitted = Normalizer::fit_z_score(normalized.iter().map(|(inputs, _)| inputs));
+        match refitted {
+            Normalizer::ZScore { means, std_devs } => {
+                assert!(means.iter().all(|mean| mean.abs() < 1e-5), "{:?}", means);
+                assert!(std_devs.iter().all(|std_dev| (std_dev - 1.0).abs() < 1e-5), "{:?}", std_devs);
+            },
+            normalizer => panic!("{:?}", normalizer),
+        }
+
+        let (inputs, outputs) = normalized.row(0);
+        assert_eq!(outputs, data_set.row(0).1);
+        let mut denormalized = inputs.to_vec();
+        normalizer.denormalize(&mut denormalized);
+        for (a, b) in denormalized.iter().zip(data_set.row(0).0) {
+            assert!((a - b).abs() < 1e-5);
+        }
+
+        let serialized = serde_json::to_string(&normalizer).unwrap();
+        assert_eq!(serde_json::from_str::<Normalizer>(&serialized).unwrap(), normalizer);
+
+    }
+
+    #[test]
+    fn test_min_max() {
+
+        let train: Vec<&[Float]> = vec![&[1.0, 5.0], &[3.0, 5.0], &[2.0, 5.0]];
+        let normalizer = Normalizer::fit_min_max(train, (-1.0, 1.0));
+        assert_eq!(normalizer, Normalizer::MinMax { mins: vec![1.0, 5.0], maxs: vec![3.0, 5.0], range: (-1.0, 1.0) });
+
+        // new data can fall outside of the fitted ranges
+        let mut values = [4.0, 5.0];
+        normalizer.normalize(&mut values);
+        assert_eq!(values, [2.0, -1.0]);
+        normalizer.denormalize(&mut values);
+        assert_eq!(values, [4.0, 5.0]);
+
+        let data_set = lines_2x2();
+        let (normalized, normalizer) = data_set.normalize_inputs_min_max((-1.0, 1.0));
+        assert!(normalized.iter().all(|(inputs, _)| inputs.iter().all(|&value| value == -1.0 || value == 1.0)));
+        assert_eq!(data_set.normalize_inputs(&normalizer).iter().collect::<Vec<_>>(), normalized.iter().collect::<Vec<_>>());
+
+    }
+
+}
@@ -0,0 +1,214 @@
+use serde::{Serialize, Deserialize};
+
+use crate::Float;
+
+/// How many principal components to keep
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PcaComponents {
+    Count(usize),
+    /// The fewest components which explain at least this fraction of the variance
+    Variance(Float),
+}
+
+/// A projection of columns onto their principal components, fitted on a training set
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pca {
+    means: Vec<Float>,
+    /// The kept components by decreasing variance, each of which is a row of `means.len()` values
+    components: Vec<Float>,
+    /// The variance along each kept component
+    variances: Vec<Float>,
+    /// The variance of all components, kept and dropped
+    total_variance: Float,
+    /// Whether the projections are scaled to unit variance
+    whiten: bool,
+}
+
+impl Pca {
+
+    /// Fits the components of the rows, which must all have the same number of columns
+    pub fn fit<'a>(rows: impl IntoIterator<Item = &'a [Float]>, components: PcaComponents, whiten: bool) -> Pca {
+
+        let rows: Vec<&[Float]> = rows.into_iter().collect();
+        assert!(!rows.is_empty(), "can't fit components without any rows");
+        let cols = rows[0].len();
+        assert!(rows.iter().all(|row| row.len() == cols), "rows have different numbers of columns");
+
+        let mut means = vec![0.0f64; cols];
+        for row in &rows {
+            for (mean, &value) in means.iter_mut().zip(row.iter()) {
+                *mean += value as f64 / rows.len() as f64;
+            }
+        }
+        let mut covariance = vec![0.0f64; cols * cols];
+        for row in &rows {
+            for i in 0..cols {
+                let a = row[i] as f64 - means[i];
+                for j in i..cols {
+                    covariance[i * cols + j] += a * (row[j] as f64 - means[j]) / rows.len() as f64;
+                }
+            }
+        }
+        for i in 0..cols {
+            for j in 0..i {
+                covariance[i * cols + j] = covariance[j * cols + i];
+            }
+        }
+
+        let (eigenvalues, eigenvectors) = symmetric_eigen(covariance, cols);
+        let mut order: Vec<usize> = (0..cols).collect();
+        order.sort_by(|&a, &b| eigenvalues[b].partial_cmp(&eigenvalues[a]).unwrap());
+        let total_variance: f64 = eigenvalues.iter().map(|value| value.max(0.0)).sum();
+        let num_components = match components {
+            PcaComponents::Count(count) => {
+                assert!(count > 0 && count <= cols, "can't keep {} of {} components", count, cols);
+                count
+            },
+            PcaComponents::Variance(fraction) => {
+                assert!(fraction > 0.0 && fraction <= 1.0, "variance fraction must be in (0, 1]");
+                let mut explained = 0.0;
+                order.iter()
+                    .position(|&index| {
+                        explained += eigenvalues[index].max(0.0);
+                        explained >= fraction as f64 * total_variance * (1.0 - 1e-9)
+                    })
+                    .map_or(cols, |position| position + 1)
+            },
+        };
+
+        let mut kept = Vec::with_capacity(num_components * cols);
+        for &index in &order[..num_components] {
+            // eigenvectors are columns, and their sign is arbitrary, so make the largest entry positive
+            let column: Vec<f64> = (0..cols).map(|row| eigenvectors[row * cols + index]).collect();
+            let largest = column.iter().cloned().fold(0.0, |a: f64, b| if b.abs() > a.abs() { b } else { a });
+            let sign = if largest < 0.0 { -1.0 } else { 1.0 };
+            kept.extend(column.iter().map(|value| (sign * value) as Float));
+        }
+        Pca {
+            means: means.into_iter().map(|mean| mean as Float).collect(),
+            components: kept,
+            variances: order[..num_components].iter().map(|&index| eigenvalues[index].max(0.0) as Float).collect(),
+            total_variance: total_variance as Float,
+            whiten,
+        }
+
+    }
+
+    #[inline]
+    pub fn input_cols(&self) -> usize {
+        self.means.len()
+    }
+
+    #[inline]
+    pub fn num_components(&self) -> usize {
+        self.variances.len()
+    }
+
+    /// The kept components, each of `input_cols` values
+    pub fn components(&self) -> impl Iterator<Item = &[Float]> {
+        self.components.chunks_exact(self.input_cols())
+    }
+
+    /// The fraction of the variance of the training set along each kept component
+    pub fn explained_variance_ratio(&self) -> Vec<Float> {
+        self.variances.iter().map(|variance| variance / self.total_variance).collect()
+    }
+
+    /// Appends the projection of the input onto the kept components to the output
+    pub fn project(&self, input: &[Float], output: &mut Vec<Float>) {
+        assert_eq!(input.len(), self.input_cols());
+        for (component, &variance) in self.components().zip(&self.variances) {
+            let projection: Float = component.iter().zip(input).zip(&self.means)
+                .map(|((weight, value), mean)| weight * (value - mean))
+                .sum();
+            output.push(if self.whiten && variance > 0.0 { projection / variance.sqrt() } else { projection });
+        }
+    }
+
+}
+
+/// The eigenvalues and eigenvectors (as columns of a row-major matrix) of a symmetric matrix,
+/// by the cyclic Jacobi method
+fn symmetric_eigen(mut matrix: Vec<f64>, n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut vectors = vec![0.0; n * n];
+    for i in 0..n {
+        vectors[i * n + i] = 1.0;
+    }
+    let scale: f64 = matrix.iter().map(|value| value * value).sum::<f64>().sqrt();
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n).flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| matrix[i * n + j] * matrix[i * n + j])
+            .sum::<f64>()
+            .sqrt();
+        if off_diagonal <= 1e-12 * scale {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = matrix[p * n + q];
+                if apq == 0.0 {
+                    continue;
+                }
+                // the rotation which zeroes the (p, q) entry
+                let theta = (matrix[q * n + q] - matrix[p * n + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (matrix[k * n + p], matrix[k * n + q]);
+                    matrix[k * n + p] = c * akp - s * akq;
+                    matrix[k * n + q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (matrix[p * n + k], matrix[q * n + k]);
+                    matrix[p * n + k] = c * apk - s * aqk;
+                    matrix[q * n + k] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (vectors[k * n + p], vectors[k * n + q]);
+                    vectors[k * n + p] = c * vkp - s * vkq;
+                    vectors[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| matrix[i * n + i]).collect(), vectors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{PipelineConfig, PreparedDataSet};
+
+    #[test]
+    fn test_pca() {
+
+        // points along y = 2x, with a little noise across the line
+        let rows: Vec<[Float; 2]> = (0..20)
+            .map(|i| {
+                let t = (i / 2) as Float - 4.5;
+                let noise = if i % 2 == 0 { 0.1 } else { -0.1 };
+                [t - 2.0 * noise, 2.0 * t + noise]
+            })
+            .collect();
+        let pca = Pca::fit(rows.iter().map(|row| &row[..]), PcaComponents::Count(2), false);
+        let direction = pca.components().next().unwrap();
+        let expected = [1.0 / (5.0 as Float).sqrt(), 2.0 / (5.0 as Float).sqrt()];
+        assert!(direction.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-4), "{:?}", direction);
+        let ratios = pca.explained_variance_ratio();
+        assert!(ratios[0] > 0.99 && (ratios[0] + ratios[1] - 1.0).abs() < 1e-5, "{:?}", ratios);
+
+        let mut projected = Vec::new();
+        pca.project(&[1.0, 2.0], &mut projected);
+        assert!((projected[0] - (5.0 as Float).sqrt()).abs() < 1e-4 && projected[1].abs() < 1e-4, "{:?}", projected);
+
+        let data_set = PreparedDataSet::from_rows(&rows, &vec![[0.0]; rows.len()]);
+        let pipeline = PipelineConfig::new().with_pca(PcaComponents::Variance(0.95), true).fit(&data_set);
+        let whitened = data_set.transform_inputs(&pipeline);
+        assert_eq!(whitened.independent_cols(), 1);
+        let stats = whitened.column_stats();
+        assert!(stats[0].mean().abs() < 1e-4 && (stats[0].std_dev() - 1.0).abs() < 1e-4, "{:?}", stats[0]);
+
+    }
+
+}
@@ -0,0 +1,492 @@
+use std::error::Error;
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+use crate::Float;
+use crate::data::{ColumnSchema, ColumnType, DataSetSchema, Normalizer, Pca, PcaComponents, PreparedDataSet};
+use crate::func::ErrorFn;
+use crate::net::Net;
+use crate::stats::Stats;
+
+/// How missing (NaN) values of a column are filled in
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ImputeStrategy {
+    Mean,
+    Median,
+    Constant(Float),
+}
+
+/// A transform of the independent columns, which is fitted on the output of the transforms
+/// before it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Transform {
+    Impute(ImputeStrategy),
+    /// Replaces each of the columns, which must hold class indices, with one-hot columns
+    OneHot { columns: Vec<usize> },
+    ZScore,
+    MinMax { range: (Float, Float) },
+    /// Appends the products of every combination of two up to `degree` of the columns, e.g.
+    /// `a^2`, `a*b` and `b^2` for two columns and a degree of 2
+    Polynomial { columns: Vec<usize>, degree: usize },
+    /// Replaces the columns with their projections onto their principal components, scaled to
+    /// unit variance if whitened
+    Pca { components: PcaComponents, whiten: bool },
+}
+
+/// A fitted `Transform`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PipelineStep {
+    Impute { values: Vec<Float> },
+    /// The number of classes of each encoded column. Classes which weren't seen when fitting are
+    /// encoded as all zeros.
+    OneHot { columns: Vec<usize>, num_classes: Vec<usize> },
+    Scale(Normalizer),
+    /// The columns multiplied for each appended term
+    Polynomial { terms: Vec<Vec<usize>> },
+    Pca(Pca),
+}
+
+/// The transforms of a pipeline, before they're fitted to a training set
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    pub transforms: Vec<Transform>,
+}
+
+/// Fitted transforms of the independent columns, e.g. imputing, then encoding, then scaling, to
+/// apply to every data set and prediction input in the same way as to the training set
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pipeline {
+    input_cols: usize,
+    steps: Vec<PipelineStep>,
+}
+
+impl PipelineConfig {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_transform(self, transform: Transform) -> Self {
+        let mut new = self;
+        new.transforms.push(transform);
+        new
+    }
+
+    pub fn with_imputer(self, strategy: ImputeStrategy) -> Self {
+        self.with_transform(Transform::Impute(strategy))
+    }
+
+    pub fn with_one_hot(self, columns: impl Into<Vec<usize>>) -> Self {
+        self.with_transform(Transform::OneHot { columns: columns.into() })
+    }
+
+    pub fn with_z_score(self) -> Self {
+        self.with_transform(Transform::ZScore)
+    }
+
+    pub fn with_min_max(self, range: (Float, Float)) -> Self {
+        self.with_transform(Transform::MinMax { range })
+    }
+
+    pub fn with_polynomial(self, columns: impl Into<Vec<usize>>, degree: usize) -> Self {
+        self.with_transform(Transform::Polynomial { columns: columns.into(), degree })
+    }
+
+    pub fn with_pca(self, components: PcaComponents, whiten: bool) -> Self {
+        self.with_transform(Transform::Pca { components, whiten })
+    }
+
+    /// Fits the transforms in order to the independent columns of the data set
+    pub fn fit(&self, data_set: &PreparedDataSet) -> Pipeline {
+        assert!(data_set.num_rows() > 0, "can't fit a pipeline without any rows");
+        let input_cols = data_set.independent_cols();
+        let mut cols = input_cols;
+        let mut rows = data_set.inputs_row_major();
+        let mut steps = Vec::with_capacity(self.transforms.len());
+        for transform in &self.transforms {
+            let step = PipelineStep::fit(transform, &rows, cols);
+            let mut transformed = Vec::with_capacity(rows.len() / cols * step.output_cols(cols));
+            for row in rows.chunks_exact(cols) {
+                step.apply(row, &mut transformed);
+            }
+            cols = step.output_cols(cols);
+            rows = transformed;
+            steps.push(step);
+        }
+        Pipeline { input_cols, steps }
+    }
+
+}
+
+impl PipelineStep {
+
+    fn fit(transform: &Transform, rows: &[Float], cols: usize) -> PipelineStep {
+        let column = |index: usize| rows.iter().skip(index).step_by(cols).cloned();
+        match transform {
+            Transform::Impute(strategy) => PipelineStep::Impute {
+                values: (0..cols)
+                    .map(|index| {
+                        let mut present: Vec<Float> = column(index).filter(|value| !value.is_nan()).collect();
+                        match strategy {
+                            _ if present.is_empty() => 0.0,
+                            ImputeStrategy::Mean => present.iter().map(|&value| value as f64).sum::<f64>() as Float / present.len() as Float,
+                            ImputeStrategy::Median => {
+                                present.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                                let middle = present.len() / 2;
+                                if present.len().is_multiple_of(2) { (present[middle - 1] + present[middle]) / 2.0 } else { present[middle] }
+                            },
+                            ImputeStrategy::Constant(value) => *value,
+                        }
+                    })
+                    .collect(),
+            },
+            Transform::OneHot { columns } => {
+                assert!(columns.windows(2).all(|w| w[0] < w[1]), "one-hot columns must be in ascending order");
+                assert!(columns.last().is_none_or(|&index| index < cols), "one-hot column out of range");
+                let num_classes = columns.iter()
+                    .map(|&index| {
+                        column(index).filter(|value| !value.is_nan())
+                            .map(|value| {
+                                assert!(value >= 0.0 && value.fract() == 0.0, "column {} holds {}, which is not a class index", index, value);
+                                value as usize + 1
+                            })
+                            .max()
+                            .unwrap_or(0)
+                    })
+                    .collect();
+                PipelineStep::OneHot { columns: columns.clone(), num_classes }
+            },
+            Transform::ZScore => PipelineStep::Scale(Normalizer::fit_z_score(rows.chunks_exact(cols))),
+            Transform::MinMax { range } => PipelineStep::Scale(Normalizer::fit_min_max(rows.chunks_exact(cols), *range)),
+            Transform::Polynomial { columns, degree } => {
+                assert!(columns.iter().all(|&index| index < cols), "polynomial column out of range");
+                PipelineStep::Polynomial { terms: polynomial_terms(columns, *degree) }
+            },
+            Transform::Pca { components, whiten } => PipelineStep::Pca(Pca::fit(rows.chunks_exact(cols), *components, *whiten)),
+        }
+    }
+
+    fn output_cols(&self, input_cols: usize) -> usize {
+        match self {
+            PipelineStep::OneHot { num_classes, .. } => input_cols - num_classes.len() + num_classes.iter().sum::<usize>(),
+            PipelineStep::Polynomial { terms } => input_cols + terms.len(),
+            PipelineStep::Pca(pca) => pca.num_components(),
+            _ => input_cols,
+        }
+    }
+
+    fn apply(&self, input: &[Float], output: &mut Vec<Float>) {
+        match self {
+            PipelineStep::Impute { values } => {
+                output.extend(input.iter().zip(values).map(|(&value, &fill)| if value.is_nan() { fill } else { value }));
+            },
+            PipelineStep::OneHot { columns, num_classes } => {
+                let mut encoded = columns.iter().zip(num_classes).peekable();
+                for (index, &value) in input.iter().enumerate() {
+                    match encoded.next_if(|(&column, _)| column == index) {
+                        Some((_, &classes)) => {
+                            let start = output.len();
+                            output.resize(start + classes, 0.0);
+                            if value >= 0.0 && value.fract() == 0.0 && (value as usize) < classes {
+                                output[start + value as usize] = 1.0;
+                            }
+                        },
+                        None => output.push(value),
+                    }
+                }
+            },
+            PipelineStep::Scale(normalizer) => {
+                let start = output.len();
+                output.extend_from_slice(input);
+                normalizer.normalize(&mut output[start..]);
+            },
+            PipelineStep::Polynomial { terms } => {
+                output.extend_from_slice(input);
+                output.extend(terms.iter().map(|term| term.iter().map(|&index| input[index]).product::<Float>()));
+            },
+            PipelineStep::Pca(pca) => pca.project(input, output),
+        }
+    }
+
+    fn column_names(&self, names: Vec<String>) -> Vec<String> {
+        match self {
+            PipelineStep::OneHot { columns, num_classes } => {
+                let mut encoded = columns.iter().zip(num_classes).peekable();
+                let mut output = Vec::with_capacity(self.output_cols(names.len()));
+                for (index, name) in names.into_iter().enumerate() {
+                    match encoded.next_if(|(&column, _)| column == index) {
+                        Some((_, &classes)) => output.extend((0..classes).map(|class| format!("{}={}", name, class))),
+                        None => output.push(name),
+                    }
+                }
+                output
+            },
+            PipelineStep::Polynomial { terms } => {
+                let generated: Vec<String> = terms.iter()
+                    .map(|term| {
+                        // runs of the same column become powers, e.g. [0, 0, 1] is a^2*b
+                        let mut factors: Vec<String> = Vec::new();
+                        for run in term.chunk_by(|a, b| a == b) {
+                            factors.push(match run.len() {
+                                1 => names[run[0]].clone(),
+                                power => format!("{}^{}", names[run[0]], power),
+                            });
+                        }
+                        factors.join("*")
+                    })
+                    .collect();
+                let mut output = names;
+                output.extend(generated);
+                output
+            },
+            PipelineStep::Pca(pca) => (0..pca.num_components()).map(|component| format!("pc{}", component)).collect(),
+            _ => names,
+        }
+    }
+
+}
+
+/// The combinations with repetition of two up to `degree` of the columns, by degree
+fn polynomial_terms(columns: &[usize], degree: usize) -> Vec<Vec<usize>> {
+    let mut terms = Vec::new();
+    let mut previous: Vec<Vec<usize>> = (0..columns.len()).map(|position| vec![position]).collect();
+    for _ in 2..=degree {
+        let mut next = Vec::new();
+        for term in &previous {
+            for position in *term.last().unwrap()..columns.len() {
+                let mut extended = term.clone();
+                extended.push(position);
+                next.push(extended);
+            }
+        }
+        terms.extend(next.iter().map(|term| term.iter().map(|&position| columns[position]).collect()));
+        previous = next;
+    }
+    terms
+}
+
+impl Pipeline {
+
+    #[inline]
+    pub fn input_cols(&self) -> usize {
+        self.input_cols
+    }
+
+    pub fn output_cols(&self) -> usize {
+        self.steps.iter().fold(self.input_cols, |cols, step| step.output_cols(cols))
+    }
+
+    #[inline]
+    pub fn steps(&self) -> &[PipelineStep] {
+        &self.steps
+    }
+
+    /// The names of the output columns, given the names of the input columns
+    pub fn output_column_names(&self, names: &[String]) -> Vec<String> {
+        assert_eq!(names.len(), self.input_cols, "names do not match pipeline");
+        self.steps.iter().fold(names.to_vec(), |names, step| step.column_names(names))
+    }
+
+    pub fn transform(&self, input: &[Float]) -> Vec<Float> {
+        assert_eq!(input.len(), self.input_cols, "input does not match pipeline");
+        let mut values = input.to_vec();
+        let mut transformed = Vec::with_capacity(self.output_cols());
+        for step in &self.steps {
+            transformed.clear();
+            step.apply(&values, &mut transformed);
+            std::mem::swap(&mut values, &mut transformed);
+        }
+        values
+    }
+
+}
+
+impl PreparedDataSet {
+
+    /// A copy of the data set with the independent columns transformed by a fitted pipeline. The
+    /// schema describes the original columns, so it isn't kept.
+    pub fn transform_inputs(&self, pipeline: &Pipeline) -> PreparedDataSet {
+        assert_eq!(pipeline.input_cols(), self.independent_cols(), "pipeline does not match the independent columns");
+        let output_cols = pipeline.output_cols();
+        let mut data = Vec::with_capacity(self.num_rows() * (output_cols + self.dependent_cols()));
+        for (inputs, outputs) in self {
+            data.extend(pipeline.transform(inputs));
+            data.extend_from_slice(outputs);
+        }
+        let transformed = Self::from_vec(data, output_cols, self.dependent_cols(), self.num_rows());
+        match self.labels() {
+            Some(labels) => transformed.with_labels(labels.clone()),
+            None => transformed,
+        }
+    }
+
+    /// A copy of the data set with the products of every combination of two up to `degree` of
+    /// the independent columns appended, see `Transform::Polynomial`. If the data set has a
+    /// schema, the new columns are named after the columns they multiply, e.g. `x^2*y`.
+    pub fn expand_polynomial(&self, columns: &[usize], degree: usize) -> PreparedDataSet {
+        let pipeline = PipelineConfig::new().with_polynomial(columns, degree).fit(self);
+        let expanded = self.transform_inputs(&pipeline);
+        match self.schema() {
+            Some(schema) => {
+                let names = pipeline.output_column_names(&schema.independent_column_names());
+                let mut independent = schema.independent_columns().to_vec();
+                independent.extend(names[independent.len()..].iter().map(|name| ColumnSchema::new(name, ColumnType::Continuous)));
+                expanded.with_derived_schema(DataSetSchema::new(independent, schema.dependent_columns().to_vec()))
+            },
+            None => expanded,
+        }
+    }
+
+}
+
+/// A net bundled with the pipeline its training inputs were transformed with, so it predicts
+/// from untransformed inputs and is saved as a single model
+#[derive(Clone, Debug)]
+pub struct PipelineNet {
+    pipeline: Pipeline,
+    net: Net,
+}
+
+impl PipelineNet {
+
+    pub fn new(pipeline: Pipeline, net: Net) -> Self {
+        assert_eq!(pipeline.output_cols(), net.input_size(), "pipeline does not match net inputs");
+        PipelineNet { pipeline, net }
+    }
+
+    #[inline]
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    #[inline]
+    pub fn net(&self) -> &Net {
+        &self.net
+    }
+
+    pub fn into_parts(self) -> (Pipeline, Net) {
+        (self.pipeline, self.net)
+    }
+
+    #[inline]
+    pub fn input_size(&self) -> usize {
+        self.pipeline.input_cols()
+    }
+
+    pub fn predict(&self, input: &[Float]) -> Vec<Float> {
+        self.net.predict(&self.pipeline.transform(input))
+    }
+
+    /// The error stats of the predictions for an untransformed data set
+    pub fn compute_error(&self, data_set: &PreparedDataSet, error_fn: &ErrorFn) -> Stats {
+        self.net.compute_error(&data_set.transform_inputs(&self.pipeline), error_fn)
+    }
+
+    pub fn save_json(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load_json(path: impl AsRef<Path>) -> Result<PipelineNet, Box<dyn Error>> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+}
+
+/// The serialized form of a `PipelineNet`, which is checked for consistency when loaded
+#[derive(Serialize, Deserialize)]
+struct SerializedPipelineNet<P, N> {
+    pipeline: P,
+    net: N,
+}
+
+impl Serialize for PipelineNet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedPipelineNet { pipeline: &self.pipeline, net: &self.net }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PipelineNet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = SerializedPipelineNet::<Pipeline, Net>::deserialize(deserializer)?;
+        if serialized.pipeline.output_cols() != serialized.net.input_size() {
+            return Err(serde::de::Error::custom("pipeline does not match net inputs"));
+        }
+        Ok(PipelineNet { pipeline: serialized.pipeline, net: serialized.net })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::NetConfig;
+    use crate::initializer::RandomNetInitializer;
+    use crate::func::ActivationFn;
+
+    #[test]
+    fn test_pipeline() {
+
+        let train = PreparedDataSet::from_rows(
+            &[[1.0, 0.0, 10.0], [Float::NAN, 2.0, 20.0], [3.0, 1.0, Float::NAN], [5.0, 2.0, 30.0]],
+            &[[0.0], [1.0], [0.0], [1.0]],
+        );
+        let pipeline = PipelineConfig::new()
+            .with_imputer(ImputeStrategy::Median)
+            .with_one_hot([1])
+            .with_min_max((0.0, 1.0))
+            .fit(&train);
+        assert_eq!((pipeline.input_cols(), pipeline.output_cols()), (3, 5));
+        assert_eq!(pipeline.steps()[0], PipelineStep::Impute { values: vec![3.0, 1.5, 20.0] });
+        assert_eq!(pipeline.transform(&[Float::NAN, 1.0, 10.0]), [0.5, 0.0, 1.0, 0.0, 0.0]);
+        // unseen classes are all zeros
+        assert_eq!(pipeline.transform(&[1.0, 7.0, 30.0]), [0.0, 0.0, 0.0, 0.0, 1.0]);
+
+        let transformed = train.transform_inputs(&pipeline);
+        assert_eq!(transformed.independent_cols(), 5);
+        assert_eq!(transformed.row(1), (&[0.5, 0.0, 0.0, 1.0, 0.5][..], &[1.0][..]));
+
+        let mut net = NetConfig::new_fully_connected(5, 1, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("pipeline"));
+        let model = PipelineNet::new(pipeline.clone(), net.clone());
+        assert_eq!(model.predict(&[Float::NAN, 2.0, 20.0]), net.predict(transformed.row(1).0));
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let loaded: PipelineNet = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(loaded.pipeline(), &pipeline);
+        assert_eq!(loaded.predict(&[4.0, 0.0, Float::NAN]), model.predict(&[4.0, 0.0, Float::NAN]));
+        let mismatched = serialized.replace("\"input_cols\":3", "\"input_cols\":4");
+        assert!(serde_json::from_str::<PipelineNet>(&mismatched).is_err());
+
+
+    }
+
+    #[test]
+    fn test_polynomial() {
+
+        let schema = DataSetSchema::new(
+            vec![ColumnSchema::new("a", ColumnType::Continuous), ColumnSchema::new("b", ColumnType::Integer), ColumnSchema::new("c", ColumnType::Continuous)],
+            vec![ColumnSchema::new("y", ColumnType::Continuous)],
+        );
+        let data_set = PreparedDataSet::from_rows(&[[2.0, 3.0, 5.0]], &[[1.0]]).with_schema(schema).unwrap();
+        let expanded = data_set.expand_polynomial(&[0, 1], 3);
+        assert_eq!(expanded.row(0).0, [2.0, 3.0, 5.0, 4.0, 6.0, 9.0, 8.0, 12.0, 18.0, 27.0]);
+        assert_eq!(
+            expanded.schema().unwrap().independent_column_names(),
+            ["a", "b", "c", "a^2", "a*b", "b^2", "a^3", "a^2*b", "a*b^2", "b^3"]
+        );
+        assert_eq!(expanded.schema().unwrap().dependent_column_names(), ["y"]);
+
+        let pipeline = PipelineConfig::new().with_one_hot([1]).with_polynomial([0, 1], 2).fit(&data_set);
+        let names: Vec<String> = ["a", "b", "c"].iter().map(|name| name.to_string()).collect();
+        assert_eq!(pipeline.output_column_names(&names), ["a", "b=0", "b=1", "b=2", "b=3", "c", "a^2", "a*b=0", "b=0^2"]);
+        assert!(PreparedDataSet::from_rows(&[[1.0]], &[[1.0]]).expand_polynomial(&[0], 2).schema().is_none());
+
+    }
+
+}
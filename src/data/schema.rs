@@ -0,0 +1,178 @@
+use serde::{Serialize, Deserialize};
+use crate::Float;
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum SchemaError {
+        ColumnCountMismatch(independent: usize, dependent: usize, expected_independent: usize, expected_dependent: usize) {
+            description("Number of columns does not match schema")
+            display("Data has {} independent and {} dependent columns, schema expects {} and {}",
+                independent, dependent, expected_independent, expected_dependent)
+        }
+        NotFinite(column: String, value: Float) {
+            description("Value is not finite")
+            display("Value {} of column {} is not finite", value, column)
+        }
+        TypeMismatch(column: String, value: Float, column_type: ColumnType) {
+            description("Value does not match column type")
+            display("Value {} of column {} is not of type {:?}", value, column, column_type)
+        }
+        OutOfRange(column: String, value: Float, min: Float, max: Float) {
+            description("Value is outside of the allowed range")
+            display("Value {} of column {} is outside of the allowed range [{}, {}]", value, column, min, max)
+        }
+    }
+}
+
+/// A hint for the kind of values a column holds. All values are stored as floats regardless.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ColumnType {
+    Continuous,
+    Integer,
+    /// Only 0 or 1
+    Binary,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    /// The allowed (inclusive) range of values
+    pub range: Option<(Float, Float)>,
+}
+
+impl ColumnSchema {
+
+    pub fn new(name: impl ToString, column_type: ColumnType) -> Self {
+        ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            unit: None,
+            description: None,
+            range: None,
+        }
+    }
+
+    pub fn with_unit(self, unit: impl ToString) -> Self {
+        let mut new = self;
+        new.unit = Some(unit.to_string());
+        new
+    }
+
+    pub fn with_description(self, description: impl ToString) -> Self {
+        let mut new = self;
+        new.description = Some(description.to_string());
+        new
+    }
+
+    pub fn with_range(self, min: Float, max: Float) -> Self {
+        assert!(min <= max);
+        let mut new = self;
+        new.range = Some((min, max));
+        new
+    }
+
+    pub fn validate(&self, value: Float) -> Result<(), SchemaError> {
+        if !value.is_finite() {
+            return Err(SchemaError::NotFinite(self.name.clone(), value));
+        }
+        let type_matches = match self.column_type {
+            ColumnType::Continuous => true,
+            ColumnType::Integer => value.fract() == 0.0,
+            ColumnType::Binary => value == 0.0 || value == 1.0,
+        };
+        if !type_matches {
+            return Err(SchemaError::TypeMismatch(self.name.clone(), value, self.column_type));
+        }
+        if let Some((min, max)) = self.range {
+            if value < min || value > max {
+                return Err(SchemaError::OutOfRange(self.name.clone(), value, min, max));
+            }
+        }
+        Ok(())
+    }
+
+}
+
+/// Describes the independent (input) and dependent (target) columns of a data set, in order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DataSetSchema {
+    independent: Vec<ColumnSchema>,
+    dependent: Vec<ColumnSchema>,
+}
+
+impl DataSetSchema {
+
+    pub fn new(independent: Vec<ColumnSchema>, dependent: Vec<ColumnSchema>) -> Self {
+        DataSetSchema {
+            independent,
+            dependent,
+        }
+    }
+
+    #[inline]
+    pub fn independent_columns(&self) -> &[ColumnSchema] {
+        &self.independent
+    }
+
+    #[inline]
+    pub fn dependent_columns(&self) -> &[ColumnSchema] {
+        &self.dependent
+    }
+
+    pub fn independent_column_names(&self) -> Vec<String> {
+        self.independent.iter().map(|c| c.name.clone()).collect()
+    }
+
+    pub fn dependent_column_names(&self) -> Vec<String> {
+        self.dependent.iter().map(|c| c.name.clone()).collect()
+    }
+
+    pub fn validate_counts(&self, independent_cols: usize, dependent_cols: usize) -> Result<(), SchemaError> {
+        if independent_cols != self.independent.len() || dependent_cols != self.dependent.len() {
+            return Err(SchemaError::ColumnCountMismatch(
+                independent_cols, dependent_cols, self.independent.len(), self.dependent.len()));
+        }
+        Ok(())
+    }
+
+    pub fn validate_inputs(&self, inputs: &[Float]) -> Result<(), SchemaError> {
+        for (column, &value) in self.independent.iter().zip(inputs) {
+            column.validate(value)?;
+        }
+        Ok(())
+    }
+
+    pub fn validate_row(&self, inputs: &[Float], outputs: &[Float]) -> Result<(), SchemaError> {
+        self.validate_inputs(inputs)?;
+        for (column, &value) in self.dependent.iter().zip(outputs) {
+            column.validate(value)?;
+        }
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_column() {
+        let column = ColumnSchema::new("temp", ColumnType::Continuous)
+            .with_unit("celsius")
+            .with_range(-50.0, 60.0);
+        assert_eq!(column.validate(20.5), Ok(()));
+        assert!(column.validate(Float::NAN).is_err());
+        assert_eq!(column.validate(70.0), Err(SchemaError::OutOfRange("temp".to_string(), 70.0, -50.0, 60.0)));
+        let column = ColumnSchema::new("count", ColumnType::Integer);
+        assert_eq!(column.validate(3.0), Ok(()));
+        assert_eq!(column.validate(3.5), Err(SchemaError::TypeMismatch("count".to_string(), 3.5, ColumnType::Integer)));
+        let column = ColumnSchema::new("flag", ColumnType::Binary);
+        assert_eq!(column.validate(1.0), Ok(()));
+        assert!(column.validate(2.0).is_err());
+    }
+
+}
@@ -0,0 +1,200 @@
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::Float;
+use crate::data::PreparedDataSet;
+
+/// Whether a sequence has targets for each of its steps, or only for the whole sequence
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SequenceTargets {
+    PerStep,
+    PerSequence,
+}
+
+/// A data set of variable-length sequences, each step of which has `input_cols` values, e.g.
+/// for recurrent layers. The steps of all sequences are stored one after another.
+#[derive(Clone, Debug)]
+pub struct SequenceDataSet {
+    input_cols: usize,
+    target_cols: usize,
+    targets_kind: SequenceTargets,
+    inputs: Vec<Float>,
+    targets: Vec<Float>,
+    /// The first step of each sequence, followed by the total number of steps
+    step_offsets: Vec<usize>,
+}
+
+/// A sequence of a `SequenceDataSet`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sequence<'a> {
+    /// The inputs of every step, one after another
+    pub inputs: &'a [Float],
+    /// The targets of every step, or of the sequence
+    pub targets: &'a [Float],
+    pub num_steps: usize,
+}
+
+impl<'a> Sequence<'a> {
+
+    pub fn step_inputs(&self, step: usize) -> &'a [Float] {
+        assert!(step < self.num_steps);
+        let cols = self.inputs.len() / self.num_steps;
+        &self.inputs[step * cols..(step + 1) * cols]
+    }
+
+    /// The targets of the step, if the targets are per step
+    pub fn step_targets(&self, step: usize, targets_kind: SequenceTargets) -> Option<&'a [Float]> {
+        assert!(step < self.num_steps);
+        match targets_kind {
+            SequenceTargets::PerStep => {
+                let cols = self.targets.len() / self.num_steps;
+                Some(&self.targets[step * cols..(step + 1) * cols])
+            },
+            SequenceTargets::PerSequence => None,
+        }
+    }
+
+}
+
+impl SequenceDataSet {
+
+    pub fn new(input_cols: usize, target_cols: usize, targets_kind: SequenceTargets) -> Self {
+        assert!(input_cols > 0 && target_cols > 0, "steps must have inputs and targets");
+        SequenceDataSet {
+            input_cols,
+            target_cols,
+            targets_kind,
+            inputs: Vec::new(),
+            targets: Vec::new(),
+            step_offsets: vec![0],
+        }
+    }
+
+    /// Adds a sequence of one or more steps, whose inputs are laid out one step after another,
+    /// as are its targets if they are per step
+    pub fn push(&mut self, inputs: &[Float], targets: &[Float]) {
+        assert!(!inputs.is_empty() && inputs.len().is_multiple_of(self.input_cols), "inputs are not a whole number of steps");
+        let num_steps = inputs.len() / self.input_cols;
+        let expected_targets = match self.targets_kind {
+            SequenceTargets::PerStep => num_steps * self.target_cols,
+            SequenceTargets::PerSequence => self.target_cols,
+        };
+        assert_eq!(targets.len(), expected_targets, "wrong number of targets");
+        self.inputs.extend_from_slice(inputs);
+        self.targets.extend_from_slice(targets);
+        self.step_offsets.push(self.step_offsets.last().unwrap() + num_steps);
+    }
+
+    pub fn with_sequence(self, inputs: &[Float], targets: &[Float]) -> Self {
+        let mut new = self;
+        new.push(inputs, targets);
+        new
+    }
+
+    #[inline]
+    pub fn num_sequences(&self) -> usize {
+        self.step_offsets.len() - 1
+    }
+
+    #[inline]
+    pub fn num_steps(&self) -> usize {
+        *self.step_offsets.last().unwrap()
+    }
+
+    #[inline]
+    pub fn input_cols(&self) -> usize {
+        self.input_cols
+    }
+
+    #[inline]
+    pub fn target_cols(&self) -> usize {
+        self.target_cols
+    }
+
+    #[inline]
+    pub fn targets_kind(&self) -> SequenceTargets {
+        self.targets_kind
+    }
+
+    pub fn max_sequence_len(&self) -> usize {
+        self.step_offsets.windows(2).map(|w| w[1] - w[0]).max().unwrap_or(0)
+    }
+
+    pub fn sequence(&self, index: usize) -> Sequence<'_> {
+        let (start, end) = (self.step_offsets[index], self.step_offsets[index + 1]);
+        let targets = match self.targets_kind {
+            SequenceTargets::PerStep => &self.targets[start * self.target_cols..end * self.target_cols],
+            SequenceTargets::PerSequence => &self.targets[index * self.target_cols..(index + 1) * self.target_cols],
+        };
+        Sequence {
+            inputs: &self.inputs[start * self.input_cols..end * self.input_cols],
+            targets,
+            num_steps: end - start,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Sequence<'_>> {
+        (0..self.num_sequences()).map(move |index| self.sequence(index))
+    }
+
+    /// Iterates the sequences in a random order. The steps of each sequence stay in order.
+    pub fn iter_shuffled<'a>(&'a self, rng: &mut impl Rng) -> impl Iterator<Item = Sequence<'a>> {
+        let mut order: Vec<usize> = (0..self.num_sequences()).collect();
+        order.shuffle(rng);
+        order.into_iter().map(move |index| self.sequence(index))
+    }
+
+    /// A flat data set with a row per sequence of its last `num_steps` steps, for nets without
+    /// recurrent layers. Shorter sequences are padded at the start with `padding`. With targets
+    /// per step, the targets of the last step are used.
+    pub fn to_padded(&self, num_steps: usize, padding: Float) -> PreparedDataSet {
+        assert!(num_steps > 0);
+        let mut data = Vec::with_capacity(self.num_sequences() * (num_steps * self.input_cols + self.target_cols));
+        for sequence in self.iter() {
+            let kept = sequence.num_steps.min(num_steps);
+            data.extend(std::iter::repeat_n(padding, (num_steps - kept) * self.input_cols));
+            data.extend_from_slice(&sequence.inputs[(sequence.num_steps - kept) * self.input_cols..]);
+            data.extend_from_slice(&sequence.targets[sequence.targets.len() - self.target_cols..]);
+        }
+        PreparedDataSet::from_vec(data, num_steps * self.input_cols, self.target_cols, self.num_sequences())
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use crate::utils::stable_hash_seed;
+
+    #[test]
+    fn test_sequence_data_set() {
+
+        let per_step = SequenceDataSet::new(2, 1, SequenceTargets::PerStep)
+            .with_sequence(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], &[0.1, 0.2, 0.3])
+            .with_sequence(&[7.0, 8.0], &[0.4]);
+        assert_eq!((per_step.num_sequences(), per_step.num_steps(), per_step.max_sequence_len()), (2, 4, 3));
+        let first = per_step.sequence(0);
+        assert_eq!(first.step_inputs(1), [3.0, 4.0]);
+        assert_eq!(first.step_targets(2, SequenceTargets::PerStep), Some(&[0.3][..]));
+        assert_eq!(per_step.sequence(1).targets, [0.4]);
+
+        let padded = per_step.to_padded(2, 0.0);
+        assert_eq!(padded.row(0), (&[3.0, 4.0, 5.0, 6.0][..], &[0.3][..]));
+        assert_eq!(padded.row(1), (&[0.0, 0.0, 7.0, 8.0][..], &[0.4][..]));
+
+        let per_sequence = SequenceDataSet::new(1, 2, SequenceTargets::PerSequence)
+            .with_sequence(&[1.0, 2.0, 3.0], &[1.0, 0.0])
+            .with_sequence(&[4.0], &[0.0, 1.0]);
+        assert_eq!(per_sequence.sequence(1), Sequence { inputs: &[4.0], targets: &[0.0, 1.0], num_steps: 1 });
+        assert_eq!(per_sequence.sequence(0).step_targets(0, SequenceTargets::PerSequence), None);
+
+        let mut rng = XorShiftRng::from_seed(stable_hash_seed("sequences"));
+        let mut shuffled: Vec<usize> = per_sequence.iter_shuffled(&mut rng).map(|sequence| sequence.num_steps).collect();
+        shuffled.sort();
+        assert_eq!(shuffled, [1, 3]);
+
+    }
+
+}
@@ -0,0 +1,180 @@
+use crate::Float;
+use crate::data::PreparedDataSet;
+
+/// A data set whose independent values are stored as (index, value) pairs of the non-zero
+/// values of each row, for high-dimensional inputs such as large one-hot encodings. The
+/// dependent values are stored densely.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SparseDataSet {
+    independent_cols: usize,
+    dependent_cols: usize,
+    indices: Vec<u32>,
+    values: Vec<Float>,
+    /// The first pair of each row, followed by the number of pairs
+    row_offsets: Vec<usize>,
+    targets: Vec<Float>,
+}
+
+/// A row of a `SparseDataSet`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SparseRow<'a> {
+    /// The indices of the non-zero independent values, in ascending order
+    pub indices: &'a [u32],
+    pub values: &'a [Float],
+    pub targets: &'a [Float],
+}
+
+impl<'a> SparseRow<'a> {
+
+    /// Writes the independent values into a zeroed buffer of `independent_cols` values
+    pub fn scatter_into(&self, inputs: &mut [Float]) {
+        for (&index, &value) in self.indices.iter().zip(self.values) {
+            inputs[index as usize] = value;
+        }
+    }
+
+}
+
+impl SparseDataSet {
+
+    pub fn new(independent_cols: usize, dependent_cols: usize) -> Self {
+        assert!(independent_cols <= u32::MAX as usize, "too many independent columns");
+        SparseDataSet {
+            independent_cols,
+            dependent_cols,
+            row_offsets: vec![0],
+            ..Default::default()
+        }
+    }
+
+    /// Adds a row of (index, value) pairs in any order, leaving out zeros, and its targets
+    pub fn push(&mut self, inputs: &[(usize, Float)], targets: &[Float]) {
+        assert_eq!(targets.len(), self.dependent_cols, "wrong number of targets");
+        let mut sorted = inputs.to_vec();
+        sorted.sort_unstable_by_key(|&(index, _)| index);
+        assert!(sorted.windows(2).all(|w| w[0].0 < w[1].0), "row has duplicate indices");
+        assert!(sorted.last().is_none_or(|&(index, _)| index < self.independent_cols), "index out of range");
+        for (index, value) in sorted {
+            if value != 0.0 {
+                self.indices.push(index as u32);
+                self.values.push(value);
+            }
+        }
+        self.row_offsets.push(self.indices.len());
+        self.targets.extend_from_slice(targets);
+    }
+
+    pub fn with_row(self, inputs: &[(usize, Float)], targets: &[Float]) -> Self {
+        let mut new = self;
+        new.push(inputs, targets);
+        new
+    }
+
+    /// The non-zero independent values of a dense data set
+    pub fn from_dense(data_set: &PreparedDataSet) -> SparseDataSet {
+        let mut sparse = Self::new(data_set.independent_cols(), data_set.dependent_cols());
+        for (inputs, targets) in data_set {
+            let pairs: Vec<(usize, Float)> = inputs.iter().cloned().enumerate().filter(|&(_, value)| value != 0.0).collect();
+            sparse.push(&pairs, targets);
+        }
+        sparse
+    }
+
+    #[inline]
+    pub fn num_rows(&self) -> usize {
+        self.row_offsets.len() - 1
+    }
+
+    #[inline]
+    pub fn independent_cols(&self) -> usize {
+        self.independent_cols
+    }
+
+    #[inline]
+    pub fn dependent_cols(&self) -> usize {
+        self.dependent_cols
+    }
+
+    /// The number of stored independent values
+    #[inline]
+    pub fn num_non_zero(&self) -> usize {
+        self.values.len()
+    }
+
+    /// The fraction of the independent values which are non-zero
+    pub fn density(&self) -> Float {
+        self.num_non_zero() as Float / (self.num_rows() * self.independent_cols).max(1) as Float
+    }
+
+    pub fn row(&self, index: usize) -> SparseRow<'_> {
+        let (start, end) = (self.row_offsets[index], self.row_offsets[index + 1]);
+        SparseRow {
+            indices: &self.indices[start..end],
+            values: &self.values[start..end],
+            targets: &self.targets[index * self.dependent_cols..(index + 1) * self.dependent_cols],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = SparseRow<'_>> {
+        (0..self.num_rows()).map(move |index| self.row(index))
+    }
+
+    /// A dense copy of `num_rows` rows starting at `first_row`
+    pub fn to_dense_rows(&self, first_row: usize, num_rows: usize) -> PreparedDataSet {
+        assert!(first_row + num_rows <= self.num_rows());
+        let cols = self.independent_cols + self.dependent_cols;
+        let mut data = vec![0.0; num_rows * cols];
+        for (dense, index) in data.chunks_exact_mut(cols).zip(first_row..) {
+            let row = self.row(index);
+            let (inputs, targets) = dense.split_at_mut(self.independent_cols);
+            row.scatter_into(inputs);
+            targets.copy_from_slice(row.targets);
+        }
+        PreparedDataSet::from_vec(data, self.independent_cols, self.dependent_cols, num_rows)
+    }
+
+    pub fn to_dense(&self) -> PreparedDataSet {
+        self.to_dense_rows(0, self.num_rows())
+    }
+
+    /// Dense copies of consecutive chunks of `chunk_rows` rows, made as they're iterated, so only
+    /// one chunk is in memory densely, e.g. to train batch by batch
+    pub fn dense_chunks(&self, chunk_rows: usize) -> impl Iterator<Item = PreparedDataSet> + '_ {
+        assert!(chunk_rows > 0, "chunks must hold at least one row");
+        (0..self.num_rows()).step_by(chunk_rows)
+            .map(move |first_row| self.to_dense_rows(first_row, chunk_rows.min(self.num_rows() - first_row)))
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sparse_data_set() {
+
+        let sparse = SparseDataSet::new(5, 1)
+            .with_row(&[(3, 2.0), (0, 1.0)], &[1.0])
+            .with_row(&[], &[0.0])
+            .with_row(&[(4, -1.0), (1, 0.0)], &[1.0]);
+        assert_eq!((sparse.num_rows(), sparse.num_non_zero()), (3, 3));
+        assert_eq!(sparse.row(0), SparseRow { indices: &[0, 3], values: &[1.0, 2.0], targets: &[1.0] });
+        assert_eq!(sparse.density(), 0.2);
+
+        let dense = sparse.to_dense();
+        let expected: Vec<(&[Float], &[Float])> = vec![
+            (&[1.0, 0.0, 0.0, 2.0, 0.0], &[1.0]),
+            (&[0.0, 0.0, 0.0, 0.0, 0.0], &[0.0]),
+            (&[0.0, 0.0, 0.0, 0.0, -1.0], &[1.0]),
+        ];
+        assert_eq!(dense.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(SparseDataSet::from_dense(&dense), sparse);
+
+        let chunks: Vec<PreparedDataSet> = sparse.dense_chunks(2).collect();
+        assert_eq!(chunks.iter().map(PreparedDataSet::num_rows).collect::<Vec<_>>(), [2, 1]);
+        assert_eq!(chunks[1].row(0), expected[2]);
+
+    }
+
+}
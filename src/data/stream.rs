@@ -0,0 +1,178 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crossbeam::channel::{self, Receiver};
+
+use crate::data::{CsvDialect, PreparedDataSet};
+use crate::utils::into_string_vec;
+
+/// The error of reading a chunk, which is produced on the reader thread
+pub type ChunkError = Box<dyn Error + Send + Sync>;
+
+/// A csv data set which is read in chunks of rows on every pass rather than loaded into memory,
+/// for data sets larger than RAM. Only the header is read when opening it.
+#[derive(Clone, Debug)]
+pub struct StreamingCsvDataSet {
+    path: PathBuf,
+    dialect: CsvDialect,
+    n_cols: usize,
+    independent_indices: Vec<usize>,
+    dependent_indices: Vec<usize>,
+    chunk_rows: usize,
+}
+
+impl StreamingCsvDataSet {
+
+    pub fn open<T1, I1, T2, I2>(
+        path: impl AsRef<Path>,
+        dialect: &CsvDialect,
+        independent_cols: T1,
+        dependent_cols: T2,
+        chunk_rows: usize,
+    ) -> Result<StreamingCsvDataSet, Box<dyn Error>>
+        where T1: AsRef<[I1]>, I1: ToString,
+              T2: AsRef<[I2]>, I2: ToString
+    {
+        assert!(chunk_rows > 0, "chunks must hold at least one row");
+        let path = path.as_ref();
+        let (_, column_names) = PreparedDataSet::csv_reader(PreparedDataSet::open_decompressed(path)?, dialect)?;
+        let (independent_indices, dependent_indices) = PreparedDataSet::select_csv_columns(
+            &column_names,
+            dialect,
+            &into_string_vec(independent_cols),
+            &into_string_vec(dependent_cols),
+        )?;
+        Ok(StreamingCsvDataSet {
+            path: path.to_owned(),
+            dialect: dialect.clone(),
+            n_cols: column_names.len(),
+            independent_indices,
+            dependent_indices,
+            chunk_rows,
+        })
+    }
+
+    #[inline]
+    pub fn independent_cols(&self) -> usize {
+        self.independent_indices.len()
+    }
+
+    #[inline]
+    pub fn dependent_cols(&self) -> usize {
+        self.dependent_indices.len()
+    }
+
+    #[inline]
+    pub fn chunk_rows(&self) -> usize {
+        self.chunk_rows
+    }
+
+    /// Starts a pass over the file. The chunks are parsed on a background thread, which reads
+    /// the next chunk while the current one is used, so at most two chunks are in memory. The
+    /// pass ends at the first error.
+    pub fn chunks(&self) -> CsvChunks {
+        let (sender, receiver) = channel::bounded(0);
+        let data_set = self.clone();
+        thread::spawn(move || {
+            let mut reader = match PreparedDataSet::open_decompressed(&data_set.path) {
+                Ok(file) => data_set.dialect.reader_builder().from_reader(file),
+                Err(err) => {
+                    let _ = sender.send(Err(err.into()));
+                    return;
+                },
+            };
+            let row_cols = data_set.independent_cols() + data_set.dependent_cols();
+            loop {
+                let mut data = Vec::with_capacity(data_set.chunk_rows * row_cols);
+                let chunk = PreparedDataSet::read_csv_rows(
+                    &mut reader,
+                    &data_set.dialect,
+                    data_set.n_cols,
+                    &data_set.independent_indices,
+                    &data_set.dependent_indices,
+                    data_set.chunk_rows,
+                    &mut data,
+                ).map(|num_rows| {
+                    PreparedDataSet::from_vec(data, data_set.independent_cols(), data_set.dependent_cols(), num_rows)
+                });
+                let last = match &chunk {
+                    Ok(chunk) => chunk.num_rows() < data_set.chunk_rows,
+                    Err(_) => true,
+                };
+                if let Ok(chunk) = &chunk {
+                    if chunk.num_rows() == 0 {
+                        return;
+                    }
+                }
+                // the receiver is gone if the pass was abandoned
+                if sender.send(chunk).is_err() || last {
+                    return;
+                }
+            }
+        });
+        CsvChunks { receiver }
+    }
+
+}
+
+/// The chunks of one pass over a `StreamingCsvDataSet`
+pub struct CsvChunks {
+    receiver: Receiver<Result<PreparedDataSet, ChunkError>>,
+}
+
+impl Iterator for CsvChunks {
+    type Item = Result<PreparedDataSet, ChunkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::lines_2x2;
+
+    #[test]
+    fn test_streaming_csv() -> Result<(), Box<dyn Error>> {
+
+        let data_set = lines_2x2();
+        let stream = StreamingCsvDataSet::open(
+            "data/2x2_lines_binary.csv",
+            &CsvDialect::default(),
+            ["0_0", "0_1", "1_0", "1_1"],
+            ["has_horizontal", "has_vertical"],
+            4,
+        )?;
+
+        for _ in 0..2 {
+            let chunks: Vec<PreparedDataSet> = stream.chunks().collect::<Result<_, _>>().map_err(|err| err as Box<dyn Error>)?;
+            assert_eq!(chunks.iter().map(PreparedDataSet::num_rows).collect::<Vec<_>>(), [4, 4, 3]);
+            let rows: Vec<_> = chunks.iter().flat_map(PreparedDataSet::iter).collect();
+            assert_eq!(rows, data_set.iter().collect::<Vec<_>>());
+        }
+
+        // abandoning a pass stops the reader
+        assert_eq!(stream.chunks().next().unwrap().unwrap().num_rows(), 4);
+
+        assert!(StreamingCsvDataSet::open("data/2x2_lines_binary.csv", &CsvDialect::default(), ["x"], ["has_vertical"], 4).is_err());
+        Ok(())
+
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_streaming_compressed_csv() -> Result<(), Box<dyn Error>> {
+
+        let path = std::env::temp_dir().join(format!("rust_neural_net_streaming_{}.csv.zst", std::process::id()));
+        std::fs::write(&path, zstd::encode_all(std::fs::File::open("data/2x2_lines_binary.csv")?, 0)?)?;
+        let stream = StreamingCsvDataSet::open(&path, &CsvDialect::default(), ["0_0", "0_1", "1_0", "1_1"], ["has_horizontal", "has_vertical"], 4);
+        let num_rows: Result<Vec<usize>, ChunkError> = stream?.chunks().map(|chunk| chunk.map(|chunk| chunk.num_rows())).collect();
+        std::fs::remove_file(&path)?;
+        assert_eq!(num_rows.map_err(|err| err as Box<dyn Error>)?, [4, 4, 3]);
+        Ok(())
+
+    }
+
+}
@@ -0,0 +1,82 @@
+use rand::{Rng, SeedableRng, FromEntropy};
+use rand::distributions::StandardNormal;
+use rand_xorshift::XorShiftRng;
+
+use crate::Float;
+use crate::data::PreparedDataSet;
+use crate::utils::stable_hash_seed;
+
+impl PreparedDataSet {
+
+    /// A data set of `num_rows` rows produced by the generator, which is passed the random
+    /// number generator and returns the inputs and targets of each row. Without a seed, the
+    /// generator is seeded from entropy.
+    pub fn generate<F>(num_rows: usize, seed: Option<&str>, mut generator: F) -> PreparedDataSet
+        where F: FnMut(&mut XorShiftRng) -> (Vec<Float>, Vec<Float>)
+    {
+        let mut rng = match seed {
+            Some(seed) => XorShiftRng::from_seed(stable_hash_seed(seed)),
+            None => XorShiftRng::from_entropy(),
+        };
+        let (inputs, targets): (Vec<_>, Vec<_>) = (0..num_rows).map(|_| generator(&mut rng)).unzip();
+        Self::from_rows(&inputs, &targets)
+    }
+
+    /// Random points of the unit square, with a target of 1 if exactly one of the coordinates is
+    /// above 0.5
+    pub fn xor(num_rows: usize, seed: Option<&str>) -> PreparedDataSet {
+        Self::generate(num_rows, seed, |rng| {
+            let (x, y): (Float, Float) = (rng.gen(), rng.gen());
+            let target = if (x > 0.5) != (y > 0.5) { 1.0 } else { 0.0 };
+            (vec![x, y], vec![target])
+        })
+    }
+
+    /// Points of two interleaved spirals around the origin, one-hot encoded by spiral, with
+    /// normally distributed noise of `noise` added to the coordinates
+    pub fn two_spirals(num_rows: usize, noise: Float, seed: Option<&str>) -> PreparedDataSet {
+        Self::generate(num_rows, seed, |rng| {
+            let spiral = rng.gen_range(0, 2);
+            let t: f64 = rng.gen::<f64>() * 3.0 * std::f64::consts::PI;
+            let angle = t + spiral as f64 * std::f64::consts::PI;
+            let radius = t / (3.0 * std::f64::consts::PI);
+            let x = (radius * angle.cos()) as Float + noise * rng.sample(StandardNormal) as Float;
+            let y = (radius * angle.sin()) as Float + noise * rng.sample(StandardNormal) as Float;
+            let target = if spiral == 0 { vec![1.0, 0.0] } else { vec![0.0, 1.0] };
+            (vec![x, y], target)
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+
+        let counter = PreparedDataSet::generate(3, None, {
+            let mut i = 0.0;
+            move |_| {
+                i += 1.0;
+                (vec![i], vec![2.0 * i])
+            }
+        });
+        assert_eq!(counter.row(2), (&[3.0][..], &[6.0][..]));
+
+        let xor = PreparedDataSet::xor(100, Some("xor"));
+        assert_eq!(xor.num_rows(), 100);
+        assert!(xor.iter().all(|(inputs, targets)| ((inputs[0] > 0.5) != (inputs[1] > 0.5)) == (targets[0] == 1.0)));
+        assert_eq!(
+            PreparedDataSet::xor(10, Some("xor")).iter().collect::<Vec<_>>(),
+            xor.partition(10).last().unwrap().iter().collect::<Vec<_>>()
+        );
+
+        let spirals = PreparedDataSet::two_spirals(200, 0.0, Some("spirals"));
+        assert_eq!((spirals.independent_cols(), spirals.dependent_cols()), (2, 2));
+        assert!(spirals.iter().all(|(inputs, _)| inputs[0].hypot(inputs[1]) <= 1.0 + 1e-5));
+
+    }
+
+}
@@ -0,0 +1,63 @@
+use crate::Float;
+use crate::data::PreparedDataSet;
+
+impl PreparedDataSet {
+
+    /// Sliding windows over a series for forecasting, with a row for every `window` consecutive
+    /// values as inputs and the value `horizon` steps after the last of them as the target, so a
+    /// horizon of 1 forecasts the next value. Load a series from a file with e.g.
+    /// `PreparedDataSet::from_csv(..)?.column_values(..)`.
+    pub fn from_time_series(series: &[Float], window: usize, horizon: usize) -> PreparedDataSet {
+        assert!(window > 0, "windows must hold at least one value");
+        assert!(horizon > 0, "the horizon must be at least one step");
+        let span = window + horizon;
+        assert!(series.len() >= span, "series of {} values is too short for a window of {} and horizon of {}",
+            series.len(), window, horizon);
+        let num_rows = series.len() - span + 1;
+        let mut data = Vec::with_capacity(num_rows * (window + 1));
+        for start in 0..num_rows {
+            data.extend_from_slice(&series[start..start + window]);
+            data.push(series[start + span - 1]);
+        }
+        Self::from_vec(data, window, 1, num_rows)
+    }
+
+    /// The values of a column over all rows, counting the independent columns first
+    pub fn column_values(&self, index: usize) -> Vec<Float> {
+        assert!(index < self.independent_cols() + self.dependent_cols(), "column index out of range");
+        self.iter()
+            .map(|(inputs, outputs)| if index < inputs.len() { inputs[index] } else { outputs[index - inputs.len()] })
+            .collect()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_time_series() {
+
+        let series = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let next = PreparedDataSet::from_time_series(&series, 3, 1);
+        let expected: Vec<(&[Float], &[Float])> = vec![
+            (&[1.0, 2.0, 3.0], &[4.0]),
+            (&[2.0, 3.0, 4.0], &[5.0]),
+            (&[3.0, 4.0, 5.0], &[6.0]),
+        ];
+        assert_eq!(next.iter().collect::<Vec<_>>(), expected);
+
+        let ahead = PreparedDataSet::from_time_series(&series, 2, 3);
+        assert_eq!(ahead.num_rows(), 2);
+        assert_eq!(ahead.row(1), (&[2.0, 3.0][..], &[6.0][..]));
+
+        let sine = PreparedDataSet::from_csv("data/sine_regression.csv", ["x"], ["y"]).unwrap();
+        let values = sine.column_values(1);
+        assert_eq!(values.len(), 41);
+        assert_eq!(values[1], 0.5626);
+        assert_eq!(PreparedDataSet::from_time_series(&values, 8, 1).num_rows(), 33);
+
+    }
+
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+
+use crate::Float;
+use crate::net::Net;
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum DiffError {
+        ConfigMismatch {
+            description("Nets with different configs can't be compared")
+            display("Nets with different configs can't be compared")
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerDiff {
+    pub num_weights: usize,
+    pub max_abs_diff: Float,
+    pub mean_abs_diff: Float,
+    /// The earlier layer whose weights this layer shares, in which case its weights aren't
+    /// compared again
+    pub shared_with: Option<usize>,
+}
+
+/// The differences between the weights of two nets with the same config, layer by layer, e.g.
+/// to check that differently trained nets converged to similar solutions. All of the weights of
+/// a layer are compared, so this includes any state such as running statistics.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetDiff {
+    pub layers: Vec<LayerDiff>,
+}
+
+impl NetDiff {
+
+    pub fn compute(a: &Net, b: &Net) -> Result<NetDiff, DiffError> {
+        if a.get_config() != b.get_config() {
+            return Err(DiffError::ConfigMismatch);
+        }
+        let layers = (0..a.num_layers())
+            .map(|index| {
+                let shared_with = a.shared_weights(index);
+                let (row_a, row_b) = match shared_with {
+                    Some(_) => (&[][..], &[][..]),
+                    None => (a.get_weights().get_row(index), b.get_weights().get_row(index)),
+                };
+                let mut max_abs_diff: Float = 0.0;
+                let mut sum_abs_diff = 0f64;
+                for (&weight_a, &weight_b) in row_a.iter().zip(row_b) {
+                    let diff = (weight_a - weight_b).abs();
+                    max_abs_diff = max_abs_diff.max(diff);
+                    sum_abs_diff += diff as f64;
+                }
+                LayerDiff {
+                    num_weights: row_a.len(),
+                    max_abs_diff,
+                    mean_abs_diff: if row_a.is_empty() { 0.0 } else { (sum_abs_diff / row_a.len() as f64) as Float },
+                    shared_with,
+                }
+            })
+            .collect();
+        Ok(NetDiff { layers })
+    }
+
+    /// The largest difference of any weight
+    pub fn max_abs_diff(&self) -> Float {
+        self.layers.iter().map(|layer| layer.max_abs_diff).fold(0.0, Float::max)
+    }
+
+    /// The mean difference over all distinct weights
+    pub fn mean_abs_diff(&self) -> Float {
+        let num_weights: usize = self.layers.iter().map(|layer| layer.num_weights).sum();
+        if num_weights == 0 {
+            return 0.0;
+        }
+        let sum: f64 = self.layers.iter().map(|layer| layer.mean_abs_diff as f64 * layer.num_weights as f64).sum();
+        (sum / num_weights as f64) as Float
+    }
+
+}
+
+impl fmt::Display for NetDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        writeln!(f, "{:>5} {:>10} {:>14} {:>14}", "layer", "weights", "max abs diff", "mean abs diff")?;
+        for (index, layer) in self.layers.iter().enumerate() {
+            match layer.shared_with {
+                Some(source) => writeln!(f, "{:>5} {:>10} {:>14} {:>14}", index, format!("= {}", source), "-", "-")?,
+                None => writeln!(f, "{:>5} {:>10} {:>14.6} {:>14.6}",
+                    index, layer.num_weights, layer.max_abs_diff, layer.mean_abs_diff)?,
+            }
+        }
+        writeln!(f, "total {:>10} {:>14.6} {:>14.6}",
+            self.layers.iter().map(|layer| layer.num_weights).sum::<usize>(), self.max_abs_diff(), self.mean_abs_diff())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::func::ActivationFn;
+    use crate::net::NetConfig;
+
+    #[test]
+    fn test_net_diff() {
+
+        let config = NetConfig::new_fully_connected(2, 1, [2], ActivationFn::standard_logistic_sigmoid());
+        let a = config.create_net();
+        let mut b = config.create_net();
+        b.get_weights_mut().get_row_mut(0)[1] = 0.5;
+        b.get_weights_mut().get_row_mut(1)[0] = -0.25;
+
+        let diff = NetDiff::compute(&a, &b).unwrap();
+        assert_eq!(diff.layers[0], LayerDiff { num_weights: 6, max_abs_diff: 0.5, mean_abs_diff: 0.5 / 6.0, shared_with: None });
+        assert_eq!(diff.layers[1].max_abs_diff, 0.25);
+        assert_eq!(diff.max_abs_diff(), 0.5);
+        assert!((diff.mean_abs_diff() - 0.75 / 9.0).abs() < 1e-7);
+        assert_eq!(diff.to_string().lines().count(), 4);
+
+        assert_eq!(NetDiff::compute(&a, &a).unwrap().max_abs_diff(), 0.0);
+        let other = NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        assert_eq!(NetDiff::compute(&a, &other), Err(DiffError::ConfigMismatch));
+
+    }
+
+}
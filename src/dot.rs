@@ -0,0 +1,145 @@
+use std::fmt::Write;
+
+use crate::func::ActivationFn;
+use crate::graph::{LayerInput, LayerSource};
+use crate::layer::NetLayerConfig;
+use crate::net::{Net, NetConfig};
+use crate::summary::layer_kind;
+
+fn activation_label(activation_fn: &ActivationFn) -> String {
+    match *activation_fn {
+        ActivationFn::LogisticSigmoid { steepness, scale, y_offset } => {
+            let range = if scale == 1.0 && y_offset == 0.0 {
+                String::new()
+            } else {
+                format!(" [{}, {}]", y_offset, y_offset + scale)
+            };
+            if steepness == 1.0 {
+                format!("sigmoid{}", range)
+            } else {
+                format!("sigmoid x{}{}", steepness, range)
+            }
+        },
+    }
+}
+
+/// The details of the layer shown below its kind
+fn layer_details(config: &NetLayerConfig) -> Option<String> {
+    match config {
+        NetLayerConfig::FullyConnected(size, activation_fn)
+        | NetLayerConfig::SparseFullyConnected(size, activation_fn, _) => {
+            Some(format!("{} nodes, {}", size, activation_label(activation_fn)))
+        },
+        NetLayerConfig::BatchNorm { momentum } => Some(format!("momentum {}", momentum)),
+        NetLayerConfig::GlobalAveragePooling { channels } => Some(format!("{} channels", channels)),
+        NetLayerConfig::SoftmaxCrossEntropy(classes) => Some(format!("{} classes", classes)),
+        NetLayerConfig::Flatten | NetLayerConfig::Custom { .. } => None,
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn node_name(source: LayerSource) -> String {
+    match source {
+        LayerSource::Input | LayerSource::InputSlice { .. } => "input".to_string(),
+        LayerSource::Layer(index) => format!("layer_{}", index),
+    }
+}
+
+impl NetConfig {
+
+    /// A Graphviz description of the layers of the net and the connections between them, with
+    /// the output shape of each layer if the config is valid. Render it with e.g.
+    /// `dot -Tsvg net.dot > net.svg`.
+    pub fn to_dot(&self) -> String {
+        let shapes = self.layer_shapes().ok();
+        let mut dot = String::new();
+        writeln!(dot, "digraph net {{").unwrap();
+        writeln!(dot, "    node [shape=box];").unwrap();
+        writeln!(dot, "    input [label=\"input\\n{}\", shape=ellipse];", self.input_shape()).unwrap();
+        for (index, layer) in self.layers().iter().enumerate() {
+            let mut label = format!("{}: {}", index, escape(&layer_kind(layer)));
+            if let Some(details) = layer_details(layer) {
+                write!(label, "\\n{}", details).unwrap();
+            }
+            if let Some(shapes) = &shapes {
+                write!(label, "\\n{}", shapes[index]).unwrap();
+            }
+            writeln!(dot, "    layer_{} [label=\"{}\"];", index, label).unwrap();
+        }
+        for index in 0..self.layers().len() {
+            let layer_input = self.layer_input(index);
+            let sources = layer_input.sources(index);
+            for (position, &source) in sources.iter().enumerate() {
+                let mut labels = Vec::new();
+                if let LayerSource::InputSlice { offset, size } = source {
+                    let group = self.input_groups().iter()
+                        .find(|group| self.input_group(&group.name) == Some(source));
+                    labels.push(match group {
+                        Some(group) => escape(&group.name),
+                        None => format!("{}..{}", offset, offset + size),
+                    });
+                }
+                match layer_input {
+                    LayerInput::Previous => {},
+                    LayerInput::Concat(_) => labels.push(format!("concat {}", position)),
+                    LayerInput::Sum(_) => labels.push("+".to_string()),
+                }
+                let attributes = if labels.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [label=\"{}\"]", labels.join(", "))
+                };
+                writeln!(dot, "    {} -> layer_{}{};", node_name(source), index, attributes).unwrap();
+            }
+            if let Some(source) = self.shared_weights(index) {
+                writeln!(dot, "    layer_{} -> layer_{} [style=dashed, arrowhead=none, constraint=false, label=\"shared weights\"];",
+                    source, index).unwrap();
+            }
+        }
+        for (index, head) in self.output_heads().iter().enumerate() {
+            writeln!(dot, "    head_{} [label=\"{}\", shape=ellipse];", index, escape(&head.name)).unwrap();
+            writeln!(dot, "    layer_{} -> head_{};", head.layer_index, index).unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+}
+
+impl Net {
+
+    pub fn to_dot(&self) -> String {
+        self.get_config().to_dot()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_dot() {
+
+        let hidden = NetLayerConfig::FullyConnected(4, ActivationFn::standard_logistic_sigmoid_neg());
+        let config = NetConfig::new(4, vec![hidden.clone(), hidden, NetLayerConfig::SoftmaxCrossEntropy(3)])
+            .with_shared_weights(1, 0)
+            .with_input(1, LayerInput::Sum(vec![LayerSource::Layer(0), LayerSource::Input]));
+
+        let dot = config.to_dot();
+        assert!(dot.starts_with("digraph net {\n"));
+        assert!(dot.contains("layer_0 [label=\"0: FullyConnected\\n4 nodes, sigmoid [-1, 1]\\n[4]\"];"), "{}", dot);
+        assert!(dot.contains("layer_2 [label=\"2: SoftmaxCrossEntropy\\n3 classes\\n[3]\"];"));
+        assert!(dot.contains("    input -> layer_0;\n"));
+        assert!(dot.contains("    layer_0 -> layer_1 [label=\"+\"];\n"));
+        assert!(dot.contains("    input -> layer_1 [label=\"+\"];\n"));
+        assert!(dot.contains("    layer_1 -> layer_2;\n"));
+        assert!(dot.contains("layer_0 -> layer_1 [style=dashed"));
+        assert_eq!(config.create_net().to_dot(), dot);
+
+    }
+
+}
@@ -0,0 +1,368 @@
+use std::collections::VecDeque;
+
+use serde::{Serialize, Deserialize};
+
+use crate::Float;
+use crate::data::PreparedDataSet;
+use crate::stats::Stats;
+
+/// The smallest bin fraction used when computing the PSI, so that empty bins don't yield
+/// infinite scores
+const MIN_BIN_FRACTION: f64 = 0.0001;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DriftMetric {
+    /// The shift of the mean from that of the reference, in standard deviations of the reference.
+    /// Any shift of a constant reference column is an infinite drift.
+    MeanShift,
+    /// Population stability index over the reference bins. As a rule of thumb, values below 0.1
+    /// indicate no significant change and values above 0.25 a major shift.
+    PopulationStabilityIndex,
+    /// The Kolmogorov-Smirnov statistic, i.e. the largest distance between the cumulative
+    /// distributions, evaluated at the reference bin edges
+    KolmogorovSmirnov,
+}
+
+/// The distribution of a single input column in the training data
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColumnReference {
+    pub name: Option<String>,
+    pub stats: Stats,
+    /// Upper (inclusive) bounds of all but the last bin, at the quantiles of the training data
+    bin_edges: Vec<Float>,
+    bin_fractions: Vec<f64>,
+}
+
+impl ColumnReference {
+
+    fn new(name: Option<String>, mut values: Vec<Float>, num_bins: usize) -> Self {
+        let mut stats = Stats::new();
+        for &value in &values {
+            stats.report(value);
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let mut bin_edges: Vec<Float> = (1..num_bins)
+            .filter_map(|i| values.get(i * values.len() / num_bins).cloned())
+            .collect();
+        bin_edges.dedup();
+        // the largest value would leave the last bin empty
+        if bin_edges.last().is_some_and(|&edge| Some(&edge) == values.last()) {
+            bin_edges.pop();
+        }
+        let mut column = ColumnReference {
+            name,
+            stats,
+            bin_edges,
+            bin_fractions: Vec::new(),
+        };
+        let mut counts = vec![0usize; column.num_bins()];
+        for &value in &values {
+            counts[column.bin(value)] += 1;
+        }
+        column.bin_fractions = fractions(&counts);
+        column
+    }
+
+    #[inline]
+    pub fn num_bins(&self) -> usize {
+        self.bin_edges.len() + 1
+    }
+
+    /// The index of the bin the value falls into
+    pub fn bin(&self, value: Float) -> usize {
+        self.bin_edges.iter().position(|&edge| value <= edge).unwrap_or(self.bin_edges.len())
+    }
+
+    /// Compares the counts of recent values to the reference distribution
+    pub fn score(&self, counts: &ColumnCounts, metric: DriftMetric) -> f64 {
+        debug_assert_eq!(counts.bins.len(), self.num_bins());
+        let total = counts.total();
+        if total == 0 {
+            return 0.0;
+        }
+        let current = fractions(&counts.bins);
+        match metric {
+            DriftMetric::MeanShift => {
+                let shift = (counts.sum / total as f64 - self.stats.mean()).abs();
+                let std_dev = self.stats.variance().sqrt();
+                if shift == 0.0 {
+                    0.0
+                } else if std_dev > 0.0 {
+                    shift / std_dev
+                } else {
+                    f64::INFINITY
+                }
+            },
+            DriftMetric::PopulationStabilityIndex => {
+                self.bin_fractions.iter().zip(&current)
+                    .map(|(&expected, &actual)| {
+                        let expected = f64::max(expected, MIN_BIN_FRACTION);
+                        let actual = f64::max(actual, MIN_BIN_FRACTION);
+                        (actual - expected) * (actual / expected).ln()
+                    })
+                    .sum()
+            },
+            DriftMetric::KolmogorovSmirnov => {
+                let mut expected_cdf = 0.0;
+                let mut actual_cdf = 0.0;
+                let mut max_distance: f64 = 0.0;
+                for (&expected, &actual) in self.bin_fractions.iter().zip(&current) {
+                    expected_cdf += expected;
+                    actual_cdf += actual;
+                    max_distance = max_distance.max((actual_cdf - expected_cdf).abs());
+                }
+                max_distance
+            },
+        }
+    }
+
+}
+
+/// The values of a column binned like its reference, and their sum for the mean
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnCounts {
+    pub bins: Vec<usize>,
+    pub sum: f64,
+}
+
+impl ColumnCounts {
+
+    pub fn new(bins: Vec<usize>, sum: f64) -> Self {
+        ColumnCounts { bins, sum }
+    }
+
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.bins.iter().sum()
+    }
+
+}
+
+fn fractions(counts: &[usize]) -> Vec<f64> {
+    let total: usize = counts.iter().sum();
+    counts.iter().map(|&c| c as f64 / usize::max(total, 1) as f64).collect()
+}
+
+/// The distributions of the input columns of the data a net was trained on. Serializable, so it
+/// can be stored alongside the net.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DriftReference {
+    columns: Vec<ColumnReference>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnDrift {
+    pub column_index: usize,
+    pub name: Option<String>,
+    pub score: f64,
+}
+
+impl DriftReference {
+
+    /// Bins every independent column of the data set at `num_bins` quantiles
+    pub fn from_data_set(data_set: &PreparedDataSet, num_bins: usize) -> Self {
+        assert!(num_bins > 0);
+        let names: Vec<Option<String>> = match data_set.schema() {
+            Some(schema) => schema.independent_column_names().into_iter().map(Some).collect(),
+            None => vec![None; data_set.independent_cols()],
+        };
+        let mut values = vec![Vec::with_capacity(data_set.num_rows()); data_set.independent_cols()];
+        for (inputs, _) in data_set {
+            for (column, &value) in values.iter_mut().zip(inputs) {
+                column.push(value);
+            }
+        }
+        DriftReference {
+            columns: names.into_iter().zip(values)
+                .map(|(name, values)| ColumnReference::new(name, values, num_bins))
+                .collect(),
+        }
+    }
+
+    #[inline]
+    pub fn columns(&self) -> &[ColumnReference] {
+        &self.columns
+    }
+
+    /// Scores the inputs of another data set, e.g. newly collected data, against the reference
+    pub fn compare_data_set(&self, data_set: &PreparedDataSet, metric: DriftMetric) -> Vec<ColumnDrift> {
+        assert_eq!(data_set.independent_cols(), self.columns.len());
+        let mut counts = self.new_counts();
+        for (inputs, _) in data_set {
+            self.count(inputs, &mut counts, 1);
+        }
+        self.scores(&counts, metric)
+    }
+
+    fn new_counts(&self) -> Vec<ColumnCounts> {
+        self.columns.iter().map(|c| ColumnCounts::new(vec![0; c.num_bins()], 0.0)).collect()
+    }
+
+    fn count(&self, inputs: &[Float], counts: &mut [ColumnCounts], increment: isize) {
+        for ((column, column_counts), &value) in self.columns.iter().zip(counts.iter_mut()).zip(inputs) {
+            let count = &mut column_counts.bins[column.bin(value)];
+            *count = (*count as isize + increment) as usize;
+            column_counts.sum += increment as f64 * value as f64;
+        }
+    }
+
+    fn scores(&self, counts: &[ColumnCounts], metric: DriftMetric) -> Vec<ColumnDrift> {
+        self.columns.iter().zip(counts).enumerate()
+            .map(|(column_index, (column, counts))| ColumnDrift {
+                column_index,
+                name: column.name.clone(),
+                score: column.score(counts, metric),
+            })
+            .collect()
+    }
+
+}
+
+/// Tracks the inputs seen at inference time over a sliding window and compares their
+/// distribution against the training data, alerting on the columns whose drift score reaches
+/// the threshold.
+#[derive(Clone, Debug)]
+pub struct DriftMonitor {
+    reference: DriftReference,
+    metric: DriftMetric,
+    threshold: f64,
+    window_size: usize,
+    /// Scores are only reported once this many inputs were observed
+    min_observations: usize,
+    window: VecDeque<Vec<Float>>,
+    counts: Vec<ColumnCounts>,
+}
+
+impl DriftMonitor {
+
+    pub fn new(reference: DriftReference, metric: DriftMetric, threshold: f64, window_size: usize) -> Self {
+        assert!(window_size > 0);
+        let counts = reference.new_counts();
+        DriftMonitor {
+            reference,
+            metric,
+            threshold,
+            window_size,
+            min_observations: usize::min(100, window_size),
+            window: VecDeque::with_capacity(window_size),
+            counts,
+        }
+    }
+
+    pub fn with_min_observations(self, min_observations: usize) -> Self {
+        assert!(min_observations <= self.window_size);
+        let mut new = self;
+        new.min_observations = min_observations;
+        new
+    }
+
+    /// Records the inputs of a prediction, evicting the oldest inputs once the window is full
+    pub fn observe(&mut self, inputs: &[Float]) {
+        assert_eq!(inputs.len(), self.reference.columns.len());
+        if self.window.len() == self.window_size {
+            let evicted = self.window.pop_front().unwrap();
+            self.reference.count(&evicted, &mut self.counts, -1);
+        }
+        self.reference.count(inputs, &mut self.counts, 1);
+        self.window.push_back(inputs.to_vec());
+    }
+
+    #[inline]
+    pub fn num_observed(&self) -> usize {
+        self.window.len()
+    }
+
+    #[inline]
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// The drift score of every column, empty until enough inputs were observed
+    pub fn scores(&self) -> Vec<ColumnDrift> {
+        if self.window.len() < self.min_observations {
+            return Vec::new();
+        }
+        self.reference.scores(&self.counts, self.metric)
+    }
+
+    /// The columns whose drift score reaches the threshold
+    pub fn alerts(&self) -> Vec<ColumnDrift> {
+        self.scores().into_iter()
+            .filter(|drift| drift.score >= self.threshold)
+            .collect()
+    }
+
+    /// The largest drift score of any column, or 0 until enough inputs were observed
+    pub fn max_score(&self) -> f64 {
+        self.scores().iter().map(|drift| drift.score).fold(0.0, f64::max)
+    }
+
+    /// Replaces the reference, e.g. after retraining, and clears the window
+    pub fn reset(&mut self, reference: DriftReference) {
+        self.counts = reference.new_counts();
+        self.reference = reference;
+        self.window.clear();
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::lines_2x2;
+
+    #[test]
+    fn test_column_reference() {
+        let column = ColumnReference::new(None, (0..100).map(|i| i as Float).collect(), 4);
+        assert_eq!(column.num_bins(), 4);
+        assert_eq!(column.bin(-1.0), 0);
+        assert_eq!(column.bin(99.0), 3);
+        let uniform = ColumnCounts::new(vec![25, 25, 25, 25], 4950.0);
+        let top = ColumnCounts::new(vec![0, 0, 0, 100], 8700.0);
+        assert!(column.score(&uniform, DriftMetric::PopulationStabilityIndex).abs() < 0.001);
+        assert!(column.score(&uniform, DriftMetric::KolmogorovSmirnov).abs() < 0.02);
+        assert!(column.score(&uniform, DriftMetric::MeanShift).abs() < 0.001);
+        assert!(column.score(&top, DriftMetric::PopulationStabilityIndex) > 1.0);
+        assert!((column.score(&top, DriftMetric::KolmogorovSmirnov) - 0.75).abs() < 0.02);
+        // the mean of 87 is 37.5 above that of 0 to 99, whose std dev is about 28.9
+        assert!((column.score(&top, DriftMetric::MeanShift) - 37.5 / 28.866).abs() < 0.001);
+        // binary columns have a single edge
+        let column = ColumnReference::new(None, vec![0.0, 1.0, 1.0, 0.0], 10);
+        assert_eq!(column.num_bins(), 2);
+        let constant = ColumnReference::new(None, vec![1.0; 4], 10);
+        assert_eq!(constant.score(&ColumnCounts::new(vec![2], 2.0), DriftMetric::MeanShift), 0.0);
+        assert_eq!(constant.score(&ColumnCounts::new(vec![2], 3.0), DriftMetric::MeanShift), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_drift_monitor() {
+
+        let data_set = lines_2x2();
+        let reference = DriftReference::from_data_set(&data_set, 10);
+        assert_eq!(reference.columns().len(), 4);
+
+        let no_drift = reference.compare_data_set(&data_set, DriftMetric::PopulationStabilityIndex);
+        assert!(no_drift.iter().all(|drift| drift.score.abs() < 0.0001));
+
+        let mut monitor = DriftMonitor::new(reference, DriftMetric::PopulationStabilityIndex, 0.25, 20)
+            .with_min_observations(10);
+        for (inputs, _) in &data_set {
+            monitor.observe(inputs);
+        }
+        assert!(monitor.alerts().is_empty());
+
+        // a stream of constant inputs eventually fills the window
+        for _ in 0..20 {
+            monitor.observe(&[1.0, 1.0, 1.0, 1.0]);
+        }
+        assert_eq!(monitor.num_observed(), 20);
+        assert_eq!(monitor.alerts().len(), 4);
+        assert!(monitor.max_score() > 0.25);
+
+        monitor.reset(DriftReference::from_data_set(&data_set, 10));
+        assert_eq!(monitor.num_observed(), 0);
+        assert!(monitor.scores().is_empty());
+
+    }
+
+}
@@ -0,0 +1,285 @@
+use serde::{Serialize, Deserialize};
+
+use crate::Float;
+use crate::net::Net;
+use crate::data::PreparedDataSet;
+use crate::func::ErrorFn;
+use crate::stats::Stats;
+
+/// A set of nets with the same inputs and outputs, whose outputs are combined by a weighted
+/// average. The weights always lie on the probability simplex (non-negative, summing to 1).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ensemble {
+    members: Vec<Net>,
+    weights: Vec<Float>,
+    #[serde(default)]
+    combination: EnsembleCombination,
+}
+
+/// How the outputs of the members of an `Ensemble` are combined
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum EnsembleCombination {
+    /// The weighted average of the outputs
+    #[default]
+    Average,
+    /// Each member votes for the class of its largest output (or, with a single output, for
+    /// the output being above 0.5), and each output is the weighted fraction of votes for it
+    Vote,
+}
+
+impl Ensemble {
+
+    /// Creates an ensemble which averages the outputs of its members uniformly
+    pub fn new(members: Vec<Net>) -> Self {
+        assert!(!members.is_empty());
+        let input_size = members[0].input_size();
+        let output_size = members[0].output_size();
+        for member in &members {
+            assert_eq!(member.input_size(), input_size, "ensemble members must have the same input size");
+            assert_eq!(member.output_size(), output_size, "ensemble members must have the same output size");
+        }
+        let weights = vec![1.0 / members.len() as Float; members.len()];
+        Ensemble {
+            members,
+            weights,
+            combination: EnsembleCombination::Average,
+        }
+    }
+
+    pub fn with_combination(self, combination: EnsembleCombination) -> Self {
+        let mut new = self;
+        new.combination = combination;
+        new
+    }
+
+    #[inline]
+    pub fn combination(&self) -> EnsembleCombination {
+        self.combination
+    }
+
+    #[inline]
+    pub fn members(&self) -> &[Net] {
+        &self.members
+    }
+
+    #[inline]
+    pub fn weights(&self) -> &[Float] {
+        &self.weights
+    }
+
+    pub fn set_weights(&mut self, weights: Vec<Float>) {
+        assert_eq!(weights.len(), self.members.len());
+        assert!(weights.iter().all(|&w| w >= 0.0), "weights must be non-negative");
+        assert!((weights.iter().sum::<Float>() - 1.0).abs() < 0.0001, "weights must sum to 1");
+        self.weights = weights;
+    }
+
+    #[inline]
+    pub fn input_size(&self) -> usize {
+        self.members[0].input_size()
+    }
+
+    #[inline]
+    pub fn output_size(&self) -> usize {
+        self.members[0].output_size()
+    }
+
+    pub fn predict(&self, input: &[Float]) -> Vec<Float> {
+        let mut output = vec![0.0; self.output_size()];
+        for (member, &weight) in self.members.iter().zip(&self.weights) {
+            let prediction = member.predict(input);
+            match self.combination {
+                EnsembleCombination::Average => {
+                    for (o, p) in output.iter_mut().zip(prediction) {
+                        *o += weight * p;
+                    }
+                },
+                EnsembleCombination::Vote if output.len() == 1 => {
+                    if prediction[0] > 0.5 {
+                        output[0] += weight;
+                    }
+                },
+                EnsembleCombination::Vote => {
+                    let vote = (0..prediction.len())
+                        .fold(0, |best, index| if prediction[index] > prediction[best] { index } else { best });
+                    output[vote] += weight;
+                },
+            }
+        }
+        output
+    }
+
+    /// The members share their outputs, so the error is computed like that of a member, see
+    /// `Net::output_error`
+    pub fn compute_error(&self, data_set: &PreparedDataSet, error_fn: &ErrorFn) -> Stats {
+        let mut error_stats = Stats::new();
+        for (inputs, expected_outputs) in data_set {
+            let outputs = self.predict(inputs);
+            error_stats.report(self.members[0].output_error(expected_outputs, &outputs, error_fn));
+        }
+        error_stats
+    }
+
+    /// Learns the weights of the average which minimize the error on the (validation) data set, by
+    /// projected gradient descent on the simplex, starting from the current weights. The member
+    /// predictions are computed once up front, so each iteration is cheap.
+    ///
+    /// Returns the error stats of the ensemble with the learned weights.
+    pub fn optimize_weights(
+        &mut self,
+        data_set: &PreparedDataSet,
+        error_fn: &ErrorFn,
+        iterations: usize,
+        learning_rate: Float,
+    ) -> Stats {
+
+        let num_members = self.members.len();
+        let output_size = self.output_size();
+
+        // predictions[row][member][output], flattened
+        let mut predictions: Vec<Float> = Vec::with_capacity(data_set.num_rows() * num_members * output_size);
+        for (inputs, _) in data_set {
+            for member in &self.members {
+                predictions.extend(member.predict(inputs));
+            }
+        }
+
+        let row_size = num_members * output_size;
+        let mut combined = vec![0.0; output_size];
+        let mut gradient = vec![0.0; num_members];
+
+        for _ in 0..iterations {
+
+            for g in gradient.iter_mut() {
+                *g = 0.0;
+            }
+
+            for ((_, expected_outputs), row) in data_set.iter().zip(predictions.chunks(row_size)) {
+                for c in combined.iter_mut() {
+                    *c = 0.0;
+                }
+                for (member_outputs, &weight) in row.chunks(output_size).zip(&self.weights) {
+                    for (c, &p) in combined.iter_mut().zip(member_outputs) {
+                        *c += weight * p;
+                    }
+                }
+                for (g, member_outputs) in gradient.iter_mut().zip(row.chunks(output_size)) {
+                    for output_index in 0..output_size {
+                        *g += error_fn.get_error_derivative(expected_outputs[output_index], combined[output_index])
+                            * member_outputs[output_index];
+                    }
+                }
+            }
+
+            let num_rows = data_set.num_rows() as Float;
+            for (w, g) in self.weights.iter_mut().zip(&gradient) {
+                *w -= learning_rate * g / num_rows;
+            }
+            project_onto_simplex(&mut self.weights);
+        }
+
+        self.compute_error(data_set, error_fn)
+    }
+
+}
+
+/// Euclidean projection onto the probability simplex, see
+/// "Efficient Projections onto the l1-Ball for Learning in High Dimensions" (Duchi et al., 2008)
+fn project_onto_simplex(values: &mut [Float]) {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    let mut cumulative_sum = 0.0;
+    let mut theta = 0.0;
+    for (i, &value) in sorted.iter().enumerate() {
+        cumulative_sum += value;
+        let t = (cumulative_sum - 1.0) / (i + 1) as Float;
+        if value - t > 0.0 {
+            theta = t;
+        }
+    }
+    for value in values.iter_mut() {
+        *value = Float::max(*value - theta, 0.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::lines_2x2;
+    use crate::net::NetConfig;
+    use crate::func::ActivationFn;
+    use crate::initializer::RandomNetInitializer;
+
+    #[test]
+    fn test_project_onto_simplex() {
+        let mut values = [0.5, 0.5];
+        project_onto_simplex(&mut values);
+        assert_eq!(values, [0.5, 0.5]);
+        let mut values = [2.0, 0.0, -1.0];
+        project_onto_simplex(&mut values);
+        assert_eq!(values, [1.0, 0.0, 0.0]);
+        let mut values = [0.6, 0.6, 0.0];
+        project_onto_simplex(&mut values);
+        assert!((values[0] - 0.5).abs() < 0.0001 && (values[1] - 0.5).abs() < 0.0001 && values[2] == 0.0);
+    }
+
+    #[test]
+    fn test_optimize_weights() {
+
+        let data_set = lines_2x2();
+
+        let config = NetConfig::new_fully_connected(4, 2, [4], ActivationFn::standard_logistic_sigmoid());
+        let mut initializer = RandomNetInitializer::new_standard_with_seed("ensemble");
+
+        let mut trained = config.create_net();
+        trained.initialize_weights(&mut initializer);
+        {
+            let mut context = trained.get_training_context();
+            for _ in 0..2000 {
+                context.train_backprop_single_batch(&data_set, 2.0, &ErrorFn::SquaredError, None, None);
+            }
+        }
+
+        let mut untrained = config.create_net();
+        untrained.initialize_weights(&mut initializer);
+
+        let mut ensemble = Ensemble::new(vec![untrained, trained]);
+        assert_eq!(ensemble.weights(), &[0.5, 0.5]);
+        let uniform_error = ensemble.compute_error(&data_set, &ErrorFn::SquaredError);
+
+        let optimized_error = ensemble.optimize_weights(&data_set, &ErrorFn::SquaredError, 200, 1.0);
+
+        assert!(optimized_error.mean() < uniform_error.mean());
+        assert!(ensemble.weights()[1] > 0.9);
+        assert!((ensemble.weights().iter().sum::<Float>() - 1.0).abs() < 0.0001);
+
+    }
+
+    #[test]
+    fn test_vote() {
+
+        let config = NetConfig::new_fully_connected(4, 2, [4], ActivationFn::standard_logistic_sigmoid());
+        let mut initializer = RandomNetInitializer::new_standard_with_seed("vote");
+        let members: Vec<Net> = (0..3)
+            .map(|_| {
+                let mut net = config.create_net();
+                net.initialize_weights(&mut initializer);
+                net
+            })
+            .collect();
+        let input = [1.0, 0.0, 1.0, 0.0];
+        let votes: Vec<usize> = members.iter()
+            .map(|net| {
+                let output = net.predict(&input);
+                if output[1] > output[0] { 1 } else { 0 }
+            })
+            .collect();
+
+        let ensemble = Ensemble::new(members).with_combination(EnsembleCombination::Vote);
+        let predicted = ensemble.predict(&input);
+        let expected_for_second = votes.iter().sum::<usize>() as Float / 3.0;
+        assert!((predicted[1] - expected_for_second).abs() < 1e-5 && (predicted[0] + predicted[1] - 1.0).abs() < 1e-5, "{:?}", predicted);
+
+    }
+
+}
@@ -0,0 +1,39 @@
+//! Data sets and options shared by the tests of the crate
+
+use crate::data::PreparedDataSet;
+use crate::func::{CompletionFn, ErrorFn, LearningRateFn, MiniBatchSize};
+use crate::train::BackpropOptions;
+
+/// The 11 rows of `data/2x2_lines_binary.csv`, 2x2 images labelled with whether they hold a
+/// horizontal and a vertical line
+pub(crate) fn lines_2x2() -> PreparedDataSet {
+    PreparedDataSet::from_csv(
+        "data/2x2_lines_binary.csv",
+        ["0_0", "0_1", "1_0", "1_1"],
+        ["has_horizontal", "has_vertical"]
+    ).unwrap()
+}
+
+/// Full mini-batches with a constant learning rate of 0.5 and the squared error for 100 epochs,
+/// on a single thread and without any of the optional extras, for tests to override the fields
+/// they're about
+pub(crate) fn backprop_options() -> BackpropOptions {
+    BackpropOptions {
+        completion_fn: CompletionFn::stop_after_epoch(100),
+        mini_batch_size_fn: MiniBatchSize::Full,
+        learning_rate_fn: LearningRateFn::Constant(0.5),
+        error_fn: ErrorFn::SquaredError,
+        gradient_noise_fn: None,
+        gradient_noise_layer_scales: Vec::new(),
+        shuffle: false,
+        multi_threading: None,
+        validation_set: None,
+        update_interval: None,
+        check_divergence: false,
+        seed: None,
+        track_norms: false,
+        ema_decay: None,
+        mixed_precision: None,
+        batch_threads: None,
+    }
+}
@@ -1,15 +1,16 @@
-use crate::utils::square_f32;
+use crate::Float;
+use crate::utils::square;
+use serde::{Serialize, Deserialize};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ActivationFn {
     LogisticSigmoid {
-        steepness: f32,
-        scale: f32,
-        y_offset: f32
+        steepness: Float,
+        scale: Float,
+        y_offset: Float
     }
 }
 
-#[allow(dead_code)]
 impl ActivationFn {
 
     pub fn standard_logistic_sigmoid() -> Self {
@@ -28,21 +29,48 @@ impl ActivationFn {
         }
     }
 
-    pub fn get_activation(&self, n: f32) -> f32 {
+    pub fn get_activation(&self, n: Float) -> Float {
         match self {
             &ActivationFn::LogisticSigmoid { steepness, scale, y_offset } => {
-                scale / (1.0 + f32::exp(-steepness * n)) + y_offset
+                scale / (1.0 + Float::exp(-steepness * n)) + y_offset
             },
         }
     }
 
-    pub fn get_activation_derivative(&self, n: f32) -> f32 {
+    pub fn get_activation_derivative(&self, n: Float) -> Float {
         match self {
             &ActivationFn::LogisticSigmoid { steepness, scale, y_offset: _ } => {
-                let z = f32::exp(-steepness * n);
-                scale * steepness * z / square_f32(z + 1.0)
+                let z = Float::exp(-steepness * n);
+                scale * steepness * z / square(z + 1.0)
             },
         }
     }
 
-}
\ No newline at end of file
+    /// The derivative at the input which produced the given activation `output`, for use
+    /// during backprop where only the outputs of a layer are retained.
+    pub fn get_activation_derivative_at_output(&self, output: Float) -> Float {
+        match self {
+            &ActivationFn::LogisticSigmoid { steepness, scale, y_offset } => {
+                let sigmoid = (output - y_offset) / scale;
+                scale * steepness * sigmoid * (1.0 - sigmoid)
+            },
+        }
+    }
+
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_derivative_at_output() {
+        for activation_fn in &[ActivationFn::standard_logistic_sigmoid(), ActivationFn::standard_logistic_sigmoid_neg()] {
+            for &n in &[-2.0, -0.5, 0.0, 0.3, 1.5] {
+                let output = activation_fn.get_activation(n);
+                let expected = activation_fn.get_activation_derivative(n);
+                assert!((activation_fn.get_activation_derivative_at_output(output) - expected).abs() < 0.0001);
+            }
+        }
+    }
+
+}
@@ -1,46 +1,350 @@
+use crate::Float;
 use crate::stats::Stats;
+use std::fmt;
+use std::sync::Arc;
 use std::time::{SystemTime, Duration};
 
-#[derive(Clone, Copy, Debug)]
-pub struct CompletionFn {
-    max_epoch: Option<usize>,
-    max_duration: Option<Duration>,
-    target_avg_error: f32,
+/// Determines when a training stage (or the trainer as a whole) should stop.
+///
+/// Criteria can be combined with `all` and `any`, e.g. "stop after 10 minutes, or once the error
+/// is below 0.01 and at least 100 epochs have passed":
+///
+/// ```ignore
+/// CompletionFn::any(vec![
+///     CompletionFn::stop_after_duration(Duration::from_secs(600)),
+///     CompletionFn::all(vec![
+///         CompletionFn::stop_at_avg_error(0.01),
+///         CompletionFn::stop_after_epoch(100),
+///     ]),
+/// ])
+/// ```
+#[derive(Clone, Debug)]
+pub enum CompletionFn {
+    /// Stops after this many passes over the training data
+    MaxEpoch(usize),
+    /// Stops after this many mini-batch weight updates
+    MaxBatch(usize),
+    MaxDuration(Duration),
+    TargetAvgError(Float),
+    /// Stops once the fraction of rows for which every output, thresholded at `threshold`,
+    /// matches the thresholded expected output reaches `accuracy`
+    TargetAccuracy {
+        accuracy: Float,
+        threshold: Float,
+    },
+    /// Stops once the callback, which receives the epoch, the elapsed time and the error stats,
+    /// returns true. Allows external systems to decide when training should stop.
+    Custom(CompletionCallback),
+    /// Stops once every contained criterion is met. Never stops if empty.
+    All(Vec<CompletionFn>),
+    /// Stops as soon as any contained criterion is met. Never stops if empty.
+    Any(Vec<CompletionFn>),
 }
 
 impl CompletionFn {
 
     pub fn stop_after_epoch(epoch: usize) -> Self {
-        CompletionFn {
-            max_epoch: Some(epoch),
-            max_duration: None,
-            target_avg_error: 0.0
-        }
+        CompletionFn::MaxEpoch(epoch)
+    }
+
+    pub fn stop_after_batch(batch: usize) -> Self {
+        CompletionFn::MaxBatch(batch)
     }
 
     pub fn stop_after_duration(duration: Duration) -> Self {
-        CompletionFn {
-            max_epoch: None,
-            max_duration: Some(duration),
-            target_avg_error: 0.0
+        CompletionFn::MaxDuration(duration)
+    }
+
+    pub fn stop_at_avg_error(target_avg_error: Float) -> Self {
+        CompletionFn::TargetAvgError(target_avg_error)
+    }
+
+    pub fn stop_at_accuracy(accuracy: Float, threshold: Float) -> Self {
+        CompletionFn::TargetAccuracy {
+            accuracy,
+            threshold,
         }
     }
 
-    pub fn should_stop_training(&self, epoch: usize, start_time: SystemTime, error_stats: &Stats) -> bool {
-        if self.target_avg_error as f64 >= error_stats.mean() {
-            return true;
+    pub fn builder() -> CompletionFnBuilder {
+        CompletionFnBuilder::default()
+    }
+
+    pub fn custom<F>(callback: F) -> Self
+        where F: Fn(usize, Duration, &Stats) -> bool + Send + Sync + 'static
+    {
+        CompletionFn::Custom(CompletionCallback(Arc::new(callback)))
+    }
+
+    pub fn all(criteria: impl Into<Vec<CompletionFn>>) -> Self {
+        CompletionFn::All(criteria.into())
+    }
+
+    pub fn any(criteria: impl Into<Vec<CompletionFn>>) -> Self {
+        CompletionFn::Any(criteria.into())
+    }
+
+    /// The first threshold at which accuracy stats are computed, if any criterion depends on them.
+    pub fn accuracy_threshold(&self) -> Option<Float> {
+        self.accuracy_thresholds().first().copied()
+    }
+
+    /// Every distinct threshold of the accuracy criteria, in order, at which accuracy stats must
+    /// be computed for `should_stop_training`.
+    pub fn accuracy_thresholds(&self) -> Vec<Float> {
+        let mut thresholds = Vec::new();
+        self.collect_accuracy_thresholds(&mut thresholds);
+        thresholds
+    }
+
+    fn collect_accuracy_thresholds(&self, thresholds: &mut Vec<Float>) {
+        match self {
+            &CompletionFn::TargetAccuracy { accuracy: _, threshold } => {
+                if !thresholds.contains(&threshold) {
+                    thresholds.push(threshold);
+                }
+            },
+            CompletionFn::All(criteria) | CompletionFn::Any(criteria) => {
+                criteria.iter().for_each(|c| c.collect_accuracy_thresholds(thresholds));
+            },
+            _ => {},
         }
-        if let Some(max_batch_count) = self.max_epoch {
-            if max_batch_count - 1 <= epoch {
-                return true;
-            }
+    }
+
+    /// `epoch` is the index of the pass over the training data which just completed, and `batch`
+    /// the number of mini-batches trained so far. `accuracies` pairs each of the
+    /// `accuracy_thresholds` with stats holding a value of 1.0 for every correctly classified row
+    /// and 0.0 otherwise. An accuracy criterion whose threshold is missing is never met.
+    pub fn should_stop_training(&self, epoch: usize, batch: usize, start_time: SystemTime, error_stats: &Stats, accuracies: &[(Float, Stats)]) -> bool {
+        match self {
+            &CompletionFn::MaxEpoch(max_epoch) => max_epoch <= epoch + 1,
+            &CompletionFn::MaxBatch(max_batch) => max_batch <= batch,
+            &CompletionFn::MaxDuration(max_duration) => {
+                max_duration <= SystemTime::now().duration_since(start_time).unwrap_or(max_duration)
+            },
+            &CompletionFn::TargetAvgError(target_avg_error) => target_avg_error as f64 >= error_stats.mean(),
+            &CompletionFn::TargetAccuracy { accuracy, threshold } => {
+                accuracies.iter()
+                    .find(|(t, _)| *t == threshold)
+                    .is_some_and(|(_, stats)| stats.mean() >= accuracy as f64)
+            },
+            CompletionFn::Custom(callback) => {
+                let elapsed = SystemTime::now().duration_since(start_time).unwrap_or_default();
+                (callback.0)(epoch, elapsed, error_stats)
+            },
+            CompletionFn::All(criteria) => {
+                !criteria.is_empty() && criteria.iter()
+                    .all(|c| c.should_stop_training(epoch, batch, start_time, error_stats, accuracies))
+            },
+            CompletionFn::Any(criteria) => {
+                criteria.iter()
+                    .any(|c| c.should_stop_training(epoch, batch, start_time, error_stats, accuracies))
+            },
+        }
+    }
+
+}
+
+type CompletionCallbackFn = dyn Fn(usize, Duration, &Stats) -> bool + Send + Sync;
+
+#[derive(Clone)]
+pub struct CompletionCallback(Arc<CompletionCallbackFn>);
+
+impl fmt::Debug for CompletionCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str("CompletionCallback")
+    }
+}
+
+/// Builds a `CompletionFn` that stops as soon as any of the configured criteria is met.
+///
+/// ```ignore
+/// CompletionFn::builder()
+///     .max_epoch(1000)
+///     .max_duration(Duration::from_secs(60))
+///     .target_avg_error(0.01)
+///     .build()?
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CompletionFnBuilder {
+    max_epoch: Option<usize>,
+    max_batch: Option<usize>,
+    max_duration: Option<Duration>,
+    target_avg_error: Option<Float>,
+    target_accuracy: Option<(Float, Float)>,
+}
+
+impl CompletionFnBuilder {
+
+    pub fn max_epoch(self, max_epoch: usize) -> Self {
+        let mut new = self;
+        new.max_epoch = Some(max_epoch);
+        new
+    }
+
+    pub fn max_batch(self, max_batch: usize) -> Self {
+        let mut new = self;
+        new.max_batch = Some(max_batch);
+        new
+    }
+
+    pub fn max_duration(self, max_duration: Duration) -> Self {
+        let mut new = self;
+        new.max_duration = Some(max_duration);
+        new
+    }
+
+    pub fn target_avg_error(self, target_avg_error: Float) -> Self {
+        let mut new = self;
+        new.target_avg_error = Some(target_avg_error);
+        new
+    }
+
+    pub fn target_accuracy(self, accuracy: Float, threshold: Float) -> Self {
+        let mut new = self;
+        new.target_accuracy = Some((accuracy, threshold));
+        new
+    }
+
+    /// Fails if no criterion was configured, since the result would never stop training.
+    pub fn build(self) -> Result<CompletionFn, String> {
+        let mut criteria = Vec::new();
+        if let Some(max_epoch) = self.max_epoch {
+            criteria.push(CompletionFn::stop_after_epoch(max_epoch));
+        }
+        if let Some(max_batch) = self.max_batch {
+            criteria.push(CompletionFn::stop_after_batch(max_batch));
         }
         if let Some(max_duration) = self.max_duration {
-            if max_duration <= SystemTime::now().duration_since(start_time).unwrap_or(max_duration) {
-                return true
-            }
+            criteria.push(CompletionFn::stop_after_duration(max_duration));
+        }
+        if let Some(target_avg_error) = self.target_avg_error {
+            criteria.push(CompletionFn::stop_at_avg_error(target_avg_error));
+        }
+        if let Some((accuracy, threshold)) = self.target_accuracy {
+            criteria.push(CompletionFn::stop_at_accuracy(accuracy, threshold));
+        }
+        match criteria.len() {
+            0 => Err("At least one completion criterion must be set".to_string()),
+            1 => Ok(criteria.pop().unwrap()),
+            _ => Ok(CompletionFn::Any(criteria)),
         }
-        false
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stats_with_mean(mean: Float) -> Stats {
+        let mut stats = Stats::new();
+        stats.report(mean);
+        stats
+    }
+
+    #[test]
+    fn test_single_criteria() {
+        let now = SystemTime::now();
+        let stats = stats_with_mean(0.5);
+        assert!(!CompletionFn::stop_after_epoch(10).should_stop_training(8, 0, now, &stats, &[]));
+        assert!(CompletionFn::stop_after_epoch(10).should_stop_training(9, 0, now, &stats, &[]));
+        assert!(!CompletionFn::stop_after_batch(40).should_stop_training(9, 39, now, &stats, &[]));
+        assert!(CompletionFn::stop_after_batch(40).should_stop_training(9, 40, now, &stats, &[]));
+        assert!(CompletionFn::stop_after_duration(Duration::from_secs(0)).should_stop_training(0, 0, now, &stats, &[]));
+        assert!(!CompletionFn::stop_after_duration(Duration::from_secs(600)).should_stop_training(0, 0, now, &stats, &[]));
+        assert!(CompletionFn::stop_at_avg_error(0.5).should_stop_training(0, 0, now, &stats, &[]));
+        assert!(!CompletionFn::stop_at_avg_error(0.1).should_stop_training(0, 0, now, &stats, &[]));
+        assert!(!CompletionFn::stop_at_avg_error(0.1).should_stop_training(0, 0, now, &Stats::new(), &[]));
+    }
+
+    #[test]
+    fn test_composite_criteria() {
+        let now = SystemTime::now();
+        let completion_fn = CompletionFn::any(vec![
+            CompletionFn::stop_after_duration(Duration::from_secs(600)),
+            CompletionFn::all(vec![
+                CompletionFn::stop_at_avg_error(0.01),
+                CompletionFn::stop_after_epoch(100),
+            ]),
+        ]);
+        assert!(!completion_fn.should_stop_training(200, 0, now, &stats_with_mean(0.5), &[]));
+        assert!(!completion_fn.should_stop_training(10, 0, now, &stats_with_mean(0.001), &[]));
+        assert!(completion_fn.should_stop_training(200, 0, now, &stats_with_mean(0.001), &[]));
+        assert!(!CompletionFn::all(vec![]).should_stop_training(0, 0, now, &stats_with_mean(0.0), &[]));
+        assert!(!CompletionFn::any(vec![]).should_stop_training(0, 0, now, &stats_with_mean(0.0), &[]));
+    }
+
+    #[test]
+    fn test_accuracy_criteria() {
+        let now = SystemTime::now();
+        let completion_fn = CompletionFn::any(vec![
+            CompletionFn::stop_after_epoch(100),
+            CompletionFn::stop_at_accuracy(0.75, 0.5),
+        ]);
+        assert_eq!(completion_fn.accuracy_threshold(), Some(0.5));
+        assert_eq!(CompletionFn::stop_after_epoch(100).accuracy_threshold(), None);
+        let error_stats = stats_with_mean(0.5);
+        let mut accuracy_stats = Stats::new();
+        accuracy_stats.report(1.0);
+        accuracy_stats.report(0.0);
+        assert!(!completion_fn.should_stop_training(0, 0, now, &error_stats, &[]));
+        assert!(!completion_fn.should_stop_training(0, 0, now, &error_stats, &[(0.5, accuracy_stats.clone())]));
+        accuracy_stats.report(1.0);
+        accuracy_stats.report(1.0);
+        assert!(completion_fn.should_stop_training(0, 0, now, &error_stats, &[(0.5, accuracy_stats.clone())]));
+        // accuracy at another threshold doesn't count
+        assert!(!completion_fn.should_stop_training(0, 0, now, &error_stats, &[(0.7, accuracy_stats)]));
+    }
+
+    #[test]
+    fn test_accuracy_criteria_with_different_thresholds() {
+        let now = SystemTime::now();
+        let completion_fn = CompletionFn::all(vec![
+            CompletionFn::stop_at_accuracy(0.75, 0.5),
+            CompletionFn::any(vec![
+                CompletionFn::stop_at_accuracy(0.9, 0.8),
+                CompletionFn::stop_at_accuracy(0.5, 0.5),
+            ]),
+        ]);
+        assert_eq!(completion_fn.accuracy_thresholds(), vec![0.5, 0.8]);
+        let error_stats = stats_with_mean(0.5);
+        let accurate = stats_with_mean(1.0);
+        let inaccurate = stats_with_mean(0.0);
+        assert!(completion_fn.should_stop_training(0, 0, now, &error_stats, &[(0.5, accurate.clone()), (0.8, inaccurate.clone())]));
+        assert!(!completion_fn.should_stop_training(0, 0, now, &error_stats, &[(0.5, inaccurate.clone()), (0.8, accurate.clone())]));
+        assert!(!completion_fn.should_stop_training(0, 0, now, &error_stats, &[(0.8, accurate)]));
+    }
+
+    #[test]
+    fn test_builder() {
+        let now = SystemTime::now();
+        assert!(CompletionFn::builder().build().is_err());
+        let completion_fn = CompletionFn::builder()
+            .max_epoch(100)
+            .max_duration(Duration::from_secs(600))
+            .target_avg_error(0.01)
+            .build()
+            .unwrap();
+        assert!(!completion_fn.should_stop_training(10, 0, now, &stats_with_mean(0.5), &[]));
+        assert!(completion_fn.should_stop_training(99, 0, now, &stats_with_mean(0.5), &[]));
+        assert!(completion_fn.should_stop_training(10, 0, now, &stats_with_mean(0.001), &[]));
+        match CompletionFn::builder().target_avg_error(0.01).build().unwrap() {
+            CompletionFn::TargetAvgError(error) => assert_eq!(error, 0.01),
+            other => panic!("unexpected completion fn {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_custom_criteria() {
+        let now = SystemTime::now();
+        let completion_fn = CompletionFn::custom(|epoch, elapsed, error_stats| {
+            epoch >= 5 && elapsed < Duration::from_secs(600) && error_stats.count() > 0
+        });
+        assert!(!completion_fn.should_stop_training(4, 0, now, &stats_with_mean(0.5), &[]));
+        assert!(!completion_fn.should_stop_training(5, 0, now, &Stats::new(), &[]));
+        assert!(completion_fn.clone().should_stop_training(5, 0, now, &stats_with_mean(0.5), &[]));
+        assert_eq!(format!("{:?}", completion_fn), "Custom(CompletionCallback)");
+    }
+
+}
@@ -1,20 +1,40 @@
-use crate::utils::square_f32;
+use crate::Float;
+use crate::utils::square;
+use serde::{Serialize, Deserialize};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ErrorFn {
     SquaredError,
-    // TOOD: cross-entropy loss?
+    /// Categorical cross-entropy, for outputs which are class probabilities. Prefer a
+    /// `SoftmaxCrossEntropy` output layer to composing this with a sigmoid output.
+    CrossEntropy,
+    /// The cross-entropy of each output as the probability of a binary outcome, for sigmoid
+    /// outputs. Also suits soft targets, e.g. for distillation.
+    BinaryCrossEntropy,
 }
 
+/// Lower bound for probabilities passed to the logarithm
+const MIN_PROBABILITY: Float = 1e-7;
+
 impl ErrorFn {
-    pub fn get_error(&self, expected: f32, actual: f32) -> f32 {
+    pub fn get_error(&self, expected: Float, actual: Float) -> Float {
         match self {
-            ErrorFn::SquaredError => 0.5 * square_f32(expected - actual),
+            ErrorFn::SquaredError => 0.5 * square(expected - actual),
+            ErrorFn::CrossEntropy => -expected * actual.max(MIN_PROBABILITY).ln(),
+            ErrorFn::BinaryCrossEntropy => {
+                let actual = actual.clamp(MIN_PROBABILITY, 1.0 - MIN_PROBABILITY);
+                -expected * actual.ln() - (1.0 - expected) * (1.0 - actual).ln()
+            },
         }
     }
-    pub fn get_error_derivative(&self, expected: f32, actual: f32) -> f32 {
+    pub fn get_error_derivative(&self, expected: Float, actual: Float) -> Float {
         match self {
             ErrorFn::SquaredError => actual - expected,
+            ErrorFn::CrossEntropy => -expected / actual.max(MIN_PROBABILITY),
+            ErrorFn::BinaryCrossEntropy => {
+                let actual = actual.clamp(MIN_PROBABILITY, 1.0 - MIN_PROBABILITY);
+                (actual - expected) / (actual * (1.0 - actual))
+            },
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+use crate::Float;
+
+/// Annealed gaussian noise added to the gradients of every weight update, as described in
+/// "Adding Gradient Noise Improves Learning for Very Deep Networks" (Neelakantan et al., 2015).
+#[derive(Clone, Copy, Debug)]
+pub enum GradientNoiseFn {
+    /// Noise with variance `eta / (1 + batch_num)^gamma`
+    Annealed {
+        eta: Float,
+        gamma: Float,
+    }
+}
+
+impl GradientNoiseFn {
+
+    /// The schedule recommended in the paper, with eta = 0.01 and gamma = 0.55
+    pub fn standard_annealed() -> Self {
+        GradientNoiseFn::Annealed {
+            eta: 0.01,
+            gamma: 0.55,
+        }
+    }
+
+    pub fn get_std_dev(&self, batch_num: usize) -> Float {
+        match self {
+            &GradientNoiseFn::Annealed { eta, gamma } => {
+                Float::sqrt(eta / Float::powf(1.0 + batch_num as Float, gamma))
+            },
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_annealed_std_dev() {
+        let noise_fn = GradientNoiseFn::Annealed { eta: 1.0, gamma: 1.0 };
+        assert_eq!(noise_fn.get_std_dev(0), 1.0);
+        assert_eq!(noise_fn.get_std_dev(3), 0.5);
+        let noise_fn = GradientNoiseFn::standard_annealed();
+        assert!(noise_fn.get_std_dev(10) < noise_fn.get_std_dev(1));
+    }
+
+}
@@ -1,19 +1,26 @@
-#[allow(dead_code)]
+use crate::Float;
+
+/// fast_math only approximates the f32 logarithm
+#[allow(clippy::unnecessary_cast)]
+fn fast_log2(value: Float) -> Float {
+    fast_math::log2(value as f32) as Float
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum LearningRateFn {
-    Constant(f32),
+    Constant(Float),
     TanhLogarithmicDescent {
-        constant_factor: f32,
-        log_of_log_base: f32,
-        scale: f32,
+        constant_factor: Float,
+        log_of_log_base: Float,
+        scale: Float,
     }
 }
 
 impl LearningRateFn {
 
-    pub fn tanh_logarithmic_descent(log_base: f32, scale: f32) -> Self {
-        let log_of_log_base = fast_math::log2(log_base);
-        let constant_factor = 1.0 / (1.0 - f32::tanh(fast_math::log2(1.0) / log_of_log_base as f32));
+    pub fn tanh_logarithmic_descent(log_base: Float, scale: Float) -> Self {
+        let log_of_log_base = fast_log2(log_base);
+        let constant_factor = 1.0 / (1.0 - Float::tanh(fast_log2(1.0) / log_of_log_base));
         LearningRateFn::TanhLogarithmicDescent {
             constant_factor,
             log_of_log_base,
@@ -25,11 +32,11 @@ impl LearningRateFn {
         Self::tanh_logarithmic_descent(100.0, 1.0)
     }
 
-    pub fn get_learning_rate(&self, batch_num: usize) -> f32 {
+    pub fn get_learning_rate(&self, batch_num: usize) -> Float {
         match self {
             &LearningRateFn::Constant(val) => val,
             &LearningRateFn::TanhLogarithmicDescent { constant_factor, log_of_log_base, scale } => {
-                scale * (1.0 - f32::tanh(fast_math::log2(batch_num as f32 + 1.0) as f32 / log_of_log_base) * constant_factor)
+                scale * (1.0 - Float::tanh(fast_log2(batch_num as Float + 1.0) / log_of_log_base) * constant_factor)
             },
         }
     }
@@ -1,27 +1,47 @@
 use std::num::NonZeroU32;
+use crate::Float;
 
-#[allow(dead_code)]
 #[derive(Clone, Copy, Debug)]
 pub enum MiniBatchSize {
     Full,
     Constant(NonZeroU32),
     Linear {
         initial: u32,
-        slope: f32,
+        slope: Float,
         max: u32,
     },
+    /// A fraction of the number of rows in the data set being trained on, at least 1 row
+    Fraction(Float),
 }
 
 impl MiniBatchSize {
-    pub fn get_mini_batch_size(&self, batch_num: usize) -> Option<NonZeroU32> {
+    pub fn get_mini_batch_size(&self, batch_num: usize, num_rows: usize) -> Option<NonZeroU32> {
         match self {
             MiniBatchSize::Full => None,
             &MiniBatchSize::Constant(val) => Some(val),
             &MiniBatchSize::Linear { initial, slope, max } => {
-                let val = initial + (slope * batch_num as f32) as u32;
+                let val = initial + (slope * batch_num as Float) as u32;
                 NonZeroU32::new(if val > max { max } else { val })
             },
+            &MiniBatchSize::Fraction(fraction) => {
+                debug_assert!(fraction > 0.0 && fraction <= 1.0);
+                let val = (fraction * num_rows as Float).round() as u32;
+                NonZeroU32::new(u32::max(val, 1))
+            },
         }
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fraction() {
+        assert_eq!(MiniBatchSize::Fraction(0.25).get_mini_batch_size(0, 100), NonZeroU32::new(25));
+        assert_eq!(MiniBatchSize::Fraction(0.25).get_mini_batch_size(10, 10), NonZeroU32::new(3));
+        assert_eq!(MiniBatchSize::Fraction(0.01).get_mini_batch_size(0, 10), NonZeroU32::new(1));
+        assert_eq!(MiniBatchSize::Full.get_mini_batch_size(0, 10), None);
+    }
+
+}
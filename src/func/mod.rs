@@ -3,6 +3,7 @@ mod error;
 mod learningrate;
 mod completion;
 mod minibatch;
+mod gradientnoise;
 
 pub use self::{
     activation::*,
@@ -10,4 +11,5 @@ pub use self::{
     completion::*,
     minibatch::*,
     learningrate::*,
+    gradientnoise::*,
 };
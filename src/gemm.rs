@@ -0,0 +1,165 @@
+//! Dense matrix products of row-major matrices, for the batched passes of fully connected
+//! layers. Each output value is summed in the same order as the per-row loops of the layers, so
+//! batched and per-row training give identical results.
+//!
+//! With the `matrixmultiply` feature, large products are handed to the blocked kernels of the
+//! matrixmultiply crate instead. Those sum in their own order, so the results differ from the
+//! per-row loops in the last bits.
+
+use crate::Float;
+use crate::simd::axpy;
+
+/// The number of rows of `a` multiplied at once, so each row of `b` is loaded once per block
+const ROW_BLOCK: usize = 4;
+
+/// Products of at least this many multiply-adds go through matrixmultiply, smaller ones aren't
+/// worth the packing
+#[cfg(feature = "matrixmultiply")]
+const BLOCKED_MIN_OPS: usize = 32 * 32 * 32;
+
+/// `c = a' * b' + beta * c` of row-major matrices, where `a'` (`m x k`) and `b'` (`k x n`) are
+/// `a` and `b`, or their transposes
+#[cfg(feature = "matrixmultiply")]
+#[allow(clippy::too_many_arguments)]
+fn blocked_gemm(trans_a: bool, trans_b: bool, m: usize, n: usize, k: usize, a: &[Float], b: &[Float], beta: Float, c: &mut [Float]) {
+    assert!(a.len() == m * k && b.len() == k * n && c.len() == m * n);
+    let (rsa, csa) = if trans_a { (1, m as isize) } else { (k as isize, 1) };
+    let (rsb, csb) = if trans_b { (1, k as isize) } else { (n as isize, 1) };
+    #[cfg(not(feature = "f64"))]
+    use matrixmultiply::sgemm as gemm;
+    #[cfg(feature = "f64")]
+    use matrixmultiply::dgemm as gemm;
+    // the strides describe exactly the matrices held by the slices
+    unsafe {
+        gemm(m, k, n, 1.0, a.as_ptr(), rsa, csa, b.as_ptr(), rsb, csb, beta, c.as_mut_ptr(), n as isize, 1);
+    }
+}
+
+/// `c += a * b`, where `a` is `m x k`, `b` is `k x n` and `c` is `m x n`
+pub fn gemm_acc(a: &[Float], b: &[Float], c: &mut [Float], m: usize, k: usize, n: usize) {
+    assert!(a.len() == m * k && b.len() == k * n && c.len() == m * n);
+    #[cfg(feature = "matrixmultiply")]
+    if m * k * n >= BLOCKED_MIN_OPS {
+        return blocked_gemm(false, false, m, n, k, a, b, 1.0, c);
+    }
+    let mut a_rows = a.chunks_exact(k.max(1));
+    let mut c_blocks = c.chunks_mut(ROW_BLOCK * n.max(1));
+    for _ in (0..m).step_by(ROW_BLOCK) {
+        let c_block = c_blocks.next().unwrap();
+        let rows = c_block.len() / n.max(1);
+        if rows == ROW_BLOCK && n > 0 {
+            let (c0, rest) = c_block.split_at_mut(n);
+            let (c1, rest) = rest.split_at_mut(n);
+            let (c2, c3) = rest.split_at_mut(n);
+            let (a0, a1, a2, a3) = (a_rows.next().unwrap(), a_rows.next().unwrap(), a_rows.next().unwrap(), a_rows.next().unwrap());
+            for (index, b_row) in b.chunks_exact(n).enumerate() {
+                axpy(a0[index], b_row, c0);
+                axpy(a1[index], b_row, c1);
+                axpy(a2[index], b_row, c2);
+                axpy(a3[index], b_row, c3);
+            }
+        } else {
+            for c_row in c_block.chunks_exact_mut(n.max(1)).take(rows) {
+                let a_row = a_rows.next().unwrap();
+                for (&x, b_row) in a_row.iter().zip(b.chunks_exact(n.max(1))) {
+                    axpy(x, b_row, c_row);
+                }
+            }
+        }
+    }
+}
+
+/// `c += transpose(a) * b`, where `a` is `m x k`, `b` is `m x n` and `c` is `k x n`. The rows
+/// of `a` and `b` are added in order.
+pub fn gemm_at_acc(a: &[Float], b: &[Float], c: &mut [Float], m: usize, k: usize, n: usize) {
+    assert!(a.len() == m * k && b.len() == m * n && c.len() == k * n);
+    #[cfg(feature = "matrixmultiply")]
+    if m * k * n >= BLOCKED_MIN_OPS {
+        return blocked_gemm(true, false, k, n, m, a, b, 1.0, c);
+    }
+    if k == 0 || n == 0 {
+        return;
+    }
+    let a_rows: Vec<&[Float]> = a.chunks_exact(k).collect();
+    let b_rows: Vec<&[Float]> = b.chunks_exact(n).collect();
+    for (a_block, b_block) in a_rows.chunks(ROW_BLOCK).zip(b_rows.chunks(ROW_BLOCK)) {
+        // each row of c stays in cache while the rows of the block are added to it
+        for (index, c_row) in c.chunks_exact_mut(n).enumerate() {
+            for (a_row, b_row) in a_block.iter().zip(b_block) {
+                axpy(a_row[index], b_row, c_row);
+            }
+        }
+    }
+}
+
+/// `c = a * transpose(b)`, where `a` is `m x n`, `b` is `k x n` and `c` is `m x k`
+pub fn gemm_bt(a: &[Float], b: &[Float], c: &mut [Float], m: usize, n: usize, k: usize) {
+    assert!(a.len() == m * n && b.len() == k * n && c.len() == m * k);
+    #[cfg(feature = "matrixmultiply")]
+    if m * k * n >= BLOCKED_MIN_OPS {
+        return blocked_gemm(false, true, m, k, n, a, b, 0.0, c);
+    }
+    if k == 0 {
+        return;
+    }
+    for (a_row, c_row) in a.chunks_exact(n.max(1)).zip(c.chunks_exact_mut(k)) {
+        for (c, b_row) in c_row.iter_mut().zip(b.chunks_exact(n.max(1))) {
+            let mut sum = 0.0;
+            for (&x, &y) in a_row.iter().zip(b_row) {
+                sum += y * x;
+            }
+            *c = sum;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gemm() {
+
+        // 5 rows cover a full block and a partial one
+        let a: Vec<Float> = (0..15).map(|i| i as Float - 7.0).collect();
+        let b: Vec<Float> = (0..6).map(|i| (i as Float) / 2.0).collect();
+        let transpose = |m: &[Float], rows: usize, cols: usize| -> Vec<Float> {
+            (0..cols * rows).map(|i| m[(i % rows) * cols + i / rows]).collect()
+        };
+        let naive = |a: &[Float], b: &[Float], m: usize, k: usize, n: usize| -> Vec<Float> {
+            (0..m * n).map(|i| (0..k).map(|l| a[(i / n) * k + l] * b[l * n + i % n]).sum()).collect()
+        };
+
+        let mut c = vec![1.0; 10];
+        gemm_acc(&a, &b, &mut c, 5, 3, 2);
+        let expected: Vec<Float> = naive(&a, &b, 5, 3, 2).iter().map(|v| v + 1.0).collect();
+        assert_eq!(c, expected);
+
+        let mut c = vec![0.0; 6];
+        let b_rows: Vec<Float> = (0..10).map(|i| i as Float).collect();
+        gemm_at_acc(&a, &b_rows, &mut c, 5, 3, 2);
+        assert_eq!(c, naive(&transpose(&a, 5, 3), &b_rows, 3, 5, 2));
+
+        let mut c = vec![0.0; 10];
+        gemm_bt(&a, &b, &mut c, 5, 3, 2);
+        assert_eq!(c, naive(&a, &transpose(&b, 2, 3), 5, 3, 2));
+
+        // large enough for matrixmultiply, which sums in another order
+        let (m, k, n) = (33, 40, 35);
+        let a: Vec<Float> = (0..m * k).map(|i| ((i * 7) % 13) as Float / 13.0 - 0.5).collect();
+        let b: Vec<Float> = (0..k * n).map(|i| ((i * 5) % 11) as Float / 11.0 - 0.5).collect();
+        let close = |x: &[Float], y: &[Float]| x.iter().zip(y).all(|(x, y)| (x - y).abs() < 1e-4);
+        let mut c = vec![0.0; m * n];
+        gemm_acc(&a, &b, &mut c, m, k, n);
+        assert!(close(&c, &naive(&a, &b, m, k, n)));
+        let mut c = vec![0.0; k * n];
+        let b_rows: Vec<Float> = (0..m * n).map(|i| ((i * 3) % 7) as Float / 7.0).collect();
+        gemm_at_acc(&a, &b_rows, &mut c, m, k, n);
+        assert!(close(&c, &naive(&transpose(&a, m, k), &b_rows, k, m, n)));
+        let mut c = vec![0.0; m * n];
+        gemm_bt(&a, &transpose(&b, k, n), &mut c, m, k, n);
+        assert!(close(&c, &naive(&a, &b, m, k, n)));
+
+    }
+
+}
@@ -0,0 +1,244 @@
+use serde::{Serialize, Deserialize};
+
+use crate::Float;
+use crate::buffer::RowBuffer;
+use crate::func::ErrorFn;
+
+/// The output of the net input or of an earlier layer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayerSource {
+    Input,
+    Layer(usize),
+    /// A contiguous part of the net input, e.g. a named input group, see
+    /// `NetConfig::input_group`
+    InputSlice { offset: usize, size: usize },
+}
+
+/// A named part of the net input. The groups of a net are laid out one after another in the
+/// net input.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputGroup {
+    pub name: String,
+    pub size: usize,
+}
+
+impl InputGroup {
+    pub fn new(name: impl ToString, size: usize) -> Self {
+        assert!(size > 0);
+        InputGroup { name: name.to_string(), size }
+    }
+}
+
+/// A named output of a net, which is the output of one of its layers. The outputs of a net with
+/// heads are the outputs of the heads, one after another.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OutputHead {
+    pub name: String,
+    pub layer_index: usize,
+    /// The error function minimized for this head, or `None` to use the error function the net
+    /// is trained with
+    pub error_fn: Option<ErrorFn>,
+}
+
+/// Where a layer takes its inputs from. Sources must precede the layer, so the layers of a net
+/// are always in a valid execution order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayerInput {
+    /// The output of the layer before, or the net input for the first layer
+    #[default]
+    Previous,
+    /// The outputs of the sources, one after another
+    Concat(Vec<LayerSource>),
+    /// The element-wise sum of the outputs of the sources, which must all be the same size,
+    /// e.g. to add the input of a residual block to its output
+    Sum(Vec<LayerSource>),
+}
+
+impl LayerInput {
+
+    /// The sources of the inputs of the layer at `layer_index`
+    pub fn sources(&self, layer_index: usize) -> Vec<LayerSource> {
+        match self {
+            LayerInput::Previous if layer_index == 0 => vec![LayerSource::Input],
+            LayerInput::Previous => vec![LayerSource::Layer(layer_index - 1)],
+            LayerInput::Concat(sources) | LayerInput::Sum(sources) => sources.clone(),
+        }
+    }
+
+    /// The input size of the layer at `layer_index`, given the sizes of the net input and the
+    /// outputs of the layers before it. Panics if the sources are invalid.
+    pub(crate) fn input_size(&self, layer_index: usize, net_input_size: usize, layer_output_sizes: &[usize]) -> usize {
+        let sources = self.sources(layer_index);
+        assert!(!sources.is_empty(), "layer {} has no inputs", layer_index);
+        let sizes: Vec<usize> = sources.iter()
+            .map(|&source| match source {
+                LayerSource::Input => net_input_size,
+                LayerSource::InputSlice { offset, size } => {
+                    assert!(size > 0 && offset + size <= net_input_size, "layer {} takes inputs past the end of the net input", layer_index);
+                    size
+                },
+                LayerSource::Layer(index) => {
+                    assert!(index < layer_index, "layer {} takes inputs from later layer {}", layer_index, index);
+                    layer_output_sizes[index]
+                },
+            })
+            .collect();
+        match self {
+            LayerInput::Sum(_) => {
+                assert!(sizes.iter().all(|&size| size == sizes[0]),
+                    "summed inputs of layer {} differ in size: {:?}", layer_index, sizes);
+                sizes[0]
+            },
+            _ => sizes.iter().sum(),
+        }
+    }
+
+    /// Combines the source outputs into the inputs of the layer at `layer_index`
+    pub(crate) fn gather(&self, layer_index: usize, net_input: &[Float], layer_outputs: &RowBuffer, target: &mut [Float]) {
+        let sources = self.sources(layer_index);
+        match self {
+            LayerInput::Sum(_) => {
+                target.copy_from_slice(source_values(sources[0], net_input, layer_outputs));
+                for &source in &sources[1..] {
+                    for (t, &value) in target.iter_mut().zip(source_values(source, net_input, layer_outputs)) {
+                        *t += value;
+                    }
+                }
+            },
+            _ => {
+                let mut offset = 0;
+                for source in sources {
+                    let values = source_values(source, net_input, layer_outputs);
+                    target[offset..offset + values.len()].copy_from_slice(values);
+                    offset += values.len();
+                }
+            },
+        }
+    }
+
+    /// Adds the errors of the inputs of the layer at `layer_index` to the errors of its sources
+    pub(crate) fn scatter_errors(&self, layer_index: usize, input_errors: &[Float], net_input_errors: &mut [Float], layer_errors: &mut RowBuffer) {
+        let mut offset = 0;
+        for source in self.sources(layer_index) {
+            let target = match source {
+                LayerSource::Input => &mut *net_input_errors,
+                LayerSource::InputSlice { offset, size } => &mut net_input_errors[offset..offset + size],
+                LayerSource::Layer(index) => layer_errors.get_row_mut(index),
+            };
+            let errors = match self {
+                LayerInput::Sum(_) => input_errors,
+                _ => &input_errors[offset..offset + target.len()],
+            };
+            offset += target.len();
+            for (t, &error) in target.iter_mut().zip(errors) {
+                *t += error;
+            }
+        }
+    }
+
+}
+
+#[inline]
+fn source_values<'b>(source: LayerSource, net_input: &'b [Float], layer_outputs: &'b RowBuffer) -> &'b [Float] {
+    match source {
+        LayerSource::Input => net_input,
+        LayerSource::InputSlice { offset, size } => &net_input[offset..offset + size],
+        LayerSource::Layer(index) => layer_outputs.get_row(index),
+    }
+}
+
+/// The indices of the layers whose outputs are the outputs of the net
+pub(crate) fn output_layers(heads: &[OutputHead], num_layers: usize) -> Vec<usize> {
+    if heads.is_empty() {
+        vec![num_layers - 1]
+    } else {
+        heads.iter().map(|head| head.layer_index).collect()
+    }
+}
+
+/// Copies the outputs of the heads, or of the last layer if there are none, into `target`
+pub(crate) fn collect_outputs(heads: &[OutputHead], layer_outputs: &RowBuffer, target: &mut Vec<Float>) {
+    target.clear();
+    for layer_index in output_layers(heads, layer_outputs.num_rows()) {
+        target.extend_from_slice(layer_outputs.get_row(layer_index));
+    }
+}
+
+/// Runs the layers before `end_layer` in order, keeping the output of every layer in its row of
+/// `layer_outputs`. The gathered inputs of layers which don't simply take the output of the layer
+/// before are kept in their row of `layer_inputs`. `forward` runs a single layer given its index.
+pub(crate) fn forward_graph(
+    layer_inputs_plan: &[LayerInput],
+    net_input: &[Float],
+    end_layer: usize,
+    layer_outputs: &mut RowBuffer,
+    layer_inputs: &mut RowBuffer,
+    mut forward: impl FnMut(usize, &[Float], &mut [Float]),
+) {
+    for (layer_index, layer_input) in layer_inputs_plan[..end_layer].iter().enumerate() {
+        match layer_input {
+            LayerInput::Previous if layer_index == 0 => {
+                forward(layer_index, net_input, layer_outputs.get_row_mut(layer_index));
+            },
+            LayerInput::Previous => {
+                let (input, output) = layer_outputs.split_rows(layer_index - 1, layer_index);
+                forward(layer_index, input, output);
+            },
+            _ => {
+                layer_input.gather(layer_index, net_input, layer_outputs, layer_inputs.get_row_mut(layer_index));
+                forward(layer_index, layer_inputs.get_row(layer_index), layer_outputs.get_row_mut(layer_index));
+            },
+        }
+    }
+}
+
+/// Buffers for running a forward pass over a net which isn't a simple chain of layers
+pub(crate) fn new_graph_buffers(layer_inputs_plan: &[LayerInput], input_sizes: &[usize], output_sizes: &[usize]) -> (RowBuffer, RowBuffer) {
+    let gathered_sizes: Vec<usize> = layer_inputs_plan.iter().zip(input_sizes)
+        .map(|(layer_input, &size)| if *layer_input == LayerInput::Previous { 0 } else { size })
+        .collect();
+    (RowBuffer::new_with_row_sizes(0.0, output_sizes), RowBuffer::new_with_row_sizes(0.0, gathered_sizes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gather_and_scatter() {
+
+        let outputs = RowBuffer::new_with_row_sizes(1.0, [2, 3]);
+        let net_input = [5.0, 6.0, 7.0];
+
+        let concat = LayerInput::Concat(vec![LayerSource::Layer(0), LayerSource::Input]);
+        assert_eq!(concat.input_size(2, 3, &[2, 3]), 5);
+        let mut target = [0.0; 5];
+        concat.gather(2, &net_input, &outputs, &mut target);
+        assert_eq!(target, [1.0, 1.0, 5.0, 6.0, 7.0]);
+
+        let sum = LayerInput::Sum(vec![LayerSource::Layer(1), LayerSource::Input]);
+        assert_eq!(sum.input_size(2, 3, &[2, 3]), 3);
+        let mut target = [0.0; 3];
+        sum.gather(2, &net_input, &outputs, &mut target);
+        assert_eq!(target, [6.0, 7.0, 8.0]);
+
+        let mut errors = RowBuffer::new_with_row_sizes(0.0, [2, 3]);
+        let mut net_input_errors = [0.0; 3];
+        concat.scatter_errors(2, &[1.0, 2.0, 3.0, 4.0, 5.0], &mut net_input_errors, &mut errors);
+        sum.scatter_errors(2, &[1.0, 1.0, 1.0], &mut net_input_errors, &mut errors);
+        assert_eq!(errors.get_row(0), &[1.0, 2.0]);
+        assert_eq!(errors.get_row(1), &[1.0, 1.0, 1.0]);
+        assert_eq!(net_input_errors, [4.0, 5.0, 6.0]);
+
+        assert_eq!(LayerInput::Previous.sources(0), vec![LayerSource::Input]);
+        assert_eq!(LayerInput::Previous.input_size(1, 3, &[2]), 2);
+
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_sum_size_mismatch() {
+        LayerInput::Sum(vec![LayerSource::Layer(0), LayerSource::Input]).input_size(1, 3, &[2]);
+    }
+
+}
@@ -1,16 +1,40 @@
+use std::error::Error;
+use std::path::Path;
 use rand::{SeedableRng, FromEntropy};
 use rand::Rng;
+use crate::Float;
+use crate::net::Net;
 use crate::utils::stable_hash_seed;
 use rand::distributions::StandardNormal;
+use serde::{Serialize, Deserialize};
 
-#[derive(Clone)]
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum InitializerError {
+        ArchitectureMismatch {
+            description("The pretrained net has a different config than the net to initialize")
+            display("The pretrained net has a different config than the net to initialize")
+        }
+    }
+}
+
+/// The source of the initial weights of a net. Layers ask for each of their weights and biases
+/// along with their fan-in and fan-out, so schemes can scale them to the size of the layer.
+pub trait NetInitializer {
+    fn get_weight(&mut self, fan_in: usize, fan_out: usize) -> Float;
+    fn get_bias(&mut self, fan_in: usize, fan_out: usize) -> Float;
+    /// A sample of the standard normal distribution. The layer initializers of a net config draw
+    /// from this, so they are seeded along with the initializer of the net.
+    fn sample_standard_normal(&mut self) -> Float;
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RandomNetInitializer {
-    weight_std_dev: f32,
-    bias_std_dev: f32,
+    weight_std_dev: Float,
+    bias_std_dev: Float,
     rng: rand_xorshift::XorShiftRng
 }
 
-#[allow(dead_code)]
 impl RandomNetInitializer {
 
     pub fn new_standard_from_entropy() -> Self {
@@ -30,12 +54,101 @@ impl RandomNetInitializer {
         }
     }
 
-    pub fn get_weight(&mut self) -> f32 {
-        self.rng.sample(StandardNormal) as f32 * self.weight_std_dev
+    pub fn get_weight(&mut self) -> Float {
+        self.rng.sample(StandardNormal) as Float * self.weight_std_dev
+    }
+
+    pub fn get_bias(&mut self) -> Float {
+        self.rng.sample(StandardNormal) as Float * self.bias_std_dev
+    }
+
+}
+
+impl NetInitializer for RandomNetInitializer {
+
+    fn get_weight(&mut self, _fan_in: usize, _fan_out: usize) -> Float {
+        RandomNetInitializer::get_weight(self)
+    }
+
+    fn get_bias(&mut self, _fan_in: usize, _fan_out: usize) -> Float {
+        RandomNetInitializer::get_bias(self)
+    }
+
+    fn sample_standard_normal(&mut self) -> Float {
+        self.rng.sample(StandardNormal) as Float
+    }
+
+}
+
+/// An initialization scheme for the weights of a single layer, overriding the initializer of
+/// the net, see `NetConfig::with_layer_initializer`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LayerInitializer {
+    /// Normally distributed weights and biases with fixed standard deviations
+    Normal { weight_std_dev: Float, bias_std_dev: Float },
+    /// Weights with a standard deviation of `sqrt(2 / fan_in)` and zero biases, for layers with
+    /// ReLU-like activations (He et al., 2015)
+    He,
+    /// Weights with a standard deviation of `sqrt(2 / (fan_in + fan_out))` and zero biases, for
+    /// layers with sigmoid or tanh activations (Glorot & Bengio, 2010)
+    Glorot,
+}
+
+/// A layer initializer drawing from the random source of the net initializer
+pub(crate) struct LayerInitializerWithSource<'a> {
+    pub layer_initializer: LayerInitializer,
+    pub source: &'a mut dyn NetInitializer,
+}
+
+impl<'a> NetInitializer for LayerInitializerWithSource<'a> {
+
+    fn get_weight(&mut self, fan_in: usize, fan_out: usize) -> Float {
+        let std_dev = match self.layer_initializer {
+            LayerInitializer::Normal { weight_std_dev, .. } => weight_std_dev,
+            LayerInitializer::He => (2.0 / fan_in as Float).sqrt(),
+            LayerInitializer::Glorot => (2.0 / (fan_in + fan_out) as Float).sqrt(),
+        };
+        self.source.sample_standard_normal() * std_dev
+    }
+
+    fn get_bias(&mut self, _fan_in: usize, _fan_out: usize) -> Float {
+        match self.layer_initializer {
+            LayerInitializer::Normal { bias_std_dev, .. } => self.source.sample_standard_normal() * bias_std_dev,
+            LayerInitializer::He | LayerInitializer::Glorot => 0.0,
+        }
+    }
+
+    fn sample_standard_normal(&mut self) -> Float {
+        self.source.sample_standard_normal()
+    }
+
+}
+
+/// Initial weights copied from a trained net, e.g. so that every trial of a search over training
+/// options starts from the same pretrained weights. Nets must have the same config as the
+/// pretrained net to be initialized from it.
+#[derive(Clone, Debug)]
+pub struct FromPretrained {
+    net: Net,
+}
+
+impl FromPretrained {
+
+    pub fn new(net: Net) -> Self {
+        FromPretrained { net }
+    }
+
+    /// Loads the pretrained net from a file written by `Net::save_json`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        Ok(FromPretrained::new(Net::load_json(path)?))
     }
 
-    pub fn get_bias(&mut self) -> f32 {
-        self.rng.sample(StandardNormal) as f32 * self.bias_std_dev
+    pub fn initialize(&self, net: &mut Net) -> Result<(), InitializerError> {
+        if net.get_config() != self.net.get_config() {
+            return Err(InitializerError::ArchitectureMismatch);
+        }
+        self.net.get_weights().copy_into(net.get_weights_mut());
+        Ok(())
     }
 
 }
@@ -43,6 +156,8 @@ impl RandomNetInitializer {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::func::ActivationFn;
+    use crate::net::NetConfig;
 
     #[test]
     fn test_from_seed() {
@@ -53,4 +168,46 @@ mod test {
         assert!((init.get_bias() - -0.005462957).abs() < 0.0001);
     }
 
+    #[test]
+    fn test_layer_initializers() {
+
+        let config = NetConfig::new_fully_connected(200, 1, [100], ActivationFn::standard_logistic_sigmoid())
+            .with_layer_initializer(0, LayerInitializer::Glorot);
+        let mut net = config.create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("glorot"));
+        assert_eq!(net.get_config(), config);
+
+        let std_dev = |values: &[Float]| {
+            (values.iter().map(|value| value * value).sum::<Float>() / values.len() as Float).sqrt()
+        };
+        let (weights, biases) = net.layer_weights(0).unwrap();
+        assert!((std_dev(weights) - (2.0 / 300.0 as Float).sqrt()).abs() < 0.005, "{}", std_dev(weights));
+        assert!(biases.iter().all(|&bias| bias == 0.0));
+        let (weights, biases) = net.layer_weights(1).unwrap();
+        assert!((std_dev(weights) - 0.01).abs() < 0.005);
+        assert_ne!(biases[0], 0.0);
+
+    }
+
+    #[test]
+    fn test_from_pretrained() -> Result<(), Box<dyn Error>> {
+
+        let config = NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid());
+        let mut pretrained = config.create_net();
+        pretrained.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("pretrained"));
+        let path = std::env::temp_dir().join(format!("rust_neural_net_pretrained_{}.json", std::process::id()));
+        pretrained.save_json(&path)?;
+        let initializer = FromPretrained::load(&path)?;
+        std::fs::remove_file(&path)?;
+
+        let mut net = config.create_net();
+        initializer.initialize(&mut net)?;
+        assert_eq!(net.get_weights().get_buffer(), pretrained.get_weights().get_buffer());
+
+        let mut other = NetConfig::new_fully_connected(2, 1, [4], ActivationFn::standard_logistic_sigmoid()).create_net();
+        assert_eq!(initializer.initialize(&mut other), Err(InitializerError::ArchitectureMismatch));
+        Ok(())
+
+    }
+
 }
\ No newline at end of file
@@ -1,22 +1,94 @@
-use crate::initializer::RandomNetInitializer;
-use crate::func::ActivationFn;
+use crate::Float;
+use crate::initializer::NetInitializer;
+use crate::func::{ActivationFn, ErrorFn};
 use std::fmt;
-use crate::utils::{split_slice_mut, split_slice};
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+use crate::utils::{split_slice_mut, split_slice, stable_hash_seed};
+use crate::gemm;
+use serde::{Serialize, Deserialize};
+use rand::SeedableRng;
 
 pub trait NetLayerBase {
-    fn forward_pass(&self, weight_buffer: &[f32], input: &[f32], output: &mut[f32]);
-    fn backprop(&self, weight_buffer: &[f32], output_errors: &[f32], inputs: &[f32], outputs: &[f32],
-                learning_rate: f32, input_errors: &mut[f32], delta_target: &mut [f32]);
+    fn forward_pass(&self, weight_buffer: &[Float], input: &[Float], output: &mut[Float]);
+    fn backprop(&self, weight_buffer: &[Float], output_errors: &[Float], inputs: &[Float], outputs: &[Float],
+                learning_rate: Float, input_errors: &mut[Float], delta_target: &mut [Float]);
     fn input_size(&self) -> usize;
     fn output_size(&self) -> usize;
     fn weight_buffer_size(&self) -> usize;
-    fn initialize_weights(&self, weight_buffer: &mut [f32], initializer: &mut RandomNetInitializer);
+    /// The number of weights at the start of the weight buffer which are learned by backprop.
+    /// Any remaining weights are state maintained by the layer itself.
+    fn num_trainable_weights(&self) -> usize;
+    fn initialize_weights(&self, weight_buffer: &mut [Float], initializer: &mut dyn NetInitializer);
     fn get_config(&self) -> NetLayerConfig;
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum NetLayerConfig {
-    FullyConnected(usize, ActivationFn)
+    FullyConnected(usize, ActivationFn),
+    /// Normalizes each input by the statistics of the mini-batch during training, and by running
+    /// averages of them, updated with the given momentum, at prediction time
+    BatchNorm { momentum: Float },
+    /// Averages each of the given number of channels over all positions of its feature map, so
+    /// the output size doesn't depend on the resolution of the input
+    GlobalAveragePooling { channels: usize },
+    /// Passes its inputs through unchanged, but as a flat shape, e.g. between feature maps and
+    /// dense layers
+    Flatten,
+    /// A fully connected classification output layer with the given number of classes, whose
+    /// outputs are the softmax of its weighted inputs. Training always minimizes the
+    /// cross-entropy of these, whatever error function is configured, since the fused gradient
+    /// with respect to the weighted inputs is simply the predicted minus the expected
+    /// probabilities. Only valid as the last layer.
+    SoftmaxCrossEntropy(usize),
+    /// A fully connected layer which only connects each node to some of its inputs
+    SparseFullyConnected(usize, ActivationFn, SparseConnections),
+    /// A layer implemented outside this crate, created by the factory registered under the type
+    /// name with `register_custom_layer`
+    Custom { type_name: String, params: serde_json::Value },
+}
+
+/// The inputs each node of a sparse fully connected layer is connected to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SparseConnections {
+    /// The input indices of each node
+    Explicit(Vec<Vec<usize>>),
+    /// Each node is connected to the given number of distinct inputs, chosen at random from the
+    /// seed so the same config always yields the same connectivity
+    Random { inputs_per_node: usize, seed: String },
+}
+
+impl SparseConnections {
+
+    /// The sorted input indices of each of `size` nodes
+    fn resolve(&self, input_size: usize, size: usize) -> Vec<Vec<usize>> {
+        match self {
+            SparseConnections::Explicit(connections) => {
+                assert_eq!(connections.len(), size, "expected the connections of {} nodes", size);
+                connections.iter()
+                    .map(|inputs| {
+                        let mut inputs = inputs.clone();
+                        inputs.sort_unstable();
+                        inputs.dedup();
+                        assert!(inputs.iter().all(|&i| i < input_size), "connection to missing input");
+                        inputs
+                    })
+                    .collect()
+            },
+            SparseConnections::Random { inputs_per_node, seed } => {
+                assert!(*inputs_per_node <= input_size);
+                let mut rng = rand_xorshift::XorShiftRng::from_seed(stable_hash_seed(seed));
+                (0..size)
+                    .map(|_| {
+                        let mut inputs = rand::seq::index::sample(&mut rng, input_size, *inputs_per_node).into_vec();
+                        inputs.sort_unstable();
+                        inputs
+                    })
+                    .collect()
+            },
+        }
+    }
+
 }
 
 impl NetLayerConfig {
@@ -24,8 +96,8 @@ impl NetLayerConfig {
         &self,
         input_size: usize,
     ) -> NetLayer {
-        match self {
-            &NetLayerConfig::FullyConnected(size, activation_fn) => {
+        match *self {
+            NetLayerConfig::FullyConnected(size, activation_fn) => {
                 NetLayer::FullyConnected(
                     FullyConnectedNetLayer::new(
                         input_size,
@@ -34,13 +106,39 @@ impl NetLayerConfig {
                     )
                 )
             },
+            NetLayerConfig::BatchNorm { momentum } => {
+                NetLayer::BatchNorm(BatchNormNetLayer::new(input_size, momentum))
+            },
+            NetLayerConfig::GlobalAveragePooling { channels } => {
+                NetLayer::GlobalAveragePooling(GlobalAveragePoolingNetLayer::new(input_size, channels))
+            },
+            NetLayerConfig::Flatten => NetLayer::Flatten(FlattenNetLayer::new(input_size)),
+            NetLayerConfig::SoftmaxCrossEntropy(size) => {
+                NetLayer::SoftmaxCrossEntropy(SoftmaxCrossEntropyNetLayer::new(input_size, size))
+            },
+            NetLayerConfig::SparseFullyConnected(size, activation_fn, ref connections) => {
+                NetLayer::SparseFullyConnected(
+                    SparseFullyConnectedNetLayer::new(input_size, size, activation_fn, connections.clone())
+                )
+            },
+            NetLayerConfig::Custom { ref type_name, ref params } => {
+                let factory = custom_layer_factory(type_name)
+                    .unwrap_or_else(|| panic!("custom layer {} is not registered", type_name));
+                NetLayer::Custom(factory(input_size, params))
+            },
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum NetLayer {
-    FullyConnected(FullyConnectedNetLayer)
+    FullyConnected(FullyConnectedNetLayer),
+    BatchNorm(BatchNormNetLayer),
+    GlobalAveragePooling(GlobalAveragePoolingNetLayer),
+    Flatten(FlattenNetLayer),
+    SoftmaxCrossEntropy(SoftmaxCrossEntropyNetLayer),
+    SparseFullyConnected(SparseFullyConnectedNetLayer),
+    Custom(Box<dyn CustomNetLayer>),
 }
 
 impl NetLayer {
@@ -49,24 +147,134 @@ impl NetLayer {
     fn get_delegate(&self) -> &dyn NetLayerBase {
         match self {
             NetLayer::FullyConnected(layer) => layer,
+            NetLayer::BatchNorm(layer) => layer,
+            NetLayer::GlobalAveragePooling(layer) => layer,
+            NetLayer::Flatten(layer) => layer,
+            NetLayer::SoftmaxCrossEntropy(layer) => layer,
+            NetLayer::SparseFullyConnected(layer) => layer,
+            NetLayer::Custom(layer) => layer.as_ref(),
+        }
+    }
+
+    /// The number of connection weights and biases at the start of the weight row of a dense
+    /// layer, or `None` for other layers
+    fn dense_weight_counts(&self) -> Option<(usize, usize)> {
+        match self {
+            NetLayer::FullyConnected(layer) => Some((layer.num_weights, layer.size)),
+            NetLayer::SoftmaxCrossEntropy(layer) => Some((layer.num_weights, layer.size)),
+            NetLayer::SparseFullyConnected(layer) => Some((layer.num_connections(), layer.size)),
+            _ => None,
+        }
+    }
+
+    /// Splits the weight row of a dense layer into its connection weights and biases
+    pub fn split_weights<'w>(&self, weight_buffer: &'w [Float]) -> Option<(&'w [Float], &'w [Float])> {
+        self.dense_weight_counts()
+            .map(|(num_weights, num_biases)| split_slice(weight_buffer, num_weights, num_biases))
+    }
+
+    pub fn split_weights_mut<'w>(&self, weight_buffer: &'w mut [Float]) -> Option<(&'w mut [Float], &'w mut [Float])> {
+        self.dense_weight_counts()
+            .map(move |(num_weights, num_biases)| split_slice_mut(weight_buffer, num_weights, num_biases))
+    }
+
+    /// The index into the connection weights of a dense layer of the weight from `input_index`
+    /// to `node_index`, or `None` if there is no such connection
+    pub fn connection_index(&self, input_index: usize, node_index: usize) -> Option<usize> {
+        match self {
+            NetLayer::FullyConnected(FullyConnectedNetLayer { input_size, size, .. })
+            | NetLayer::SoftmaxCrossEntropy(SoftmaxCrossEntropyNetLayer { input_size, size, .. }) => {
+                if input_index < *input_size && node_index < *size {
+                    Some(input_index * size + node_index)
+                } else {
+                    None
+                }
+            },
+            NetLayer::SparseFullyConnected(layer) if node_index < layer.size => {
+                layer.node_inputs(node_index).iter()
+                    .position(|&input| input == input_index)
+                    .map(|position| layer.node_offsets[node_index] + position)
+            },
+            _ => None,
+        }
+    }
+
+    /// Like `forward_pass`, for an input given by the indices and values of its non-zero values,
+    /// in time proportional to their number rather than to the input size. Returns false for
+    /// layers without a sparse path, whose inputs have to be made dense instead.
+    pub fn forward_pass_sparse(&self, weight_buffer: &[Float], indices: &[u32], values: &[Float], output: &mut [Float]) -> bool {
+        match self {
+            NetLayer::FullyConnected(layer) => {
+                sparse_weighted_sums(weight_buffer, layer.num_weights, indices, values, output);
+                for value in output.iter_mut() {
+                    *value = layer.activation_fn.get_activation(*value);
+                }
+                true
+            },
+            NetLayer::SoftmaxCrossEntropy(layer) => {
+                sparse_weighted_sums(weight_buffer, layer.num_weights, indices, values, output);
+                softmax(output);
+                true
+            },
+            _ => false,
         }
     }
 
 }
 
+/// The biases plus the weighted sums of the non-zero inputs of a dense layer, whose weights are
+/// stored by input so the weights of each non-zero input are contiguous
+fn sparse_weighted_sums(weight_buffer: &[Float], num_weights: usize, indices: &[u32], values: &[Float], output: &mut [Float]) {
+    let size = output.len();
+    let (weights, biases) = split_slice(weight_buffer, num_weights, size);
+    output.copy_from_slice(biases);
+    for (&index, &value) in indices.iter().zip(values) {
+        let input_weights = &weights[index as usize * size..(index as usize + 1) * size];
+        for (sum, &weight) in output.iter_mut().zip(input_weights) {
+            *sum += value * weight;
+        }
+    }
+}
+
+/// Replaces the values with their softmax
+fn softmax(values: &mut [Float]) {
+    // subtracting the largest weighted input keeps exp from overflowing
+    let max = values.iter().cloned().fold(Float::NEG_INFINITY, Float::max);
+    let mut sum = 0.0;
+    for value in values.iter_mut() {
+        *value = (*value - max).exp();
+        sum += *value;
+    }
+    for value in values.iter_mut() {
+        *value /= sum;
+    }
+}
+
 impl NetLayerBase for NetLayer {
 
     // NOTE: not using delegate functions for most frequently called methods to avoid dynamic dispatch
 
-    fn forward_pass(&self, weight_buffer: &[f32], input: &[f32], output: &mut [f32]) {
+    fn forward_pass(&self, weight_buffer: &[Float], input: &[Float], output: &mut [Float]) {
         match self {
             NetLayer::FullyConnected(layer) => layer.forward_pass(weight_buffer, input, output),
+            NetLayer::BatchNorm(layer) => layer.forward_pass(weight_buffer, input, output),
+            NetLayer::GlobalAveragePooling(layer) => layer.forward_pass(weight_buffer, input, output),
+            NetLayer::Flatten(layer) => layer.forward_pass(weight_buffer, input, output),
+            NetLayer::SoftmaxCrossEntropy(layer) => layer.forward_pass(weight_buffer, input, output),
+            NetLayer::SparseFullyConnected(layer) => layer.forward_pass(weight_buffer, input, output),
+            NetLayer::Custom(layer) => layer.forward_pass(weight_buffer, input, output),
         }
     }
 
-    fn backprop(&self, weight_buffer: &[f32], output_errors: &[f32], inputs: &[f32], outputs: &[f32], learning_rate: f32, input_errors: &mut [f32], delta_target: &mut [f32]) {
+    fn backprop(&self, weight_buffer: &[Float], output_errors: &[Float], inputs: &[Float], outputs: &[Float], learning_rate: Float, input_errors: &mut [Float], delta_target: &mut [Float]) {
         match self {
             NetLayer::FullyConnected(layer) => layer.backprop(weight_buffer, output_errors, inputs, outputs, learning_rate, input_errors, delta_target),
+            NetLayer::BatchNorm(layer) => layer.backprop(weight_buffer, output_errors, inputs, outputs, learning_rate, input_errors, delta_target),
+            NetLayer::GlobalAveragePooling(layer) => layer.backprop(weight_buffer, output_errors, inputs, outputs, learning_rate, input_errors, delta_target),
+            NetLayer::Flatten(layer) => layer.backprop(weight_buffer, output_errors, inputs, outputs, learning_rate, input_errors, delta_target),
+            NetLayer::SoftmaxCrossEntropy(layer) => layer.backprop(weight_buffer, output_errors, inputs, outputs, learning_rate, input_errors, delta_target),
+            NetLayer::SparseFullyConnected(layer) => layer.backprop(weight_buffer, output_errors, inputs, outputs, learning_rate, input_errors, delta_target),
+            NetLayer::Custom(layer) => layer.backprop(weight_buffer, output_errors, inputs, outputs, learning_rate, input_errors, delta_target),
         }
     }
 
@@ -82,7 +290,11 @@ impl NetLayerBase for NetLayer {
         self.get_delegate().weight_buffer_size()
     }
 
-    fn initialize_weights(&self, weight_buffer: &mut [f32], initializer: &mut RandomNetInitializer) {
+    fn num_trainable_weights(&self) -> usize {
+        self.get_delegate().num_trainable_weights()
+    }
+
+    fn initialize_weights(&self, weight_buffer: &mut [Float], initializer: &mut dyn NetInitializer) {
         self.get_delegate().initialize_weights(weight_buffer, initializer);
     }
 
@@ -91,6 +303,33 @@ impl NetLayerBase for NetLayer {
     }
 }
 
+/// A layer type defined outside this crate. Any cloneable, thread safe `NetLayerBase` is one,
+/// provided its `get_config` returns a `NetLayerConfig::Custom` whose type name is registered
+/// with a factory that recreates the layer from the params.
+pub trait CustomNetLayer: NetLayerBase + objekt::Clone + fmt::Debug + Send + Sync {}
+
+impl<T> CustomNetLayer for T where T: NetLayerBase + Clone + fmt::Debug + Send + Sync + 'static {}
+
+objekt::clone_trait_object!(CustomNetLayer);
+
+/// Creates a custom layer given its input size and the params of its config
+pub type CustomLayerFactory = fn(usize, &serde_json::Value) -> Box<dyn CustomNetLayer>;
+
+fn custom_layer_registry() -> &'static RwLock<HashMap<String, CustomLayerFactory>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, CustomLayerFactory>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Registers the factory for the custom layers with the given type name, replacing any factory
+/// registered before. Must be called before nets with such layers are created or deserialized.
+pub fn register_custom_layer(type_name: &str, factory: CustomLayerFactory) {
+    custom_layer_registry().write().unwrap().insert(type_name.to_string(), factory);
+}
+
+pub fn custom_layer_factory(type_name: &str) -> Option<CustomLayerFactory> {
+    custom_layer_registry().read().unwrap().get(type_name).cloned()
+}
+
 #[derive(Clone)]
 pub struct FullyConnectedNetLayer {
     input_size: usize,
@@ -127,31 +366,77 @@ impl FullyConnectedNetLayer {
     }
 
     #[inline(always)]
-    fn get_weight(&self, weights: &[f32], input_index: usize, node_index: usize) -> f32 {
+    fn get_weight(&self, weights: &[Float], input_index: usize, node_index: usize) -> Float {
         weights[input_index * self.size + node_index]
     }
 
-}
+    /// Like `forward_pass`, for a mini-batch of rows at once, as one matrix product. The inputs
+    /// and outputs of the rows are laid out one after another.
+    pub fn forward_pass_batch(&self, weight_buffer: &[Float], inputs: &[Float], outputs: &mut [Float]) {
 
-impl NetLayerBase for FullyConnectedNetLayer {
+        let rows = inputs.len() / self.input_size;
+        debug_assert_eq!(inputs.len(), rows * self.input_size);
+        debug_assert_eq!(outputs.len(), rows * self.size);
 
-    fn forward_pass(&self, weight_buffer: &[f32], input: &[f32], output: &mut[f32]) {
+        let (weights, biases) = split_slice(weight_buffer, self.num_weights, self.size);
+        for output in outputs.chunks_exact_mut(self.size) {
+            output.copy_from_slice(biases);
+        }
+        gemm::gemm_acc(inputs, weights, outputs, rows, self.input_size, self.size);
+        for output in outputs.iter_mut() {
+            *output = self.activation_fn.get_activation(*output);
+        }
+    }
 
-        debug_assert_eq!(input.len(), self.input_size);
+    /// Like `backprop`, for a mini-batch of rows at once, laid out like `forward_pass_batch`.
+    /// The output errors are replaced by the error gradients of the nodes, and the input errors
+    /// are only computed if given, since the first layer doesn't need them.
+    #[allow(clippy::too_many_arguments)]
+    pub fn backprop_batch(&self, weight_buffer: &[Float], output_errors: &mut [Float], inputs: &[Float], outputs: &[Float],
+                          learning_rate: Float, input_errors: Option<&mut [Float]>, delta_target: &mut [Float]) {
 
-        let (weights, biases) = split_slice(weight_buffer, self.num_weights, self.size);
+        let rows = inputs.len() / self.input_size;
+        debug_assert_eq!(output_errors.len(), rows * self.size);
+        debug_assert_eq!(outputs.len(), rows * self.size);
+        debug_assert_eq!(delta_target.len(), self.num_weights + self.size);
 
-        for node_index in 0..self.size {
-            let mut sum = biases[node_index];
-            for input_index in 0..self.input_size {
-                sum += input[input_index] * self.get_weight(weights, input_index, node_index);
+        let (weights, _) = split_slice(weight_buffer, self.num_weights, self.size);
+        let (weight_deltas, bias_deltas) = split_slice_mut(delta_target, self.num_weights, self.size);
+
+        for (error, &output) in output_errors.iter_mut().zip(outputs) {
+            *error *= self.activation_fn.get_activation_derivative_at_output(output);
+        }
+        if let Some(input_errors) = input_errors {
+            debug_assert_eq!(input_errors.len(), rows * self.input_size);
+            gemm::gemm_bt(output_errors, weights, input_errors, rows, self.size, self.input_size);
+        }
+        for node_error_gradients in output_errors.chunks_exact(self.size) {
+            for (bias_delta, &node_error_gradient) in bias_deltas.iter_mut().zip(node_error_gradients) {
+                *bias_delta -= learning_rate * node_error_gradient;
             }
-            output[node_index] = self.activation_fn.get_activation(sum);
         }
+        // scaled in place, as the gradients aren't needed anymore
+        for error in output_errors.iter_mut() {
+            *error *= -learning_rate;
+        }
+        gemm::gemm_at_acc(inputs, output_errors, weight_deltas, rows, self.input_size, self.size);
+    }
+
+}
+
+impl NetLayerBase for FullyConnectedNetLayer {
+
+    fn forward_pass(&self, weight_buffer: &[Float], input: &[Float], output: &mut[Float]) {
+
+        debug_assert_eq!(input.len(), self.input_size);
+
+        // a batch of one row, which adds up the weighted inputs in the same order as a loop over
+        // them, but with unit-stride, vectorized loops over the nodes
+        self.forward_pass_batch(weight_buffer, input, &mut output[..self.size]);
     }
 
-    fn backprop(&self, weight_buffer: &[f32], output_errors: &[f32], inputs: &[f32], outputs: &[f32],
-                learning_rate: f32, input_errors: &mut [f32], delta_target: &mut [f32]) {
+    fn backprop(&self, weight_buffer: &[Float], output_errors: &[Float], inputs: &[Float], outputs: &[Float],
+                learning_rate: Float, input_errors: &mut [Float], delta_target: &mut [Float]) {
 
         debug_assert_eq!(output_errors.len(), self.size);
         debug_assert_eq!(input_errors.len(), self.input_size);
@@ -169,7 +454,7 @@ impl NetLayerBase for FullyConnectedNetLayer {
             let node_error = output_errors[node_index];
             // gradient describes the rate of change of the activation function at the output value,
             // reflecting how much change in the output we would see for a given change in the input
-            let node_gradient = self.activation_fn.get_activation_derivative(outputs[node_index]);
+            let node_gradient = self.activation_fn.get_activation_derivative_at_output(outputs[node_index]);
             let node_error_gradient = node_gradient * node_error;
             // compute the error for each connection and update the weight
             for input_index in 0..self.input_size {
@@ -200,19 +485,848 @@ impl NetLayerBase for FullyConnectedNetLayer {
         self.num_weights + self.size
     }
 
+    fn num_trainable_weights(&self) -> usize {
+        self.weight_buffer_size()
+    }
 
-    fn initialize_weights(&self, weight_buffer: &mut [f32], initializer: &mut RandomNetInitializer) {
+    fn initialize_weights(&self, weight_buffer: &mut [Float], initializer: &mut dyn NetInitializer) {
         let (weights, biases) = split_slice_mut(weight_buffer, self.num_weights, self.size);
 
         for weight in weights.iter_mut() {
-            *weight = initializer.get_weight();
+            *weight = initializer.get_weight(self.input_size, self.size);
         }
         for bias in biases.iter_mut() {
-            *bias = initializer.get_bias();
+            *bias = initializer.get_bias(self.input_size, self.size);
         }
     }
 
     fn get_config(&self) -> NetLayerConfig {
         NetLayerConfig::FullyConnected(self.size, self.activation_fn)
     }
-}
\ No newline at end of file
+}
+const BATCH_NORM_EPSILON: Float = 0.00001;
+
+/// The per-input mean and (population) variance of the inputs of a batch norm layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchNormStats {
+    pub mean: Vec<Float>,
+    pub variance: Vec<Float>,
+    /// The means of the output errors over the batch, and of their products with the normalized
+    /// inputs, through which the input errors depend on the statistics. While these are empty the
+    /// statistics are treated as constants when back-propagating.
+    pub output_error_mean: Vec<Float>,
+    pub normalized_output_error_mean: Vec<Float>,
+}
+
+/// Normalizes its inputs, then scales and shifts them by the learned gamma and beta.
+///
+/// The weight buffer holds gamma, beta, the running mean and the running variance, in that
+/// order, each with one value per input. Only gamma and beta are trainable; the running averages
+/// are updated by the training context from the statistics of each mini-batch. The gradient
+/// through the statistics needs the output errors of the whole mini-batch, which the training
+/// context gathers in an extra pass over it for each batch norm layer.
+#[derive(Clone, Debug)]
+pub struct BatchNormNetLayer {
+    size: usize,
+    momentum: Float,
+}
+
+impl BatchNormNetLayer {
+
+    pub fn new(size: usize, momentum: Float) -> Self {
+        assert!((0.0..1.0).contains(&momentum));
+        BatchNormNetLayer {
+            size,
+            momentum,
+        }
+    }
+
+    #[inline]
+    fn split_weights<'a>(&self, weight_buffer: &'a [Float]) -> (&'a [Float], &'a [Float], BatchNormStatsRef<'a>) {
+        debug_assert_eq!(weight_buffer.len(), 4 * self.size);
+        let (gamma, rest) = weight_buffer.split_at(self.size);
+        let (beta, rest) = rest.split_at(self.size);
+        let (mean, variance) = rest.split_at(self.size);
+        (gamma, beta, BatchNormStatsRef { mean, variance, output_error_means: None })
+    }
+
+    pub fn forward_pass_with_stats(&self, weight_buffer: &[Float], stats: &BatchNormStats, input: &[Float], output: &mut [Float]) {
+        let (gamma, beta, _) = self.split_weights(weight_buffer);
+        self.normalize(gamma, beta, BatchNormStatsRef::from(stats), input, output);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn backprop_with_stats(&self, weight_buffer: &[Float], stats: &BatchNormStats, output_errors: &[Float], inputs: &[Float],
+                               learning_rate: Float, input_errors: &mut [Float], delta_target: &mut [Float]) {
+        let (gamma, _, _) = self.split_weights(weight_buffer);
+        self.backprop_normalized(gamma, BatchNormStatsRef::from(stats), output_errors, inputs, learning_rate, input_errors, delta_target);
+    }
+
+    /// Adds the output errors of a row of the batch, and their products with the normalized inputs,
+    /// to the sums whose means are the `output_error_mean` and `normalized_output_error_mean` of
+    /// the statistics
+    pub fn sum_output_errors(&self, stats: &BatchNormStats, output_errors: &[Float], inputs: &[Float],
+                             error_sums: &mut [Float], normalized_error_sums: &mut [Float]) {
+        for i in 0..self.size {
+            let normalized = (inputs[i] - stats.mean[i]) / (stats.variance[i] + BATCH_NORM_EPSILON).sqrt();
+            error_sums[i] += output_errors[i];
+            normalized_error_sums[i] += output_errors[i] * normalized;
+        }
+    }
+
+    /// Blends the statistics of a mini-batch into the running averages held in the weight buffer
+    pub fn update_running_stats(&self, weight_buffer: &mut [Float], stats: &BatchNormStats) {
+        let (mean, variance) = weight_buffer[2 * self.size..].split_at_mut(self.size);
+        for (running, &batch) in mean.iter_mut().zip(&stats.mean) {
+            *running = self.momentum * *running + (1.0 - self.momentum) * batch;
+        }
+        for (running, &batch) in variance.iter_mut().zip(&stats.variance) {
+            *running = self.momentum * *running + (1.0 - self.momentum) * batch;
+        }
+    }
+
+    fn normalize(&self, gamma: &[Float], beta: &[Float], stats: BatchNormStatsRef, input: &[Float], output: &mut [Float]) {
+        debug_assert_eq!(input.len(), self.size);
+        for i in 0..self.size {
+            let normalized = (input[i] - stats.mean[i]) / (stats.variance[i] + BATCH_NORM_EPSILON).sqrt();
+            output[i] = gamma[i] * normalized + beta[i];
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn backprop_normalized(&self, gamma: &[Float], stats: BatchNormStatsRef, output_errors: &[Float], inputs: &[Float],
+                           learning_rate: Float, input_errors: &mut [Float], delta_target: &mut [Float]) {
+        debug_assert_eq!(output_errors.len(), self.size);
+        debug_assert_eq!(input_errors.len(), self.size);
+        debug_assert_eq!(delta_target.len(), 4 * self.size);
+        let (gamma_deltas, rest) = delta_target.split_at_mut(self.size);
+        let beta_deltas = &mut rest[..self.size];
+        for i in 0..self.size {
+            let inv_std_dev = 1.0 / (stats.variance[i] + BATCH_NORM_EPSILON).sqrt();
+            let normalized = (inputs[i] - stats.mean[i]) * inv_std_dev;
+            gamma_deltas[i] -= learning_rate * output_errors[i] * normalized;
+            beta_deltas[i] -= learning_rate * output_errors[i];
+            let output_error = match stats.output_error_means {
+                Some((error_mean, normalized_error_mean)) => output_errors[i] - error_mean[i] - normalized * normalized_error_mean[i],
+                None => output_errors[i],
+            };
+            input_errors[i] = output_error * gamma[i] * inv_std_dev;
+        }
+    }
+
+}
+
+#[derive(Clone, Copy)]
+struct BatchNormStatsRef<'a> {
+    mean: &'a [Float],
+    variance: &'a [Float],
+    output_error_means: Option<(&'a [Float], &'a [Float])>,
+}
+
+impl<'a> From<&'a BatchNormStats> for BatchNormStatsRef<'a> {
+    fn from(stats: &'a BatchNormStats) -> Self {
+        BatchNormStatsRef {
+            mean: &stats.mean,
+            variance: &stats.variance,
+            output_error_means: match stats.output_error_mean.is_empty() {
+                true => None,
+                false => Some((&stats.output_error_mean, &stats.normalized_output_error_mean)),
+            },
+        }
+    }
+}
+
+impl NetLayerBase for BatchNormNetLayer {
+
+    /// Normalizes by the running statistics
+    fn forward_pass(&self, weight_buffer: &[Float], input: &[Float], output: &mut [Float]) {
+        let (gamma, beta, running_stats) = self.split_weights(weight_buffer);
+        self.normalize(gamma, beta, running_stats, input, output);
+    }
+
+    fn backprop(&self, weight_buffer: &[Float], output_errors: &[Float], inputs: &[Float], _outputs: &[Float],
+                learning_rate: Float, input_errors: &mut [Float], delta_target: &mut [Float]) {
+        let (gamma, _, running_stats) = self.split_weights(weight_buffer);
+        self.backprop_normalized(gamma, running_stats, output_errors, inputs, learning_rate, input_errors, delta_target);
+    }
+
+    fn input_size(&self) -> usize {
+        self.size
+    }
+
+    fn output_size(&self) -> usize {
+        self.size
+    }
+
+    fn weight_buffer_size(&self) -> usize {
+        4 * self.size
+    }
+
+    fn num_trainable_weights(&self) -> usize {
+        2 * self.size
+    }
+
+    fn initialize_weights(&self, weight_buffer: &mut [Float], _initializer: &mut dyn NetInitializer) {
+        let (gamma, rest) = weight_buffer.split_at_mut(self.size);
+        let (beta, rest) = rest.split_at_mut(self.size);
+        let (mean, variance) = rest.split_at_mut(self.size);
+        for value in gamma.iter_mut().chain(variance.iter_mut()) {
+            *value = 1.0;
+        }
+        for value in beta.iter_mut().chain(mean.iter_mut()) {
+            *value = 0.0;
+        }
+    }
+
+    fn get_config(&self) -> NetLayerConfig {
+        NetLayerConfig::BatchNorm { momentum: self.momentum }
+    }
+}
+
+/// Reduces feature maps to one value per channel by averaging over all positions.
+///
+/// The inputs are expected in channel-major order, i.e. the feature map of each channel is
+/// contiguous, and the input size must be a multiple of the number of channels. The layer has
+/// no weights.
+#[derive(Clone, Debug)]
+pub struct GlobalAveragePoolingNetLayer {
+    input_size: usize,
+    channels: usize,
+}
+
+impl GlobalAveragePoolingNetLayer {
+
+    pub fn new(input_size: usize, channels: usize) -> Self {
+        assert!(channels > 0);
+        assert_eq!(input_size % channels, 0, "input size {} is not a multiple of {} channels", input_size, channels);
+        GlobalAveragePoolingNetLayer {
+            input_size,
+            channels,
+        }
+    }
+
+    /// The number of positions in the feature map of each channel
+    #[inline]
+    pub fn feature_map_size(&self) -> usize {
+        self.input_size / self.channels
+    }
+
+}
+
+impl NetLayerBase for GlobalAveragePoolingNetLayer {
+
+    fn forward_pass(&self, _weight_buffer: &[Float], input: &[Float], output: &mut [Float]) {
+        debug_assert_eq!(input.len(), self.input_size);
+        debug_assert_eq!(output.len(), self.channels);
+        let feature_map_size = self.feature_map_size();
+        for (out, feature_map) in output.iter_mut().zip(input.chunks_exact(feature_map_size)) {
+            *out = feature_map.iter().sum::<Float>() / feature_map_size as Float;
+        }
+    }
+
+    fn backprop(&self, _weight_buffer: &[Float], output_errors: &[Float], _inputs: &[Float], _outputs: &[Float],
+                _learning_rate: Float, input_errors: &mut [Float], _delta_target: &mut [Float]) {
+        debug_assert_eq!(output_errors.len(), self.channels);
+        debug_assert_eq!(input_errors.len(), self.input_size);
+        let feature_map_size = self.feature_map_size();
+        for (&error, feature_map_errors) in output_errors.iter().zip(input_errors.chunks_exact_mut(feature_map_size)) {
+            for input_error in feature_map_errors {
+                *input_error = error / feature_map_size as Float;
+            }
+        }
+    }
+
+    fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    fn output_size(&self) -> usize {
+        self.channels
+    }
+
+    fn weight_buffer_size(&self) -> usize {
+        0
+    }
+
+    fn num_trainable_weights(&self) -> usize {
+        0
+    }
+
+    fn initialize_weights(&self, _weight_buffer: &mut [Float], _initializer: &mut dyn NetInitializer) {}
+
+    fn get_config(&self) -> NetLayerConfig {
+        NetLayerConfig::GlobalAveragePooling { channels: self.channels }
+    }
+}
+
+/// Copies its inputs to its outputs. Values are always stored flat, so flattening only changes
+/// the shape metadata.
+#[derive(Clone, Debug)]
+pub struct FlattenNetLayer {
+    size: usize,
+}
+
+impl FlattenNetLayer {
+    pub fn new(size: usize) -> Self {
+        FlattenNetLayer { size }
+    }
+}
+
+impl NetLayerBase for FlattenNetLayer {
+
+    fn forward_pass(&self, _weight_buffer: &[Float], input: &[Float], output: &mut [Float]) {
+        output.copy_from_slice(input);
+    }
+
+    fn backprop(&self, _weight_buffer: &[Float], output_errors: &[Float], _inputs: &[Float], _outputs: &[Float],
+                _learning_rate: Float, input_errors: &mut [Float], _delta_target: &mut [Float]) {
+        input_errors.copy_from_slice(output_errors);
+    }
+
+    fn input_size(&self) -> usize {
+        self.size
+    }
+
+    fn output_size(&self) -> usize {
+        self.size
+    }
+
+    fn weight_buffer_size(&self) -> usize {
+        0
+    }
+
+    fn num_trainable_weights(&self) -> usize {
+        0
+    }
+
+    fn initialize_weights(&self, _weight_buffer: &mut [Float], _initializer: &mut dyn NetInitializer) {}
+
+    fn get_config(&self) -> NetLayerConfig {
+        NetLayerConfig::Flatten
+    }
+}
+
+/// A fully connected layer with a fixed sparse connectivity.
+///
+/// Only the weights of actual connections are stored: the weight buffer holds the weights of the
+/// connections of each node in turn, ordered by input index, followed by the biases. Forward and
+/// backward passes only visit these connections.
+#[derive(Clone, Debug)]
+pub struct SparseFullyConnectedNetLayer {
+    input_size: usize,
+    size: usize,
+    activation_fn: ActivationFn,
+    connections: SparseConnections,
+    /// The input index of every connection
+    input_indices: Vec<usize>,
+    /// The offset of the first connection of each node into the weights, plus the total count
+    node_offsets: Vec<usize>,
+}
+
+impl SparseFullyConnectedNetLayer {
+
+    pub fn new(input_size: usize, size: usize, activation_fn: ActivationFn, connections: SparseConnections) -> Self {
+        let mut input_indices = Vec::new();
+        let mut node_offsets = Vec::with_capacity(size + 1);
+        for node_inputs in connections.resolve(input_size, size) {
+            node_offsets.push(input_indices.len());
+            input_indices.extend(node_inputs);
+        }
+        node_offsets.push(input_indices.len());
+        SparseFullyConnectedNetLayer {
+            input_size,
+            size,
+            activation_fn,
+            connections,
+            input_indices,
+            node_offsets,
+        }
+    }
+
+    /// Keeps the `inputs_per_node` largest weights by magnitude of each node of a densely
+    /// connected layer, returning the sparse layer and its weight buffer
+    pub fn from_dense_top_k(layer: &FullyConnectedNetLayer, weight_buffer: &[Float], inputs_per_node: usize) -> (Self, Vec<Float>) {
+        assert!(inputs_per_node <= layer.input_size);
+        let (_, biases) = split_slice(weight_buffer, layer.num_weights, layer.size);
+        let connections: Vec<Vec<usize>> = (0..layer.size)
+            .map(|node_index| {
+                let mut inputs: Vec<usize> = (0..layer.input_size).collect();
+                inputs.sort_by(|&a, &b| {
+                    let weight_a = layer.get_weight(weight_buffer, a, node_index).abs();
+                    let weight_b = layer.get_weight(weight_buffer, b, node_index).abs();
+                    weight_b.partial_cmp(&weight_a).unwrap_or(std::cmp::Ordering::Equal)
+                });
+                inputs.truncate(inputs_per_node);
+                inputs
+            })
+            .collect();
+        let sparse = SparseFullyConnectedNetLayer::new(
+            layer.input_size,
+            layer.size,
+            layer.activation_fn,
+            SparseConnections::Explicit(connections),
+        );
+        let mut weights = Vec::with_capacity(sparse.weight_buffer_size());
+        for node_index in 0..sparse.size {
+            for &input_index in sparse.node_inputs(node_index) {
+                weights.push(layer.get_weight(weight_buffer, input_index, node_index));
+            }
+        }
+        weights.extend_from_slice(biases);
+        (sparse, weights)
+    }
+
+    /// The number of connections
+    #[inline]
+    pub fn num_connections(&self) -> usize {
+        self.input_indices.len()
+    }
+
+    /// The input indices the node is connected to
+    #[inline]
+    pub fn node_inputs(&self, node_index: usize) -> &[usize] {
+        &self.input_indices[self.node_offsets[node_index]..self.node_offsets[node_index + 1]]
+    }
+
+}
+
+impl NetLayerBase for SparseFullyConnectedNetLayer {
+
+    fn forward_pass(&self, weight_buffer: &[Float], input: &[Float], output: &mut [Float]) {
+
+        debug_assert_eq!(input.len(), self.input_size);
+
+        let (weights, biases) = split_slice(weight_buffer, self.num_connections(), self.size);
+
+        for node_index in 0..self.size {
+            let (start, end) = (self.node_offsets[node_index], self.node_offsets[node_index + 1]);
+            let mut sum = biases[node_index];
+            for (&weight, &input_index) in weights[start..end].iter().zip(&self.input_indices[start..end]) {
+                sum += input[input_index] * weight;
+            }
+            output[node_index] = self.activation_fn.get_activation(sum);
+        }
+    }
+
+    fn backprop(&self, weight_buffer: &[Float], output_errors: &[Float], inputs: &[Float], outputs: &[Float],
+                learning_rate: Float, input_errors: &mut [Float], delta_target: &mut [Float]) {
+
+        debug_assert_eq!(output_errors.len(), self.size);
+        debug_assert_eq!(input_errors.len(), self.input_size);
+        debug_assert_eq!(delta_target.len(), self.weight_buffer_size());
+
+        let (weights, _) = split_slice(weight_buffer, self.num_connections(), self.size);
+        let (weight_deltas, bias_deltas) = split_slice_mut(delta_target, self.num_connections(), self.size);
+
+        for error in input_errors.iter_mut() {
+            *error = 0.0;
+        }
+        for node_index in 0..self.size {
+            let node_gradient = self.activation_fn.get_activation_derivative_at_output(outputs[node_index]);
+            let node_error_gradient = node_gradient * output_errors[node_index];
+            for connection in self.node_offsets[node_index]..self.node_offsets[node_index + 1] {
+                let input_index = self.input_indices[connection];
+                weight_deltas[connection] -= learning_rate * node_error_gradient * inputs[input_index];
+                input_errors[input_index] += weights[connection] * node_error_gradient;
+            }
+            bias_deltas[node_index] -= learning_rate * node_error_gradient;
+        }
+    }
+
+    fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    fn output_size(&self) -> usize {
+        self.size
+    }
+
+    fn weight_buffer_size(&self) -> usize {
+        self.num_connections() + self.size
+    }
+
+    fn num_trainable_weights(&self) -> usize {
+        self.weight_buffer_size()
+    }
+
+    fn initialize_weights(&self, weight_buffer: &mut [Float], initializer: &mut dyn NetInitializer) {
+        let (weights, biases) = split_slice_mut(weight_buffer, self.num_connections(), self.size);
+        // the fan-in of each node is its own number of connections
+        for (node_index, bias) in biases.iter_mut().enumerate() {
+            let (start, end) = (self.node_offsets[node_index], self.node_offsets[node_index + 1]);
+            for weight in weights[start..end].iter_mut() {
+                *weight = initializer.get_weight(end - start, self.size);
+            }
+            *bias = initializer.get_bias(end - start, self.size);
+        }
+    }
+
+    fn get_config(&self) -> NetLayerConfig {
+        NetLayerConfig::SparseFullyConnected(self.size, self.activation_fn, self.connections.clone())
+    }
+}
+
+/// Fully connected weights followed by a softmax over the outputs.
+///
+/// Back-propagation expects the output errors to already be the gradient of the loss with
+/// respect to the weighted inputs, i.e. `p - y` for the cross-entropy loss computed by `loss`
+/// and `loss_gradient`. The training context does this in place of the configured error
+/// function.
+#[derive(Clone, Debug)]
+pub struct SoftmaxCrossEntropyNetLayer {
+    input_size: usize,
+    size: usize,
+    num_weights: usize,
+}
+
+impl SoftmaxCrossEntropyNetLayer {
+
+    pub fn new(input_size: usize, size: usize) -> Self {
+        assert!(size > 1, "softmax needs at least 2 classes");
+        SoftmaxCrossEntropyNetLayer {
+            input_size,
+            size,
+            num_weights: size * input_size,
+        }
+    }
+
+    /// The cross-entropy of the predicted probabilities given the expected ones
+    pub fn loss(&self, outputs: &[Float], expected_outputs: &[Float]) -> Float {
+        debug_assert_eq!(outputs.len(), self.size);
+        outputs.iter().zip(expected_outputs)
+            .map(|(&actual, &expected)| ErrorFn::CrossEntropy.get_error(expected, actual))
+            .sum()
+    }
+
+    /// Writes the gradient of the loss with respect to the weighted inputs to `gradient`
+    pub fn loss_gradient(&self, outputs: &[Float], expected_outputs: &[Float], gradient: &mut [Float]) {
+        debug_assert_eq!(outputs.len(), self.size);
+        for ((g, &actual), &expected) in gradient.iter_mut().zip(outputs).zip(expected_outputs) {
+            *g = actual - expected;
+        }
+    }
+
+}
+
+impl NetLayerBase for SoftmaxCrossEntropyNetLayer {
+
+    fn forward_pass(&self, weight_buffer: &[Float], input: &[Float], output: &mut [Float]) {
+
+        debug_assert_eq!(input.len(), self.input_size);
+
+        let (weights, biases) = split_slice(weight_buffer, self.num_weights, self.size);
+
+        for node_index in 0..self.size {
+            let mut sum = biases[node_index];
+            for input_index in 0..self.input_size {
+                sum += input[input_index] * weights[input_index * self.size + node_index];
+            }
+            output[node_index] = sum;
+        }
+        softmax(output);
+    }
+
+    fn backprop(&self, weight_buffer: &[Float], output_errors: &[Float], inputs: &[Float], _outputs: &[Float],
+                learning_rate: Float, input_errors: &mut [Float], delta_target: &mut [Float]) {
+
+        debug_assert_eq!(output_errors.len(), self.size);
+        debug_assert_eq!(input_errors.len(), self.input_size);
+        debug_assert_eq!(delta_target.len(), self.num_weights + self.size);
+
+        let (weights, _) = split_slice(weight_buffer, self.num_weights, self.size);
+        let (weight_deltas, bias_deltas) = split_slice_mut(delta_target, self.num_weights, self.size);
+
+        // the weights are stored in rows of one weight per node for each input
+        let rows = weights.chunks_exact(self.size).zip(weight_deltas.chunks_exact_mut(self.size));
+        for ((input_error, &input), (weights, weight_deltas)) in input_errors.iter_mut().zip(inputs).zip(rows) {
+            *input_error = 0.0;
+            for ((&error, &weight), delta) in output_errors.iter().zip(weights).zip(weight_deltas) {
+                *delta -= learning_rate * error * input;
+                *input_error += weight * error;
+            }
+        }
+        for (delta, &error) in bias_deltas.iter_mut().zip(output_errors) {
+            *delta -= learning_rate * error;
+        }
+    }
+
+    fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    fn output_size(&self) -> usize {
+        self.size
+    }
+
+    fn weight_buffer_size(&self) -> usize {
+        self.num_weights + self.size
+    }
+
+    fn num_trainable_weights(&self) -> usize {
+        self.weight_buffer_size()
+    }
+
+    fn initialize_weights(&self, weight_buffer: &mut [Float], initializer: &mut dyn NetInitializer) {
+        let (weights, biases) = split_slice_mut(weight_buffer, self.num_weights, self.size);
+        for weight in weights.iter_mut() {
+            *weight = initializer.get_weight(self.input_size, self.size);
+        }
+        for bias in biases.iter_mut() {
+            *bias = initializer.get_bias(self.input_size, self.size);
+        }
+    }
+
+    fn get_config(&self) -> NetLayerConfig {
+        NetLayerConfig::SoftmaxCrossEntropy(self.size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::initializer::RandomNetInitializer;
+
+    #[test]
+    fn test_batch_norm() {
+
+        let layer = BatchNormNetLayer::new(2, 0.5);
+        let mut weights = vec![0.0; layer.weight_buffer_size()];
+        layer.initialize_weights(&mut weights, &mut RandomNetInitializer::new_standard_with_seed("batch norm"));
+        assert_eq!(weights, [1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0]);
+
+        let stats = BatchNormStats {
+            mean: vec![2.0, -1.0],
+            variance: vec![4.0, 0.25],
+            output_error_mean: Vec::new(),
+            normalized_output_error_mean: Vec::new(),
+        };
+        let mut output = [0.0; 2];
+        layer.forward_pass_with_stats(&weights, &stats, &[4.0, -1.5], &mut output);
+        assert!((output[0] - 1.0).abs() < 0.0001 && (output[1] - -1.0).abs() < 0.001);
+
+        // gamma 2, beta 1
+        weights[0] = 2.0;
+        weights[2] = 1.0;
+        layer.forward_pass_with_stats(&weights, &stats, &[4.0, -1.5], &mut output);
+        assert!((output[0] - 3.0).abs() < 0.0001);
+
+        let mut input_errors = [0.0; 2];
+        let mut deltas = vec![0.0; layer.weight_buffer_size()];
+        layer.backprop_with_stats(&weights, &stats, &[1.0, 1.0], &[4.0, -1.5], 0.1, &mut input_errors, &mut deltas);
+        assert!((input_errors[0] - 1.0).abs() < 0.0001);
+        assert!((deltas[0] - -0.1).abs() < 0.0001 && (deltas[2] - -0.1).abs() < 0.0001);
+        assert!(deltas[4..].iter().all(|&d| d == 0.0));
+
+        layer.update_running_stats(&mut weights, &stats);
+        assert_eq!(&weights[4..], &[1.0, -0.5, 2.5, 0.625]);
+
+    }
+
+    #[test]
+    fn test_fully_connected_batch() {
+
+        let layer = FullyConnectedNetLayer::new(6, 5, ActivationFn::standard_logistic_sigmoid());
+        let mut weights = vec![0.0; layer.weight_buffer_size()];
+        layer.initialize_weights(&mut weights, &mut RandomNetInitializer::new_standard_with_seed("batch"));
+        let inputs: Vec<Float> = (0..42).map(|i| ((i * 7) % 11) as Float / 11.0).collect();
+        let output_errors: Vec<Float> = (0..35).map(|i| ((i * 3) % 5) as Float - 2.0).collect();
+
+        let mut outputs = vec![0.0; 35];
+        let mut input_errors = vec![0.0; 42];
+        let mut deltas = vec![0.0; layer.weight_buffer_size()];
+        for (row, input) in inputs.chunks_exact(6).enumerate() {
+            let output = &mut outputs[row * 5..row * 5 + 5];
+            layer.forward_pass(&weights, input, output);
+            let errors = &output_errors[row * 5..row * 5 + 5];
+            layer.backprop(&weights, errors, input, &outputs[row * 5..row * 5 + 5], 0.5, &mut input_errors[row * 6..row * 6 + 6], &mut deltas);
+        }
+
+        // the batched passes sum in the same order, so they match exactly
+        let mut batch_outputs = vec![0.0; 35];
+        layer.forward_pass_batch(&weights, &inputs, &mut batch_outputs);
+        assert_eq!(batch_outputs, outputs);
+        let mut batch_errors = output_errors.clone();
+        let mut batch_input_errors = vec![0.0; 42];
+        let mut batch_deltas = vec![0.0; layer.weight_buffer_size()];
+        layer.backprop_batch(&weights, &mut batch_errors, &inputs, &batch_outputs, 0.5, Some(&mut batch_input_errors), &mut batch_deltas);
+        assert_eq!(batch_input_errors, input_errors);
+        assert_eq!(batch_deltas, deltas);
+
+    }
+
+    #[test]
+    fn test_global_average_pooling() {
+
+        // 2 channels with 3 positions each
+        let layer = NetLayerConfig::GlobalAveragePooling { channels: 2 }.create_layer(6);
+        assert_eq!(layer.output_size(), 2);
+        assert_eq!(layer.weight_buffer_size(), 0);
+        assert_eq!(layer.get_config(), NetLayerConfig::GlobalAveragePooling { channels: 2 });
+
+        let inputs = [1.0, 2.0, 3.0, -1.0, 0.0, 4.0];
+        let mut output = [0.0; 2];
+        layer.forward_pass(&[], &inputs, &mut output);
+        assert_eq!(output, [2.0, 1.0]);
+
+        let mut input_errors = [0.0; 6];
+        layer.backprop(&[], &[3.0, -0.3], &inputs, &output, 0.1, &mut input_errors, &mut []);
+        assert_eq!(&input_errors[..3], &[1.0, 1.0, 1.0]);
+        assert!(input_errors[3..].iter().all(|&e| (e - -0.1).abs() < 0.0001));
+
+    }
+
+    #[test]
+    fn test_softmax_cross_entropy() {
+
+        let layer = SoftmaxCrossEntropyNetLayer::new(2, 3);
+        // no weights, biases favouring the last class
+        let weights = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, (2.0 as Float).ln()];
+        let mut output = [0.0; 3];
+        layer.forward_pass(&weights, &[1.0, -1.0], &mut output);
+        assert!((output[0] - 0.25).abs() < 0.0001 && (output[2] - 0.5).abs() < 0.0001);
+
+        // large weighted inputs don't overflow
+        layer.forward_pass(&[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1000.0, 0.0, 0.0], &[1.0, -1.0], &mut output);
+        assert!((output[0] - 1.0).abs() < 0.0001);
+
+        let expected = [0.0, 0.0, 1.0];
+        layer.forward_pass(&weights, &[1.0, -1.0], &mut output);
+        assert!((layer.loss(&output, &expected) - (2.0 as Float).ln()).abs() < 0.0001);
+        let mut gradient = [0.0; 3];
+        layer.loss_gradient(&output, &expected, &mut gradient);
+        assert!((gradient[0] - 0.25).abs() < 0.0001 && (gradient[2] - -0.5).abs() < 0.0001);
+
+        let mut input_errors = [0.0; 2];
+        let mut deltas = [0.0; 9];
+        layer.backprop(&weights, &gradient, &[1.0, -1.0], &output, 1.0, &mut input_errors, &mut deltas);
+        assert_eq!(input_errors, [0.0, 0.0]);
+        assert!((deltas[0] - -0.25).abs() < 0.0001 && (deltas[5] - -0.5).abs() < 0.0001);
+        assert!((deltas[8] - 0.5).abs() < 0.0001);
+
+    }
+
+    #[test]
+    fn test_sparse_fully_connected() {
+
+        let sigmoid = ActivationFn::standard_logistic_sigmoid();
+        let connections = SparseConnections::Explicit(vec![vec![2, 0], vec![1]]);
+        let layer = NetLayerConfig::SparseFullyConnected(2, sigmoid, connections.clone()).create_layer(3);
+        assert_eq!(layer.weight_buffer_size(), 3 + 2);
+        assert_eq!(layer.get_config(), NetLayerConfig::SparseFullyConnected(2, sigmoid, connections));
+
+        // node 0: 1 * in_0 + 2 * in_2, node 1: -1 * in_1 + 0.5
+        let weights = [1.0, 2.0, -1.0, 0.0, 0.5];
+        let inputs = [0.5, 1.0, -0.25];
+        let mut output = [0.0; 2];
+        layer.forward_pass(&weights, &inputs, &mut output);
+        assert_eq!(output, [sigmoid.get_activation(0.0), sigmoid.get_activation(-0.5)]);
+
+        let mut input_errors = [0.0; 3];
+        let mut deltas = [0.0; 5];
+        layer.backprop(&weights, &[1.0, 1.0], &inputs, &output, 1.0, &mut input_errors, &mut deltas);
+        let gradients: Vec<Float> = output.iter().map(|&o| sigmoid.get_activation_derivative_at_output(o)).collect();
+        assert_eq!(input_errors, [gradients[0], -gradients[1], 2.0 * gradients[0]]);
+        assert_eq!(deltas, [-gradients[0] * 0.5, gradients[0] * 0.25, -gradients[1], -gradients[0], -gradients[1]]);
+
+        // random connectivity is reproducible
+        let random = SparseConnections::Random { inputs_per_node: 2, seed: "sparse".to_string() };
+        let a = SparseFullyConnectedNetLayer::new(10, 4, sigmoid, random.clone());
+        let b = SparseFullyConnectedNetLayer::new(10, 4, sigmoid, random);
+        assert_eq!(a.num_connections(), 8);
+        assert_eq!(a.input_indices, b.input_indices);
+
+    }
+
+    #[test]
+    fn test_sparse_from_dense_top_k() {
+
+        let dense = FullyConnectedNetLayer::new(3, 2, ActivationFn::standard_logistic_sigmoid());
+        // weights are stored by input, then node
+        let weights = [0.1, -3.0, -2.0, 0.2, 0.5, 1.0, 7.0, 8.0];
+        let (sparse, sparse_weights) = SparseFullyConnectedNetLayer::from_dense_top_k(&dense, &weights, 2);
+        assert_eq!(sparse.node_inputs(0), &[1, 2]);
+        assert_eq!(sparse.node_inputs(1), &[0, 2]);
+        assert_eq!(sparse_weights, [-2.0, 0.5, -3.0, 1.0, 7.0, 8.0]);
+
+        // dropping a connection of zero weight doesn't change the outputs
+        let mut weights = weights;
+        weights[1] = 0.0;
+        weights[0] = 0.0;
+        let (sparse, sparse_weights) = SparseFullyConnectedNetLayer::from_dense_top_k(&dense, &weights, 2);
+        let (mut dense_output, mut sparse_output) = ([0.0; 2], [0.0; 2]);
+        dense.forward_pass(&weights, &[1.0, 2.0, 3.0], &mut dense_output);
+        sparse.forward_pass(&sparse_weights, &[1.0, 2.0, 3.0], &mut sparse_output);
+        assert_eq!(dense_output, sparse_output);
+
+    }
+
+    /// Multiplies every input by a single learned factor
+    #[derive(Clone, Debug)]
+    struct ScaleLayer {
+        size: usize,
+    }
+
+    impl NetLayerBase for ScaleLayer {
+        fn forward_pass(&self, weight_buffer: &[Float], input: &[Float], output: &mut [Float]) {
+            for (out, &value) in output.iter_mut().zip(input) {
+                *out = weight_buffer[0] * value;
+            }
+        }
+        fn backprop(&self, weight_buffer: &[Float], output_errors: &[Float], inputs: &[Float], _outputs: &[Float],
+                    learning_rate: Float, input_errors: &mut [Float], delta_target: &mut [Float]) {
+            for ((input_error, &error), &input) in input_errors.iter_mut().zip(output_errors).zip(inputs) {
+                *input_error = weight_buffer[0] * error;
+                delta_target[0] -= learning_rate * error * input;
+            }
+        }
+        fn input_size(&self) -> usize { self.size }
+        fn output_size(&self) -> usize { self.size }
+        fn weight_buffer_size(&self) -> usize { 1 }
+        fn num_trainable_weights(&self) -> usize { 1 }
+        fn initialize_weights(&self, weight_buffer: &mut [Float], _initializer: &mut dyn NetInitializer) {
+            weight_buffer[0] = 1.0;
+        }
+        fn get_config(&self) -> NetLayerConfig {
+            NetLayerConfig::Custom { type_name: "test_scale".to_string(), params: serde_json::Value::Null }
+        }
+    }
+
+    #[test]
+    fn test_custom_layer() {
+
+        register_custom_layer("test_scale", |input_size, _| Box::new(ScaleLayer { size: input_size }));
+
+        let config = NetLayerConfig::Custom { type_name: "test_scale".to_string(), params: serde_json::Value::Null };
+        let layer = config.create_layer(2);
+        assert_eq!(layer.get_config(), config);
+        let layer = layer.clone();
+
+        let mut weights = [0.0];
+        layer.initialize_weights(&mut weights, &mut RandomNetInitializer::new_standard_with_seed("custom"));
+        let mut output = [0.0; 2];
+        layer.forward_pass(&[2.0], &[1.0, -3.0], &mut output);
+        assert_eq!(output, [2.0, -6.0]);
+
+        let mut input_errors = [0.0; 2];
+        let mut deltas = [0.0];
+        layer.backprop(&[2.0], &[1.0, 1.0], &[1.0, -3.0], &output, 0.5, &mut input_errors, &mut deltas);
+        assert_eq!(input_errors, [2.0, 2.0]);
+        assert_eq!(deltas, [1.0]);
+
+        // nets with custom layers serialize through the layer config
+        let mut net = crate::net::NetConfig::new(2, vec![
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid()),
+            config,
+        ]).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("custom"));
+        let json = serde_json::to_string(&net).unwrap();
+        let deserialized: crate::net::Net = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.predict(&[1.0, 0.0]), net.predict(&[1.0, 0.0]));
+
+        assert!(custom_layer_factory("test_unregistered").is_none());
+        let unregistered = json.replace("test_scale", "test_unregistered");
+        assert!(serde_json::from_str::<crate::net::Net>(&unregistered).is_err());
+
+    }
+
+}
@@ -0,0 +1,41 @@
+#![feature(slice_index_methods)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+
+#[macro_use]
+extern crate quick_error;
+
+#[macro_use]
+extern crate derive_builder;
+
+/// The float type of the weights, activations and data of nets. Enable the `f64` feature to
+/// train in double precision, e.g. for numerically sensitive experiments.
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+pub mod layer;
+pub mod net;
+pub mod initializer;
+pub mod utils;
+pub mod data;
+pub mod stats;
+pub mod train;
+pub mod buffer;
+pub mod func;
+pub mod ensemble;
+pub mod compression;
+pub mod drift;
+pub mod graph;
+pub mod shape;
+pub mod onnx;
+pub mod summary;
+pub mod quantized;
+pub mod precision;
+pub mod dot;
+pub mod diff;
+pub mod gemm;
+pub mod simd;
+
+#[cfg(test)]
+mod fixtures;
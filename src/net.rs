@@ -1,25 +1,243 @@
 use std::slice;
-use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::borrow::Borrow;
+use std::error::Error;
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
+use crate::Float;
 use crate::layer::NetLayer;
 use crate::layer::NetLayerBase;
 use crate::layer::NetLayerConfig;
-use crate::buffer::RowBuffer;
-use crate::initializer::RandomNetInitializer;
+use crate::layer::{SparseFullyConnectedNetLayer, custom_layer_factory};
+use crate::buffer::{BufferPool, RowBuffer};
+use crate::initializer::{LayerInitializer, LayerInitializerWithSource, NetInitializer};
 use crate::func::ActivationFn;
 use crate::utils::split_slice_mut;
 use crate::func::ErrorFn;
 use crate::train::NetTrainingContext;
+use crate::data::{DataSetSchema, Normalizer, PreparedDataSet, SchemaError, SparseDataSet};
+use crate::stats::Stats;
+use crate::graph::{self, InputGroup, LayerInput, LayerSource, OutputHead};
+use crate::shape::{Shape, ShapeError};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum PredictionError {
+        NoSchema {
+            description("Net has no schema")
+        }
+        MissingFeature(name: String) {
+            description("Feature missing from input")
+            display("Feature {} missing from input", name)
+        }
+        UnknownFeature(name: String) {
+            description("Feature is not part of the schema")
+            display("Feature {} is not part of the schema", name)
+        }
+        InvalidFeature(err: SchemaError) {
+            from()
+            description("Invalid feature value")
+            display("Invalid feature value: {}", err)
+        }
+    }
+}
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct NetConfig {
     input_size: usize,
+    /// The shape of the net input, flat if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input_shape: Option<Shape>,
     layers: Vec<NetLayerConfig>,
+    /// The input of each layer, empty if every layer takes the output of the layer before
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    inputs: Vec<LayerInput>,
+    /// For each layer, the earlier layer whose weights it shares, empty if none are shared
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    shared_weights: Vec<Option<usize>>,
+    /// The named parts of the net input, empty if the input is a single unnamed group
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    input_groups: Vec<InputGroup>,
+    /// The named outputs of the net, empty if the output is that of the last layer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    heads: Vec<OutputHead>,
+    /// For each layer, the initializer overriding that of the net, empty if none are overridden
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    layer_initializers: Vec<Option<LayerInitializer>>,
 }
 
 impl NetConfig {
 
+    pub fn new(input_size: usize, layers: Vec<NetLayerConfig>) -> Self {
+        assert!(input_size > 0);
+        assert!(!layers.is_empty());
+        NetConfig {
+            input_size,
+            input_shape: None,
+            layers,
+            inputs: Vec::new(),
+            shared_weights: Vec::new(),
+            input_groups: Vec::new(),
+            heads: Vec::new(),
+            layer_initializers: Vec::new(),
+        }
+    }
+
+    /// Splits the net input into named groups, laid out one after another, e.g. for the
+    /// features of different modalities. Layers take inputs from a group with `input_group`.
+    pub fn with_input_groups(self, input_groups: Vec<InputGroup>) -> Self {
+        assert_eq!(input_groups.iter().map(|group| group.size).sum::<usize>(), self.input_size,
+            "input groups do not add up to the input size");
+        for (index, group) in input_groups.iter().enumerate() {
+            assert!(input_groups[..index].iter().all(|other| other.name != group.name), "duplicate input group {}", group.name);
+        }
+        let mut new = self;
+        new.input_groups = input_groups;
+        new
+    }
+
+    #[inline]
+    pub fn input_groups(&self) -> &[InputGroup] {
+        &self.input_groups
+    }
+
+    /// The part of the net input which makes up the named input group
+    pub fn input_group(&self, name: &str) -> Option<LayerSource> {
+        let mut offset = 0;
+        for group in &self.input_groups {
+            if group.name == name {
+                return Some(LayerSource::InputSlice { offset, size: group.size });
+            }
+            offset += group.size;
+        }
+        None
+    }
+
+    /// Adds a named output of the layer at `layer_index`, e.g. for multi-task learning. Once a
+    /// net has heads, its outputs are the outputs of the heads in the order they were added,
+    /// rather than those of the last layer. Each head is trained with its own error function,
+    /// or with the one given to the trainer if `None`.
+    pub fn with_output_head(self, name: impl ToString, layer_index: usize, error_fn: Option<ErrorFn>) -> Self {
+        let name = name.to_string();
+        assert!(layer_index < self.layers.len());
+        assert!(self.heads.iter().all(|head| head.name != name), "duplicate output head {}", name);
+        assert!(self.heads.iter().all(|head| head.layer_index != layer_index), "layer {} is already an output head", layer_index);
+        let mut new = self;
+        new.heads.push(OutputHead { name, layer_index, error_fn });
+        new
+    }
+
+    #[inline]
+    pub fn output_heads(&self) -> &[OutputHead] {
+        &self.heads
+    }
+
+    #[inline]
+    pub fn layers(&self) -> &[NetLayerConfig] {
+        &self.layers
+    }
+
+    /// Ties the weights of the layer at `layer_index` to those of an earlier layer with the same
+    /// weight layout, e.g. for repeated blocks or siamese branches. Both layers read the same
+    /// weights, and the gradients of both are accumulated into them.
+    pub fn with_shared_weights(self, layer_index: usize, source_layer_index: usize) -> Self {
+        assert!(source_layer_index < layer_index && layer_index < self.layers.len());
+        let mut new = self;
+        new.shared_weights.resize(new.layers.len(), None);
+        new.shared_weights[layer_index] = Some(source_layer_index);
+        new
+    }
+
+    /// The earlier layer whose weights the layer shares, if any
+    #[inline]
+    pub fn shared_weights(&self, layer_index: usize) -> Option<usize> {
+        self.shared_weights.get(layer_index).cloned().flatten()
+    }
+
+    /// Initializes the weights of the layer at `layer_index` with its own scheme rather than the
+    /// initializer passed to `Net::initialize_weights`, e.g. He for hidden layers and Glorot for
+    /// a sigmoid output layer. The scheme still draws from the random source of that initializer.
+    pub fn with_layer_initializer(self, layer_index: usize, layer_initializer: LayerInitializer) -> Self {
+        assert!(layer_index < self.layers.len());
+        let mut new = self;
+        new.layer_initializers.resize(new.layers.len(), None);
+        new.layer_initializers[layer_index] = Some(layer_initializer);
+        new
+    }
+
+    #[inline]
+    pub fn layer_initializer(&self, layer_index: usize) -> Option<LayerInitializer> {
+        self.layer_initializers.get(layer_index).cloned().flatten()
+    }
+
+    /// Describes the shape of the net input, e.g. `[channels, height, width]` for images, so the
+    /// shapes layers expect can be checked
+    pub fn with_input_shape(self, input_shape: Shape) -> Self {
+        assert_eq!(input_shape.size(), self.input_size, "input shape {} does not match input size", input_shape);
+        let mut new = self;
+        new.input_shape = if input_shape.rank() == 1 { None } else { Some(input_shape) };
+        new
+    }
+
+    pub fn input_shape(&self) -> Shape {
+        self.input_shape.clone().unwrap_or_else(|| Shape::flat(self.input_size))
+    }
+
+    /// Infers the output shape of every layer, failing if any layer can't take the shape of its
+    /// inputs
+    pub fn layer_shapes(&self) -> Result<Vec<Shape>, ShapeError> {
+        let input_shape = self.input_shape();
+        let mut shapes: Vec<Shape> = Vec::with_capacity(self.layers.len());
+        for (layer_index, layer_config) in self.layers.iter().enumerate() {
+            let layer_input = self.layer_input(layer_index);
+            let source_shapes: Vec<Shape> = layer_input.sources(layer_index).into_iter()
+                .map(|source| match source {
+                    LayerSource::Input => input_shape.clone(),
+                    LayerSource::InputSlice { size, .. } => Shape::flat(size),
+                    LayerSource::Layer(index) => shapes[index].clone(),
+                })
+                .collect();
+            let layer_input_shape = match layer_input {
+                LayerInput::Sum(_) => {
+                    if let Some(shape) = source_shapes.iter().find(|&shape| *shape != source_shapes[0]) {
+                        return Err(ShapeError::IncompatibleInput(layer_index, shape.clone(),
+                            format!("summed inputs must all have shape {}", source_shapes[0])));
+                    }
+                    source_shapes[0].clone()
+                },
+                _ => Shape::concat(&source_shapes),
+            };
+            let output_shape = match layer_config.output_shape(layer_index, &layer_input_shape)? {
+                Some(shape) => shape,
+                None => Shape::flat(layer_config.create_layer(layer_input_shape.size()).output_size()),
+            };
+            shapes.push(output_shape);
+        }
+        Ok(shapes)
+    }
+
+    /// Sets where the layer at `layer_index` takes its inputs from, e.g. to add skip or
+    /// residual connections
+    pub fn with_input(self, layer_index: usize, input: LayerInput) -> Self {
+        assert!(layer_index < self.layers.len());
+        let mut new = self;
+        new.inputs.resize(new.layers.len(), LayerInput::Previous);
+        new.inputs[layer_index] = input;
+        if new.inputs.iter().all(|input| *input == LayerInput::Previous) {
+            new.inputs.clear();
+        }
+        new
+    }
+
+    #[inline]
+    pub fn layer_input(&self, layer_index: usize) -> LayerInput {
+        self.inputs.get(layer_index).cloned().unwrap_or_default()
+    }
+
     pub fn new_fully_connected(
         input_size: usize,
         output_size: usize,
@@ -35,24 +253,47 @@ impl NetConfig {
             layers.push(NetLayerConfig::FullyConnected(*layer_size, activation_fn));
         }
         layers.push(NetLayerConfig::FullyConnected(output_size, activation_fn));
-        NetConfig {
-            input_size,
-            layers
-        }
+        NetConfig::new(input_size, layers)
+    }
+
+    /// Hidden fully connected layers followed by a softmax cross-entropy output layer with one
+    /// output per class
+    pub fn new_classifier(
+        input_size: usize,
+        num_classes: usize,
+        hidden_layer_sizes: impl AsRef<[usize]>,
+        activation_fn: ActivationFn,
+    ) -> Self {
+        let mut layers: Vec<NetLayerConfig> = hidden_layer_sizes.as_ref().iter()
+            .map(|&size| NetLayerConfig::FullyConnected(size, activation_fn))
+            .collect();
+        layers.push(NetLayerConfig::SoftmaxCrossEntropy(num_classes));
+        NetConfig::new(input_size, layers)
     }
 
     pub fn create_net(&self) -> Net {
 
-        let mut layers = Vec::with_capacity(self.layers.len());
-        let mut layer_input_size = self.input_size;
+        if let Err(err) = self.layer_shapes() {
+            panic!("invalid net config: {}", err);
+        }
+
+        let mut layers: Vec<NetLayer> = Vec::with_capacity(self.layers.len());
+        let mut output_sizes = Vec::with_capacity(self.layers.len());
+        let inputs: Vec<LayerInput> = (0..self.layers.len()).map(|i| self.layer_input(i)).collect();
 
-        for layer_config in &self.layers {
+        for (layer_index, layer_config) in self.layers.iter().enumerate() {
+            let layer_input_size = inputs[layer_index].input_size(layer_index, self.input_size, &output_sizes);
             let layer = layer_config.create_layer(layer_input_size);
-            layer_input_size = layer.output_size();
+            output_sizes.push(layer.output_size());
             layers.push(layer);
         }
 
-        Net::new(self.input_size, layers)
+        let shared_weights = (0..self.layers.len()).map(|i| self.shared_weights(i)).collect();
+        let mut net = Net::new(self.input_size, layers, inputs, shared_weights, self.heads.clone());
+        net.input_shape = self.input_shape.clone();
+        net.input_groups = self.input_groups.clone().into_boxed_slice();
+        net.layer_initializers = (0..self.layers.len()).map(|i| self.layer_initializer(i)).collect();
+        net
 
     }
 
@@ -64,79 +305,283 @@ pub struct Net {
     input_size: usize,
     output_size: usize,
     layers: Box<[NetLayer]>,
-    prediction_buffers: RefCell<RowBuffer>, // RefCell is needed to allow mutable borrow
+    /// The input of each layer
+    layer_inputs: Box<[LayerInput]>,
+    /// The earlier layer whose weight row each layer shares, if any
+    shared_weights: Box<[Option<usize>]>,
+    /// The schema of the data set the net was trained on, if known
+    schema: Option<DataSetSchema>,
+    /// The normalizer of the independent columns of the data set the net was trained on
+    input_normalizer: Option<Normalizer>,
+    /// The normalizer of the dependent columns, which is inverted on every prediction
+    output_normalizer: Option<Normalizer>,
+    /// The shape of the net input, if not flat
+    input_shape: Option<Shape>,
+    input_groups: Box<[InputGroup]>,
+    heads: Box<[OutputHead]>,
+    layer_initializers: Box<[Option<LayerInitializer>]>,
 }
 
-#[allow(dead_code)]
 impl<'a> Net {
 
-    fn new(input_size: usize, layers: Vec<NetLayer>) -> Self {
+    fn new(input_size: usize, layers: Vec<NetLayer>, layer_inputs: Vec<LayerInput>, shared_weights: Vec<Option<usize>>, heads: Vec<OutputHead>) -> Self {
 
         assert!(input_size > 0);
         assert!(layers.len() > 0);
+        assert_eq!(layers.len(), layer_inputs.len());
 
-        for layer in &layers {
+        let output_sizes: Vec<usize> = layers.iter().map(NetLayer::output_size).collect();
+        for (layer_index, layer) in layers.iter().enumerate() {
             assert!(layer.input_size() > 0);
             assert!(layer.output_size() > 0);
+            assert_eq!(layer.input_size(), layer_inputs[layer_index].input_size(layer_index, input_size, &output_sizes));
         }
+        assert!(heads.iter().all(|head| head.layer_index < layers.len()), "output head of missing layer");
+        let output_layers = graph::output_layers(&heads, layers.len());
+        assert!(
+            layers.iter().enumerate()
+                .all(|(index, layer)| !matches!(layer, NetLayer::SoftmaxCrossEntropy(_)) || output_layers.contains(&index)),
+            "softmax cross-entropy is only valid as an output layer"
+        );
 
         let row_buffer_sizes: Vec<usize> = layers.iter()
             .map(NetLayer::weight_buffer_size)
             .collect();
 
-        let weight_buffer = RowBuffer::new_with_row_sizes(0.0, row_buffer_sizes);
-        let max_output_size = layers.iter().map(NetLayer::output_size).max().unwrap();
+        let weight_buffer = RowBuffer::new_with_shared_rows(0.0, row_buffer_sizes, &shared_weights);
 
         Net {
             weight_buffer,
             input_size,
-            output_size: layers.last().unwrap().output_size(),
+            output_size: output_layers.iter().map(|&index| output_sizes[index]).sum(),
             layers: layers.into_boxed_slice(),
-            prediction_buffers: RefCell::new(RowBuffer::new_with_row_sizes(0.0, [max_output_size, max_output_size])),
+            layer_inputs: layer_inputs.into_boxed_slice(),
+            shared_weights: shared_weights.into_boxed_slice(),
+            schema: None,
+            input_normalizer: None,
+            output_normalizer: None,
+            input_shape: None,
+            input_groups: Box::new([]),
+            heads: heads.into_boxed_slice(),
+            layer_initializers: Box::new([]),
         }
 
     }
 
-    fn predict_with(&mut self, input: &[f32], output: &mut[f32]) {
+    /// Scratch space for `predict_with_buffers`, which can be reused across predictions
+    pub fn new_prediction_buffers(&self) -> PredictionBuffers {
+        let output_sizes: Vec<usize> = self.layers.iter().map(NetLayer::output_size).collect();
+        let max_output_size = output_sizes.iter().cloned().max().unwrap();
+        let graph = if self.is_chain() {
+            None
+        } else {
+            let input_sizes: Vec<usize> = self.layers.iter().map(NetLayer::input_size).collect();
+            Some(graph::new_graph_buffers(&self.layer_inputs, &input_sizes, &output_sizes))
+        };
+        PredictionBuffers {
+            chain: RowBuffer::new_with_row_sizes(0.0, [max_output_size, max_output_size]),
+            graph,
+            normalized_input: Vec::with_capacity(if self.input_normalizer.is_some() { self.input_size } else { 0 }),
+        }
+    }
+
+    /// Predicts into `output` without allocating. The buffers must have been created by this net,
+    /// or by one with the same layers.
+    pub fn predict_with_buffers(&self, input: &[Float], buffers: &mut PredictionBuffers, output: &mut [Float]) {
+        assert_eq!(input.len(), self.input_size);
+        assert_eq!(output.len(), self.output_size);
+        match &self.input_normalizer {
+            Some(normalizer) => {
+                let mut normalized_input = std::mem::take(&mut buffers.normalized_input);
+                normalized_input.clear();
+                normalized_input.extend_from_slice(input);
+                normalizer.normalize(&mut normalized_input);
+                self.forward_with_buffers(&normalized_input, buffers, output);
+                buffers.normalized_input = normalized_input;
+            },
+            None => self.forward_with_buffers(input, buffers, output),
+        }
+        if let Some(normalizer) = &self.output_normalizer {
+            normalizer.denormalize(output);
+        }
+    }
+
+    fn forward_with_buffers(&self, input: &[Float], buffers: &mut PredictionBuffers, output: &mut [Float]) {
 
         let num_layers = self.layers.len();
 
-        debug_assert_eq!(input.len(), self.input_size);
-        debug_assert_eq!(output.len(), self.output_size);
+        if let Some((layer_outputs, layer_inputs)) = &mut buffers.graph {
+            let (layers, weights) = (&self.layers, &self.weight_buffer);
+            graph::forward_graph(&self.layer_inputs, input, num_layers, layer_outputs, layer_inputs, |layer_index, input, output| {
+                layers[layer_index].forward_pass(weights.get_row(layer_index), input, output);
+            });
+            let mut offset = 0;
+            for layer_index in graph::output_layers(&self.heads, num_layers) {
+                let layer_output = layer_outputs.get_row(layer_index);
+                output[offset..offset + layer_output.len()].copy_from_slice(layer_output);
+                offset += layer_output.len();
+            }
+            return;
+        }
 
-        // TODO: handle 1 layer??
-        debug_assert!(num_layers > 1);
+        if num_layers == 1 {
+            self.first_layer().forward_pass(self.weight_buffer.get_first_row(), input, output);
+            return;
+        }
 
-        let mut prediction_buffers = self.prediction_buffers.borrow_mut();
-        let (mut input_buffer, mut output_buffer) = prediction_buffers.split_rows(0, 1);
+        let (mut input_buffer, mut output_buffer) = buffers.chain.split_rows(0, 1);
 
+        // the buffers are sized for the widest layer, so slice them to the sizes of each layer
         self.first_layer().forward_pass(
             self.weight_buffer.get_first_row(),
             input,
-            input_buffer,
+            &mut input_buffer[..self.first_layer().output_size()],
         );
         for row_index in 1..num_layers-1 {
-            self.layer(row_index).forward_pass(
+            let layer = self.layer(row_index);
+            layer.forward_pass(
                 self.weight_buffer.get_row(row_index),
-                input_buffer,
-                output_buffer,
+                &input_buffer[..layer.input_size()],
+                &mut output_buffer[..layer.output_size()],
             );
             std::mem::swap(&mut input_buffer, &mut output_buffer);
         }
         self.last_layer().forward_pass(
             self.weight_buffer.get_last_row(),
-            input_buffer,
+            &input_buffer[..self.last_layer().input_size()],
             output,
         );
 
     }
 
-    pub fn predict(&mut self, input: &[f32]) -> Vec<f32> {
-        let mut output = vec![0f32; self.output_size];
-        self.predict_with(input, output.as_mut_slice());
+    /// Predicts the outputs for `input`. This only needs a shared reference, so a trained net can
+    /// be shared across threads, but allocates scratch space on every call; use
+    /// `predict_with_buffers` in hot loops.
+    pub fn predict(&self, input: &[Float]) -> Vec<Float> {
+        let mut output = vec![0.0; self.output_size];
+        self.predict_with_buffers(input, &mut self.new_prediction_buffers(), output.as_mut_slice());
         output
     }
 
+    /// Predicts for an input given by the indices and values of its non-zero values, e.g. a row
+    /// of a `SparseDataSet`. If the first layer is dense, its weighted sums only visit the
+    /// non-zero values, which makes this much faster than `predict` for very sparse inputs.
+    pub fn predict_sparse(&self, indices: &[u32], values: &[Float]) -> Vec<Float> {
+        let mut outputs = vec![0.0; self.first_layer().output_size()];
+        let has_sparse_path = self.is_chain()
+            // normalizing would make the zeros non-zero
+            && self.input_normalizer.is_none()
+            && self.first_layer().forward_pass_sparse(self.weight_buffer.get_first_row(), indices, values, &mut outputs);
+        if !has_sparse_path {
+            let mut input = vec![0.0; self.input_size];
+            for (&index, &value) in indices.iter().zip(values) {
+                input[index as usize] = value;
+            }
+            return self.predict(&input);
+        }
+        for row_index in 1..self.num_layers() {
+            let layer = self.layer(row_index);
+            let mut layer_outputs = vec![0.0; layer.output_size()];
+            layer.forward_pass(self.weight_buffer.get_row(row_index), &outputs, &mut layer_outputs);
+            outputs = layer_outputs;
+        }
+        if let Some(normalizer) = &self.output_normalizer {
+            normalizer.denormalize(&mut outputs);
+        }
+        outputs
+    }
+
+    /// Like `compute_error`, predicting with `predict_sparse`
+    pub fn compute_error_sparse(&self, data_set: &SparseDataSet, error_fn: &ErrorFn) -> Stats {
+        assert_eq!(data_set.independent_cols(), self.input_size, "data set does not match net inputs");
+        let mut error_stats = Stats::new();
+        for row in data_set.iter() {
+            let outputs = self.predict_sparse(row.indices, row.values);
+            error_stats.report(self.output_error(row.targets, &outputs, error_fn));
+        }
+        error_stats
+    }
+
+    /// Predicts from features given by name, using the schema of the data set the net was trained
+    /// on to order the inputs and validate their presence, types and ranges.
+    pub fn predict_checked<K>(&self, features: &HashMap<K, Float>) -> Result<Vec<Float>, PredictionError>
+        where K: Borrow<str> + Hash + Eq
+    {
+        let schema = self.schema.as_ref().ok_or(PredictionError::NoSchema)?;
+        for name in features.keys() {
+            if !schema.independent_columns().iter().any(|c| c.name == name.borrow()) {
+                return Err(PredictionError::UnknownFeature(name.borrow().to_string()));
+            }
+        }
+        let mut input = Vec::with_capacity(self.input_size);
+        for column in schema.independent_columns() {
+            match features.get(column.name.as_str()) {
+                None => return Err(PredictionError::MissingFeature(column.name.clone())),
+                Some(&value) => {
+                    column.validate(value)?;
+                    input.push(value);
+                }
+            }
+        }
+        Ok(self.predict(&input))
+    }
+
+    /// Class probabilities for `input`. The outputs of a softmax layer are returned as they are.
+    /// Sigmoid outputs are rescaled to [0, 1], and are then either the probability of the second
+    /// of two classes for a single output, or normalized to sum to 1 for several outputs.
+    pub fn predict_proba(&self, input: &[Float]) -> Vec<Float> {
+        let outputs = self.predict(input);
+        let outputs: Vec<Float> = match self.last_layer().get_config() {
+            NetLayerConfig::SoftmaxCrossEntropy(_) => return outputs,
+            NetLayerConfig::FullyConnected(_, ActivationFn::LogisticSigmoid { scale, y_offset, .. })
+            | NetLayerConfig::SparseFullyConnected(_, ActivationFn::LogisticSigmoid { scale, y_offset, .. }, _) => {
+                outputs.iter().map(|&output| ((output - y_offset) / scale).clamp(0.0, 1.0)).collect()
+            },
+            _ => outputs.iter().map(|&output| output.max(0.0)).collect(),
+        };
+        if outputs.len() == 1 {
+            return vec![1.0 - outputs[0], outputs[0]];
+        }
+        let sum: Float = outputs.iter().sum();
+        if sum > 0.0 {
+            outputs.iter().map(|&output| output / sum).collect()
+        } else {
+            vec![1.0 / outputs.len() as Float; outputs.len()]
+        }
+    }
+
+    /// The most probable class for `input`, see `predict_proba`. A single output is thresholded
+    /// at the middle of its range.
+    pub fn predict_class(&self, input: &[Float]) -> usize {
+        let probabilities = self.predict_proba(input);
+        let mut class = 0;
+        for (index, &probability) in probabilities.iter().enumerate() {
+            if probability > probabilities[class] {
+                class = index;
+            }
+        }
+        class
+    }
+
+    /// The error stats of the predictions for the data set, in the units of the data set's
+    /// dependent columns, and so unlike the training error not affected by an output normalizer
+    pub fn compute_error(&self, data_set: &PreparedDataSet, error_fn: &ErrorFn) -> Stats {
+        let mut buffers = self.new_prediction_buffers();
+        let mut outputs = vec![0.0; self.output_size];
+        let mut error_stats = Stats::new();
+        for (inputs, expected_outputs) in data_set {
+            self.predict_with_buffers(inputs, &mut buffers, &mut outputs);
+            error_stats.report(self.output_error(expected_outputs, &outputs, error_fn));
+        }
+        error_stats
+    }
+
+    /// The error of the outputs of the net, computed like the training error: a softmax
+    /// cross-entropy output layer uses its own loss, and an output head its own error fn if set
+    pub fn output_error(&self, expected_outputs: &[Float], outputs: &[Float], error_fn: &ErrorFn) -> Float {
+        output_error(&self.layers, &self.heads, expected_outputs, outputs, error_fn)
+    }
+
     #[inline]
     pub fn num_layers(&self) -> usize {
         self.layers.len()
@@ -147,6 +592,34 @@ impl<'a> Net {
         &self.layers[index]
     }
 
+    /// Where the layer at `index` takes its inputs from
+    #[inline]
+    pub fn layer_input(&self, index: usize) -> &LayerInput {
+        &self.layer_inputs[index]
+    }
+
+    #[inline]
+    pub(crate) fn layer_inputs(&self) -> &[LayerInput] {
+        &self.layer_inputs
+    }
+
+    /// Whether every layer simply takes the output of the layer before
+    #[inline]
+    pub fn is_chain(&self) -> bool {
+        self.layer_inputs.iter().all(|input| *input == LayerInput::Previous)
+            && graph::output_layers(&self.heads, self.layers.len()) == [self.layers.len() - 1]
+    }
+
+    #[inline]
+    pub fn input_groups(&self) -> &[InputGroup] {
+        &self.input_groups
+    }
+
+    #[inline]
+    pub fn output_heads(&self) -> &[OutputHead] {
+        &self.heads
+    }
+
     #[inline]
     pub fn first_layer(&self) -> &NetLayer {
         // guaranteed to have at least 1 layer
@@ -169,16 +642,26 @@ impl<'a> Net {
         self.input_size
     }
 
+    pub fn input_shape(&self) -> Shape {
+        self.input_shape.clone().unwrap_or_else(|| Shape::flat(self.input_size))
+    }
+
     #[inline]
     pub fn output_size(&self) -> usize {
         self.output_size
     }
 
+    /// A buffer of zeros with the same layout as the weights, including any shared rows
     pub(crate) fn new_zeroed_weight_buffer(&self) -> RowBuffer {
-        let layer_sizes: Vec<usize> = self.layer_iter()
-            .map(NetLayer::weight_buffer_size)
-            .collect();
-        RowBuffer::new_with_row_sizes(0.0, layer_sizes)
+        let mut buffer = self.weight_buffer.clone();
+        buffer.reset_to(0.0);
+        buffer
+    }
+
+    /// The earlier layer whose weights the layer at `index` shares, if any
+    #[inline]
+    pub fn shared_weights(&self, index: usize) -> Option<usize> {
+        self.shared_weights[index]
     }
 
     #[inline]
@@ -191,9 +674,45 @@ impl<'a> Net {
         &mut self.weight_buffer
     }
 
-    pub fn initialize_weights(&mut self, initializer: &mut RandomNetInitializer) {
+    /// The connection weights and biases of the layer at `index`, or `None` if it isn't a dense
+    /// layer. The layout of the connection weights is specific to the kind of layer, see
+    /// `connection_weight` to access them by input and node.
+    pub fn layer_weights(&self, index: usize) -> Option<(&[Float], &[Float])> {
+        self.layers[index].split_weights(self.weight_buffer.get_row(index))
+    }
+
+    /// Like `layer_weights`. Changes are seen by every layer sharing the same weights.
+    pub fn layer_weights_mut(&mut self, index: usize) -> Option<(&mut [Float], &mut [Float])> {
+        self.layers[index].split_weights_mut(self.weight_buffer.get_row_mut(index))
+    }
+
+    /// The weight of the connection from input `input_index` to node `node_index` of the layer
+    /// at `layer_index`, or `None` if there is no such connection
+    pub fn connection_weight(&self, layer_index: usize, input_index: usize, node_index: usize) -> Option<Float> {
+        let connection_index = self.layers[layer_index].connection_index(input_index, node_index)?;
+        Some(self.weight_buffer.get_row(layer_index)[connection_index])
+    }
+
+    /// Sets the weight of a connection, returning the previous weight, or `None` without changing
+    /// anything if there is no such connection
+    pub fn set_connection_weight(&mut self, layer_index: usize, input_index: usize, node_index: usize, weight: Float) -> Option<Float> {
+        let connection_index = self.layers[layer_index].connection_index(input_index, node_index)?;
+        let target = &mut self.weight_buffer.get_row_mut(layer_index)[connection_index];
+        Some(std::mem::replace(target, weight))
+    }
+
+    pub fn initialize_weights(&mut self, initializer: &mut dyn NetInitializer) {
         for (i, layer) in self.layers.iter_mut().enumerate() {
-            layer.initialize_weights(self.weight_buffer.get_row_mut(i), initializer);
+            if self.shared_weights[i].is_some() {
+                continue;
+            }
+            match self.layer_initializers.get(i).cloned().flatten() {
+                Some(layer_initializer) => {
+                    let mut initializer = LayerInitializerWithSource { layer_initializer, source: &mut *initializer };
+                    layer.initialize_weights(self.weight_buffer.get_row_mut(i), &mut initializer);
+                },
+                None => layer.initialize_weights(self.weight_buffer.get_row_mut(i), initializer),
+            }
         }
     }
 
@@ -201,21 +720,298 @@ impl<'a> Net {
         let layers: Vec<NetLayerConfig> = self.layer_iter()
             .map(NetLayer::get_config)
             .collect();
-        NetConfig {
-            input_size: self.input_size,
-            layers
+        let mut config = NetConfig::new(self.input_size, layers);
+        config.input_shape = self.input_shape.clone();
+        if self.layer_inputs.iter().any(|input| *input != LayerInput::Previous) {
+            config.inputs = self.layer_inputs.to_vec();
+        }
+        if self.shared_weights.iter().any(Option::is_some) {
+            config.shared_weights = self.shared_weights.to_vec();
         }
+        config.input_groups = self.input_groups.to_vec();
+        config.heads = self.heads.to_vec();
+        if self.layer_initializers.iter().any(Option::is_some) {
+            config.layer_initializers = self.layer_initializers.to_vec();
+        }
+        config
+    }
+
+    /// Whether both nets have the same config, schema and normalizers, and none of their weights
+    /// differ by more than `tolerance`. Use this rather than comparing weights exactly when
+    /// checking trained models, whose weights depend on the order of floating point operations.
+    pub fn approx_eq(&self, other: &Net, tolerance: Float) -> bool {
+        self.schema == other.schema
+            && self.input_normalizer == other.input_normalizer
+            && self.output_normalizer == other.output_normalizer
+            && self.get_config() == other.get_config()
+            && self.weight_buffer.approx_eq(&other.weight_buffer, tolerance)
+    }
+
+    #[inline]
+    pub fn schema(&self) -> Option<&DataSetSchema> {
+        self.schema.as_ref()
+    }
+
+    pub fn set_schema(&mut self, schema: Option<DataSetSchema>) {
+        if let Some(schema) = &schema {
+            assert_eq!(schema.independent_columns().len(), self.input_size, "schema does not match net inputs");
+            assert_eq!(schema.dependent_columns().len(), self.output_size, "schema does not match net outputs");
+        }
+        self.schema = schema;
+    }
+
+    #[inline]
+    pub fn input_normalizer(&self) -> Option<&Normalizer> {
+        self.input_normalizer.as_ref()
+    }
+
+    /// Normalizes every input passed to `predict` and the other prediction methods, which should
+    /// be the normalizer the training inputs were normalized with. Training contexts work on
+    /// data sets as they are, so they aren't affected.
+    pub fn set_input_normalizer(&mut self, input_normalizer: Option<Normalizer>) {
+        if let Some(normalizer) = &input_normalizer {
+            assert_eq!(normalizer.num_cols(), self.input_size, "normalizer does not match net inputs");
+        }
+        self.input_normalizer = input_normalizer;
+    }
+
+    #[inline]
+    pub fn output_normalizer(&self) -> Option<&Normalizer> {
+        self.output_normalizer.as_ref()
+    }
+
+    /// Denormalizes every output of `predict` and the other prediction methods, so a net trained
+    /// on normalized dependent columns (see `PreparedDataSet::normalize_outputs_z_score`)
+    /// predicts in their original units.
+    pub fn set_output_normalizer(&mut self, output_normalizer: Option<Normalizer>) {
+        if let Some(normalizer) = &output_normalizer {
+            assert_eq!(normalizer.num_cols(), self.output_size, "normalizer does not match net outputs");
+        }
+        self.output_normalizer = output_normalizer;
     }
 
     pub fn get_training_context(&'a mut self) -> NetTrainingContext<'a> {
         NetTrainingContext::new(self)
     }
 
+    /// Like `get_training_context`, but the scratch buffers come from the pool, to which they
+    /// can be returned with `NetTrainingContext::release_buffers`
+    pub fn get_pooled_training_context(&'a mut self, pool: &BufferPool) -> NetTrainingContext<'a> {
+        NetTrainingContext::new_pooled(self, pool)
+    }
+
+    /// Replaces the fully connected layer at `layer_index` with a sparse layer keeping only the
+    /// `inputs_per_node` largest weights by magnitude of each node
+    pub fn prune_to_sparse(self, layer_index: usize, inputs_per_node: usize) -> Net {
+        let (sparse, sparse_weights) = match &self.layers[layer_index] {
+            NetLayer::FullyConnected(layer) => {
+                SparseFullyConnectedNetLayer::from_dense_top_k(layer, self.weight_buffer.get_row(layer_index), inputs_per_node)
+            },
+            layer => panic!("can only prune fully connected layers, not {:?}", layer.get_config()),
+        };
+        let mut layers = self.layers.into_vec();
+        layers[layer_index] = NetLayer::SparseFullyConnected(sparse);
+        let mut net = Net::new(self.input_size, layers, self.layer_inputs.into_vec(), self.shared_weights.into_vec(), self.heads.into_vec());
+        for row_index in 0..net.num_layers() {
+            if row_index == layer_index {
+                net.weight_buffer.get_row_mut(row_index).copy_from_slice(&sparse_weights);
+            } else {
+                net.weight_buffer.get_row_mut(row_index).copy_from_slice(self.weight_buffer.get_row(row_index));
+            }
+        }
+        net.schema = self.schema;
+        net.input_normalizer = self.input_normalizer;
+        net.output_normalizer = self.output_normalizer;
+        net.input_shape = self.input_shape;
+        net.input_groups = self.input_groups;
+        net.layer_initializers = self.layer_initializers;
+        net
+    }
+
+    pub fn freeze(self) -> FrozenNet {
+        FrozenNet::from(self)
+    }
+
+    /// Writes the config, weights and schema of the net as JSON. Like trainer snapshots, the net
+    /// is written to a temporary file next to `path` which is then renamed over it.
+    pub fn save_json(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut tmp_file_name = path.file_name()
+            .ok_or("Net path must be a file")?
+            .to_os_string();
+        tmp_file_name.push(".tmp");
+        let tmp_path: PathBuf = path.with_file_name(tmp_file_name);
+        {
+            let mut writer = BufWriter::new(fs::File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, self)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load_json(path: impl AsRef<Path>) -> Result<Net, Box<dyn Error>> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+}
+
+/// See `Net::output_error`, for the layers and output heads of a net
+pub(crate) fn output_error(layers: &[NetLayer], heads: &[OutputHead], expected_outputs: &[Float], outputs: &[Float], error_fn: &ErrorFn) -> Float {
+    let layer_error = |layer_index: usize, expected_outputs: &[Float], outputs: &[Float], error_fn: &ErrorFn| {
+        match &layers[layer_index] {
+            NetLayer::SoftmaxCrossEntropy(layer) => layer.loss(outputs, expected_outputs),
+            _ => expected_outputs.iter().zip(outputs)
+                .map(|(&expected, &actual)| error_fn.get_error(expected, actual))
+                .sum(),
+        }
+    };
+    if heads.is_empty() {
+        return layer_error(layers.len() - 1, expected_outputs, outputs, error_fn);
+    }
+    // the outputs of the heads are laid out one after another
+    let mut error_sum = 0.0;
+    let mut offset = 0;
+    for head in heads {
+        let size = layers[head.layer_index].output_size();
+        let range = offset..offset + size;
+        error_sum += layer_error(head.layer_index, &expected_outputs[range.clone()], &outputs[range], &head.error_fn.unwrap_or(*error_fn));
+        offset += size;
+    }
+    error_sum
+}
+
+/// Scratch space for predicting with a `Net`, see `Net::new_prediction_buffers`
+#[derive(Clone, Debug)]
+pub struct PredictionBuffers {
+    /// The input and output of the current layer of a chain, sized for the widest layer
+    chain: RowBuffer,
+    /// The outputs and gathered inputs of every layer, if the layers aren't a simple chain
+    graph: Option<(RowBuffer, RowBuffer)>,
+    /// The input after normalization, if the net has an input normalizer
+    normalized_input: Vec<Float>,
+}
+
+/// An immutable snapshot of a trained net for serving, which can be shared across threads behind
+/// an `Arc` without the weights being exposed to training. Predicts exactly like the net.
+#[derive(Clone, Debug)]
+pub struct FrozenNet(Net);
+
+impl FrozenNet {
+
+    #[inline]
+    pub fn predict(&self, input: &[Float]) -> Vec<Float> {
+        self.0.predict(input)
+    }
+
+    /// See `Net::predict_with_buffers`
+    #[inline]
+    pub fn predict_with_buffers(&self, input: &[Float], buffers: &mut PredictionBuffers, output: &mut [Float]) {
+        self.0.predict_with_buffers(input, buffers, output)
+    }
+
+    #[inline]
+    pub fn new_prediction_buffers(&self) -> PredictionBuffers {
+        self.0.new_prediction_buffers()
+    }
+
+    #[inline]
+    pub fn input_size(&self) -> usize {
+        self.0.input_size()
+    }
+
+    #[inline]
+    pub fn output_size(&self) -> usize {
+        self.0.output_size()
+    }
+
+    #[inline]
+    pub fn schema(&self) -> Option<&DataSetSchema> {
+        self.0.schema()
+    }
+
+    /// Thaws the net, e.g. to continue training it
+    pub fn into_net(self) -> Net {
+        self.0
+    }
+
+}
+
+impl From<Net> for FrozenNet {
+    fn from(net: Net) -> Self {
+        FrozenNet(net)
+    }
+}
+
+/// The serialized form of a net, which is fully described by its config and weights
+#[derive(Serialize, Deserialize)]
+struct SerializedNet {
+    config: NetConfig,
+    weights: RowBuffer,
+    #[serde(default)]
+    schema: Option<DataSetSchema>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input_normalizer: Option<Normalizer>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_normalizer: Option<Normalizer>,
+}
+
+impl Serialize for Net {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedNet {
+            config: self.get_config(),
+            weights: self.weight_buffer.clone(),
+            schema: self.schema.clone(),
+            input_normalizer: self.input_normalizer.clone(),
+            output_normalizer: self.output_normalizer.clone(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Net {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = SerializedNet::deserialize(deserializer)?;
+        for layer in &serialized.config.layers {
+            if let NetLayerConfig::Custom { type_name, .. } = layer {
+                if custom_layer_factory(type_name).is_none() {
+                    return Err(serde::de::Error::custom(format!("custom layer {} is not registered", type_name)));
+                }
+            }
+        }
+        let mut net = serialized.config.create_net();
+        // the whole layout must match, so shared rows stay shared
+        let layout_matches = net.weight_buffer.buffer_len() == serialized.weights.buffer_len()
+            && net.weight_buffer.row_layout() == serialized.weights.row_layout();
+        if !layout_matches {
+            return Err(serde::de::Error::custom("weight buffer layout does not match net config"));
+        }
+        net.weight_buffer = serialized.weights;
+        net.schema = serialized.schema;
+        if let Some(normalizer) = &serialized.input_normalizer {
+            if normalizer.num_cols() != net.input_size {
+                return Err(serde::de::Error::custom("input normalizer does not match net inputs"));
+            }
+        }
+        if let Some(normalizer) = &serialized.output_normalizer {
+            if normalizer.num_cols() != net.output_size {
+                return Err(serde::de::Error::custom("output normalizer does not match net outputs"));
+            }
+        }
+        net.input_normalizer = serialized.input_normalizer;
+        net.output_normalizer = serialized.output_normalizer;
+        Ok(net)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::fixtures::lines_2x2;
+    use crate::initializer::RandomNetInitializer;
+    use crate::data::{ColumnSchema, ColumnType};
+    use crate::layer::SparseConnections;
 
     #[test]
     fn test_weight_buffer() {
@@ -237,14 +1033,14 @@ mod test {
         assert_eq!(buf.get_row(1).len(), 3 * 2 + 2);
 
         for (i, element) in buf.get_buffer_mut().iter_mut().enumerate() {
-            *element = i as f32;
+            *element = i as Float;
         }
 
         buf.copy_into(net.get_weights_mut());
         net.get_weights().copy_into(&mut buf2);
 
         for (i, element) in buf2.get_buffer().iter().enumerate() {
-            assert_eq!(i as f32, *element);
+            assert_eq!(i as Float, *element);
         }
 
     }
@@ -259,11 +1055,11 @@ mod test {
             ActivationFn::standard_logistic_sigmoid()
         );
 
-        let mut net = config.create_net();
+        let net = config.create_net();
 
         let config2 = net.get_config();
 
-        let mut net2 = config2.create_net();
+        let net2 = config2.create_net();
 
         let config3 = net2.get_config();
 
@@ -271,4 +1067,504 @@ mod test {
 
     }
 
+    #[test]
+    fn test_predict_checked() {
+
+        let config = NetConfig::new_fully_connected(
+            2,
+            1,
+            [3],
+            ActivationFn::standard_logistic_sigmoid()
+        );
+        let mut net = config.create_net();
+
+        let mut features = HashMap::new();
+        features.insert("a", 0.5);
+        features.insert("b", 1.0);
+        assert_eq!(net.predict_checked(&features), Err(PredictionError::NoSchema));
+
+        net.set_schema(Some(DataSetSchema::new(
+            vec![
+                ColumnSchema::new("b", ColumnType::Binary),
+                ColumnSchema::new("a", ColumnType::Continuous).with_range(0.0, 1.0),
+            ],
+            vec![ColumnSchema::new("out", ColumnType::Binary)],
+        )));
+
+        // inputs are ordered by the schema rather than by name
+        assert_eq!(net.predict_checked(&features), Ok(net.predict(&[1.0, 0.5])));
+
+        features.insert("a", 2.0);
+        assert_eq!(net.predict_checked(&features), Err(PredictionError::InvalidFeature(
+            SchemaError::OutOfRange("a".to_string(), 2.0, 0.0, 1.0))));
+
+        features.remove("a");
+        assert_eq!(net.predict_checked(&features), Err(PredictionError::MissingFeature("a".to_string())));
+
+        features.insert("a", 0.5);
+        features.insert("c", 0.5);
+        assert_eq!(net.predict_checked(&features), Err(PredictionError::UnknownFeature("c".to_string())));
+
+    }
+
+
+    #[test]
+    fn test_frozen_net() {
+
+        let config = NetConfig::new_fully_connected(
+            4,
+            2,
+            [5, 3],
+            ActivationFn::standard_logistic_sigmoid()
+        );
+        let mut net = config.create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("frozen"));
+
+        let input = [0.0, 1.0, 0.5, 0.25];
+        let expected = net.predict(&input);
+
+        let frozen = std::sync::Arc::new(net.freeze());
+        let shared = frozen.clone();
+        let output = std::thread::spawn(move || shared.predict(&input)).join().unwrap();
+        assert_eq!(output, expected);
+
+        let thawed = std::sync::Arc::try_unwrap(frozen).unwrap().into_net();
+        assert_eq!(thawed.predict(&input), expected);
+
+    }
+
+    #[test]
+    fn test_shared_prediction() {
+
+        fn assert_sync<T: Sync + Send>() {}
+        assert_sync::<Net>();
+
+        let config = NetConfig::new_fully_connected(3, 2, [4], ActivationFn::standard_logistic_sigmoid())
+            .with_input(1, LayerInput::Concat(vec![LayerSource::Layer(0), LayerSource::Input]));
+        let mut net = config.create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("shared"));
+
+        let input = [0.25, 0.5, 1.0];
+        let expected = net.predict(&input);
+
+        let mut buffers = net.new_prediction_buffers();
+        let mut output = [0.0; 2];
+        net.predict_with_buffers(&input, &mut buffers, &mut output);
+        assert_eq!(output.to_vec(), expected);
+
+        let net = std::sync::Arc::new(net);
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let net = net.clone();
+                std::thread::spawn(move || net.predict(&input))
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+
+    }
+
+    #[test]
+    fn test_class_prediction() {
+
+        // a single output thresholded at the middle of the range of its activation
+        let mut binary = NetConfig::new(1, vec![NetLayerConfig::FullyConnected(1, ActivationFn::standard_logistic_sigmoid_neg())])
+            .create_net();
+        binary.get_weights_mut().get_first_row_mut().copy_from_slice(&[10.0, -5.0]);
+        assert_eq!(binary.predict_class(&[0.0]), 0);
+        assert_eq!(binary.predict_class(&[1.0]), 1);
+        let probabilities = binary.predict_proba(&[0.5]);
+        assert!((probabilities[0] - 0.5).abs() < 1e-6 && (probabilities[1] - 0.5).abs() < 1e-6);
+
+        // several sigmoid outputs are normalized
+        let mut multi = NetConfig::new(1, vec![NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid())])
+            .create_net();
+        multi.get_weights_mut().get_first_row_mut().copy_from_slice(&[0.0, 0.0, 0.0, -1.0, 1.0, 0.0]);
+        let probabilities = multi.predict_proba(&[1.0]);
+        assert!((probabilities.iter().sum::<Float>() - 1.0).abs() < 1e-6);
+        assert_eq!(multi.predict_class(&[1.0]), 1);
+
+        let mut softmax = NetConfig::new(2, vec![NetLayerConfig::SoftmaxCrossEntropy(2)]).create_net();
+        softmax.get_weights_mut().get_first_row_mut().copy_from_slice(&[1.0, -1.0, -1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(softmax.predict_proba(&[1.0, 0.0]), softmax.predict(&[1.0, 0.0]));
+        assert_eq!(softmax.predict_class(&[1.0, 0.0]), 0);
+        assert_eq!(softmax.predict_class(&[0.0, 1.0]), 1);
+
+    }
+
+    #[test]
+    fn test_softmax_compute_error_matches_training() {
+
+        let data_set = lines_2x2();
+        let mut net = NetConfig::new(4, vec![
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::SoftmaxCrossEntropy(2),
+        ]).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("softmax error"));
+
+        // the softmax layer uses its cross-entropy loss whatever the error fn
+        let training_error = net.get_training_context().compute_error_for_batch(&data_set, &ErrorFn::SquaredError).mean();
+        let cross_entropy = net.compute_error(&data_set, &ErrorFn::CrossEntropy).mean();
+        assert!((net.compute_error(&data_set, &ErrorFn::SquaredError).mean() - training_error).abs() < 1e-6);
+        assert!((cross_entropy - training_error).abs() < 1e-6);
+        let ensemble = crate::ensemble::Ensemble::new(vec![net.clone(), net.clone()]);
+        assert!((ensemble.compute_error(&data_set, &ErrorFn::SquaredError).mean() - training_error).abs() < 1e-6);
+        let half = crate::precision::HalfPrecisionNet::from_net(&net);
+        assert!((half.compute_error(&data_set, &ErrorFn::SquaredError).mean() - training_error).abs() < 0.01);
+
+    }
+
+    #[test]
+    fn test_weight_accessors() {
+
+        let sparse = NetLayerConfig::SparseFullyConnected(
+            2,
+            ActivationFn::standard_logistic_sigmoid(),
+            SparseConnections::Explicit(vec![vec![1], vec![0, 1]]),
+        );
+        let mut net = NetConfig::new(3, vec![
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::BatchNorm { momentum: 0.9 },
+            sparse,
+        ]).create_net();
+
+        {
+            let (weights, biases) = net.layer_weights_mut(0).unwrap();
+            assert_eq!((weights.len(), biases.len()), (6, 2));
+            biases[1] = 0.5;
+        }
+        assert_eq!(net.set_connection_weight(0, 2, 1, 3.0), Some(0.0));
+        assert_eq!(net.connection_weight(0, 2, 1), Some(3.0));
+        assert_eq!(net.connection_weight(0, 3, 1), None);
+        // a single node with its input 2 weighted by 3 and a bias of 0.5
+        let (weights, biases) = net.layer_weights(0).unwrap();
+        assert_eq!(weights.iter().filter(|&&w| w != 0.0).count(), 1);
+        assert_eq!(biases, &[0.0, 0.5]);
+
+        assert!(net.layer_weights(1).is_none());
+        assert_eq!(net.connection_weight(1, 0, 0), None);
+
+        assert_eq!(net.layer_weights(2).unwrap().0.len(), 3);
+        assert_eq!(net.set_connection_weight(2, 1, 1, 2.0), Some(0.0));
+        assert_eq!(net.layer_weights(2).unwrap().0, &[0.0, 0.0, 2.0]);
+        assert_eq!(net.set_connection_weight(2, 0, 0, 2.0), None);
+
+    }
+
+    #[test]
+    fn test_save_load_json() -> Result<(), Box<dyn Error>> {
+
+        let mut net = NetConfig::new_fully_connected(3, 2, [4], ActivationFn::standard_logistic_sigmoid())
+            .create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("save"));
+
+        let path = std::env::temp_dir().join(format!("rust_neural_net_net_{}.json", std::process::id()));
+        net.save_json(&path)?;
+        let loaded = Net::load_json(&path)?;
+        fs::remove_file(&path)?;
+
+        assert_eq!(loaded.get_config(), net.get_config());
+        assert_eq!(loaded.get_weights().get_buffer(), net.get_weights().get_buffer());
+        assert_eq!(loaded.predict(&[0.1, 0.2, 0.3]), net.predict(&[0.1, 0.2, 0.3]));
+
+        assert!(Net::load_json(std::env::temp_dir().join("rust_neural_net_missing.json")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_residual_net() {
+
+        // the output layer sees the sum of the first two hidden layers and the net input
+        let config = NetConfig::new(2, vec![
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::FullyConnected(1, ActivationFn::standard_logistic_sigmoid()),
+        ])
+            .with_input(1, LayerInput::Previous)
+            .with_input(2, LayerInput::Concat(vec![LayerSource::Input, LayerSource::Layer(0)]))
+            .with_input(2, LayerInput::Sum(vec![LayerSource::Layer(0), LayerSource::Layer(1)]));
+
+        let mut net = config.create_net();
+        assert!(!net.is_chain());
+        assert_eq!(net.layer(2).input_size(), 3);
+        assert_eq!(net.get_config(), config);
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("residual"));
+
+        let forward = |net: &Net, layer_index: usize, input: &[Float]| {
+            let mut output = vec![0.0; net.layer(layer_index).output_size()];
+            net.layer(layer_index).forward_pass(net.get_weights().get_row(layer_index), input, &mut output);
+            output
+        };
+        let input = [0.25, 0.75];
+        let hidden_0 = forward(&net, 0, &input);
+        let hidden_1 = forward(&net, 1, &hidden_0);
+        let summed: Vec<Float> = hidden_0.iter().zip(&hidden_1).map(|(a, b)| a + b).collect();
+        let expected = forward(&net, 2, &summed);
+        assert_eq!(net.predict(&input), expected);
+
+        let json = serde_json::to_string(&net).unwrap();
+        let deserialized: Net = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.predict(&input), expected);
+        assert_eq!(net.clone().freeze().predict(&input), expected);
+
+        // chains don't serialize any inputs
+        let chain = NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid())
+            .with_input(1, LayerInput::Previous);
+        assert!(chain.create_net().is_chain());
+        assert!(!serde_json::to_string(&chain).unwrap().contains("inputs"));
+
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_residual_net_forward_reference() {
+        NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid())
+            .with_input(0, LayerInput::Concat(vec![LayerSource::Layer(1)]))
+            .create_net();
+    }
+
+    #[test]
+    fn test_prune_to_sparse() {
+
+        let mut net = NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("prune"));
+        let input = [0.0, 1.0, 0.5, 0.25];
+        let expected = net.predict(&input);
+
+        let pruned = net.clone().prune_to_sparse(0, 2);
+        assert_eq!(pruned.get_weights().get_row(0).len(), 3 * 2 + 3);
+        assert_eq!(pruned.get_weights().get_row(1), net.get_weights().get_row(1));
+        let output = pruned.predict(&input);
+        assert!(output.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 0.01));
+
+        // keeping every connection only changes the order of the sums
+        let unpruned = net.prune_to_sparse(0, 4);
+        assert!(unpruned.predict(&input).iter().zip(&expected).all(|(a, b)| (a - b).abs() < 0.00001));
+
+    }
+
+    #[test]
+    fn test_shared_weights() {
+
+        let config = NetConfig::new_fully_connected(2, 1, [3, 3, 3], ActivationFn::standard_logistic_sigmoid())
+            .with_shared_weights(2, 1);
+        let mut net = config.create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("shared"));
+        assert_eq!(net.shared_weights(2), Some(1));
+        assert_eq!(net.get_weights().buffer_len(), 9 + 12 + 4);
+        assert_eq!(net.get_weights().get_row(1), net.get_weights().get_row(2));
+        assert_eq!(net.get_config(), config);
+
+        net.get_weights_mut().get_row_mut(2)[0] = 1.5;
+        assert_eq!(net.get_weights().get_row(1)[0], 1.5);
+
+        let json = serde_json::to_string(&net).unwrap();
+        let mut deserialized: Net = serde_json::from_str(&json).unwrap();
+        deserialized.get_weights_mut().get_row_mut(1)[1] = -1.5;
+        assert_eq!(deserialized.get_weights().get_row(2)[1], -1.5);
+
+        // a row of the right size within the buffer, which isn't shared any more, doesn't load
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value["weights"]["row_offsets_and_sizes"][2][0] = 0.into();
+        assert!(serde_json::from_value::<Net>(value).is_err());
+
+    }
+
+    #[test]
+    fn test_layer_shapes() {
+
+        let sigmoid = ActivationFn::standard_logistic_sigmoid();
+        // 2 channels of 2 x 2 feature maps
+        let config = NetConfig::new(8, vec![
+            NetLayerConfig::BatchNorm { momentum: 0.9 },
+            NetLayerConfig::GlobalAveragePooling { channels: 2 },
+            NetLayerConfig::FullyConnected(1, sigmoid),
+        ]).with_input_shape(Shape::new([2, 2, 2]));
+        assert_eq!(config.layer_shapes().unwrap(), vec![Shape::new([2, 2, 2]), Shape::flat(2), Shape::flat(1)]);
+
+        let net = config.create_net();
+        assert_eq!(net.input_shape(), Shape::new([2, 2, 2]));
+        assert_eq!(net.get_config(), config);
+        let deserialized: Net = serde_json::from_str(&serde_json::to_string(&net).unwrap()).unwrap();
+        assert_eq!(deserialized.input_shape(), Shape::new([2, 2, 2]));
+
+        // the input has 2 channels, even though 8 values would divide into 4
+        let wrong_channels = NetConfig::new(8, vec![
+            NetLayerConfig::GlobalAveragePooling { channels: 4 },
+            NetLayerConfig::FullyConnected(1, sigmoid),
+        ]).with_input_shape(Shape::new([2, 2, 2]));
+        assert!(matches!(wrong_channels.layer_shapes(), Err(ShapeError::IncompatibleInput(0, _, _))));
+
+        let unflattened = NetConfig::new(8, vec![NetLayerConfig::FullyConnected(1, sigmoid)])
+            .with_input_shape(Shape::new([2, 4]));
+        assert!(unflattened.layer_shapes().is_err());
+        let flattened = NetConfig::new(8, vec![NetLayerConfig::Flatten, NetLayerConfig::FullyConnected(1, sigmoid)])
+            .with_input_shape(Shape::new([2, 4]));
+        assert_eq!(flattened.layer_shapes().unwrap()[0], Shape::flat(8));
+
+        // concatenated feature maps stack their channels
+        let stacked = NetConfig::new(8, vec![
+            NetLayerConfig::BatchNorm { momentum: 0.9 },
+            NetLayerConfig::GlobalAveragePooling { channels: 4 },
+        ])
+            .with_input_shape(Shape::new([2, 2, 2]))
+            .with_input(1, LayerInput::Concat(vec![LayerSource::Input, LayerSource::Layer(0)]));
+        assert_eq!(stacked.layer_shapes().unwrap()[1], Shape::flat(4));
+
+    }
+
+    #[test]
+    fn test_single_layer_net() {
+
+        let config = NetConfig::new_fully_connected(2, 1, [], ActivationFn::standard_logistic_sigmoid());
+        let mut net = config.create_net();
+        assert_eq!(net.num_layers(), 1);
+        assert_eq!(net.get_config(), config);
+
+        // weights 2 and -1, bias 0.5
+        net.get_weights_mut().get_first_row_mut().copy_from_slice(&[2.0, -1.0, 0.5]);
+        let sigmoid = ActivationFn::standard_logistic_sigmoid();
+        let expected = vec![sigmoid.get_activation(2.0 * 0.5 - 1.0 * 0.25 + 0.5)];
+        assert_eq!(net.predict(&[0.5, 0.25]), expected);
+        assert_eq!(net.clone().freeze().predict(&[0.5, 0.25]), expected);
+
+    }
+
+    #[test]
+    fn test_input_groups_and_heads() {
+
+        let sigmoid = ActivationFn::standard_logistic_sigmoid();
+        let config = NetConfig::new(4, vec![
+            NetLayerConfig::FullyConnected(3, sigmoid),
+            NetLayerConfig::FullyConnected(3, sigmoid),
+            NetLayerConfig::FullyConnected(1, sigmoid),
+            NetLayerConfig::FullyConnected(2, sigmoid),
+        ]).with_input_groups(vec![InputGroup::new("a", 2), InputGroup::new("b", 2)]);
+        assert_eq!(config.input_group("b"), Some(LayerSource::InputSlice { offset: 2, size: 2 }));
+        assert_eq!(config.input_group("c"), None);
+        let group_a = config.input_group("a").unwrap();
+        let group_b = config.input_group("b").unwrap();
+        let config = config
+            .with_input(0, LayerInput::Concat(vec![group_a]))
+            .with_input(1, LayerInput::Concat(vec![group_b]))
+            .with_input(2, LayerInput::Concat(vec![LayerSource::Layer(0), LayerSource::Layer(1)]))
+            .with_input(3, LayerInput::Concat(vec![LayerSource::Layer(1)]))
+            .with_output_head("x", 2, Some(ErrorFn::SquaredError))
+            .with_output_head("y", 3, None);
+
+        let mut net = config.create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("heads"));
+        assert_eq!(net.output_size(), 3);
+        assert_eq!(net.get_config(), config);
+        assert_eq!(serde_json::from_str::<NetConfig>(&serde_json::to_string(&config).unwrap()).unwrap(), config);
+
+        // group a only reaches head x, while group b reaches both heads
+        let output = net.predict(&[0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(output.len(), 3);
+        let changed_a = net.predict(&[0.9, 0.2, 0.3, 0.4]);
+        assert_ne!(changed_a[0], output[0]);
+        assert_eq!(&changed_a[1..], &output[1..]);
+        let changed_b = net.predict(&[0.1, 0.2, 0.9, 0.4]);
+        assert!(changed_b.iter().zip(&output).all(|(a, b)| a != b));
+
+        assert_eq!(net.clone().freeze().predict(&[0.1, 0.2, 0.3, 0.4]), output);
+
+    }
+
+    #[test]
+    fn test_approx_eq() {
+
+        let mut net = NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid())
+            .create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("approx"));
+
+        let mut nudged = net.clone();
+        nudged.get_weights_mut().get_row_mut(1)[0] += 1e-5;
+        assert!(net.approx_eq(&nudged, 1e-4));
+        assert!(!net.approx_eq(&nudged, 1e-6));
+
+        let other = NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid_neg())
+            .create_net();
+        assert!(!net.approx_eq(&other, 1.0));
+
+    }
+
+    #[test]
+    fn test_input_normalizer() {
+
+        let mut net = NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid())
+            .create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("normalizer"));
+        let normalizer = Normalizer::ZScore { means: vec![10.0, -2.0], std_devs: vec![2.0, 0.5] };
+        let expected = net.predict(&[1.0, -1.0]);
+
+        net.set_input_normalizer(Some(normalizer.clone()));
+        assert_eq!(net.predict(&[12.0, -2.5]), expected);
+        let mut buffers = net.new_prediction_buffers();
+        let mut output = [0.0];
+        net.predict_with_buffers(&[12.0, -2.5], &mut buffers, &mut output);
+        assert_eq!(&output[..], &expected[..]);
+
+        let loaded: Net = serde_json::from_str(&serde_json::to_string(&net).unwrap()).unwrap();
+        assert_eq!(loaded.input_normalizer(), Some(&normalizer));
+        assert_eq!(loaded.freeze().predict(&[12.0, -2.5]), expected);
+
+    }
+
+    #[test]
+    fn test_output_normalizer() {
+
+        let data_set = lines_2x2();
+        let (normalized, normalizer) = data_set.normalize_outputs_min_max((0.25, 0.75));
+
+        let mut net = NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid())
+            .create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("normalizer"));
+        let normalized_outputs = net.predict(data_set.row(0).0);
+        let normalized_error = net.compute_error(&normalized, &ErrorFn::SquaredError);
+
+        net.set_output_normalizer(Some(normalizer.clone()));
+        let mut expected = normalized_outputs;
+        normalizer.denormalize(&mut expected);
+        assert_eq!(net.predict(data_set.row(0).0), expected);
+        // the outputs are denormalized with a scale of 2, so the squared errors are 4 times larger
+        let error = net.compute_error(&data_set, &ErrorFn::SquaredError);
+        assert!((error.mean() - 4.0 * normalized_error.mean()).abs() < 1e-4, "{:?}", error);
+
+        let loaded: Net = serde_json::from_str(&serde_json::to_string(&net).unwrap()).unwrap();
+        assert!(loaded.approx_eq(&net, 0.0));
+        assert_eq!(loaded.freeze().predict(data_set.row(0).0), expected);
+
+    }
+
+    #[test]
+    fn test_predict_sparse() {
+
+        let data_set = SparseDataSet::new(6, 1)
+            .with_row(&[(1, 1.0), (4, 0.5)], &[1.0])
+            .with_row(&[], &[0.0])
+            .with_row(&[(5, -2.0)], &[1.0]);
+        let dense = data_set.to_dense();
+        let mut net = NetConfig::new_fully_connected(6, 1, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("sparse"));
+        let mut softmax = NetConfig::new(6, vec![NetLayerConfig::SoftmaxCrossEntropy(2)]).create_net();
+        softmax.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("sparse"));
+
+        for net in [&net, &softmax] {
+            for (row, (inputs, _)) in data_set.iter().zip(&dense) {
+                let sparse = net.predict_sparse(row.indices, row.values);
+                assert!(sparse.iter().zip(net.predict(inputs)).all(|(a, b)| (a - b).abs() < 1e-6), "{:?}", sparse);
+            }
+        }
+        let error = net.compute_error_sparse(&data_set, &ErrorFn::SquaredError);
+        assert!((error.mean() - net.compute_error(&dense, &ErrorFn::SquaredError).mean()).abs() < 1e-6);
+
+        // normalized inputs are dense
+        net.set_input_normalizer(Some(Normalizer::ZScore { means: vec![1.0; 6], std_devs: vec![1.0; 6] }));
+        assert_eq!(net.predict_sparse(&[], &[]), net.predict(&[0.0; 6]));
+
+    }
+
 }
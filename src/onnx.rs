@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::Float;
+use crate::func::ActivationFn;
+use crate::layer::NetLayerConfig;
+use crate::net::{Net, NetConfig};
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum OnnxError {
+        Decode(reason: &'static str) {
+            description("Invalid ONNX protobuf")
+            display("Invalid ONNX protobuf: {}", reason)
+        }
+        Unsupported(reason: String) {
+            description("Unsupported ONNX graph")
+            display("Unsupported ONNX graph: {}", reason)
+        }
+    }
+}
+
+/// A field of a protobuf message. Only the wire types used by ONNX are supported.
+enum ProtoValue<'a> {
+    Varint(u64),
+    Fixed32(u32),
+    Fixed64,
+    Bytes(&'a [u8]),
+}
+
+struct ProtoReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+
+    fn new(data: &'a [u8]) -> Self {
+        ProtoReader { data, pos: 0 }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, OnnxError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.data.get(self.pos).ok_or(OnnxError::Decode("truncated varint"))?;
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(OnnxError::Decode("varint too long"))
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], OnnxError> {
+        let end = self.pos.checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(OnnxError::Decode("truncated field"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// The next field number and value, or `None` at the end of the message
+    fn next_field(&mut self) -> Result<Option<(u64, ProtoValue<'a>)>, OnnxError> {
+        if self.pos >= self.data.len() {
+            return Ok(None);
+        }
+        let key = self.read_varint()?;
+        let value = match key & 0x7 {
+            0 => ProtoValue::Varint(self.read_varint()?),
+            1 => {
+                self.read_bytes(8)?;
+                ProtoValue::Fixed64
+            },
+            2 => {
+                let len = self.read_varint()? as usize;
+                ProtoValue::Bytes(self.read_bytes(len)?)
+            },
+            5 => {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(self.read_bytes(4)?);
+                ProtoValue::Fixed32(u32::from_le_bytes(bytes))
+            },
+            _ => return Err(OnnxError::Decode("unsupported wire type")),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+}
+
+fn to_string(bytes: &[u8]) -> Result<String, OnnxError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| OnnxError::Decode("invalid string"))
+}
+
+#[derive(Default)]
+struct Tensor {
+    name: String,
+    dims: Vec<usize>,
+    data_type: u64,
+    values: Vec<Float>,
+}
+
+impl Tensor {
+
+    fn parse(data: &[u8]) -> Result<Tensor, OnnxError> {
+        let mut tensor = Tensor::default();
+        let mut reader = ProtoReader::new(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, ProtoValue::Varint(dim)) => tensor.dims.push(dim as usize),
+                (1, ProtoValue::Bytes(packed)) => {
+                    let mut dims = ProtoReader::new(packed);
+                    while dims.pos < packed.len() {
+                        tensor.dims.push(dims.read_varint()? as usize);
+                    }
+                },
+                (2, ProtoValue::Varint(data_type)) => tensor.data_type = data_type,
+                (4, ProtoValue::Fixed32(bits)) => tensor.values.push(f32::from_bits(bits) as Float),
+                (4, ProtoValue::Bytes(packed)) | (9, ProtoValue::Bytes(packed)) => {
+                    if packed.len() % 4 != 0 {
+                        return Err(OnnxError::Decode("float data is not a multiple of 4 bytes"));
+                    }
+                    tensor.values.extend(packed.chunks_exact(4)
+                        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as Float));
+                },
+                (8, ProtoValue::Bytes(name)) => tensor.name = to_string(name)?,
+                _ => {},
+            }
+        }
+        // data type 1 is FLOAT
+        if tensor.data_type != 1 {
+            return Err(OnnxError::Unsupported(format!("tensor {} is not float32", tensor.name)));
+        }
+        if tensor.values.len() != tensor.dims.iter().product::<usize>() {
+            return Err(OnnxError::Decode("tensor data does not match its dimensions"));
+        }
+        Ok(tensor)
+    }
+
+}
+
+#[derive(Default)]
+struct Node {
+    op_type: String,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    float_attributes: HashMap<String, Float>,
+    int_attributes: HashMap<String, i64>,
+}
+
+impl Node {
+
+    fn parse(data: &[u8]) -> Result<Node, OnnxError> {
+        let mut node = Node::default();
+        let mut reader = ProtoReader::new(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, ProtoValue::Bytes(input)) => node.inputs.push(to_string(input)?),
+                (2, ProtoValue::Bytes(output)) => node.outputs.push(to_string(output)?),
+                (4, ProtoValue::Bytes(op_type)) => node.op_type = to_string(op_type)?,
+                (5, ProtoValue::Bytes(attribute)) => node.parse_attribute(attribute)?,
+                _ => {},
+            }
+        }
+        Ok(node)
+    }
+
+    fn parse_attribute(&mut self, data: &[u8]) -> Result<(), OnnxError> {
+        let mut name = String::new();
+        let mut float_value = None;
+        let mut int_value = None;
+        let mut reader = ProtoReader::new(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, ProtoValue::Bytes(bytes)) => name = to_string(bytes)?,
+                (2, ProtoValue::Fixed32(bits)) => float_value = Some(f32::from_bits(bits) as Float),
+                (3, ProtoValue::Varint(value)) => int_value = Some(value as i64),
+                _ => {},
+            }
+        }
+        if let Some(value) = float_value {
+            self.float_attributes.insert(name.clone(), value);
+        }
+        if let Some(value) = int_value {
+            self.int_attributes.insert(name, value);
+        }
+        Ok(())
+    }
+
+    fn float_attribute(&self, name: &str, default: Float) -> Float {
+        self.float_attributes.get(name).cloned().unwrap_or(default)
+    }
+
+    fn int_attribute(&self, name: &str, default: i64) -> i64 {
+        self.int_attributes.get(name).cloned().unwrap_or(default)
+    }
+
+}
+
+struct Graph {
+    nodes: Vec<Node>,
+    initializers: HashMap<String, Tensor>,
+    inputs: Vec<String>,
+}
+
+impl Graph {
+
+    fn parse_model(data: &[u8]) -> Result<Graph, OnnxError> {
+        let mut reader = ProtoReader::new(data);
+        while let Some((field, value)) = reader.next_field()? {
+            if let (7, ProtoValue::Bytes(graph)) = (field, value) {
+                return Graph::parse(graph);
+            }
+        }
+        Err(OnnxError::Decode("model has no graph"))
+    }
+
+    fn parse(data: &[u8]) -> Result<Graph, OnnxError> {
+        let mut graph = Graph { nodes: Vec::new(), initializers: HashMap::new(), inputs: Vec::new() };
+        let mut reader = ProtoReader::new(data);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, ProtoValue::Bytes(node)) => graph.nodes.push(Node::parse(node)?),
+                (5, ProtoValue::Bytes(tensor)) => {
+                    let tensor = Tensor::parse(tensor)?;
+                    graph.initializers.insert(tensor.name.clone(), tensor);
+                },
+                (11, ProtoValue::Bytes(value_info)) => {
+                    let mut value_info = ProtoReader::new(value_info);
+                    while let Some((field, value)) = value_info.next_field()? {
+                        if let (1, ProtoValue::Bytes(name)) = (field, value) {
+                            graph.inputs.push(to_string(name)?);
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+        Ok(graph)
+    }
+
+    fn initializer(&self, name: &str) -> Result<&Tensor, OnnxError> {
+        self.initializers.get(name)
+            .ok_or_else(|| OnnxError::Unsupported(format!("{} is not an initializer", name)))
+    }
+
+}
+
+/// A dense layer read from a `Gemm` node and the activation node after it
+struct DenseLayer {
+    config: NetLayerConfig,
+    input_size: usize,
+    weights: Vec<Float>,
+}
+
+impl DenseLayer {
+
+    /// Reads `Y = alpha * A * B + beta * C`, where `A` is the output of the previous layer and `B`
+    /// and `C` are initializers, into weights in the layout of a fully connected layer
+    fn from_gemm(gemm: &Node, activation: Option<&Node>, graph: &Graph) -> Result<DenseLayer, OnnxError> {
+        if gemm.int_attribute("transA", 0) != 0 {
+            return Err(OnnxError::Unsupported("Gemm with transA".to_string()));
+        }
+        let weights = graph.initializer(gemm.inputs.get(1).map(String::as_str).unwrap_or(""))?;
+        if weights.dims.len() != 2 {
+            return Err(OnnxError::Unsupported(format!("Gemm weights {} are not a matrix", weights.name)));
+        }
+        let trans_b = gemm.int_attribute("transB", 0) != 0;
+        let (input_size, size) = if trans_b {
+            (weights.dims[1], weights.dims[0])
+        } else {
+            (weights.dims[0], weights.dims[1])
+        };
+        let alpha = gemm.float_attribute("alpha", 1.0);
+        let beta = gemm.float_attribute("beta", 1.0);
+
+        let mut values = Vec::with_capacity(input_size * size + size);
+        for input_index in 0..input_size {
+            for node_index in 0..size {
+                let weight = if trans_b {
+                    weights.values[node_index * input_size + input_index]
+                } else {
+                    weights.values[input_index * size + node_index]
+                };
+                values.push(alpha * weight);
+            }
+        }
+        match gemm.inputs.get(2).filter(|name| !name.is_empty()) {
+            Some(name) => {
+                let biases = graph.initializer(name)?;
+                if biases.values.len() != size {
+                    return Err(OnnxError::Unsupported(format!("Gemm bias {} is not one value per node", name)));
+                }
+                values.extend(biases.values.iter().map(|&bias| beta * bias));
+            },
+            None => values.resize(input_size * size + size, 0.0),
+        }
+
+        let config = match activation.map(|node| node.op_type.as_str()) {
+            Some("Sigmoid") => NetLayerConfig::FullyConnected(size, ActivationFn::standard_logistic_sigmoid()),
+            // tanh(x) = 2 * sigmoid(2x) - 1
+            Some("Tanh") => NetLayerConfig::FullyConnected(size, ActivationFn::LogisticSigmoid {
+                steepness: 2.0,
+                scale: 2.0,
+                y_offset: -1.0,
+            }),
+            Some("Softmax") => NetLayerConfig::SoftmaxCrossEntropy(size),
+            Some(op_type) => return Err(OnnxError::Unsupported(format!("activation {}", op_type))),
+            None => return Err(OnnxError::Unsupported("Gemm without an activation".to_string())),
+        };
+        Ok(DenseLayer { config, input_size, weights: values })
+    }
+
+}
+
+impl Net {
+
+    /// Reads a net from an ONNX model made of a chain of `Gemm` nodes, each followed by a
+    /// `Sigmoid`, `Tanh` or, for the last layer, `Softmax` node
+    pub fn from_onnx_bytes(data: &[u8]) -> Result<Net, OnnxError> {
+        let graph = Graph::parse_model(data)?;
+        let mut current = graph.inputs.iter()
+            .find(|name| !graph.initializers.contains_key(*name))
+            .cloned()
+            .ok_or_else(|| OnnxError::Unsupported("graph has no input".to_string()))?;
+
+        let mut layers: Vec<DenseLayer> = Vec::new();
+        let mut nodes = graph.nodes.iter().peekable();
+        while let Some(node) = nodes.next() {
+            if node.inputs.first() != Some(&current) {
+                return Err(OnnxError::Unsupported(format!("{} does not follow the previous node", node.op_type)));
+            }
+            match node.op_type.as_str() {
+                "Gemm" => {
+                    let activation = nodes.next_if(|next| next.inputs.first() == node.outputs.first());
+                    let layer = DenseLayer::from_gemm(node, activation, &graph)?;
+                    if let Some(previous) = layers.last() {
+                        if previous.weights.len() / (previous.input_size + 1) != layer.input_size {
+                            return Err(OnnxError::Unsupported("Gemm sizes do not chain".to_string()));
+                        }
+                    }
+                    current = activation.unwrap_or(node).outputs.first().cloned().unwrap_or_default();
+                    layers.push(layer);
+                },
+                op_type => return Err(OnnxError::Unsupported(format!("operator {}", op_type))),
+            }
+        }
+        if layers.is_empty() {
+            return Err(OnnxError::Unsupported("graph has no Gemm nodes".to_string()));
+        }
+        if layers[..layers.len() - 1].iter().any(|layer| matches!(layer.config, NetLayerConfig::SoftmaxCrossEntropy(_))) {
+            return Err(OnnxError::Unsupported("Softmax before the last layer".to_string()));
+        }
+
+        let input_size = layers[0].input_size;
+        let config = NetConfig::new(input_size, layers.iter().map(|layer| layer.config.clone()).collect());
+        let mut net = config.create_net();
+        for (index, layer) in layers.iter().enumerate() {
+            net.get_weights_mut().get_row_mut(index).copy_from_slice(&layer.weights);
+        }
+        Ok(net)
+    }
+
+    pub fn load_onnx(path: impl AsRef<Path>) -> Result<Net, Box<dyn Error>> {
+        Ok(Net::from_onnx_bytes(&fs::read(path)?)?)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn varint(mut value: u64, target: &mut Vec<u8>) {
+        while value >= 0x80 {
+            target.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        target.push(value as u8);
+    }
+
+    fn bytes_field(field: u64, bytes: &[u8], target: &mut Vec<u8>) {
+        varint(field << 3 | 2, target);
+        varint(bytes.len() as u64, target);
+        target.extend_from_slice(bytes);
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    fn tensor(name: &str, dims: &[u64], values: &[Float]) -> Vec<u8> {
+        let mut tensor = Vec::new();
+        for &dim in dims {
+            varint(1 << 3, &mut tensor);
+            varint(dim, &mut tensor);
+        }
+        varint(2 << 3, &mut tensor);
+        varint(1, &mut tensor);
+        bytes_field(8, name.as_bytes(), &mut tensor);
+        let raw: Vec<u8> = values.iter().flat_map(|&value| (value as f32).to_le_bytes()).collect();
+        bytes_field(9, &raw, &mut tensor);
+        tensor
+    }
+
+    fn node(op_type: &str, inputs: &[&str], output: &str, trans_b: bool) -> Vec<u8> {
+        let mut node = Vec::new();
+        for input in inputs {
+            bytes_field(1, input.as_bytes(), &mut node);
+        }
+        bytes_field(2, output.as_bytes(), &mut node);
+        bytes_field(4, op_type.as_bytes(), &mut node);
+        if trans_b {
+            let mut attribute = Vec::new();
+            bytes_field(1, b"transB", &mut attribute);
+            varint(3 << 3, &mut attribute);
+            varint(1, &mut attribute);
+            bytes_field(5, &attribute, &mut node);
+        }
+        node
+    }
+
+    fn model(nodes: &[Vec<u8>], initializers: &[Vec<u8>]) -> Vec<u8> {
+        let mut graph = Vec::new();
+        for node in nodes {
+            bytes_field(1, node, &mut graph);
+        }
+        for initializer in initializers {
+            bytes_field(5, initializer, &mut graph);
+        }
+        let mut input = Vec::new();
+        bytes_field(1, b"x", &mut input);
+        bytes_field(11, &input, &mut graph);
+        let mut model = Vec::new();
+        varint(1 << 3, &mut model);
+        varint(7, &mut model);
+        bytes_field(7, &graph, &mut model);
+        model
+    }
+
+    fn sigmoid(x: Float) -> Float {
+        1.0 / (1.0 + (-x).exp())
+    }
+
+    #[test]
+    fn test_from_onnx_bytes() {
+
+        // a hidden layer stored as [out, in] with transB, followed by a softmax output layer
+        let model = model(
+            &[
+                node("Gemm", &["x", "w0", "b0"], "h0", true),
+                node("Tanh", &["h0"], "a0", false),
+                node("Gemm", &["a0", "w1", "b1"], "h1", false),
+                node("Softmax", &["h1"], "y", false),
+            ],
+            &[
+                tensor("w0", &[2, 3], &[0.1, 0.2, 0.3, -0.4, 0.5, -0.6]),
+                tensor("b0", &[2], &[0.05, -0.05]),
+                tensor("w1", &[2, 2], &[1.0, -1.0, 0.5, 2.0]),
+                tensor("b1", &[2], &[0.0, 0.1]),
+            ],
+        );
+
+        let net = Net::from_onnx_bytes(&model).unwrap();
+        assert_eq!(net.input_size(), 3);
+        assert_eq!(net.get_config(), NetConfig::new(3, vec![
+            NetLayerConfig::FullyConnected(2, ActivationFn::LogisticSigmoid { steepness: 2.0, scale: 2.0, y_offset: -1.0 }),
+            NetLayerConfig::SoftmaxCrossEntropy(2),
+        ]));
+
+        let input = [1.0, 0.5, -1.0];
+        let hidden = [
+            (0.1 + 0.2 * 0.5 - 0.3 + 0.05 as Float).tanh(),
+            (-0.4 + 0.5 * 0.5 + 0.6 - 0.05 as Float).tanh(),
+        ];
+        let logits = [
+            hidden[0] + hidden[1] * 0.5,
+            -hidden[0] + hidden[1] * 2.0 + 0.1,
+        ];
+        let expected = sigmoid(logits[0] - logits[1]);
+        let output = net.predict(&input);
+        assert!((output[0] - expected).abs() < 1e-5, "{:?} vs {}", output, expected);
+        assert!((output[0] + output[1] - 1.0).abs() < 1e-5);
+
+    }
+
+    #[test]
+    fn test_unsupported_onnx() {
+
+        let relu = model(
+            &[node("Gemm", &["x", "w0"], "h0", false), node("Relu", &["h0"], "y", false)],
+            &[tensor("w0", &[2, 1], &[1.0, 1.0])],
+        );
+        assert_eq!(Net::from_onnx_bytes(&relu).err(), Some(OnnxError::Unsupported("activation Relu".to_string())));
+
+        assert!(matches!(Net::from_onnx_bytes(&[0x3a, 0x10]), Err(OnnxError::Decode(_))));
+
+    }
+
+}
@@ -0,0 +1,333 @@
+use half::{bf16, f16};
+use serde::{Serialize, Deserialize};
+
+use crate::Float;
+use crate::buffer::RowBuffer;
+use crate::data::{DataSetSchema, Normalizer, PreparedDataSet};
+use crate::func::ErrorFn;
+use crate::graph::{self, LayerInput, OutputHead};
+use crate::layer::{NetLayer, NetLayerBase};
+use crate::net::{self, Net, NetConfig};
+use crate::stats::Stats;
+
+/// A trained net whose weights are stored as f16, halving their memory for serving. Each weight
+/// row is widened to full precision into scratch space as its layer runs, so any net can be converted.
+#[derive(Clone, Debug)]
+pub struct HalfPrecisionNet {
+    input_size: usize,
+    output_size: usize,
+    layers: Box<[NetLayer]>,
+    layer_inputs: Box<[LayerInput]>,
+    heads: Box<[OutputHead]>,
+    /// The weights of each layer, or `None` for layers sharing the weights of an earlier layer
+    weight_rows: Box<[Option<Box<[f16]>>]>,
+    shared_weights: Box<[Option<usize>]>,
+    max_weight_row_size: usize,
+    config: NetConfig,
+    schema: Option<DataSetSchema>,
+    input_normalizer: Option<Normalizer>,
+    output_normalizer: Option<Normalizer>,
+}
+
+impl HalfPrecisionNet {
+
+    pub fn from_net(net: &Net) -> Self {
+        let weight_rows = (0..net.num_layers())
+            .map(|index| match net.shared_weights(index) {
+                Some(_) => None,
+                None => Some(net.get_weights().get_row(index).iter().map(|&w| f16::from_f64(w as f64)).collect()),
+            })
+            .collect();
+        HalfPrecisionNet {
+            input_size: net.input_size(),
+            output_size: net.output_size(),
+            layers: net.layer_iter().cloned().collect(),
+            layer_inputs: net.layer_inputs().into(),
+            heads: net.output_heads().into(),
+            weight_rows,
+            shared_weights: (0..net.num_layers()).map(|index| net.shared_weights(index)).collect(),
+            max_weight_row_size: net.layer_iter().map(NetLayer::weight_buffer_size).max().unwrap(),
+            config: net.get_config(),
+            schema: net.schema().cloned(),
+            input_normalizer: net.input_normalizer().cloned(),
+            output_normalizer: net.output_normalizer().cloned(),
+        }
+    }
+
+    /// Widens the weights back to full precision, e.g. to continue training
+    pub fn to_net(&self) -> Net {
+        let mut net = self.config.create_net();
+        net.set_schema(self.schema.clone());
+        net.set_input_normalizer(self.input_normalizer.clone());
+        net.set_output_normalizer(self.output_normalizer.clone());
+        for index in 0..self.layers.len() {
+            if let Some(row) = &self.weight_rows[index] {
+                for (target, &weight) in net.get_weights_mut().get_row_mut(index).iter_mut().zip(row.iter()) {
+                    *target = weight.to_f32() as Float;
+                }
+            }
+        }
+        net
+    }
+
+    fn widen_weights(&self, layer_index: usize, target: &mut Vec<Float>) {
+        let row_index = self.shared_weights[layer_index].unwrap_or(layer_index);
+        target.clear();
+        target.extend(self.weight_rows[row_index].as_ref().unwrap().iter().map(|w| w.to_f32() as Float));
+    }
+
+    pub fn predict(&self, input: &[Float]) -> Vec<Float> {
+        assert_eq!(input.len(), self.input_size);
+        let mut input = input.to_vec();
+        if let Some(normalizer) = &self.input_normalizer {
+            normalizer.normalize(&mut input);
+        }
+        let mut weights = Vec::with_capacity(self.max_weight_row_size);
+        let input_sizes: Vec<usize> = self.layers.iter().map(NetLayer::input_size).collect();
+        let output_sizes: Vec<usize> = self.layers.iter().map(NetLayer::output_size).collect();
+        let (mut layer_outputs, mut layer_inputs) = graph::new_graph_buffers(&self.layer_inputs, &input_sizes, &output_sizes);
+        graph::forward_graph(&self.layer_inputs, &input, self.layers.len(), &mut layer_outputs, &mut layer_inputs, |layer_index, input, output| {
+            self.widen_weights(layer_index, &mut weights);
+            self.layers[layer_index].forward_pass(&weights, input, output);
+        });
+        let mut outputs = Vec::with_capacity(self.output_size);
+        graph::collect_outputs(&self.heads, &layer_outputs, &mut outputs);
+        if let Some(normalizer) = &self.output_normalizer {
+            normalizer.denormalize(&mut outputs);
+        }
+        outputs
+    }
+
+    #[inline]
+    pub fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    #[inline]
+    pub fn output_size(&self) -> usize {
+        self.output_size
+    }
+
+    /// The size of the stored weights in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.weight_rows.iter().flatten().map(|row| row.len() * 2).sum()
+    }
+
+    pub fn compute_error(&self, data_set: &PreparedDataSet, error_fn: &ErrorFn) -> Stats {
+        let mut error_stats = Stats::new();
+        for (inputs, expected_outputs) in data_set {
+            let outputs = self.predict(inputs);
+            error_stats.report(net::output_error(&self.layers, &self.heads, expected_outputs, &outputs, error_fn));
+        }
+        error_stats
+    }
+
+    /// The absolute differences of every output to those of the full precision net, over the data set
+    pub fn compare_to(&self, net: &Net, data_set: &PreparedDataSet) -> Stats {
+        compare_predictions(data_set, |input| net.predict(input), |input| self.predict(input))
+    }
+
+}
+
+/// The absolute differences between every output of two models over the inputs of the data set,
+/// e.g. to check the precision lost by converting a net
+pub fn compare_predictions(
+    data_set: &PreparedDataSet,
+    reference: impl Fn(&[Float]) -> Vec<Float>,
+    candidate: impl Fn(&[Float]) -> Vec<Float>,
+) -> Stats {
+    let mut differences = Stats::new();
+    for (inputs, _) in data_set {
+        for (a, b) in reference(inputs).iter().zip(&candidate(inputs)) {
+            differences.report((a - b).abs());
+        }
+    }
+    differences
+}
+
+/// A 16-bit float format
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HalfFormat {
+    F16,
+    /// The exponent range of f32 with less precision than f16, so it rarely overflows
+    Bf16,
+}
+
+impl HalfFormat {
+
+    /// The nearest value the format can hold, or infinity if it overflows
+    pub fn round(&self, value: Float) -> Float {
+        match self {
+            HalfFormat::F16 => f16::from_f64(value as f64).to_f64() as Float,
+            HalfFormat::Bf16 => bf16::from_f64(value as f64).to_f64() as Float,
+        }
+    }
+
+}
+
+/// Trains with the weights rounded to a 16-bit format for the forward and backward passes, while
+/// the updates are applied to a full precision master copy, see `BackpropOptions::mixed_precision`.
+/// The weight deltas of each mini-batch are multiplied by the loss scale before being rounded,
+/// so small updates don't underflow, and divided by it again before they are applied. A
+/// mini-batch whose scaled deltas overflow is skipped and halves the scale, and every
+/// `growth_interval` mini-batches without overflow double it.
+///
+/// The 16-bit arithmetic is emulated by rounding, so this models the accuracy of mixed-precision
+/// training, but doesn't speed anything up until there are 16-bit kernels.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MixedPrecision {
+    pub format: HalfFormat,
+    pub initial_loss_scale: Float,
+    pub growth_interval: usize,
+}
+
+impl MixedPrecision {
+
+    /// A loss scale of 2^16 which grows every 2000 mini-batches
+    pub fn new(format: HalfFormat) -> Self {
+        MixedPrecision {
+            format,
+            initial_loss_scale: 65536.0,
+            growth_interval: 2000,
+        }
+    }
+
+}
+
+/// The loss scale and master weights of a training context using mixed precision
+#[derive(Clone, Debug)]
+pub(crate) struct MixedPrecisionState {
+    options: MixedPrecision,
+    loss_scale: Float,
+    batches_since_overflow: usize,
+    skipped_batches: usize,
+    master_weights: RowBuffer,
+}
+
+impl MixedPrecisionState {
+
+    pub(crate) fn new(options: MixedPrecision, weights: &RowBuffer) -> Self {
+        assert!(options.initial_loss_scale > 0.0 && options.growth_interval > 0);
+        MixedPrecisionState {
+            options,
+            loss_scale: options.initial_loss_scale,
+            batches_since_overflow: 0,
+            skipped_batches: 0,
+            master_weights: weights.clone(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn loss_scale(&self) -> Float {
+        self.loss_scale
+    }
+
+    #[inline]
+    pub(crate) fn skipped_batches(&self) -> usize {
+        self.skipped_batches
+    }
+
+    /// Keeps the weights as the master copy, and rounds them for the next mini-batch
+    pub(crate) fn start_batch(&mut self, weights: &mut RowBuffer) {
+        weights.copy_into(&mut self.master_weights);
+        for weight in weights.get_buffer_mut() {
+            *weight = self.options.format.round(*weight);
+        }
+    }
+
+    /// Restores the master copy of the weights and applies the deltas to it, unless the scaled
+    /// deltas overflow. Returns whether the deltas were applied.
+    pub(crate) fn finish_batch(&mut self, weights: &mut RowBuffer, deltas: &mut RowBuffer) -> bool {
+        self.master_weights.copy_into(weights);
+        let mut overflowed = false;
+        for delta in deltas.get_buffer_mut() {
+            let scaled = self.options.format.round(*delta * self.loss_scale);
+            overflowed |= !scaled.is_finite();
+            *delta = scaled / self.loss_scale;
+        }
+        if overflowed {
+            self.loss_scale /= 2.0;
+            self.batches_since_overflow = 0;
+            self.skipped_batches += 1;
+            return false;
+        }
+        weights.add(deltas);
+        self.batches_since_overflow += 1;
+        if self.batches_since_overflow >= self.options.growth_interval {
+            self.loss_scale *= 2.0;
+            self.batches_since_overflow = 0;
+        }
+        true
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::lines_2x2;
+    use crate::func::ActivationFn;
+    use crate::graph::LayerSource;
+    use crate::initializer::RandomNetInitializer;
+    use crate::layer::NetLayerConfig;
+
+    #[test]
+    fn test_half_precision_net() {
+
+        let data_set = lines_2x2();
+
+        // a shared residual block followed by an output layer which also sees the inputs
+        let hidden = NetLayerConfig::FullyConnected(4, ActivationFn::standard_logistic_sigmoid());
+        let output = NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid());
+        let config = NetConfig::new(4, vec![hidden.clone(), hidden, output])
+            .with_shared_weights(1, 0)
+            .with_input(1, LayerInput::Sum(vec![LayerSource::Layer(0), LayerSource::Input]))
+            .with_input(2, LayerInput::Concat(vec![LayerSource::Layer(1), LayerSource::Input]));
+        let mut net = config.create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("half"));
+
+        let half = HalfPrecisionNet::from_net(&net);
+        assert_eq!(half.size_bytes(), (20 + 18) * 2);
+
+        let differences = half.compare_to(&net, &data_set);
+        assert_eq!(differences.count() as usize, data_set.num_rows() * 2);
+        assert!(differences.max() < 0.005, "{:?}", differences);
+        assert!(half.compute_error(&data_set, &ErrorFn::SquaredError).mean() > 0.0);
+
+        let widened = half.to_net();
+        assert_eq!(widened.get_config(), net.get_config());
+        for (&a, &b) in widened.get_weights().get_buffer().iter().zip(net.get_weights().get_buffer()) {
+            assert!((a - b).abs() <= b.abs() / 1000.0 + 1e-6);
+        }
+        assert_eq!(compare_predictions(&data_set, |input| widened.predict(input), |input| half.predict(input)).max(), 0.0);
+
+    }
+
+    #[test]
+    fn test_mixed_precision_training() {
+
+        let data_set = lines_2x2();
+        let mut net = NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("mixed"));
+        let initial = net.clone();
+
+        let mut context = net.get_training_context();
+        let before = context.compute_error_for_batch(&data_set, &ErrorFn::SquaredError).mean();
+        context.set_mixed_precision(MixedPrecision { growth_interval: 10, ..MixedPrecision::new(HalfFormat::F16) });
+        for _ in 0..20 {
+            context.train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, None, None);
+        }
+        assert_eq!(context.mixed_precision_state(), Some((262144.0, 0)));
+        assert!(context.compute_error_for_batch(&data_set, &ErrorFn::SquaredError).mean() < before);
+
+        // scaled deltas past the range of f16 skip the mini-batch and halve the scale
+        let mut net = initial.clone();
+        let mut context = net.get_training_context();
+        context.set_mixed_precision(MixedPrecision { initial_loss_scale: 1e30, ..MixedPrecision::new(HalfFormat::F16) });
+        context.train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, None, None);
+        assert_eq!(context.mixed_precision_state(), Some((5e29, 1)));
+        assert_eq!(net.get_weights().get_buffer(), initial.get_weights().get_buffer());
+
+    }
+
+}
@@ -0,0 +1,290 @@
+use std::mem;
+
+use serde::{Serialize, Deserialize};
+
+use crate::Float;
+use crate::compression::{int8_quantization_params, quantize_int8};
+use crate::data::{Normalizer, PreparedDataSet};
+use crate::func::ActivationFn;
+use crate::layer::{NetLayerBase, NetLayerConfig};
+use crate::net::Net;
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum QuantizationError {
+        NotAChain {
+            description("Only nets whose layers form a simple chain can be quantized")
+            display("Only nets whose layers form a simple chain can be quantized")
+        }
+        UnsupportedLayer(layer_index: usize) {
+            description("Only fully connected and softmax layers can be quantized")
+            display("Layer {} is not a fully connected or softmax layer", layer_index)
+        }
+        NoCalibrationData {
+            description("The calibration data set has no rows")
+            display("The calibration data set has no rows")
+        }
+    }
+}
+
+/// A dense layer with int8 weights, whose inputs are int8 values quantized with the scale and
+/// zero point of the layer before
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct QuantizedLayer {
+    input_size: usize,
+    size: usize,
+    input_scale: Float,
+    input_zero_point: i8,
+    /// The weights of each node, one after another
+    weights: Vec<i8>,
+    weight_scale: Float,
+    weight_zero_point: i8,
+    biases: Vec<Float>,
+    /// The activation function, or `None` for a softmax output layer
+    activation_fn: Option<ActivationFn>,
+    /// The quantization of the outputs, which covers the range of the activation function
+    output_scale: Float,
+    output_zero_point: i8,
+}
+
+impl QuantizedLayer {
+
+    /// The weighted inputs of each node, summed in integer arithmetic
+    fn weighted_sums(&self, input: &[i8], output: &mut [Float]) {
+        debug_assert_eq!(input.len(), self.input_size);
+        let multiplier = self.input_scale * self.weight_scale;
+        for ((sum, weights), &bias) in output.iter_mut().zip(self.weights.chunks_exact(self.input_size)).zip(&self.biases) {
+            let mut accumulator = 0i32;
+            for (&input, &weight) in input.iter().zip(weights) {
+                accumulator += (input as i32 - self.input_zero_point as i32) * (weight as i32 - self.weight_zero_point as i32);
+            }
+            *sum = accumulator as Float * multiplier + bias;
+        }
+    }
+
+    /// The activations of the layer, before they are quantized
+    fn activations(&self, input: &[i8]) -> Vec<Float> {
+        let mut output = vec![0.0; self.size];
+        self.weighted_sums(input, &mut output);
+        match self.activation_fn {
+            Some(activation_fn) => {
+                for value in output.iter_mut() {
+                    *value = activation_fn.get_activation(*value);
+                }
+            },
+            None => {
+                let max = output.iter().cloned().fold(Float::NEG_INFINITY, Float::max);
+                let mut sum = 0.0;
+                for value in output.iter_mut() {
+                    *value = (*value - max).exp();
+                    sum += *value;
+                }
+                for value in output.iter_mut() {
+                    *value /= sum;
+                }
+            },
+        }
+        output
+    }
+
+}
+
+/// An int8 inference model of a trained net, e.g. for embedded deployment. Inputs and the outputs
+/// of hidden layers are quantized, so the weighted sums only need integer multiplications; each
+/// value is only converted to a float once per node to apply the activation function.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuantizedNet {
+    layers: Vec<QuantizedLayer>,
+    /// The normalizers of the net, applied to the float inputs and outputs as by the net
+    #[serde(default)]
+    input_normalizer: Option<Normalizer>,
+    #[serde(default)]
+    output_normalizer: Option<Normalizer>,
+}
+
+impl QuantizedNet {
+
+    /// Quantizes the net. The quantization of the inputs covers the range of the inputs of the
+    /// calibration data set, so that should be representative of the inputs seen in deployment.
+    /// Its inputs are normalized by the input normalizer of the net, like those of `predict`.
+    pub fn from_net(net: &Net, calibration_data: &PreparedDataSet) -> Result<QuantizedNet, QuantizationError> {
+
+        if !net.is_chain() {
+            return Err(QuantizationError::NotAChain);
+        }
+        if calibration_data.num_rows() == 0 {
+            return Err(QuantizationError::NoCalibrationData);
+        }
+        let mut input_range = Vec::with_capacity(2);
+        let mut normalized_inputs = Vec::with_capacity(net.input_size());
+        for (inputs, _) in calibration_data {
+            normalized_inputs.clear();
+            normalized_inputs.extend_from_slice(inputs);
+            if let Some(normalizer) = net.input_normalizer() {
+                normalizer.normalize(&mut normalized_inputs);
+            }
+            input_range.push(normalized_inputs.iter().cloned().fold(Float::INFINITY, Float::min));
+            input_range.push(normalized_inputs.iter().cloned().fold(Float::NEG_INFINITY, Float::max));
+        }
+        let (mut input_scale, mut input_zero_point) = int8_quantization_params(&input_range);
+
+        let mut layers = Vec::with_capacity(net.num_layers());
+        for (layer_index, layer) in net.layer_iter().enumerate() {
+            let (size, activation_fn) = match layer.get_config() {
+                NetLayerConfig::FullyConnected(size, activation_fn) => (size, Some(activation_fn)),
+                NetLayerConfig::SoftmaxCrossEntropy(size) => (size, None),
+                _ => return Err(QuantizationError::UnsupportedLayer(layer_index)),
+            };
+            let input_size = layer.input_size();
+            let (dense_weights, biases) = net.layer_weights(layer_index)
+                .ok_or(QuantizationError::UnsupportedLayer(layer_index))?;
+            let (weight_scale, weight_zero_point) = int8_quantization_params(dense_weights);
+            let mut weights = Vec::with_capacity(dense_weights.len());
+            for node_index in 0..size {
+                for input_index in 0..input_size {
+                    let weight = net.connection_weight(layer_index, input_index, node_index).unwrap();
+                    weights.push(quantize_int8(weight, weight_scale, weight_zero_point));
+                }
+            }
+            let (output_scale, output_zero_point) = match activation_fn {
+                Some(ActivationFn::LogisticSigmoid { scale, y_offset, .. }) => {
+                    int8_quantization_params(&[y_offset, y_offset + scale])
+                },
+                None => int8_quantization_params(&[0.0, 1.0]),
+            };
+            layers.push(QuantizedLayer {
+                input_size,
+                size,
+                input_scale,
+                input_zero_point,
+                weights,
+                weight_scale,
+                weight_zero_point,
+                biases: biases.to_vec(),
+                activation_fn,
+                output_scale,
+                output_zero_point,
+            });
+            input_scale = output_scale;
+            input_zero_point = output_zero_point;
+        }
+
+        Ok(QuantizedNet {
+            layers,
+            input_normalizer: net.input_normalizer().cloned(),
+            output_normalizer: net.output_normalizer().cloned(),
+        })
+
+    }
+
+    pub fn predict(&self, input: &[Float]) -> Vec<Float> {
+        assert_eq!(input.len(), self.input_size());
+        let mut input = input.to_vec();
+        if let Some(normalizer) = &self.input_normalizer {
+            normalizer.normalize(&mut input);
+        }
+        let first = &self.layers[0];
+        let mut quantized: Vec<i8> = input.iter()
+            .map(|&value| quantize_int8(value, first.input_scale, first.input_zero_point))
+            .collect();
+        let (last, hidden) = self.layers.split_last().unwrap();
+        for layer in hidden {
+            quantized = layer.activations(&quantized).iter()
+                .map(|&value| quantize_int8(value, layer.output_scale, layer.output_zero_point))
+                .collect();
+        }
+        let mut outputs = last.activations(&quantized);
+        if let Some(normalizer) = &self.output_normalizer {
+            normalizer.denormalize(&mut outputs);
+        }
+        outputs
+    }
+
+    #[inline]
+    pub fn input_size(&self) -> usize {
+        self.layers[0].input_size
+    }
+
+    #[inline]
+    pub fn output_size(&self) -> usize {
+        self.layers.last().unwrap().size
+    }
+
+    /// The size of the weights and quantization parameters in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.layers.iter()
+            // the biases are floats, and each layer has three scales and zero points
+            .map(|layer| layer.weights.len() + layer.biases.len() * mem::size_of::<Float>() + 3 * 5)
+            .sum()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::lines_2x2;
+    use crate::net::NetConfig;
+    use crate::initializer::RandomNetInitializer;
+
+    #[test]
+    fn test_quantized_net() {
+
+        let data_set = lines_2x2();
+
+        let mut net = NetConfig::new(4, vec![
+            NetLayerConfig::FullyConnected(6, ActivationFn::standard_logistic_sigmoid_neg()),
+            NetLayerConfig::FullyConnected(4, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::SoftmaxCrossEntropy(2),
+        ]).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("quantized"));
+
+        let quantized = QuantizedNet::from_net(&net, &data_set).unwrap();
+        assert_eq!((quantized.input_size(), quantized.output_size()), (4, 2));
+        let float_size = net.get_weights().buffer_len() * mem::size_of::<Float>();
+        assert!(quantized.size_bytes() < float_size, "{} vs {}", quantized.size_bytes(), float_size);
+
+        for (inputs, _) in &data_set {
+            let expected = net.predict(inputs);
+            let output = quantized.predict(inputs);
+            for (a, b) in output.iter().zip(&expected) {
+                assert!((a - b).abs() < 0.02, "{:?} vs {:?}", output, expected);
+            }
+        }
+
+        let batch_norm = NetConfig::new(4, vec![
+            NetLayerConfig::BatchNorm { momentum: 0.9 },
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid()),
+        ]).create_net();
+        assert_eq!(QuantizedNet::from_net(&batch_norm, &data_set).err(), Some(QuantizationError::UnsupportedLayer(0)));
+
+    }
+
+    #[test]
+    fn test_quantized_net_normalizers() {
+
+        let data_set = lines_2x2();
+        // raw inputs far outside of the range of the normalized ones
+        let inputs: Vec<Float> = data_set.iter().flat_map(|(inputs, _)| inputs.iter().map(|value| value * 100.0 + 50.0)).collect();
+        let targets: Vec<Float> = data_set.iter().flat_map(|(_, targets)| targets.iter().map(|value| value * 10.0)).collect();
+        let scaled = PreparedDataSet::from_row_major(&inputs, &targets, 4, 2);
+        let (_, input_normalizer) = scaled.normalize_inputs_z_score();
+        let (_, output_normalizer) = scaled.normalize_outputs_z_score();
+
+        let mut net = NetConfig::new_fully_connected(4, 2, [6], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("quantized"));
+        net.set_input_normalizer(Some(input_normalizer));
+        net.set_output_normalizer(Some(output_normalizer));
+
+        let quantized = QuantizedNet::from_net(&net, &scaled).unwrap();
+        for (inputs, _) in &scaled {
+            let expected = net.predict(inputs);
+            let output = quantized.predict(inputs);
+            for (a, b) in output.iter().zip(&expected) {
+                assert!((a - b).abs() < 0.05, "{:?} vs {:?}", output, expected);
+            }
+        }
+
+    }
+
+}
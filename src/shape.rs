@@ -0,0 +1,138 @@
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+
+use crate::layer::NetLayerConfig;
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum ShapeError {
+        IncompatibleInput(layer_index: usize, shape: Shape, reason: String) {
+            description("Layer can't take inputs of this shape")
+            display("Layer {} can't take inputs of shape {}: {}", layer_index, shape, reason)
+        }
+    }
+}
+
+/// The dimensions of the values passed between layers, outermost first, e.g.
+/// `[channels, height, width]` for images. The values themselves are always stored flat in
+/// row-major order, so this is metadata which allows shapes to be checked between layers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Shape(Vec<usize>);
+
+impl Shape {
+
+    pub fn new(dims: impl Into<Vec<usize>>) -> Self {
+        let dims = dims.into();
+        assert!(!dims.is_empty() && dims.iter().all(|&d| d > 0), "invalid shape {:?}", dims);
+        Shape(dims)
+    }
+
+    /// A single dimension of the given size
+    pub fn flat(size: usize) -> Self {
+        Shape::new(vec![size])
+    }
+
+    #[inline]
+    pub fn dims(&self) -> &[usize] {
+        &self.0
+    }
+
+    #[inline]
+    pub fn rank(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The number of values
+    pub fn size(&self) -> usize {
+        self.0.iter().product()
+    }
+
+    /// Concatenates shapes along their outermost dimension if the remaining dimensions match,
+    /// e.g. stacking the channels of feature maps, and flattens them otherwise
+    pub fn concat<'s>(shapes: impl IntoIterator<Item = &'s Shape>) -> Shape {
+        let shapes: Vec<&Shape> = shapes.into_iter().collect();
+        assert!(!shapes.is_empty());
+        if shapes.iter().all(|shape| shape.0[1..] == shapes[0].0[1..]) {
+            let mut dims = shapes[0].0.clone();
+            dims[0] = shapes.iter().map(|shape| shape.0[0]).sum();
+            Shape(dims)
+        } else {
+            Shape::flat(shapes.iter().map(|shape| shape.size()).sum())
+        }
+    }
+
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl NetLayerConfig {
+
+    /// The shape of the outputs of the layer at `layer_index` given the shape of its inputs, or
+    /// `None` for custom layers, whose outputs are treated as flat
+    pub fn output_shape(&self, layer_index: usize, input_shape: &Shape) -> Result<Option<Shape>, ShapeError> {
+        let require_flat = || if input_shape.rank() == 1 {
+            Ok(())
+        } else {
+            Err(ShapeError::IncompatibleInput(layer_index, input_shape.clone(), "dense layers need a flat input, add a Flatten layer".to_string()))
+        };
+        match *self {
+            NetLayerConfig::FullyConnected(size, _)
+            | NetLayerConfig::SoftmaxCrossEntropy(size)
+            | NetLayerConfig::SparseFullyConnected(size, _, _) => {
+                require_flat()?;
+                Ok(Some(Shape::flat(size)))
+            },
+            NetLayerConfig::BatchNorm { .. } => Ok(Some(input_shape.clone())),
+            NetLayerConfig::GlobalAveragePooling { channels } => {
+                // flat inputs are taken to be channel-major feature maps
+                let valid = match input_shape.rank() {
+                    1 => input_shape.size().is_multiple_of(channels),
+                    _ => input_shape.dims()[0] == channels,
+                };
+                if valid {
+                    Ok(Some(Shape::flat(channels)))
+                } else {
+                    Err(ShapeError::IncompatibleInput(layer_index, input_shape.clone(), format!("expected {} channels", channels)))
+                }
+            },
+            NetLayerConfig::Flatten => Ok(Some(Shape::flat(input_shape.size()))),
+            NetLayerConfig::Custom { .. } => Ok(None),
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::func::ActivationFn;
+
+    #[test]
+    fn test_output_shapes() {
+
+        let image = Shape::new([3, 8, 8]);
+        assert_eq!(image.size(), 192);
+        assert_eq!(image.to_string(), "[3, 8, 8]");
+
+        let pooling = NetLayerConfig::GlobalAveragePooling { channels: 3 };
+        assert_eq!(pooling.output_shape(0, &image), Ok(Some(Shape::flat(3))));
+        assert!(NetLayerConfig::GlobalAveragePooling { channels: 4 }.output_shape(0, &image).is_err());
+        // a flat input only needs to divide into the channels
+        assert!(NetLayerConfig::GlobalAveragePooling { channels: 4 }.output_shape(0, &Shape::flat(192)).is_ok());
+
+        let dense = NetLayerConfig::FullyConnected(4, ActivationFn::standard_logistic_sigmoid());
+        assert!(matches!(dense.output_shape(1, &image), Err(ShapeError::IncompatibleInput(1, _, _))));
+        assert_eq!(NetLayerConfig::Flatten.output_shape(0, &image), Ok(Some(Shape::flat(192))));
+        assert_eq!(NetLayerConfig::BatchNorm { momentum: 0.9 }.output_shape(0, &image), Ok(Some(image.clone())));
+
+        assert_eq!(Shape::concat(&[image.clone(), Shape::new([2, 8, 8])]), Shape::new([5, 8, 8]));
+        assert_eq!(Shape::concat(&[image, Shape::flat(4)]), Shape::flat(196));
+
+    }
+
+}
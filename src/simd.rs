@@ -0,0 +1,97 @@
+//! Element-wise kernels of the layer math and `RowBuffer` arithmetic. With the `simd` feature
+//! (nightly only) they use `std::simd`, otherwise plain loops the compiler vectorizes where it
+//! can. Every lane computes the same expression as the scalar loop, without fused multiply-adds,
+//! so both give identical results. Reductions such as dot products are left scalar for the same
+//! reason.
+
+use crate::Float;
+
+#[cfg(feature = "simd")]
+mod lanes {
+    use std::simd::Simd;
+    use crate::Float;
+
+    #[cfg(not(feature = "f64"))]
+    pub const LANES: usize = 8;
+    #[cfg(feature = "f64")]
+    pub const LANES: usize = 4;
+
+    pub type FloatVec = Simd<Float, LANES>;
+}
+
+/// `y += a * x`
+#[inline]
+pub fn axpy(a: Float, x: &[Float], y: &mut [Float]) {
+    assert_eq!(x.len(), y.len());
+    #[cfg(feature = "simd")]
+    let (x, y) = {
+        use self::lanes::{FloatVec, LANES};
+        let split = x.len() - x.len() % LANES;
+        let a_vec = FloatVec::splat(a);
+        for (x, y) in x[..split].chunks_exact(LANES).zip(y[..split].chunks_exact_mut(LANES)) {
+            (FloatVec::from_slice(y) + a_vec * FloatVec::from_slice(x)).copy_to_slice(y);
+        }
+        (&x[split..], &mut y[split..])
+    };
+    for (&x, y) in x.iter().zip(y.iter_mut()) {
+        *y += a * x;
+    }
+}
+
+/// `y += x`
+#[inline]
+pub fn add_assign(y: &mut [Float], x: &[Float]) {
+    assert_eq!(x.len(), y.len());
+    #[cfg(feature = "simd")]
+    let (x, y) = {
+        use self::lanes::{FloatVec, LANES};
+        let split = x.len() - x.len() % LANES;
+        for (x, y) in x[..split].chunks_exact(LANES).zip(y[..split].chunks_exact_mut(LANES)) {
+            (FloatVec::from_slice(y) + FloatVec::from_slice(x)).copy_to_slice(y);
+        }
+        (&x[split..], &mut y[split..])
+    };
+    for (&x, y) in x.iter().zip(y.iter_mut()) {
+        *y += x;
+    }
+}
+
+/// `y -= x`
+#[inline]
+pub fn sub_assign(y: &mut [Float], x: &[Float]) {
+    assert_eq!(x.len(), y.len());
+    #[cfg(feature = "simd")]
+    let (x, y) = {
+        use self::lanes::{FloatVec, LANES};
+        let split = x.len() - x.len() % LANES;
+        for (x, y) in x[..split].chunks_exact(LANES).zip(y[..split].chunks_exact_mut(LANES)) {
+            (FloatVec::from_slice(y) - FloatVec::from_slice(x)).copy_to_slice(y);
+        }
+        (&x[split..], &mut y[split..])
+    };
+    for (&x, y) in x.iter().zip(y.iter_mut()) {
+        *y -= x;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kernels() {
+
+        // long enough for full lanes and a remainder
+        let x: Vec<Float> = (0..19).map(|i| i as Float * 0.25).collect();
+        let mut y: Vec<Float> = (0..19).map(|i| 1.0 - i as Float).collect();
+        let expected: Vec<Float> = x.iter().zip(&y).map(|(x, y)| y + 3.0 * x).collect();
+        axpy(3.0, &x, &mut y);
+        assert_eq!(y, expected);
+
+        add_assign(&mut y, &x);
+        sub_assign(&mut y, &x);
+        assert_eq!(y, expected);
+
+    }
+
+}
@@ -1,15 +1,22 @@
-use std::{f32, fmt};
-use crate::utils::square_f32;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::Float;
+use crate::utils::square;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Stats {
+    #[serde(with = "crate::utils::serde_nan")]
     sum: f64,
     count: u32,
-    max: f32,
-    min: f32,
+    #[serde(with = "crate::utils::serde_nan")]
+    max: Float,
+    #[serde(with = "crate::utils::serde_nan")]
+    min: Float,
     // variables for variance computation
     // see https://stackoverflow.com/a/897463
+    #[serde(with = "crate::utils::serde_nan")]
     var_m: f64,
+    #[serde(with = "crate::utils::serde_nan")]
     var_s: f64,
 }
 
@@ -26,21 +33,20 @@ impl fmt::Debug for Stats {
     }
 }
 
-#[allow(dead_code)]
 impl Stats {
 
     pub fn new() -> Self {
         Stats {
             sum: 0.0,
             count: 0,
-            max: f32::NAN,
-            min: f32::NAN,
+            max: Float::NAN,
+            min: Float::NAN,
             var_m: 0.0,
             var_s: 0.0,
         }
     }
 
-    pub fn report(&mut self, value: f32) {
+    pub fn report(&mut self, value: Float) {
         let val64 = value as f64;
         self.sum += val64;
         self.count += 1;
@@ -78,12 +84,12 @@ impl Stats {
     }
 
     #[inline]
-    pub fn max(&self) -> f32 {
+    pub fn max(&self) -> Float {
         self.max
     }
 
     #[inline]
-    pub fn min(&self) -> f32 {
+    pub fn min(&self) -> Float {
         self.min
     }
 
@@ -97,16 +103,58 @@ impl Stats {
         self.count
     }
 
+    /// Combines the stats of another set of values into these, as if all values had been
+    /// reported here. Allows stats to be computed in parallel over partitions of the data.
+    /// See https://en.wikipedia.org/wiki/Algorithms_for_calculating_variance#Parallel_algorithm
+    pub fn merge(&mut self, other: &Stats) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+        let count = self.count as f64 + other.count as f64;
+        let delta = other.var_m - self.var_m;
+        self.var_m += delta * other.count as f64 / count;
+        self.var_s += other.var_s + delta * delta * self.count as f64 * other.count as f64 / count;
+        self.sum += other.sum;
+        self.count += other.count;
+        self.min = Float::min(self.min, other.min);
+        self.max = Float::max(self.max, other.max);
+    }
+
+    /// The stats of each column of the rows, which must all have the same number of columns
+    pub fn of_columns<'a>(rows: impl IntoIterator<Item = &'a [Float]>) -> Vec<Stats> {
+        let mut columns: Vec<Stats> = Vec::new();
+        for (index, row) in rows.into_iter().enumerate() {
+            if index == 0 {
+                columns.resize(row.len(), Stats::new());
+            }
+            assert_eq!(row.len(), columns.len(), "rows have different numbers of columns");
+            for (stats, &value) in columns.iter_mut().zip(row) {
+                stats.report(value);
+            }
+        }
+        columns
+    }
+
     pub fn reset(&mut self) {
         self.sum = 0.0;
         self.count = 0;
-        self.max = f32::NAN;
-        self.min = f32::NAN;
+        self.max = Float::NAN;
+        self.min = Float::NAN;
         self.var_m = 0.0;
         self.var_s = 0.0;
     }
 }
 
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new()
+    }
+}
+
 
 #[derive(Clone)]
 pub struct ConfusionMatrix {
@@ -117,7 +165,6 @@ pub struct ConfusionMatrix {
     false_negative: u32,
 }
 
-#[allow(dead_code)]
 impl ConfusionMatrix {
 
     pub fn new() -> Self {
@@ -156,28 +203,34 @@ impl ConfusionMatrix {
         self.false_negative = 0;
     }
 
-    pub fn true_positive_rate(&self) -> f32 {
-        return self.true_positive as f32 / self.count as f32
+    pub fn true_positive_rate(&self) -> Float {
+        return self.true_positive as Float / self.count as Float
     }
 
-    pub fn false_positive_rate(&self) -> f32 {
-        return self.false_positive as f32 / self.count as f32
+    pub fn false_positive_rate(&self) -> Float {
+        return self.false_positive as Float / self.count as Float
     }
 
-    pub fn true_negative_rate(&self) -> f32 {
-        return self.true_negative as f32 / self.count as f32
+    pub fn true_negative_rate(&self) -> Float {
+        return self.true_negative as Float / self.count as Float
     }
 
-    pub fn false_negative_rate(&self) -> f32 {
-        return self.false_negative as f32 / self.count as f32
+    pub fn false_negative_rate(&self) -> Float {
+        return self.false_negative as Float / self.count as Float
     }
 
-    pub fn error_rate(&self) -> f32 {
-        return (self.false_negative as f32 + self.false_positive as f32) / self.count as f32
+    pub fn error_rate(&self) -> Float {
+        return (self.false_negative as Float + self.false_positive as Float) / self.count as Float
     }
 
 }
 
+impl Default for ConfusionMatrix {
+    fn default() -> Self {
+        ConfusionMatrix::new()
+    }
+}
+
 impl ToString for ConfusionMatrix {
     fn to_string(&self) -> String {
         format!("[t+ = {}, t- = {}, f+ = {}, f- = {}]",
@@ -189,12 +242,10 @@ impl ToString for ConfusionMatrix {
     }
 }
 
-#[allow(dead_code)]
 pub struct ConfusionMatrices {
     matrices: Vec<(usize, Option<String>, ConfusionMatrix)>
 }
 
-#[allow(dead_code)]
 impl ConfusionMatrices {
 
     #[inline]
@@ -224,6 +275,120 @@ impl ConfusionMatrices {
 
 }
 
+/// Collects (score, actual) pairs of a binary classifier output for threshold-free ranking
+/// evaluation, i.e. ROC and precision-recall curves. The curves and their summaries are `None`
+/// when undefined, i.e. without any positive, or for ROC also without any negative, sample.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RankingStats {
+    samples: Vec<(Float, bool)>,
+}
+
+impl RankingStats {
+
+    pub fn new() -> Self {
+        RankingStats {
+            samples: Vec::new()
+        }
+    }
+
+    #[inline]
+    pub fn record(&mut self, score: Float, actual: bool) {
+        self.samples.push((score, actual));
+    }
+
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Adds the samples of other stats, e.g. those of another partition of the data
+    pub fn merge(&mut self, other: &RankingStats) {
+        self.samples.extend_from_slice(&other.samples);
+    }
+
+    /// Cumulative (true positive, false positive) counts for every distinct score, using each
+    /// score in descending order as the threshold for a positive classification.
+    fn cumulative_counts(&self) -> (Vec<(u32, u32)>, u32, u32) {
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        let mut counts = Vec::new();
+        let (mut tp, mut fp) = (0u32, 0u32);
+        for (i, &(score, actual)) in sorted.iter().enumerate() {
+            if actual {
+                tp += 1;
+            } else {
+                fp += 1;
+            }
+            // only emit a point once all samples sharing a score have been counted
+            if i + 1 == sorted.len() || sorted[i + 1].0 != score {
+                counts.push((tp, fp));
+            }
+        }
+        (counts, tp, fp)
+    }
+
+    /// Points of the ROC curve as (false positive rate, true positive rate), ordered by
+    /// decreasing threshold and starting at (0, 0).
+    pub fn roc_curve(&self) -> Option<Vec<(Float, Float)>> {
+        let (counts, positives, negatives) = self.cumulative_counts();
+        if positives == 0 || negatives == 0 {
+            return None;
+        }
+        let mut curve = Vec::with_capacity(counts.len() + 1);
+        curve.push((0.0, 0.0));
+        for (tp, fp) in counts {
+            curve.push((fp as Float / negatives as Float, tp as Float / positives as Float));
+        }
+        Some(curve)
+    }
+
+    /// Area under the ROC curve, using the trapezoidal rule.
+    pub fn roc_auc(&self) -> Option<Float> {
+        let auc = self.roc_curve()?
+            .windows(2)
+            .map(|w| (w[1].0 - w[0].0) * (w[1].1 + w[0].1) / 2.0)
+            .sum();
+        Some(auc)
+    }
+
+    /// Points of the precision-recall curve as (recall, precision), ordered by decreasing
+    /// threshold (i.e. increasing recall).
+    pub fn precision_recall_curve(&self) -> Option<Vec<(Float, Float)>> {
+        let (counts, positives, _) = self.cumulative_counts();
+        if positives == 0 {
+            return None;
+        }
+        let curve = counts.into_iter()
+            .map(|(tp, fp)| (tp as Float / positives as Float, tp as Float / (tp + fp) as Float))
+            .collect();
+        Some(curve)
+    }
+
+    /// Average precision, the sum of the precision at each threshold weighted by the increase
+    /// in recall from the previous threshold. Unlike ROC AUC, this is sensitive to the
+    /// performance on the positive class and so remains informative for imbalanced data sets.
+    pub fn average_precision(&self) -> Option<Float> {
+        let mut prev_recall = 0.0;
+        let mut sum = 0.0;
+        for (recall, precision) in self.precision_recall_curve()? {
+            sum += (recall - prev_recall) * precision;
+            prev_recall = recall;
+        }
+        Some(sum)
+    }
+
+}
+
+impl Default for RankingStats {
+    fn default() -> Self {
+        RankingStats::new()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -254,6 +419,40 @@ mod test {
 
     }
 
+    #[test]
+    fn test_merge() {
+
+        let values = [1.0, 1.0, 2.5, 10.0, -2.0, 3.0, 7.5];
+        let mut all = Stats::new();
+        for &v in &values {
+            all.report(v);
+        }
+
+        let mut a = Stats::new();
+        let mut b = Stats::new();
+        for &v in &values[..3] {
+            a.report(v);
+        }
+        for &v in &values[3..] {
+            b.report(v);
+        }
+        a.merge(&b);
+        a.merge(&Stats::new());
+
+        assert_eq!(a.count(), all.count());
+        assert_eq!(a.sum(), all.sum());
+        assert_eq!(a.min(), -2.0);
+        assert_eq!(a.max(), 10.0);
+        assert!((a.mean() - all.mean()).abs() < 0.00001);
+        assert!((a.std_dev() - all.std_dev()).abs() < 0.00001);
+
+        let mut empty = Stats::new();
+        empty.merge(&all);
+        assert_eq!(empty.count(), all.count());
+        assert!((empty.std_dev() - all.std_dev()).abs() < 0.00001);
+
+    }
+
     #[test]
     fn test_confusion_matrix() {
 
@@ -264,9 +463,51 @@ mod test {
         m.record(false, false);
         m.record(false, false);
         m.record(false, true);
-        assert_eq!(m.to_string(), "[t+ = 0.16666667, t- = 0.33333334, f+ = 0.33333334, f- = 0.16666667]".to_string());
+        let (sixth, third): (Float, Float) = (1.0 / 6.0, 2.0 / 6.0);
+        assert_eq!(m.to_string(), format!("[t+ = {}, t- = {}, f+ = {}, f- = {}]", sixth, third, third, sixth));
 
 
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_ranking_stats() {
+
+        let mut r = RankingStats::new();
+        r.record(0.1, false);
+        r.record(0.4, false);
+        r.record(0.35, true);
+        r.record(0.8, true);
+
+        assert_eq!(r.count(), 4);
+        assert_eq!(r.roc_curve().unwrap(), vec![(0.0, 0.0), (0.0, 0.5), (0.5, 0.5), (0.5, 1.0), (1.0, 1.0)]);
+        assert!((r.roc_auc().unwrap() - 0.75).abs() < 0.0001);
+        assert_eq!(r.precision_recall_curve().unwrap(), vec![(0.5, 1.0), (0.5, 0.5), (1.0, 2.0 / 3.0), (1.0, 0.5)]);
+        assert!((r.average_precision().unwrap() - 0.8333333).abs() < 0.0001);
+
+        // ties share a single threshold
+        r.reset();
+        r.record(0.5, true);
+        r.record(0.5, false);
+        assert_eq!(r.roc_curve().unwrap(), vec![(0.0, 0.0), (1.0, 1.0)]);
+        assert!((r.roc_auc().unwrap() - 0.5).abs() < 0.0001);
+        assert!((r.average_precision().unwrap() - 0.5).abs() < 0.0001);
+
+        // without negatives there's no ROC, without positives no precision either
+        let mut positives = RankingStats::new();
+        positives.record(0.3, true);
+        positives.record(0.6, true);
+        assert_eq!((positives.roc_curve(), positives.roc_auc()), (None, None));
+        assert_eq!(positives.average_precision(), Some(1.0));
+        let mut negatives = RankingStats::new();
+        negatives.record(0.1, false);
+        assert_eq!((negatives.roc_auc(), negatives.precision_recall_curve(), negatives.average_precision()), (None, None, None));
+        assert_eq!(RankingStats::new().roc_auc(), None);
+
+        // merged stats rank the samples of both
+        positives.merge(&negatives);
+        assert_eq!(positives.count(), 3);
+        assert!((positives.roc_auc().unwrap() - 1.0).abs() < 0.0001);
+
+    }
+
+}
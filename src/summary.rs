@@ -0,0 +1,127 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::layer::{NetLayerBase, NetLayerConfig};
+use crate::net::Net;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LayerSummary {
+    /// The kind of layer, e.g. `FullyConnected`
+    pub kind: String,
+    pub input_size: usize,
+    pub output_size: usize,
+    /// The size of the weight row of the layer, including any state such as running statistics
+    pub num_weights: usize,
+    pub num_trainable_weights: usize,
+    /// The earlier layer whose weights this layer shares, in which case they aren't counted again
+    /// in the totals
+    pub shared_with: Option<usize>,
+}
+
+/// A per-layer description of a net, e.g. for logging the nets produced by a config factory
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NetSummary {
+    pub input_size: usize,
+    pub output_size: usize,
+    pub layers: Vec<LayerSummary>,
+    /// The number of distinct weights of the net
+    pub total_weights: usize,
+    pub total_trainable_weights: usize,
+}
+
+pub(crate) fn layer_kind(config: &NetLayerConfig) -> String {
+    match config {
+        NetLayerConfig::FullyConnected(..) => "FullyConnected".to_string(),
+        NetLayerConfig::BatchNorm { .. } => "BatchNorm".to_string(),
+        NetLayerConfig::GlobalAveragePooling { .. } => "GlobalAveragePooling".to_string(),
+        NetLayerConfig::Flatten => "Flatten".to_string(),
+        NetLayerConfig::SoftmaxCrossEntropy(_) => "SoftmaxCrossEntropy".to_string(),
+        NetLayerConfig::SparseFullyConnected(..) => "SparseFullyConnected".to_string(),
+        NetLayerConfig::Custom { type_name, .. } => type_name.clone(),
+    }
+}
+
+impl Net {
+
+    pub fn summary(&self) -> NetSummary {
+        let layers: Vec<LayerSummary> = self.layer_iter()
+            .enumerate()
+            .map(|(index, layer)| LayerSummary {
+                kind: layer_kind(&layer.get_config()),
+                input_size: layer.input_size(),
+                output_size: layer.output_size(),
+                num_weights: layer.weight_buffer_size(),
+                num_trainable_weights: layer.num_trainable_weights(),
+                shared_with: self.shared_weights(index),
+            })
+            .collect();
+        let distinct = || layers.iter().filter(|layer| layer.shared_with.is_none());
+        NetSummary {
+            input_size: self.input_size(),
+            output_size: self.output_size(),
+            total_weights: distinct().map(|layer| layer.num_weights).sum(),
+            total_trainable_weights: distinct().map(|layer| layer.num_trainable_weights).sum(),
+            layers,
+        }
+    }
+
+}
+
+impl fmt::Display for NetSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        writeln!(f, "{:>5} {:<22} {:>8} {:>8} {:>10} {:>10}",
+            "layer", "kind", "inputs", "outputs", "weights", "trainable")?;
+        for (index, layer) in self.layers.iter().enumerate() {
+            let (weights, trainable) = match layer.shared_with {
+                Some(source) => (format!("= {}", source), "-".to_string()),
+                None => (layer.num_weights.to_string(), layer.num_trainable_weights.to_string()),
+            };
+            writeln!(f, "{:>5} {:<22} {:>8} {:>8} {:>10} {:>10}",
+                index, layer.kind, layer.input_size, layer.output_size, weights, trainable)?;
+        }
+        writeln!(f, "total weights: {} ({} trainable)", self.total_weights, self.total_trainable_weights)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::func::ActivationFn;
+    use crate::net::NetConfig;
+
+    #[test]
+    fn test_summary() {
+
+        let net = NetConfig::new(4, vec![
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::BatchNorm { momentum: 0.9 },
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::SoftmaxCrossEntropy(2),
+        ]).with_shared_weights(3, 2).create_net();
+
+        let summary = net.summary();
+        assert_eq!(summary.layers.len(), 5);
+        assert_eq!(summary.layers[0], LayerSummary {
+            kind: "FullyConnected".to_string(),
+            input_size: 4,
+            output_size: 3,
+            num_weights: 15,
+            num_trainable_weights: 15,
+            shared_with: None,
+        });
+        assert_eq!(summary.layers[3].shared_with, Some(2));
+        let batch_norm = &summary.layers[1];
+        assert!(batch_norm.num_trainable_weights < batch_norm.num_weights);
+        assert_eq!(summary.total_weights, 15 + batch_norm.num_weights + 12 + 8);
+        assert_eq!(summary.total_trainable_weights, 15 + batch_norm.num_trainable_weights + 12 + 8);
+
+        let table = summary.to_string();
+        assert_eq!(table.lines().count(), 7);
+        assert!(table.contains("SoftmaxCrossEntropy"));
+        assert!(table.lines().nth(4).unwrap().contains("= 2"));
+
+    }
+
+}
@@ -3,11 +3,19 @@ mod multithreaded;
 
 use self::multithreaded::*;
 use self::singlethreaded::*;
+use std::time::{Duration, SystemTime};
+use serde::{Serialize, Deserialize};
 use crate::{
+    Float,
     net::Net,
-    data::PreparedDataSet,
-    func::{CompletionFn, MiniBatchSize, LearningRateFn, ErrorFn},
-    stats::Stats
+    buffer::{BufferPool, RowBuffer},
+    train::task::{TaskUpdate, TaskUpdateEmitter, Throughput},
+    train::control::TrainingControl,
+    train::context::DivergenceReport,
+    data::{PartitionStrategy, PreparedDataSet},
+    func::{CompletionFn, MiniBatchSize, LearningRateFn, ErrorFn, GradientNoiseFn},
+    stats::Stats,
+    precision::MixedPrecision,
 };
 
 #[derive(Clone, Debug)]
@@ -16,7 +24,57 @@ pub struct BackpropOptions {
     pub mini_batch_size_fn: MiniBatchSize,
     pub learning_rate_fn: LearningRateFn,
     pub error_fn: ErrorFn,
+    pub gradient_noise_fn: Option<GradientNoiseFn>,
+    /// Scales the gradient noise of each layer, in layer order, e.g. 0.0 to leave a layer
+    /// without noise. Layers past the end of the scales get the full noise.
+    pub gradient_noise_layer_scales: Vec<Float>,
+    /// Visit the rows in a new random order on every pass over the data instead of in file order
+    pub shuffle: bool,
     pub multi_threading: Option<BackpropMultithreadingOptions>,
+    /// If set, the error and accuracy reported after each batch, and checked by the completion
+    /// function, are computed on these held-out rows instead of the training set
+    pub validation_set: Option<PreparedDataSet>,
+    /// If set, the stage emits a `TaskUpdate` every this many epochs
+    pub update_interval: Option<usize>,
+    /// Check for NaN or infinite values after every mini-batch, and stop the stage with a report
+    /// of where they first appeared. Slow, so only meant for debugging.
+    pub check_divergence: bool,
+    /// Seeds the row shuffling and gradient noise of the stage, of each worker thread in turn
+    /// when multi-threaded. Unseeded stages are seeded from the task id.
+    pub seed: Option<String>,
+    /// Emit a `TaskUpdate` with the norms of the weights and gradients of every layer on every
+    /// completion check, i.e. every sync when multi-threaded, to diagnose vanishing or exploding
+    /// gradients
+    pub track_norms: bool,
+    /// If set, the stage keeps an exponential moving average of the weights with this decay
+    /// (e.g. 0.999), updated after every mini-batch, or every sync when multi-threaded
+    pub ema_decay: Option<Float>,
+    /// If set, mini-batches are trained in (emulated) mixed precision, see `MixedPrecision`
+    pub mixed_precision: Option<MixedPrecision>,
+    /// If set, single-threaded stages split each mini-batch into this many parts, trained on the
+    /// rayon thread pool, and add up their deltas, which pays off for large mini-batches.
+    /// Ignored when multi-threaded.
+    pub batch_threads: Option<usize>,
+}
+
+/// A stage of a curriculum, which trains the net on its data set until its completion fn stops
+/// it, before the next stage takes over, e.g. to start on clean data and end on the full set
+#[derive(Clone, Debug)]
+pub struct CurriculumStage {
+    pub data_set: PreparedDataSet,
+    pub options: BackpropOptions,
+}
+
+impl CurriculumStage {
+
+    /// The completion fn replaces that of the options
+    pub fn new(data_set: PreparedDataSet, options: BackpropOptions, completion_fn: CompletionFn) -> Self {
+        CurriculumStage {
+            data_set,
+            options: BackpropOptions { completion_fn, ..options },
+        }
+    }
+
 }
 
 #[derive(Clone, Debug)]
@@ -24,13 +82,217 @@ pub struct BackpropMultithreadingOptions {
     pub worker_threads: Option<usize>,
     pub partitions: usize,
     pub batches_per_sync: usize,
+    /// How the training rows are split into the partitions
+    pub partition_strategy: PartitionStrategy,
+    /// If set, every visit of a partition shifts its window over the rows by this fraction of a
+    /// partition (e.g. 4 for a quarter), so the boundaries rotate between syncs instead of every
+    /// worker revisiting the same rows
+    pub partition_shift_steps: Option<usize>,
+    pub strategy: MultithreadingStrategy,
+    /// How synchronized workers merge their updates into the shared weights
+    pub sync: WeightSync,
+    /// What the updates merged at a sync are divided by
+    pub sync_denominator: SyncDenominator,
+}
+
+/// How the worker threads of a multi-threaded stage share their weight updates
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MultithreadingStrategy {
+    /// Each worker trains a copy of the weights, and adds its updates to the shared weights
+    /// under a lock every `batches_per_sync` passes
+    #[default]
+    Synchronized,
+    /// Hogwild: every mini-batch of every worker reads the shared weights and adds its non-zero
+    /// deltas to them without a lock. Workers never wait for each other, which suits small nets
+    /// with many workers, but concurrent updates of a weight can be lost.
+    Hogwild,
+}
+
+/// How a synchronized worker merges the weights it trained into the shared weights, after
+/// dividing by the `SyncDenominator`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WeightSync {
+    /// Adds the change of the worker's weights since it last synced
+    #[default]
+    DeltaAveraging,
+    /// Moves the shared weights towards the worker's weights, so updates made by other workers
+    /// in the meantime are averaged too
+    ParameterAveraging,
+    /// Waits for every worker to finish its passes, then adds the sum of their changes at once,
+    /// so all workers start each round from the same weights
+    GradientAllReduce,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SyncDenominator {
+    /// The number of partitions, which only averages the updates when there is a worker per
+    /// partition
+    #[default]
+    Partitions,
+    /// The number of workers
+    Workers,
+    Constant(Float),
+}
+
+impl SyncDenominator {
+
+    pub fn get(&self, num_workers: usize, num_partitions: usize) -> Float {
+        let denominator = match *self {
+            SyncDenominator::Partitions => num_partitions as Float,
+            SyncDenominator::Workers => num_workers as Float,
+            SyncDenominator::Constant(denominator) => denominator,
+        };
+        assert!(denominator > 0.0, "sync denominator must be positive");
+        denominator
+    }
+
+}
+
+pub struct BackpropStageResult {
+    pub error_stats: Stats,
+    /// The accuracy stats at each of the completion function's accuracy thresholds, if any
+    pub accuracies: Vec<(Float, Stats)>,
+    /// The number of passes over the training set
+    pub epoch_count: usize,
+    /// The number of mini-batch weight updates, across all worker threads
+    pub batch_count: usize,
+    /// The number of rows trained on, across all worker threads
+    pub row_count: usize,
+    /// Set if the stage was aborted because the error became NaN or infinite
+    pub diverged: bool,
+    /// Where the training first diverged, if checked
+    pub divergence: Option<DivergenceReport>,
+    /// The metrics of every completion check, in order
+    pub history: Vec<EpochMetrics>,
+    /// The moving average of the weights, if the options have an EMA decay
+    pub ema_weights: Option<RowBuffer>,
+}
+
+impl BackpropStageResult {
+
+    /// The result of a stage with nothing to train on
+    fn empty() -> Self {
+        BackpropStageResult {
+            error_stats: Stats::new(),
+            accuracies: Vec::new(),
+            epoch_count: 0,
+            batch_count: 0,
+            row_count: 0,
+            diverged: false,
+            divergence: None,
+            history: Vec::new(),
+            ema_weights: None,
+        }
+    }
+
+}
+
+/// Whether the error computed at a completion check shows that training diverged. The error of
+/// an empty data set has no mean, but that isn't a divergence.
+pub(crate) fn error_diverged(error_stats: &Stats) -> bool {
+    error_stats.count() > 0 && !error_stats.mean().is_finite()
+}
+
+/// The state of a backprop stage when its completion was checked, for plotting learning curves
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EpochMetrics {
+    /// The number of passes over the training set so far
+    pub epoch: usize,
+    /// The number of mini-batches trained so far
+    #[serde(default)]
+    pub batch: usize,
+    /// The mean error on the training set
+    #[serde(with = "crate::utils::serde_nan")]
+    pub train_error: f64,
+    /// The mean error on the validation set, if the stage has one
+    pub validation_error: Option<f64>,
+    pub learning_rate: Float,
+    /// The time since the stage started
+    pub elapsed: Duration,
+}
+
+/// The norms of a layer's weights when a backprop stage checked its completion
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerNorms {
+    pub layer: usize,
+    /// The L2 norm of the weights
+    pub weight_norm: f64,
+    /// The L2 norm of the change of the weights since the previous check, i.e. of the gradients
+    /// accumulated since, scaled by the learning rate
+    pub gradient_norm: f64,
+}
+
+impl LayerNorms {
+
+    fn of_layers(weights: &RowBuffer, previous_weights: &RowBuffer) -> Vec<LayerNorms> {
+        let norm = |values: &mut dyn Iterator<Item = Float>| values.map(|v| v as f64 * v as f64).sum::<f64>().sqrt();
+        (0..weights.num_rows())
+            .map(|layer| {
+                let (current, previous) = (weights.get_row(layer), previous_weights.get_row(layer));
+                LayerNorms {
+                    layer,
+                    weight_norm: norm(&mut current.iter().cloned()),
+                    gradient_norm: norm(&mut current.iter().zip(previous).map(|(a, b)| a - b)),
+                }
+            })
+            .collect()
+    }
+
+}
+
+impl EpochMetrics {
+
+    fn new(epoch: usize, batch: usize, train_error: f64, validation_error: Option<f64>, learning_rate: Float, stage_start_time: SystemTime) -> Self {
+        EpochMetrics {
+            epoch,
+            batch,
+            train_error,
+            validation_error,
+            learning_rate,
+            elapsed: SystemTime::now().duration_since(stage_start_time).unwrap_or_default(),
+        }
+    }
+
 }
 
 pub fn backprop_stage_task_impl(
     net: &mut Net,
     training_set: &PreparedDataSet,
     options: &BackpropOptions,
-) -> (Stats, usize) {
+    task_id: &str,
+    update_emitter: &dyn TaskUpdateEmitter,
+    control: &TrainingControl,
+) -> BackpropStageResult {
+    pooled_backprop_stage_task_impl(net, training_set, options, task_id, update_emitter, control, &BufferPool::new())
+}
+
+/// Like `backprop_stage_task_impl`, but the scratch buffers of the stage are taken from the pool
+/// and returned to it at the end
+pub fn pooled_backprop_stage_task_impl(
+    net: &mut Net,
+    training_set: &PreparedDataSet,
+    options: &BackpropOptions,
+    task_id: &str,
+    update_emitter: &dyn TaskUpdateEmitter,
+    control: &TrainingControl,
+    pool: &BufferPool,
+) -> BackpropStageResult {
+
+    // an empty training set would spin through empty epochs until the completion fn stops it
+    if training_set.num_rows() == 0 {
+        return BackpropStageResult::empty();
+    }
+
+    let mut monitor = StageMonitor {
+        task_id,
+        update_emitter,
+        control,
+        pool,
+        interval: options.update_interval,
+        last_epoch: 0,
+        previous_weights: if options.track_norms { Some(net.get_weights().clone()) } else { None },
+        start_time: SystemTime::now(),
+    };
 
     if let Some(ref multi_threading) = options.multi_threading {
 
@@ -38,20 +300,25 @@ pub fn backprop_stage_task_impl(
             None => num_cpus::get(),
             Some(threads) => threads,
         };
-        if worker_threads > multi_threading.partitions {
-            worker_threads = multi_threading.partitions;
+        // there's at most one partition per row
+        let partitions = multi_threading.partitions.min(training_set.num_rows().max(1));
+        if worker_threads > partitions {
+            worker_threads = partitions;
         }
 
         train_backprop_multi_threaded(
             net,
             training_set,
-            options.completion_fn,
-            options.mini_batch_size_fn,
-            options.learning_rate_fn,
-            options.error_fn,
+            options,
             multi_threading.batches_per_sync,
             worker_threads,
-            multi_threading.partitions,
+            partitions,
+            multi_threading.partition_strategy,
+            multi_threading.partition_shift_steps,
+            multi_threading.strategy,
+            multi_threading.sync,
+            multi_threading.sync_denominator,
+            &mut monitor,
         )
 
     } else {
@@ -59,11 +326,525 @@ pub fn backprop_stage_task_impl(
         train_backprop_single_threaded(
             net,
             training_set,
-            options.completion_fn,
-            options.mini_batch_size_fn,
-            options.learning_rate_fn,
-            options.error_fn,
+            options,
+            &mut monitor,
         )
 
     }
-}
\ No newline at end of file
+}
+
+/// Trains the net through the stages in turn. The counts and history add up over the stages, and
+/// the errors are those of the last stage trained. A stage which diverges or is cancelled ends
+/// the curriculum.
+pub fn curriculum_task_impl(
+    net: &mut Net,
+    stages: &[CurriculumStage],
+    task_id: &str,
+    update_emitter: &dyn TaskUpdateEmitter,
+    control: &TrainingControl,
+    pool: &BufferPool,
+) -> BackpropStageResult {
+
+    let start_time = SystemTime::now();
+    let mut combined: Option<BackpropStageResult> = None;
+
+    for stage in stages {
+        let stage_offset = SystemTime::now().duration_since(start_time).unwrap_or_default();
+        let mut result = pooled_backprop_stage_task_impl(net, &stage.data_set, &stage.options, task_id, update_emitter, control, pool);
+        if let Some(previous) = combined {
+            for metrics in &mut result.history {
+                metrics.epoch += previous.epoch_count;
+                metrics.batch += previous.batch_count;
+                metrics.elapsed += stage_offset;
+            }
+            let mut history = previous.history;
+            history.append(&mut result.history);
+            result = BackpropStageResult {
+                epoch_count: previous.epoch_count + result.epoch_count,
+                batch_count: previous.batch_count + result.batch_count,
+                row_count: previous.row_count + result.row_count,
+                divergence: previous.divergence.or(result.divergence),
+                history,
+                ..result
+            };
+        }
+        let stop = result.diverged || control.is_cancelled();
+        combined = Some(result);
+        if stop {
+            break;
+        }
+    }
+
+    combined.expect("a curriculum needs at least one stage")
+}
+
+/// Emits the updates of a stage every `interval` epochs, and holds the control of the trainer
+/// and the pool of its scratch buffers
+pub(crate) struct StageMonitor<'a> {
+    task_id: &'a str,
+    update_emitter: &'a dyn TaskUpdateEmitter,
+    control: &'a TrainingControl,
+    pool: &'a BufferPool,
+    interval: Option<usize>,
+    last_epoch: usize,
+    /// The weights at the previous check, if tracking norms
+    previous_weights: Option<RowBuffer>,
+    start_time: SystemTime,
+}
+
+impl StageMonitor<'_> {
+
+    fn control(&self) -> &TrainingControl {
+        self.control
+    }
+
+    fn pool(&self) -> &BufferPool {
+        self.pool
+    }
+
+    /// The seed of the stage, which is the task id unless the options set one
+    fn seed<'o>(&'o self, options: &'o BackpropOptions) -> &'o str {
+        options.seed.as_deref().unwrap_or(self.task_id)
+    }
+
+    /// Called after every completion check. The multi-threaded loop may advance several epochs
+    /// at a time, so an update is emitted whenever a multiple of the interval was passed.
+    fn report(&mut self, epoch: usize, batch_count: usize, row_count: usize, error_stats: &Stats, weights: &RowBuffer) {
+        let layer_norms = match &mut self.previous_weights {
+            Some(previous_weights) => {
+                let layer_norms = LayerNorms::of_layers(weights, previous_weights);
+                weights.copy_into(previous_weights);
+                layer_norms
+            },
+            None => Vec::new(),
+        };
+        let interval_passed = self.interval.is_some_and(|interval| epoch / interval > self.last_epoch / interval);
+        if interval_passed || self.previous_weights.is_some() {
+            let elapsed = SystemTime::now().duration_since(self.start_time).unwrap_or_default();
+            self.update_emitter.emit_update(TaskUpdate {
+                task_id: self.task_id.to_string(),
+                error_stats: error_stats.clone(),
+                epoch,
+                elapsed,
+                throughput: Throughput::new(row_count, batch_count, elapsed),
+                layer_norms,
+            });
+        }
+        self.last_epoch = epoch;
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::{backprop_options, lines_2x2};
+    use std::num::NonZeroU32;
+    use crate::net::NetConfig;
+    use crate::layer::NetLayerConfig;
+    use crate::func::ActivationFn;
+    use crate::initializer::RandomNetInitializer;
+    use crate::train::context::DivergenceSource;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct RecordingEmitter(RefCell<Vec<TaskUpdate>>);
+
+    impl TaskUpdateEmitter for RecordingEmitter {
+        fn emit_update(&self, update: TaskUpdate) {
+            self.0.borrow_mut().push(update);
+        }
+    }
+
+    fn test_net() -> Net {
+        let mut net = NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("epochs"));
+        net
+    }
+
+    #[test]
+    fn test_epochs_and_batches() {
+
+        let data_set = lines_2x2();
+        let net = test_net();
+        let emitter = RecordingEmitter::default();
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(10),
+            mini_batch_size_fn: MiniBatchSize::Constant(NonZeroU32::new(4).unwrap()),
+            ..backprop_options()
+        };
+
+        // 11 rows in mini-batches of 4 take 3 batches per epoch
+        let result = backprop_stage_task_impl(&mut net.clone(), &data_set, &options, "single", &emitter, &TrainingControl::new());
+        assert_eq!((result.epoch_count, result.batch_count), (10, 30));
+
+        // two partitions of 6 and 5 rows take 2 batches each per epoch. The workers keep going
+        // while the stage is checked, so the counts are lower bounds.
+        let multi_threaded = BackpropOptions {
+            multi_threading: Some(BackpropMultithreadingOptions {
+                worker_threads: Some(2),
+                partitions: 2,
+                batches_per_sync: 1,
+                partition_strategy: PartitionStrategy::Contiguous,
+                partition_shift_steps: None,
+                strategy: MultithreadingStrategy::Synchronized,
+                sync: WeightSync::DeltaAveraging,
+                sync_denominator: SyncDenominator::Partitions,
+            }),
+            ..options
+        };
+        let result = backprop_stage_task_impl(&mut net.clone(), &data_set, &multi_threaded, "multi", &emitter, &TrainingControl::new());
+        assert!(result.epoch_count >= 10);
+        assert!(result.batch_count >= 4 * result.epoch_count, "{} batches", result.batch_count);
+
+        // shifted windows keep the sizes of the partitions, so they take as many batches
+        let mut shifted = multi_threaded.clone();
+        if let Some(multi_threading) = &mut shifted.multi_threading {
+            multi_threading.partition_shift_steps = Some(3);
+        }
+        let result = backprop_stage_task_impl(&mut net.clone(), &data_set, &shifted, "shifted", &emitter, &TrainingControl::new());
+        assert!(result.epoch_count >= 10);
+        assert!(result.batch_count >= 4 * result.epoch_count, "{} batches", result.batch_count);
+
+        // each visit of a partition trains on other rows, until the windows come back around
+        let (partitioned, windows) = data_set.partition_windows(2, PartitionStrategy::Contiguous);
+        for &window in &windows {
+            let visited_rows: Vec<Vec<&[Float]>> = (0..4)
+                .map(|shift| {
+                    let (first_row, num_rows) = multithreaded::shifted_window(window, shift, Some(3), windows.len(), partitioned.num_rows());
+                    partitioned.iter_window(first_row, num_rows).map(|(inputs, _)| inputs).collect()
+                })
+                .collect();
+            assert!(visited_rows.iter().all(|rows| rows.len() == window.1));
+            assert_ne!(visited_rows[0], visited_rows[1]);
+            assert_ne!(visited_rows[1], visited_rows[2]);
+            assert_eq!(visited_rows[0], visited_rows[3]);
+        }
+
+    }
+
+    #[test]
+    fn test_hogwild() {
+
+        let data_set = lines_2x2();
+        let mut net = test_net();
+        let initial_error = net.get_training_context().compute_error_for_batch(&data_set, &ErrorFn::SquaredError).mean();
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(50),
+            mini_batch_size_fn: MiniBatchSize::Constant(NonZeroU32::new(2).unwrap()),
+            shuffle: true,
+            multi_threading: Some(BackpropMultithreadingOptions {
+                worker_threads: Some(3),
+                partitions: 3,
+                batches_per_sync: 2,
+                partition_strategy: PartitionStrategy::RoundRobin,
+                partition_shift_steps: None,
+                strategy: MultithreadingStrategy::Hogwild,
+                sync: WeightSync::DeltaAveraging,
+                sync_denominator: SyncDenominator::Partitions,
+            }),
+            check_divergence: true,
+            ..backprop_options()
+        };
+        let emitter = RecordingEmitter::default();
+        let result = backprop_stage_task_impl(&mut net, &data_set, &options, "hogwild", &emitter, &TrainingControl::new());
+        assert!(!result.diverged && result.epoch_count >= 50);
+        assert!(result.error_stats.mean() < initial_error, "{} >= {}", result.error_stats.mean(), initial_error);
+        assert_eq!(net.get_training_context().compute_error_for_batch(&data_set, &ErrorFn::SquaredError).mean(), result.error_stats.mean());
+
+    }
+
+    #[test]
+    fn test_weight_sync() {
+
+        let data_set = lines_2x2();
+        let net = test_net();
+        let initial_error = net.clone().get_training_context().compute_error_for_batch(&data_set, &ErrorFn::SquaredError).mean();
+        let emitter = RecordingEmitter::default();
+        for &sync in &[WeightSync::DeltaAveraging, WeightSync::ParameterAveraging, WeightSync::GradientAllReduce] {
+            let options = BackpropOptions {
+                completion_fn: CompletionFn::stop_after_epoch(20),
+                multi_threading: Some(BackpropMultithreadingOptions {
+                    worker_threads: Some(2),
+                    partitions: 4,
+                    batches_per_sync: 1,
+                    partition_strategy: PartitionStrategy::RoundRobin,
+                    partition_shift_steps: None,
+                    strategy: MultithreadingStrategy::Synchronized,
+                    sync,
+                    sync_denominator: SyncDenominator::Workers,
+                }),
+                ..backprop_options()
+            };
+            let result = backprop_stage_task_impl(&mut net.clone(), &data_set, &options, "sync", &emitter, &TrainingControl::new());
+            assert!(result.epoch_count >= 20);
+            assert!(result.error_stats.mean() < initial_error, "{:?}: {} >= {}", sync, result.error_stats.mean(), initial_error);
+        }
+        assert_eq!(SyncDenominator::Partitions.get(2, 4), 4.0);
+        assert_eq!(SyncDenominator::Constant(1.5).get(2, 4), 1.5);
+
+    }
+
+    #[test]
+    fn test_batch_norm_running_stats_sync() {
+
+        let data_set = lines_2x2();
+        let mut net = NetConfig::new(4, vec![
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::BatchNorm { momentum: 0.9 },
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid()),
+        ]).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("batch norm sync"));
+        let emitter = RecordingEmitter::default();
+        let strategies = [
+            (MultithreadingStrategy::Synchronized, WeightSync::DeltaAveraging),
+            (MultithreadingStrategy::Synchronized, WeightSync::ParameterAveraging),
+            (MultithreadingStrategy::Synchronized, WeightSync::GradientAllReduce),
+            (MultithreadingStrategy::Hogwild, WeightSync::DeltaAveraging),
+        ];
+        for &(strategy, sync) in &strategies {
+            // a large denominator all but stops the trained weights, but not the running statistics
+            let options = BackpropOptions {
+                completion_fn: CompletionFn::stop_after_epoch(10),
+                mini_batch_size_fn: MiniBatchSize::Constant(NonZeroU32::new(2).unwrap()),
+                multi_threading: Some(BackpropMultithreadingOptions {
+                    worker_threads: Some(2),
+                    partitions: 2,
+                    batches_per_sync: 1,
+                    partition_strategy: PartitionStrategy::RoundRobin,
+                    partition_shift_steps: None,
+                    strategy,
+                    sync,
+                    sync_denominator: SyncDenominator::Constant(1000.0),
+                }),
+                ..backprop_options()
+            };
+            let mut trained = net.clone();
+            backprop_stage_task_impl(&mut trained, &data_set, &options, "batch norm", &emitter, &TrainingControl::new());
+            // the running means approach the means of the sigmoid outputs from zero
+            let running_mean = &trained.get_weights().get_row(1)[6..9];
+            assert!(running_mean.iter().all(|&m| m > 0.1 && m < 1.0), "{:?} {:?}: {:?}", strategy, sync, running_mean);
+        }
+
+    }
+
+    #[test]
+    fn test_pooled_buffers() {
+
+        let data_set = lines_2x2();
+        let emitter = RecordingEmitter::default();
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(5),
+            mini_batch_size_fn: MiniBatchSize::Constant(NonZeroU32::new(4).unwrap()),
+            batch_threads: Some(2),
+            ..backprop_options()
+        };
+        let mut expected = test_net();
+        backprop_stage_task_impl(&mut expected, &data_set, &options, "fresh", &emitter, &TrainingControl::new());
+
+        // a context and a batch helper each return their layer and weight buffers
+        let pool = BufferPool::new();
+        let mut net = test_net();
+        pooled_backprop_stage_task_impl(&mut net, &data_set, &options, "first", &emitter, &TrainingControl::new(), &pool);
+        assert_eq!((pool.num_spare(), pool.num_reused()), (6, 0));
+        let mut net = test_net();
+        pooled_backprop_stage_task_impl(&mut net, &data_set, &options, "second", &emitter, &TrainingControl::new(), &pool);
+        assert_eq!((pool.num_spare(), pool.num_reused()), (6, 6));
+        assert_eq!(net.get_weights().get_buffer(), expected.get_weights().get_buffer());
+
+        // a multi-threaded stage needs more buffers than are spare, so it reuses all of them
+        let multi_threaded = BackpropOptions {
+            multi_threading: Some(BackpropMultithreadingOptions {
+                worker_threads: Some(2),
+                partitions: 2,
+                batches_per_sync: 1,
+                partition_strategy: PartitionStrategy::Contiguous,
+                partition_shift_steps: None,
+                strategy: MultithreadingStrategy::Synchronized,
+                sync: WeightSync::DeltaAveraging,
+                sync_denominator: SyncDenominator::Partitions,
+            }),
+            batch_threads: None,
+            ..options
+        };
+        pooled_backprop_stage_task_impl(&mut test_net(), &data_set, &multi_threaded, "multi", &emitter, &TrainingControl::new(), &pool);
+        assert_eq!(pool.num_reused(), 12);
+
+    }
+
+    #[test]
+    fn test_task_updates() {
+
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(10),
+            update_interval: Some(3),
+            ..backprop_options()
+        };
+        let emitter = RecordingEmitter::default();
+        let result = backprop_stage_task_impl(&mut test_net(), &lines_2x2(), &options, "task", &emitter, &TrainingControl::new());
+
+        let updates = emitter.0.into_inner();
+        assert_eq!(updates.iter().map(|u| u.epoch).collect::<Vec<_>>(), [3, 6, 9]);
+        assert!(updates.iter().all(|u| u.task_id == "task" && u.error_stats.count() == 11));
+        assert_eq!(updates[2].error_stats.mean(), result.history[8].train_error);
+
+        // a full batch of 11 rows per epoch
+        assert_eq!((result.batch_count, result.row_count), (10, 110));
+        let throughput = updates[2].throughput;
+        assert!(throughput.batches_per_second > 0.0);
+        assert!((throughput.rows_per_second / throughput.batches_per_second - 11.0).abs() < 1e-6);
+
+    }
+
+    #[test]
+    fn test_divergence_stops_stage() {
+
+        let mut net = test_net();
+        net.get_weights_mut().get_row_mut(1)[0] = Float::NAN;
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(10),
+            mini_batch_size_fn: MiniBatchSize::Constant(NonZeroU32::new(4).unwrap()),
+            check_divergence: true,
+            ..backprop_options()
+        };
+        let result = backprop_stage_task_impl(&mut net, &lines_2x2(), &options, "task", &RecordingEmitter::default(), &TrainingControl::new());
+        assert!(result.diverged);
+        assert_eq!(result.epoch_count, 1);
+        let divergence = result.divergence.unwrap();
+        assert_eq!((divergence.batch, divergence.layer, divergence.source), (0, 1, DivergenceSource::Outputs));
+
+    }
+
+    #[test]
+    fn test_non_finite_error_stops_stage() {
+
+        // an infinite learning rate makes the error NaN after the first epoch, which stops the
+        // stage even without checking the outputs of every batch
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(10),
+            learning_rate_fn: LearningRateFn::Constant(Float::INFINITY),
+            ..backprop_options()
+        };
+        let multi_threaded = BackpropOptions {
+            multi_threading: Some(BackpropMultithreadingOptions {
+                worker_threads: Some(2),
+                partitions: 2,
+                batches_per_sync: 1,
+                partition_strategy: PartitionStrategy::Contiguous,
+                partition_shift_steps: None,
+                strategy: MultithreadingStrategy::Synchronized,
+                sync: WeightSync::DeltaAveraging,
+                sync_denominator: SyncDenominator::Partitions,
+            }),
+            ..options.clone()
+        };
+        for options in [options, multi_threaded] {
+            let result = backprop_stage_task_impl(&mut test_net(), &lines_2x2(), &options, "task", &RecordingEmitter::default(), &TrainingControl::new());
+            assert!(result.diverged);
+            assert!(!result.error_stats.mean().is_finite());
+            assert!(result.divergence.is_none());
+            // the first completion check stops it
+            assert_eq!(result.history.len(), 1);
+        }
+
+    }
+
+    #[test]
+    fn test_empty_data_sets_dont_diverge() {
+
+        let data_set = lines_2x2();
+        let empty = data_set.select_rows(&[]);
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(3),
+            validation_set: Some(empty.clone()),
+            ..backprop_options()
+        };
+
+        // the error of an empty validation set has no mean, but training goes on
+        let result = backprop_stage_task_impl(&mut test_net(), &data_set, &options, "validation", &RecordingEmitter::default(), &TrainingControl::new());
+        assert!(!result.diverged);
+        assert_eq!((result.epoch_count, result.error_stats.count()), (3, 0));
+
+        // there's nothing to train on an empty training set
+        let result = backprop_stage_task_impl(&mut test_net(), &empty, &options, "training", &RecordingEmitter::default(), &TrainingControl::new());
+        assert!(!result.diverged);
+        assert_eq!((result.epoch_count, result.batch_count), (0, 0));
+
+    }
+
+    #[test]
+    fn test_gradient_noise_layer_scales() {
+
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(1),
+            gradient_noise_fn: Some(GradientNoiseFn::Annealed { eta: 1.0, gamma: 0.0 }),
+            gradient_noise_layer_scales: vec![0.0],
+            ..backprop_options()
+        };
+        let train = |options: &BackpropOptions, task_id: &str| {
+            let mut net = test_net();
+            backprop_stage_task_impl(&mut net, &lines_2x2(), options, task_id, &RecordingEmitter::default(), &TrainingControl::new());
+            net.get_weights().clone()
+        };
+
+        // a single batch computes the gradients of the first layer before any noise is added
+        let noiseless = train(&BackpropOptions { gradient_noise_fn: None, ..options.clone() }, "task");
+        let noisy = train(&options, "task");
+        assert_eq!(noisy.get_row(0), noiseless.get_row(0));
+        assert_ne!(noisy.get_row(1), noiseless.get_row(1));
+
+        // the noise is seeded from the task id
+        assert_eq!(train(&options, "task").get_buffer(), noisy.get_buffer());
+        assert_ne!(train(&options, "other").get_row(1), noisy.get_row(1));
+
+    }
+
+    #[test]
+    fn test_layer_norms() {
+
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(4),
+            track_norms: true,
+            ..backprop_options()
+        };
+        let emitter = RecordingEmitter::default();
+        let mut net = test_net();
+        backprop_stage_task_impl(&mut net, &lines_2x2(), &options, "task", &emitter, &TrainingControl::new());
+
+        // an update for every check, with the norms of both layers
+        let updates = emitter.0.into_inner();
+        assert_eq!(updates.iter().map(|u| u.epoch).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert!(updates.iter().all(|u| u.layer_norms.len() == 2 && u.layer_norms.iter().all(|n| n.gradient_norm > 0.0)));
+        let last_layer = net.get_weights().get_row(1);
+        let expected: f64 = last_layer.iter().map(|&w| w as f64 * w as f64).sum::<f64>().sqrt();
+        assert!((updates[3].layer_norms[1].weight_norm - expected).abs() < 1e-9);
+
+    }
+
+    #[test]
+    fn test_weight_ema() {
+
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(2),
+            ema_decay: Some(0.5),
+            ..backprop_options()
+        };
+        let emitter = RecordingEmitter::default();
+        let initial = test_net();
+        let mut after_one = initial.clone();
+        let one_epoch = BackpropOptions { completion_fn: CompletionFn::stop_after_epoch(1), ema_decay: None, ..options.clone() };
+        assert!(backprop_stage_task_impl(&mut after_one, &lines_2x2(), &one_epoch, "one", &emitter, &TrainingControl::new()).ema_weights.is_none());
+        let mut after_two = initial.clone();
+        let result = backprop_stage_task_impl(&mut after_two, &lines_2x2(), &options, "two", &emitter, &TrainingControl::new());
+
+        // one full batch per epoch, so the average is of the weights after each of them
+        let ema = result.ema_weights.unwrap();
+        let weights = [initial.get_weights(), after_one.get_weights(), after_two.get_weights()];
+        for (i, &value) in ema.get_buffer().iter().enumerate() {
+            let expected = 0.25 * weights[0].get_buffer()[i] + 0.25 * weights[1].get_buffer()[i] + 0.5 * weights[2].get_buffer()[i];
+            assert!((value - expected).abs() < 1e-5, "{} != {}", value, expected);
+        }
+
+    }
+
+}
@@ -7,36 +7,64 @@ use std::thread;
 use std::time::SystemTime;
 
 use crate::net::Net;
-use crate::data::PreparedDataSet;
-use crate::func::CompletionFn;
-use crate::func::MiniBatchSize;
-use crate::func::LearningRateFn;
-use crate::func::ErrorFn;
-use crate::stats::Stats;
-use crate::buffer::RowBuffer;
+use crate::layer::NetLayerBase;
+use crate::data::{PartitionStrategy, PreparedDataSet};
+use crate::train::backprop::{BackpropOptions, BackpropStageResult, EpochMetrics, MultithreadingStrategy, StageMonitor, SyncDenominator, WeightSync, error_diverged};
+use crate::train::context::DivergenceReport;
+use crate::buffer::{RowBuffer, SharedRowBuffer};
 
+#[allow(clippy::too_many_arguments)]
 pub fn train_backprop_multi_threaded(
     net: &mut Net,
     data_set: &PreparedDataSet,
-    completion_fn: CompletionFn,
-    mini_batch_size_fn: MiniBatchSize,
-    learning_rate_fn: LearningRateFn,
-    error_fn: ErrorFn,
+    options: &BackpropOptions,
     batches_per_sync: usize,
     num_workers: usize,
-    num_partitions: usize
-) -> (Stats, usize) {
+    num_partitions: usize,
+    partition_strategy: PartitionStrategy,
+    partition_shift_steps: Option<usize>,
+    strategy: MultithreadingStrategy,
+    sync: WeightSync,
+    sync_denominator: SyncDenominator,
+    monitor: &mut StageMonitor,
+) -> BackpropStageResult {
+
+    let completion_fn = &options.completion_fn;
+    let mini_batch_size_fn = options.mini_batch_size_fn;
+    let learning_rate_fn = options.learning_rate_fn;
+    let error_fn = options.error_fn;
+    let gradient_noise_fn = options.gradient_noise_fn;
+    let shuffle = options.shuffle;
+    let check_divergence = options.check_divergence;
+    let evaluation_set = options.validation_set.as_ref().unwrap_or(data_set);
 
     let stage_start_time = SystemTime::now();
+    let accuracy_thresholds = completion_fn.accuracy_thresholds();
+
+    let (partitioned_data_set, partition_windows) = data_set.partition_windows(num_partitions, partition_strategy);
+    let num_partitions = partition_windows.len();
+    let sync_multiplier = 1.0 / sync_denominator.get(num_workers, num_partitions);
 
     // shared state
     let shared_state = Arc::new(RwLock::new(SharedThreadState {
         worker_done_counter: 0,
+        batch_counter: 0,
+        row_counter: 0,
+        divergence: None,
         weight_buffer: net.get_weights().clone(),
+        pending_deltas: net.new_zeroed_weight_buffer(),
+        pending_workers: 0,
+        sync_round: 0,
         next_partition_index: num_workers % num_partitions,
         partition_row_shifts: vec![0; num_partitions]
     }));
 
+    // hogwild workers update these weights directly, instead of the weights of the shared state
+    let hogwild_weights = match strategy {
+        MultithreadingStrategy::Synchronized => None,
+        MultithreadingStrategy::Hogwild => Some(Arc::new(SharedRowBuffer::from_row_buffer(net.get_weights()))),
+    };
+
     // set up channel for worker threads to communicate to main thread
     let (check_error_sender, check_error_reciever) = mpsc::channel::<()>();
     let stage_complete_flag = Arc::new(AtomicBool::new(false));
@@ -47,93 +75,162 @@ pub fn train_backprop_multi_threaded(
         let shared_state = Arc::clone(&shared_state);
         let check_error_sender = check_error_sender.clone();
         let mut local_net = net.clone();
-        //let training_set = training_set.clone();//partitioned_training_sets.pop().unwrap();
-        let partitioned_data_sets = data_set.clone().partition(num_partitions);
+        let partitioned_data_set = partitioned_data_set.clone();
+        let partition_windows = partition_windows.clone();
         let stage_complete_flag = stage_complete_flag.clone();
+        let seed = format!("{}/worker_{}", monitor.seed(options), worker_index);
+        let gradient_noise_layer_scales = options.gradient_noise_layer_scales.clone();
+        let mixed_precision = options.mixed_precision;
+        let hogwild_weights = hogwild_weights.clone();
+        let control = monitor.control().clone();
+        let pool = monitor.pool().clone();
 
         thread::spawn(move || {
 
-            let mut start_weights = local_net.new_zeroed_weight_buffer();
-            let mut weight_diffs = local_net.new_zeroed_weight_buffer();
-            let mut context = local_net.get_training_context();
+            let mut start_weights = pool.take_zeroed_like(local_net.get_weights());
+            let mut weight_diffs = pool.take_zeroed_like(local_net.get_weights());
+            let mut context = local_net.get_pooled_training_context(&pool);
+            context.set_check_divergence(check_divergence);
+            context.set_seed(&seed);
+            context.set_gradient_noise_layer_scales(gradient_noise_layer_scales);
+            if let Some(mixed_precision) = mixed_precision {
+                context.set_mixed_precision(mixed_precision);
+            }
+            if let Some(hogwild_weights) = &hogwild_weights {
+                context.set_shared_weights(hogwild_weights.clone());
+            }
 
             let mut partition_index = worker_index;
             let mut partition_shift = 0;
 
-            //let mut shift = 0;
-            //let shift_steps = 5;
+            'sync: loop {
 
-            loop {
+                control.wait_while_paused();
 
                 if stage_complete_flag.load(Ordering::Relaxed) {
-                    return;
+                    break;
                 }
 
                 let mut batch_num = {
-                    // sync weights with shared state
+                    // sync weights with shared state, which hogwild workers do every mini-batch
                     let shared_state = shared_state.read().unwrap();
-                    shared_state.weight_buffer.copy_into(&mut start_weights);
-                    start_weights.copy_into(context.get_net_mut().get_weights_mut());
+                    if hogwild_weights.is_none() {
+                        shared_state.weight_buffer.copy_into(&mut start_weights);
+                        start_weights.copy_into(context.get_net_mut().get_weights_mut());
+                    }
                     shared_state.worker_done_counter * batches_per_sync / num_workers
                 };
 
-                let data_set = &partitioned_data_sets[partition_index];
+                let (first_row, num_rows) = shifted_window(partition_windows[partition_index], partition_shift, partition_shift_steps, num_partitions, partitioned_data_set.num_rows());
+                let data_set = &partitioned_data_set;
+                let mut batch_count = 0;
+                let first_batch = context.batch_counter();
 
                 for _ in 0..batches_per_sync {
 
-                    context.train_backprop_single_batch(
-                        data_set,
-                        learning_rate_fn.get_learning_rate(batch_num),
-                        &error_fn,
-                        mini_batch_size_fn.get_mini_batch_size(batch_num),
-                    );
+                    let learning_rate = learning_rate_fn.get_learning_rate(batch_num);
+                    let mini_batch_size = mini_batch_size_fn.get_mini_batch_size(batch_num, num_rows);
+                    batch_count += if shuffle {
+                        context.train_backprop_window_shuffled(data_set, first_row, num_rows, learning_rate, &error_fn, mini_batch_size, gradient_noise_fn)
+                    } else {
+                        context.train_backprop_window(data_set, first_row, num_rows, learning_rate, &error_fn, mini_batch_size, gradient_noise_fn)
+                    };
 
                     batch_num += 1;
                 }
 
                 if stage_complete_flag.load(Ordering::Relaxed) {
-                    return;
+                    break;
                 }
 
-                //shift = (shift + 1) & shift_steps;
-
                 // compute weight diff
-                context.get_net().get_weights().copy_into(&mut weight_diffs);
-                weight_diffs.subtract(&start_weights);
+                if hogwild_weights.is_none() {
+                    context.get_net().get_weights().copy_into(&mut weight_diffs);
+                    // parameter averaging merges the weights themselves
+                    match sync {
+                        WeightSync::DeltaAveraging | WeightSync::GradientAllReduce => weight_diffs.subtract(&start_weights),
+                        WeightSync::ParameterAveraging => {},
+                    }
+                }
 
-                {
+                let wait_for_round = {
                     let mut shared_state = shared_state.write().unwrap();
 
-                    shared_state.weight_buffer.add_with_multiplier(&mut weight_diffs, 1.0 / num_partitions as f32);
-                    //shared_state.weight_buffer.add(&mut weight_diffs);
+                    let mut wait_for_round = None;
+                    if hogwild_weights.is_none() {
+                        let shared_state = &mut *shared_state;
+                        take_untrained_weights(context.get_net(), sync, &mut weight_diffs, &mut shared_state.weight_buffer);
+                        match sync {
+                            WeightSync::DeltaAveraging => shared_state.weight_buffer.add_with_multiplier(&weight_diffs, sync_multiplier),
+                            WeightSync::ParameterAveraging => shared_state.weight_buffer.blend_towards(&weight_diffs, sync_multiplier),
+                            WeightSync::GradientAllReduce => {
+                                wait_for_round = Some(shared_state.sync_round);
+                                shared_state.pending_deltas.add(&weight_diffs);
+                                shared_state.pending_workers += 1;
+                                if shared_state.pending_workers == num_workers {
+                                    shared_state.weight_buffer.add_with_multiplier(&shared_state.pending_deltas, sync_multiplier);
+                                    shared_state.pending_deltas.reset_to(0.0);
+                                    shared_state.pending_workers = 0;
+                                    shared_state.sync_round += 1;
+                                }
+                            },
+                        }
+                    }
 
                     shared_state.worker_done_counter += 1;
+                    if let (None, Some(mut divergence)) = (shared_state.divergence, context.divergence()) {
+                        // count the batches of all workers
+                        divergence.batch = shared_state.batch_counter + divergence.batch - first_batch;
+                        shared_state.divergence = Some(divergence);
+                    }
+                    shared_state.batch_counter += batch_count;
+                    shared_state.row_counter += batches_per_sync * num_rows;
 
                     partition_index = shared_state.next_partition_index;
                     shared_state.next_partition_index = (partition_index + 1) % num_partitions;
 
-                    // TODO: something wrong here, variable unused
+                    // the next visit of a partition shifts its window once more
                     partition_shift = shared_state.partition_row_shifts[partition_index];
                     shared_state.partition_row_shifts[partition_index] += 1;
 
                     if shared_state.worker_done_counter % num_partitions == 0 {
                         if check_error_sender.send(()).is_err() {
                             // hung up, quit
-                            return;
+                            break 'sync;
                         }
                     }
 
+                    wait_for_round
+                };
+
+                // with all-reduce, wait until the other workers have added their changes
+                if let Some(round) = wait_for_round {
+                    while shared_state.read().unwrap().sync_round == round {
+                        if stage_complete_flag.load(Ordering::Relaxed) {
+                            break 'sync;
+                        }
+                        thread::yield_now();
+                    }
                 }
 
             }
 
+            context.release_buffers(&pool);
+            pool.put(start_weights);
+            pool.put(weight_diffs);
+
         });
     }
 
     {
 
-        let mut batch_num = 0;
-        let mut context = net.get_training_context();
+        let mut epoch = 0;
+        let pool = monitor.pool().clone();
+        let mut context = net.get_pooled_training_context(&pool);
+        let mut history = Vec::new();
+        if let Some(decay) = options.ema_decay {
+            context.set_weight_ema_decay(decay);
+        }
 
         loop {
 
@@ -145,24 +242,54 @@ pub fn train_backprop_multi_threaded(
                 sync_count = 1;
             }
 
-            batch_num += sync_count * batches_per_sync;
+            // a sync follows a pass over every partition by workers which each train
+            // `batches_per_sync` passes over their partition, which adds up to that many epochs
+            epoch += sync_count * batches_per_sync;
 
             // load state
-            {
+            let (batch_count, row_count, divergence) = {
                 // TODO: use net weight buffer as state weight buffer to avoid this copy operation?
                 let state = shared_state.read().unwrap();
-                state.weight_buffer.copy_into(context.get_net_mut().get_weights_mut());
+                match &hogwild_weights {
+                    Some(hogwild_weights) => hogwild_weights.load_into(context.get_net_mut().get_weights_mut()),
+                    None => state.weight_buffer.copy_into(context.get_net_mut().get_weights_mut()),
+                }
+                (state.batch_counter, state.row_counter, state.divergence)
             };
+            context.update_weight_ema();
 
-            let error_stats = context.compute_error_for_batch(
-                &data_set,
+            let (error_stats, accuracies) = context.compute_error_and_accuracies_for_batch(
+                evaluation_set,
                 &error_fn,
+                &accuracy_thresholds,
             );
 
-            if completion_fn.should_stop_training(batch_num, stage_start_time, &error_stats) {
+            let (train_error, validation_error) = match options.validation_set {
+                Some(_) => (context.compute_error_for_batch(data_set, &error_fn).mean(), Some(error_stats.mean())),
+                None => (error_stats.mean(), None),
+            };
+            let learning_rate = learning_rate_fn.get_learning_rate(epoch - 1);
+            history.push(EpochMetrics::new(epoch, batch_count, train_error, validation_error, learning_rate, stage_start_time));
+            monitor.report(epoch, batch_count, row_count, &error_stats, context.get_net().get_weights());
+
+            let diverged = error_diverged(&error_stats) || divergence.is_some();
+
+            if diverged || monitor.control().is_cancelled() || completion_fn.should_stop_training(epoch - 1, batch_count, stage_start_time, &error_stats, &accuracies) {
                 // return and close the channel, signaling that we've completed training
                 stage_complete_flag.store(true, Ordering::Relaxed);
-                return (error_stats, batch_num)
+                let result = BackpropStageResult {
+                    error_stats,
+                    accuracies,
+                    epoch_count: epoch,
+                    batch_count,
+                    row_count,
+                    diverged,
+                    divergence,
+                    history,
+                    ema_weights: context.weight_ema().cloned(),
+                };
+                context.release_buffers(&pool);
+                return result;
             }
 
         }
@@ -171,9 +298,49 @@ pub fn train_backprop_multi_threaded(
 
 }
 
+/// The window of a partition on a visit after `shift` earlier ones, which moves on by a fraction of
+/// the average partition with every visit and returns to the start after `partition_shift_steps`
+pub(crate) fn shifted_window(window: (usize, usize), shift: usize, partition_shift_steps: Option<usize>, num_partitions: usize, num_data_rows: usize) -> (usize, usize) {
+    let (first_row, num_rows) = window;
+    match partition_shift_steps {
+        Some(steps) if num_rows > 0 => {
+            let rows_per_shift = (num_data_rows / num_partitions / steps.max(1)).max(1);
+            ((first_row + (shift % steps.max(1)) * rows_per_shift) % num_data_rows, num_rows)
+        },
+        _ => window,
+    }
+}
+
+/// Copies the weights which aren't trained by back-propagation, such as the running statistics of
+/// batch norm layers, from the net of a worker into the shared weights, and leaves them out of the
+/// changes of the worker, so that they aren't averaged like the trained weights
+fn take_untrained_weights(net: &Net, sync: WeightSync, weight_diffs: &mut RowBuffer, shared_weights: &mut RowBuffer) {
+    for (layer_index, layer) in net.layer_iter().enumerate() {
+        let num_trainable_weights = layer.num_trainable_weights();
+        let weights = &net.get_weights().get_row(layer_index)[num_trainable_weights..];
+        shared_weights.get_row_mut(layer_index)[num_trainable_weights..].copy_from_slice(weights);
+        // averaged parameters are the weights, which now match the shared ones
+        let diffs = &mut weight_diffs.get_row_mut(layer_index)[num_trainable_weights..];
+        match sync {
+            WeightSync::DeltaAveraging | WeightSync::GradientAllReduce => diffs.iter_mut().for_each(|diff| *diff = 0.0),
+            WeightSync::ParameterAveraging => diffs.copy_from_slice(weights),
+        }
+    }
+}
+
 struct SharedThreadState {
     worker_done_counter: usize,
+    /// The number of mini-batches trained by all workers
+    batch_counter: usize,
+    /// The number of rows trained on by all workers
+    row_counter: usize,
+    /// The first divergence found by any worker
+    divergence: Option<DivergenceReport>,
     weight_buffer: RowBuffer,
+    /// The summed changes of the workers of the current all-reduce round
+    pending_deltas: RowBuffer,
+    pending_workers: usize,
+    sync_round: usize,
     next_partition_index: usize,
     partition_row_shifts: Vec<usize>
 }
@@ -5,48 +5,90 @@ use crate::data::PreparedDataSet;
 use crate::data::PreparedDataSetIterator;
 use crate::net::Net;
 use crate::layer::NetLayerBase;
-use crate::func::CompletionFn;
-use crate::func::MiniBatchSize;
-use crate::func::LearningRateFn;
-use crate::func::ErrorFn;
-use crate::stats::Stats;
+use crate::train::backprop::{BackpropOptions, BackpropStageResult, EpochMetrics, StageMonitor, error_diverged};
 
 pub fn train_backprop_single_threaded(
     net: &mut Net,
     data_set: &PreparedDataSet,
-    completion_fn: CompletionFn,
-    mini_batch_size_fn: MiniBatchSize,
-    learning_rate_fn: LearningRateFn,
-    error_fn: ErrorFn,
-) -> (Stats, usize) {
+    options: &BackpropOptions,
+    monitor: &mut StageMonitor,
+) -> BackpropStageResult {
+
+    let completion_fn = &options.completion_fn;
+    let mini_batch_size_fn = options.mini_batch_size_fn;
+    let learning_rate_fn = options.learning_rate_fn;
+    let error_fn = options.error_fn;
+    let gradient_noise_fn = options.gradient_noise_fn;
+    let shuffle = options.shuffle;
+    let evaluation_set = options.validation_set.as_ref().unwrap_or(data_set);
 
     let stage_start_time = SystemTime::now();
-    let mut context: NetTrainingContext = net.get_training_context();
+    let accuracy_thresholds = completion_fn.accuracy_thresholds();
+    let pool = monitor.pool().clone();
+    let mut context: NetTrainingContext = net.get_pooled_training_context(&pool);
+    if let Some(threads) = options.batch_threads {
+        context.set_batch_threads(threads, &pool);
+    }
+    context.set_check_divergence(options.check_divergence);
+    context.set_seed(monitor.seed(options));
+    context.set_gradient_noise_layer_scales(options.gradient_noise_layer_scales.clone());
+    if let Some(decay) = options.ema_decay {
+        context.set_weight_ema_decay(decay);
+    }
+    if let Some(mixed_precision) = options.mixed_precision {
+        context.set_mixed_precision(mixed_precision);
+    }
 
-    let mut batch_num = 0;
+    // each pass over the data set is an epoch of one or more mini-batches
+    let mut epoch = 0;
+    let mut batch_count = 0;
+    let mut row_count = 0;
+    let mut history = Vec::new();
 
     loop {
 
-        context.train_backprop_single_batch(
-            data_set,
-            learning_rate_fn.get_learning_rate(batch_num),
-            &error_fn,
-            mini_batch_size_fn.get_mini_batch_size(batch_num),
-        );
+        monitor.control().wait_while_paused();
+
+        let learning_rate = learning_rate_fn.get_learning_rate(epoch);
+        let mini_batch_size = mini_batch_size_fn.get_mini_batch_size(epoch, data_set.num_rows());
+        batch_count += if shuffle {
+            context.train_backprop_single_batch_shuffled(data_set, learning_rate, &error_fn, mini_batch_size, gradient_noise_fn)
+        } else {
+            context.train_backprop_single_batch(data_set, learning_rate, &error_fn, mini_batch_size, gradient_noise_fn)
+        };
+        row_count += data_set.num_rows();
 
-        let error_stats = context.compute_error_for_batch(
-            data_set,
+        let (error_stats, accuracies) = context.compute_error_and_accuracies_for_batch(
+            evaluation_set,
             &error_fn,
+            &accuracy_thresholds,
         );
 
-        batch_num += 1;
+        epoch += 1;
 
-        if batch_num % 100 == 0 {
-            println!("{}: {:?}", batch_num, &error_stats);
-        }
+        let (train_error, validation_error) = match options.validation_set {
+            Some(_) => (context.compute_error_for_batch(data_set, &error_fn).mean(), Some(error_stats.mean())),
+            None => (error_stats.mean(), None),
+        };
+        history.push(EpochMetrics::new(epoch, batch_count, train_error, validation_error, learning_rate, stage_start_time));
+        monitor.report(epoch, batch_count, row_count, &error_stats, context.get_net().get_weights());
+
+        let diverged = error_diverged(&error_stats) || context.divergence().is_some();
 
-        if completion_fn.should_stop_training(batch_num, stage_start_time, &error_stats) {
-            return (error_stats, batch_num)
+        if diverged || monitor.control().is_cancelled() || completion_fn.should_stop_training(epoch - 1, batch_count, stage_start_time, &error_stats, &accuracies) {
+            let result = BackpropStageResult {
+                error_stats,
+                accuracies,
+                epoch_count: epoch,
+                batch_count,
+                row_count,
+                diverged,
+                divergence: context.divergence(),
+                history,
+                ema_weights: context.weight_ema().cloned(),
+            };
+            context.release_buffers(&pool);
+            return result;
         }
 
     }
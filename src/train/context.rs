@@ -1,177 +1,1569 @@
 use std::num::NonZeroU32;
+use std::sync::Arc;
 
+use rand::{Rng, FromEntropy, SeedableRng};
+use rand::seq::SliceRandom;
+use rand::distributions::StandardNormal;
+use serde::{Serialize, Deserialize};
+
+use crate::Float;
 use crate::net::Net;
-use crate::buffer::RowBuffer;
-use crate::stats::Stats;
-use crate::layer::{NetLayer, NetLayerBase};
-use crate::func::ErrorFn;
-use crate::data::PreparedDataSet;
+use crate::buffer::{BufferPool, RowBuffer, SharedRowBuffer};
+use crate::stats::{RankingStats, Stats};
+use crate::layer::{NetLayer, NetLayerBase, BatchNormStats};
+use crate::func::{ErrorFn, GradientNoiseFn};
+use crate::data::{ChunkError, PreparedDataSet, StreamingCsvDataSet};
+use crate::graph::{self, LayerInput};
+use crate::utils::stable_hash_seed;
+use crate::precision::{MixedPrecision, MixedPrecisionState};
 
 pub struct NetTrainingContext<'a> {
     net: &'a mut Net,
+    /// The buffers of the passes over the rows this context trains itself
+    buffers: PassBuffers,
+    input_buffer: Vec<Float>,
+    rng: rand_xorshift::XorShiftRng,
+    /// The scale of the gradient noise of each layer, see `set_gradient_noise_layer_scales`
+    gradient_noise_layer_scales: Vec<Float>,
+    /// The visitation order of the rows when shuffling, reused across passes
+    row_order: Vec<usize>,
+    /// The statistics of the current mini-batch for each batch norm layer, while training
+    batch_norm_stats: Vec<Option<BatchNormStats>>,
+    /// Whether `forward_only` ran since the last `backward_only` or other pass over the net
+    forward_pending: bool,
+    /// Whether to look for NaN or infinite values while training, see `set_check_divergence`
+    check_divergence: bool,
+    /// The number of mini-batches trained
+    batch_counter: usize,
+    divergence: Option<DivergenceReport>,
+    /// The decay and exponential moving average of the weights, see `set_weight_ema_decay`
+    weight_ema: Option<(Float, RowBuffer)>,
+    mixed_precision: Option<MixedPrecisionState>,
+    /// The weights shared with other threads, see `set_shared_weights`
+    shared_weights: Option<Arc<SharedRowBuffer>>,
+    /// The buffers of the passes over the other parts of each mini-batch, which run on the rayon
+    /// thread pool, see `set_batch_threads`
+    batch_helpers: Vec<PassBuffers>,
+}
+
+/// The buffers of the forward and backward passes over the rows of a mini-batch, one set for
+/// each thread training part of it
+struct PassBuffers {
     output_buffers: RowBuffer,
     error_gradient_buffers: RowBuffer,
-    input_error_buffer: Vec<f32>,
+    input_error_buffer: Vec<Float>,
     error_stats: Stats,
     weight_deltas: RowBuffer,
+    /// The gathered inputs of each layer which doesn't take the output of the layer before
+    layer_input_buffers: RowBuffer,
+    /// The errors of the gathered inputs of a layer, before they are added to the sources
+    layer_input_error_buffer: Vec<Float>,
+    /// The layers whose outputs are the outputs of the net
+    output_layers: Vec<usize>,
+    /// The outputs of the net, if they aren't simply those of the last layer
+    output_buffer: Vec<Float>,
+    /// The inputs, outputs and errors of every row of a mini-batch, for batched passes
+    batch_buffers: BatchBuffers,
+    /// Whether to look for NaN or infinite outputs, see `NetTrainingContext::set_check_divergence`
+    check_outputs: bool,
+    /// The first layer found with NaN or infinite outputs, until the context reports it
+    non_finite_outputs: Option<usize>,
 }
 
-impl<'a> NetTrainingContext<'a> {
+#[derive(Default)]
+struct BatchBuffers {
+    inputs: Vec<Float>,
+    /// The outputs of each layer
+    outputs: Vec<Vec<Float>>,
+    /// The errors of the outputs of each layer
+    errors: Vec<Vec<Float>>,
+}
 
-    pub(crate) fn new(net: &'a mut Net) -> Self {
+/// The passes of one thread over rows of a mini-batch, which only read the net
+struct Pass<'p> {
+    net: &'p Net,
+    batch_norm_stats: &'p [Option<BatchNormStats>],
+    buffers: &'p mut PassBuffers,
+}
+
+/// Where NaN or infinite values first appeared while training with divergence checks
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DivergenceReport {
+    /// The index of the mini-batch, counting from the start of training
+    pub batch: usize,
+    pub layer: usize,
+    pub source: DivergenceSource,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DivergenceSource {
+    /// The outputs of the layer for a row of the mini-batch
+    Outputs,
+    /// The weight deltas of the layer for the mini-batch
+    Gradients,
+    /// The weights of the layer after the mini-batch was applied
+    Weights,
+}
+
+impl PassBuffers {
+
+    fn new_pooled(net: &Net, pool: &BufferPool) -> Self {
         let layer_sizes: Vec<usize> = net.layer_iter()
             .map(NetLayer::output_size)
             .collect();
+        let input_sizes: Vec<usize> = net.layer_iter().map(NetLayer::input_size).collect();
+        let (_, layer_input_buffers) = graph::new_graph_buffers(net.layer_inputs(), &input_sizes, &layer_sizes);
+        PassBuffers {
+            output_buffers: pool.take_zeroed_with_row_sizes(&layer_sizes),
+            error_gradient_buffers: pool.take_zeroed_with_row_sizes(&layer_sizes),
+            input_error_buffer: vec![0.0; net.input_size()],
+            error_stats: Stats::new(),
+            weight_deltas: pool.take_zeroed_like(net.get_weights()),
+            layer_input_buffers,
+            layer_input_error_buffer: vec![0.0; input_sizes.into_iter().max().unwrap()],
+            output_layers: graph::output_layers(net.output_heads(), net.num_layers()),
+            output_buffer: Vec::with_capacity(net.output_size()),
+            batch_buffers: BatchBuffers::default(),
+            check_outputs: false,
+            non_finite_outputs: None,
+        }
+    }
+
+    fn release(self, pool: &BufferPool) {
+        pool.put(self.output_buffers);
+        pool.put(self.error_gradient_buffers);
+        pool.put(self.weight_deltas);
+    }
+
+}
+
+impl<'a> NetTrainingContext<'a> {
+
+    pub(crate) fn new(net: &'a mut Net) -> Self {
+        Self::new_pooled(net, &BufferPool::new())
+    }
+
+    /// Takes the layer and weight buffers from the pool, see `release_buffers`
+    pub(crate) fn new_pooled(net: &'a mut Net, pool: &BufferPool) -> Self {
         let input_size = net.input_size();
-        let weight_deltas = net.new_zeroed_weight_buffer();
         NetTrainingContext {
+            buffers: PassBuffers::new_pooled(net, pool),
             net,
-            output_buffers: RowBuffer::new_with_row_sizes(0.0, &layer_sizes),
-            error_gradient_buffers: RowBuffer::new_with_row_sizes(0.0, &layer_sizes),
-            input_error_buffer: vec![0f32; input_size],
-            error_stats: Stats::new(),
-            weight_deltas,
+            input_buffer: vec![0.0; input_size],
+            rng: rand_xorshift::XorShiftRng::from_entropy(),
+            gradient_noise_layer_scales: Vec::new(),
+            row_order: Vec::new(),
+            batch_norm_stats: Vec::new(),
+            forward_pending: false,
+            check_divergence: false,
+            batch_counter: 0,
+            divergence: None,
+            weight_ema: None,
+            mixed_precision: None,
+            shared_weights: None,
+            batch_helpers: Vec::new(),
         }
     }
 
-    fn forward_pass_and_compute_error(
+    /// Returns the layer and weight buffers of this context and its batch helpers to the pool,
+    /// for the next context of a net with the same architecture
+    pub fn release_buffers(self, pool: &BufferPool) {
+        self.buffers.release(pool);
+        for helper in self.batch_helpers {
+            helper.release(pool);
+        }
+    }
+
+    /// The passes of this context over its own rows, which overwrite the buffers of a pending
+    /// `forward_only` pass
+    fn pass(&mut self) -> Pass<'_> {
+        self.forward_pending = false;
+        Pass {
+            net: &*self.net,
+            batch_norm_stats: &self.batch_norm_stats,
+            buffers: &mut self.buffers,
+        }
+    }
+
+    /// Checks the outputs of every row and the gradients and weights of every mini-batch for NaN
+    /// or infinite values while training, to find where a diverging net first went wrong. This
+    /// is slow, so it's meant for debugging.
+    pub fn set_check_divergence(&mut self, check_divergence: bool) {
+        self.check_divergence = check_divergence;
+        self.buffers.check_outputs = check_divergence;
+        for helper in &mut self.batch_helpers {
+            helper.check_outputs = check_divergence;
+        }
+    }
+
+    /// The first NaN or infinite value found by the divergence checks, if any
+    pub fn divergence(&self) -> Option<DivergenceReport> {
+        self.divergence
+    }
+
+    /// Seeds the shuffling of the rows and the gradient noise, which are otherwise random
+    pub fn set_seed(&mut self, seed: &str) {
+        self.rng = rand_xorshift::XorShiftRng::from_seed(stable_hash_seed(seed));
+    }
+
+    /// Scales the gradient noise of each layer, in layer order, e.g. by 0.0 to add none to a
+    /// layer. Layers past the end of the scales get the full noise.
+    pub fn set_gradient_noise_layer_scales(&mut self, scales: Vec<Float>) {
+        self.gradient_noise_layer_scales = scales;
+    }
+
+    /// Starts an exponential moving average of the weights, from the current weights, which is
+    /// updated after every mini-batch
+    pub fn set_weight_ema_decay(&mut self, decay: Float) {
+        assert!((0.0..1.0).contains(&decay), "EMA decay must be in [0, 1)");
+        self.weight_ema = Some((decay, self.net.get_weights().clone()));
+    }
+
+    /// Moves the moving average of the weights towards the current weights, if there is one
+    pub fn update_weight_ema(&mut self) {
+        if let Some((decay, ema)) = &mut self.weight_ema {
+            ema.blend_towards(self.net.get_weights(), 1.0 - *decay);
+        }
+    }
+
+    pub fn weight_ema(&self) -> Option<&RowBuffer> {
+        self.weight_ema.as_ref().map(|(_, ema)| ema)
+    }
+
+    /// Trains the following mini-batches in mixed precision, see `MixedPrecision`
+    pub fn set_mixed_precision(&mut self, mixed_precision: MixedPrecision) {
+        self.mixed_precision = Some(MixedPrecisionState::new(mixed_precision, self.net.get_weights()));
+    }
+
+    /// The current loss scale and the number of mini-batches skipped because they overflowed,
+    /// when training in mixed precision
+    pub fn mixed_precision_state(&self) -> Option<(Float, usize)> {
+        self.mixed_precision.as_ref().map(|state| (state.loss_scale(), state.skipped_batches()))
+    }
+
+    /// Trains hogwild style on weights shared with other threads: every mini-batch starts from
+    /// the shared weights and adds its deltas to them, without a lock
+    pub fn set_shared_weights(&mut self, shared_weights: Arc<SharedRowBuffer>) {
+        assert_eq!(shared_weights.buffer_len(), self.net.get_weights().buffer_len());
+        self.shared_weights = Some(shared_weights);
+    }
+
+    /// Splits every mini-batch into `threads` parts, whose deltas are computed on the rayon
+    /// thread pool and added up. All parts read the weights of this context's net, so mixed
+    /// precision, the seed and the divergence checks of the context cover the whole mini-batch.
+    /// Nets with batch norm layers are trained on one thread.
+    pub fn set_batch_threads(&mut self, threads: usize, pool: &BufferPool) {
+        assert!(threads > 0, "at least one thread is needed");
+        for helper in self.batch_helpers.drain(..) {
+            helper.release(pool);
+        }
+        for _ in 1..threads {
+            let mut helper = PassBuffers::new_pooled(&*self.net, pool);
+            helper.check_outputs = self.check_divergence;
+            self.batch_helpers.push(helper);
+        }
+    }
+
+    /// The number of mini-batches trained with this context
+    pub fn batch_counter(&self) -> usize {
+        self.batch_counter
+    }
+
+    fn check_for_divergence(&mut self, source: DivergenceSource) {
+        if !self.check_divergence || self.divergence.is_some() {
+            return;
+        }
+        let layer = match source {
+            DivergenceSource::Outputs => {
+                let helpers = self.batch_helpers.iter_mut().map(|helper| helper.non_finite_outputs.take());
+                std::iter::once(self.buffers.non_finite_outputs.take()).chain(helpers).flatten().min()
+            },
+            DivergenceSource::Gradients => first_non_finite_row(&self.buffers.weight_deltas),
+            DivergenceSource::Weights => first_non_finite_row(self.net.get_weights()),
+        };
+        if let Some(layer) = layer {
+            self.divergence = Some(DivergenceReport {
+                batch: self.batch_counter,
+                layer,
+                source,
+            });
+        }
+    }
+
+    /// Trains one pass over the data set, and returns the number of mini-batches trained
+    pub fn train_backprop_single_batch(
         &mut self,
-        inputs: &[f32],
-        expected_outputs: &[f32],
+        data_set: &PreparedDataSet,
+        learning_rate: Float,
         error_fn: &ErrorFn,
-    ) {
+        mini_batch_size: Option<NonZeroU32>,
+        gradient_noise_fn: Option<GradientNoiseFn>,
+    ) -> usize {
+        debug_assert!(data_set.num_rows() > 0);
+        self.train_backprop_rows(data_set.iter(), learning_rate, error_fn, mini_batch_size, gradient_noise_fn)
+    }
+
+    /// Like `train_backprop_single_batch`, but visits the rows in a new random order
+    pub fn train_backprop_single_batch_shuffled(
+        &mut self,
+        data_set: &PreparedDataSet,
+        learning_rate: Float,
+        error_fn: &ErrorFn,
+        mini_batch_size: Option<NonZeroU32>,
+        gradient_noise_fn: Option<GradientNoiseFn>,
+    ) -> usize {
+        debug_assert!(data_set.num_rows() > 0);
+        // temporarily take the row order to avoid borrowing self twice
+        let mut row_order = std::mem::take(&mut self.row_order);
+        row_order.clear();
+        row_order.extend(0..data_set.num_rows());
+        row_order.shuffle(&mut self.rng);
+        let batch_count = self.train_backprop_rows(data_set.iter_permuted(&row_order), learning_rate, error_fn, mini_batch_size, gradient_noise_fn);
+        self.row_order = row_order;
+        batch_count
+    }
+
+    /// Like `train_backprop_single_batch`, over the window of `num_rows` rows from `first_row`,
+    /// which wraps around to the first row, see `PreparedDataSet::iter_window`
+    #[allow(clippy::too_many_arguments)]
+    pub fn train_backprop_window(
+        &mut self,
+        data_set: &PreparedDataSet,
+        first_row: usize,
+        num_rows: usize,
+        learning_rate: Float,
+        error_fn: &ErrorFn,
+        mini_batch_size: Option<NonZeroU32>,
+        gradient_noise_fn: Option<GradientNoiseFn>,
+    ) -> usize {
+        self.train_backprop_rows(data_set.iter_window(first_row, num_rows), learning_rate, error_fn, mini_batch_size, gradient_noise_fn)
+    }
+
+    /// Like `train_backprop_window`, but visits the rows of the window in a new random order
+    #[allow(clippy::too_many_arguments)]
+    pub fn train_backprop_window_shuffled(
+        &mut self,
+        data_set: &PreparedDataSet,
+        first_row: usize,
+        num_rows: usize,
+        learning_rate: Float,
+        error_fn: &ErrorFn,
+        mini_batch_size: Option<NonZeroU32>,
+        gradient_noise_fn: Option<GradientNoiseFn>,
+    ) -> usize {
+        let mut row_order = std::mem::take(&mut self.row_order);
+        row_order.clear();
+        row_order.extend(0..num_rows);
+        row_order.shuffle(&mut self.rng);
+        let rows = row_order.iter().map(|&index| data_set.window_row(first_row, index));
+        let batch_count = self.train_backprop_rows(rows, learning_rate, error_fn, mini_batch_size, gradient_noise_fn);
+        self.row_order = row_order;
+        batch_count
+    }
+
+    /// Trains one pass over a data set streamed from disk, shuffling the rows within each
+    /// chunk, and returns the number of rows trained on
+    pub fn train_backprop_streamed(
+        &mut self,
+        data_set: &StreamingCsvDataSet,
+        learning_rate: Float,
+        error_fn: &ErrorFn,
+        mini_batch_size: Option<NonZeroU32>,
+        gradient_noise_fn: Option<GradientNoiseFn>,
+    ) -> Result<usize, ChunkError> {
+        let mut num_rows = 0;
+        for chunk in data_set.chunks() {
+            let chunk = chunk?;
+            self.train_backprop_single_batch_shuffled(&chunk, learning_rate, error_fn, mini_batch_size, gradient_noise_fn);
+            num_rows += chunk.num_rows();
+        }
+        Ok(num_rows)
+    }
+
+
+    fn train_backprop_rows<'d>(
+        &mut self,
+        mut rows: impl Iterator<Item = (&'d [Float], &'d [Float])>,
+        learning_rate: Float,
+        error_fn: &ErrorFn,
+        mini_batch_size: Option<NonZeroU32>,
+        gradient_noise_fn: Option<GradientNoiseFn>,
+    ) -> usize {
+
+        let has_batch_norm = self.net.layer_iter().any(|layer| matches!(layer, NetLayer::BatchNorm(_)));
+        let mut batch: Vec<(&[Float], &[Float])> = Vec::new();
+        let mut batch_count = 0;
+
+        loop {
+
+            batch.clear();
+            match mini_batch_size {
+                None => batch.extend(&mut rows),
+                Some(size) => batch.extend(rows.by_ref().take(size.get() as usize)),
+            }
+            if batch.is_empty() {
+                break;
+            }
+
+            self.buffers.weight_deltas.reset_to(0.0);
+            if let Some(shared) = &self.shared_weights {
+                shared.load_into(self.net.get_weights_mut());
+            }
+            if let Some(state) = &mut self.mixed_precision {
+                state.start_batch(self.net.get_weights_mut());
+            }
+
+            if has_batch_norm {
+                self.compute_batch_norm_stats(&batch);
+                self.compute_batch_norm_error_means(&batch, error_fn, learning_rate);
+                if let Some(shared) = &self.shared_weights {
+                    store_untrained_weights(self.net, shared);
+                }
+            }
+
+            if has_batch_norm || self.batch_helpers.is_empty() {
+                self.pass().backprop_rows(&batch, error_fn, learning_rate);
+            } else {
+                self.backprop_rows_in_parallel(&batch, error_fn, learning_rate);
+            }
+            self.check_for_divergence(DivergenceSource::Outputs);
+
+            // the noise anneals with every mini-batch trained by this context
+            if let Some(gradient_noise_fn) = gradient_noise_fn {
+                self.add_gradient_noise(learning_rate, gradient_noise_fn.get_std_dev(self.batch_counter));
+            }
+
+            // apply weight updates
+            self.check_for_divergence(DivergenceSource::Gradients);
+            let applied = match &mut self.mixed_precision {
+                Some(state) => state.finish_batch(self.net.get_weights_mut(), &mut self.buffers.weight_deltas),
+                None => {
+                    self.net.get_weights_mut().add(&self.buffers.weight_deltas);
+                    true
+                },
+            };
+            if let (true, Some(shared)) = (applied, &self.shared_weights) {
+                shared.add_sparse(&self.buffers.weight_deltas);
+            }
+            self.check_for_divergence(DivergenceSource::Weights);
+            self.update_weight_ema();
+            self.batch_counter += 1;
+            batch_count += 1;
+
+        }
+
+        // outside of training, batch norm layers use their running statistics
+        self.batch_norm_stats.clear();
+        batch_count
+    }
+
+    /// Like `Pass::backprop_rows`, with the rows split evenly between this context and its
+    /// helpers, which all read the weights of the net
+    fn backprop_rows_in_parallel(&mut self, rows: &[(&[Float], &[Float])], error_fn: &ErrorFn, learning_rate: Float) {
+        self.forward_pending = false;
+        let chunk_rows = rows.len().div_ceil(self.batch_helpers.len() + 1).max(1);
+        let mut chunks = rows.chunks(chunk_rows);
+        let own_rows = chunks.next().unwrap_or(&[]);
+        let (net, batch_norm_stats) = (&*self.net, &self.batch_norm_stats);
+        let own_buffers = &mut self.buffers;
+        let helpers = &mut self.batch_helpers;
+        for helper in helpers.iter_mut() {
+            helper.weight_deltas.reset_to(0.0);
+        }
+        rayon::scope(|scope| {
+            for (helper, rows) in helpers.iter_mut().zip(chunks) {
+                scope.spawn(move |_| Pass { net, batch_norm_stats, buffers: helper }.backprop_rows(rows, error_fn, learning_rate));
+            }
+            Pass { net, batch_norm_stats, buffers: own_buffers }.backprop_rows(own_rows, error_fn, learning_rate);
+        });
+        for helper in helpers.iter_mut() {
+            own_buffers.weight_deltas.add(&helper.weight_deltas);
+            own_buffers.error_stats.merge(&helper.error_stats);
+            helper.error_stats.reset();
+        }
+    }
+
+    /// Computes the statistics of the inputs of each batch norm layer over the mini-batch, in
+    /// order, since the inputs of a layer depend on the statistics of the layers before it. The
+    /// running statistics of the layers are updated along the way.
+    fn compute_batch_norm_stats(&mut self, batch: &[(&[Float], &[Float])]) {
+
+        self.batch_norm_stats.clear();
+        self.batch_norm_stats.resize(self.net.num_layers(), None);
+
+        for layer_index in 0..self.net.num_layers() {
+
+            let layer = match self.net.layer(layer_index) {
+                NetLayer::BatchNorm(layer) => layer.clone(),
+                _ => continue,
+            };
+
+            let mut stats = vec![Stats::new(); layer.input_size()];
+            for &(inputs, _) in batch {
+                let layer_inputs = match self.net.layer_input(layer_index) {
+                    LayerInput::Previous if layer_index == 0 => inputs,
+                    LayerInput::Previous => {
+                        self.pass().forward_pass_to(inputs, layer_index);
+                        self.buffers.output_buffers.get_row(layer_index - 1)
+                    },
+                    layer_input => {
+                        let layer_input = layer_input.clone();
+                        self.pass().forward_pass_to(inputs, layer_index);
+                        let buffers = &mut self.buffers;
+                        layer_input.gather(layer_index, inputs, &buffers.output_buffers, buffers.layer_input_buffers.get_row_mut(layer_index));
+                        buffers.layer_input_buffers.get_row(layer_index)
+                    },
+                };
+                for (s, &value) in stats.iter_mut().zip(layer_inputs) {
+                    s.report(value);
+                }
+            }
+
+            let batch_stats = BatchNormStats {
+                mean: stats.iter().map(|s| s.mean() as Float).collect(),
+                variance: stats.iter().map(|s| s.variance() as Float).collect(),
+                output_error_mean: Vec::new(),
+                normalized_output_error_mean: Vec::new(),
+            };
+            layer.update_running_stats(self.net.get_weights_mut().get_row_mut(layer_index), &batch_stats);
+            self.batch_norm_stats[layer_index] = Some(batch_stats);
+        }
+    }
+
+    /// Computes the means of the output errors of each batch norm layer over the mini-batch,
+    /// for the gradient through its statistics. The layers go last to first, since the output
+    /// errors of a layer depend on the error means of the layers after it, each with a pass over
+    /// the mini-batch whose weight deltas are discarded.
+    fn compute_batch_norm_error_means(&mut self, batch: &[(&[Float], &[Float])], error_fn: &ErrorFn, learning_rate: Float) {
+
+        for layer_index in (0..self.net.num_layers()).rev() {
+
+            let layer = match self.net.layer(layer_index) {
+                NetLayer::BatchNorm(layer) => layer.clone(),
+                _ => continue,
+            };
+
+            let mut error_sums = vec![0.0; layer.input_size()];
+            let mut normalized_error_sums = vec![0.0; layer.input_size()];
+            for &(inputs, expected_outputs) in batch {
+                let mut pass = self.pass();
+                pass.forward_pass(inputs);
+                pass.compute_error(expected_outputs, error_fn);
+                pass.backprop(inputs, learning_rate);
+                let stats = pass.batch_norm_stats[layer_index].as_ref().unwrap();
+                let output_errors = pass.buffers.error_gradient_buffers.get_row(layer_index);
+                layer.sum_output_errors(stats, output_errors, pass.layer_inputs(inputs, layer_index), &mut error_sums, &mut normalized_error_sums);
+            }
+
+            let batch_size = batch.len() as Float;
+            let stats = self.batch_norm_stats[layer_index].as_mut().unwrap();
+            stats.output_error_mean = error_sums.into_iter().map(|sum| sum / batch_size).collect();
+            stats.normalized_output_error_mean = normalized_error_sums.into_iter().map(|sum| sum / batch_size).collect();
+        }
+
+        self.buffers.weight_deltas.reset_to(0.0);
+    }
+
+    #[inline]
+    pub fn get_weight_deltas(&self) -> &RowBuffer {
+        &self.buffers.weight_deltas
+    }
+
+    /// Adds gaussian noise with the given standard deviation, scaled for each layer, to the
+    /// gradients accumulated in the weight deltas, scaled by the learning rate like the gradients
+    /// themselves.
+    fn add_gradient_noise(&mut self, learning_rate: Float, std_dev: Float) {
+        for (layer_index, layer) in self.net.layer_iter().enumerate() {
+            let layer_std_dev = std_dev * self.gradient_noise_layer_scales.get(layer_index).copied().unwrap_or(1.0);
+            if self.buffers.weight_deltas.is_aliased_row(layer_index) || layer_std_dev == 0.0 {
+                continue;
+            }
+            let trainable_deltas = &mut self.buffers.weight_deltas.get_row_mut(layer_index)[..layer.num_trainable_weights()];
+            for delta in trainable_deltas {
+                *delta -= learning_rate * layer_std_dev * self.rng.sample(StandardNormal) as Float;
+            }
+        }
+    }
+
+    pub fn compute_error_for_batch(&mut self, data_set: &PreparedDataSet, error_fn: &ErrorFn) -> Stats {
+        self.buffers.error_stats.reset();
+        for (inputs, expected_outputs) in data_set {
+            self.pass().forward_pass_and_compute_error(inputs, expected_outputs, error_fn);
+        }
+        self.buffers.error_stats.clone()
+    }
+
+    pub fn compute_error_streamed(&mut self, data_set: &StreamingCsvDataSet, error_fn: &ErrorFn) -> Result<Stats, ChunkError> {
+        let mut error_stats = Stats::new();
+        for chunk in data_set.chunks() {
+            error_stats.merge(&self.compute_error_for_batch(&chunk?, error_fn));
+        }
+        Ok(error_stats)
+    }
+
+    /// Computes the error stats for the data set and, if an `accuracy_threshold` is given, the
+    /// accuracy stats, which hold 1.0 for every row where all outputs match the expected outputs
+    /// when thresholded and 0.0 otherwise.
+    pub fn compute_error_and_accuracy_for_batch(
+        &mut self,
+        data_set: &PreparedDataSet,
+        error_fn: &ErrorFn,
+        accuracy_threshold: Option<Float>,
+    ) -> (Stats, Option<Stats>) {
+        let thresholds: Vec<Float> = accuracy_threshold.into_iter().collect();
+        let (error_stats, accuracies) = self.compute_error_and_accuracies_for_batch(data_set, error_fn, &thresholds);
+        (error_stats, accuracies.into_iter().next().map(|(_, stats)| stats))
+    }
+
+    /// Like `compute_error_and_accuracy_for_batch`, but computes the accuracy stats at each of the
+    /// thresholds in a single pass over the data set
+    pub fn compute_error_and_accuracies_for_batch(
+        &mut self,
+        data_set: &PreparedDataSet,
+        error_fn: &ErrorFn,
+        thresholds: &[Float],
+    ) -> (Stats, Vec<(Float, Stats)>) {
+        if thresholds.is_empty() {
+            return (self.compute_error_for_batch(data_set, error_fn), Vec::new());
+        }
+        self.buffers.error_stats.reset();
+        let mut accuracies: Vec<(Float, Stats)> = thresholds.iter()
+            .map(|&threshold| (threshold, Stats::new()))
+            .collect();
+        for (inputs, expected_outputs) in data_set {
+            self.pass().forward_pass_and_compute_error(inputs, expected_outputs, error_fn);
+            let outputs = self.pass().outputs();
+            for (threshold, accuracy_stats) in &mut accuracies {
+                let correct = outputs.iter()
+                    .zip(expected_outputs)
+                    .all(|(&actual, &expected)| (actual >= *threshold) == (expected >= *threshold));
+                accuracy_stats.report(if correct { 1.0 } else { 0.0 });
+            }
+        }
+        (self.buffers.error_stats.clone(), accuracies)
+    }
+
+    /// Ranks the scores of one output over the data set, the row being positive if its expected
+    /// output is at least the threshold
+    pub fn compute_ranking_for_batch(&mut self, data_set: &PreparedDataSet, output_index: usize, threshold: Float) -> RankingStats {
+        assert!(output_index < self.net.output_size(), "ranked output out of range");
+        let mut ranking_stats = RankingStats::new();
+        for (inputs, expected_outputs) in data_set {
+            self.pass().forward_pass(inputs);
+            let score = self.pass().outputs()[output_index];
+            ranking_stats.record(score, expected_outputs[output_index] >= threshold);
+        }
+        ranking_stats
+    }
+
+    #[inline]
+    pub fn get_net(&mut self) -> &Net {
+        &self.net
+    }
+
+    #[inline]
+    pub fn get_net_mut(&mut self) -> &mut Net {
+        &mut self.net
+    }
+
+}
+
+/// The single-step API, see `forward_only`
+impl<'a> NetTrainingContext<'a> {
+
+    /// Runs a single forward pass over `inputs` and returns the outputs of the net.
+    ///
+    /// The inputs are retained so that a subsequent call to `backward_only` can back-propagate
+    /// against them. This, together with `backward_only`, `apply_weight_deltas` and
+    /// `reset_weight_deltas`, is a stable single-step API for profilers, benchmarks and research
+    /// code that want to drive individual passes without the data set / batch machinery.
+    pub fn forward_only(&mut self, inputs: &[Float]) -> &[Float] {
+        assert_eq!(inputs.len(), self.net.input_size());
+        self.input_buffer.copy_from_slice(inputs);
+        self.pass().forward_pass(inputs);
+        let Self { net, batch_norm_stats, buffers, forward_pending, .. } = self;
+        *forward_pending = true;
+        Pass { net, batch_norm_stats, buffers }.outputs()
+    }
+
+    /// Back-propagates the error between the outputs of the last `forward_only` call and
+    /// `expected_outputs`, accumulating the resulting weight deltas without applying them.
+    ///
+    /// Returns the error of the outputs with respect to `expected_outputs`.
+    pub fn backward_only(&mut self, expected_outputs: &[Float], error_fn: &ErrorFn, learning_rate: Float) -> Float {
+        assert_eq!(expected_outputs.len(), self.net.output_size());
+        debug_assert!(self.forward_pending, "backward_only needs a forward_only pass first");
+        self.forward_pending = false;
+        let Self { net, batch_norm_stats, buffers, input_buffer, .. } = self;
+        let mut pass = Pass { net, batch_norm_stats, buffers };
+        let error = pass.compute_error(expected_outputs, error_fn);
+        pass.backprop(input_buffer, learning_rate);
+        error
+    }
+
+    /// Applies the weight deltas accumulated by `backward_only` to the net and resets them.
+    pub fn apply_weight_deltas(&mut self) {
+        self.net.get_weights_mut().add(&self.buffers.weight_deltas);
+        self.buffers.weight_deltas.reset_to(0.0);
+    }
+
+    /// Discards any weight deltas accumulated by `backward_only`.
+    pub fn reset_weight_deltas(&mut self) {
+        self.buffers.weight_deltas.reset_to(0.0);
+    }
+
+}
+
+impl<'p> Pass<'p> {
+
+    fn forward_pass(&mut self, inputs: &[Float]) {
+        self.forward_pass_to(inputs, self.net.num_layers());
+    }
+
+    /// Runs the forward pass through the layers before `end_layer` only
+    fn forward_pass_to(&mut self, inputs: &[Float], end_layer: usize) {
 
         debug_assert_eq!(self.net.first_layer().input_size(), inputs.len());
-        debug_assert_eq!(self.net.last_layer().output_size(), expected_outputs.len());
 
-        // forward pass
-        {
-            let layer_output = self.output_buffers.get_first_row_mut();
-            self.net.first_layer().forward_pass(
-                self.net.get_weights().get_first_row(),
-                inputs,
-                layer_output
-            );
+        if end_layer == 0 {
+            return;
         }
 
-        for layer_index in 1..self.net.num_layers() {
-            let (layer_input, layer_output) = self.output_buffers.split_rows(layer_index - 1, layer_index);
-            self.net.layer(layer_index).forward_pass(
+        if !self.net.is_chain() {
+            let net = self.net;
+            let batch_norm_stats = &self.batch_norm_stats;
+            graph::forward_graph(net.layer_inputs(), inputs, end_layer, &mut self.buffers.output_buffers, &mut self.buffers.layer_input_buffers, |layer_index, input, output| {
+                forward_layer(
+                    net.layer(layer_index),
+                    net.get_weights().get_row(layer_index),
+                    batch_norm_stats.get(layer_index).and_then(Option::as_ref),
+                    input,
+                    output,
+                );
+            });
+            return;
+        }
+
+        forward_layer(
+            self.net.first_layer(),
+            self.net.get_weights().get_first_row(),
+            self.batch_norm_stats.first().and_then(Option::as_ref),
+            inputs,
+            self.buffers.output_buffers.get_first_row_mut(),
+        );
+
+        for layer_index in 1..end_layer {
+            let (layer_input, layer_output) = self.buffers.output_buffers.split_rows(layer_index - 1, layer_index);
+            forward_layer(
+                self.net.layer(layer_index),
                 self.net.get_weights().get_row(layer_index),
+                self.batch_norm_stats.get(layer_index).and_then(Option::as_ref),
                 layer_input,
-                layer_output
+                layer_output,
             );
         }
+    }
+
+    /// The inputs of a layer in the last forward pass
+    fn layer_inputs<'i>(&'i self, inputs: &'i [Float], layer_index: usize) -> &'i [Float] {
+        match self.net.layer_input(layer_index) {
+            LayerInput::Previous if layer_index == 0 => inputs,
+            LayerInput::Previous => self.buffers.output_buffers.get_row(layer_index - 1),
+            _ => self.buffers.layer_input_buffers.get_row(layer_index),
+        }
+    }
 
-        // compute error
-        {
-            let mut error_sum = 0.0;
-            let last_error_grad_buffer = self.error_gradient_buffers.get_last_row_mut();
-            let output = self.output_buffers.get_last_row();
-            for output_index in 0..self.net.output_size() {
-                error_sum += error_fn.get_error(expected_outputs[output_index], output[output_index]);
-                last_error_grad_buffer[output_index] = error_fn.get_error_derivative(expected_outputs[output_index], output[output_index]);
-            }
-            self.error_stats.report(error_sum);
+    fn compute_error(&mut self, expected_outputs: &[Float], error_fn: &ErrorFn) -> Float {
+
+        debug_assert_eq!(self.net.output_size(), expected_outputs.len());
+
+        if self.net.output_heads().is_empty() {
+            return self.compute_layer_error(self.net.num_layers() - 1, expected_outputs, error_fn);
+        }
+
+        // the expected outputs of the heads are laid out one after another
+        let mut error_sum = 0.0;
+        let mut offset = 0;
+        for head_index in 0..self.net.output_heads().len() {
+            let head = &self.net.output_heads()[head_index];
+            let (layer_index, head_error_fn) = (head.layer_index, head.error_fn.unwrap_or(*error_fn));
+            let size = self.net.layer(layer_index).output_size();
+            error_sum += self.compute_layer_error(layer_index, &expected_outputs[offset..offset + size], &head_error_fn);
+            offset += size;
+        }
+        error_sum
+    }
+
+    /// Computes the error of the outputs of an output layer, and sets the error gradients of the
+    /// layer accordingly
+    fn compute_layer_error(&mut self, layer_index: usize, expected_outputs: &[Float], error_fn: &ErrorFn) -> Float {
+
+        let error_grad_buffer = self.buffers.error_gradient_buffers.get_row_mut(layer_index);
+        let output = self.buffers.output_buffers.get_row(layer_index);
+
+        if let NetLayer::SoftmaxCrossEntropy(layer) = self.net.layer(layer_index) {
+            layer.loss_gradient(output, expected_outputs, error_grad_buffer);
+            return layer.loss(output, expected_outputs);
+        }
+
+        let mut error_sum = 0.0;
+        for output_index in 0..output.len() {
+            error_sum += error_fn.get_error(expected_outputs[output_index], output[output_index]);
+            error_grad_buffer[output_index] = error_fn.get_error_derivative(expected_outputs[output_index], output[output_index]);
+        }
+        error_sum
+    }
+
+    /// The outputs of the net from the last forward pass
+    fn outputs(self) -> &'p [Float] {
+        let buffers = self.buffers;
+        if self.net.output_heads().is_empty() {
+            return buffers.output_buffers.get_last_row();
         }
+        graph::collect_outputs(self.net.output_heads(), &buffers.output_buffers, &mut buffers.output_buffer);
+        &buffers.output_buffer
+    }
+
+    fn forward_pass_and_compute_error(
+        &mut self,
+        inputs: &[Float],
+        expected_outputs: &[Float],
+        error_fn: &ErrorFn,
+    ) {
+        self.forward_pass(inputs);
+        let error = self.compute_error(expected_outputs, error_fn);
+        self.buffers.error_stats.report(error);
     }
 
     fn backprop(
         &mut self,
-        inputs: &[f32],
-        learning_rate: f32,
+        inputs: &[Float],
+        learning_rate: Float,
     ) {
 
         debug_assert_eq!(inputs.len(), self.net.input_size());
         debug_assert!(learning_rate > 0.0 && learning_rate <= 10.0);
 
+        if !self.net.is_chain() {
+            self.backprop_graph(inputs, learning_rate);
+            return;
+        }
+
         // back-propagate errors without updating the net
         for layer_index in (1..self.net.num_layers()).rev() {
-            let (input_errors, output_errors) = self.error_gradient_buffers.split_rows(layer_index - 1, layer_index);
-            self.net.layer(layer_index).backprop(
+            let (input_errors, output_errors) = self.buffers.error_gradient_buffers.split_rows(layer_index - 1, layer_index);
+            backprop_layer(
+                self.net.layer(layer_index),
+                self.batch_norm_stats.get(layer_index).and_then(Option::as_ref),
                 self.net.get_weights().get_row(layer_index),
                 output_errors,
-                self.output_buffers.get_row(layer_index - 1),
-                self.output_buffers.get_row(layer_index),
+                self.buffers.output_buffers.get_row(layer_index - 1),
+                self.buffers.output_buffers.get_row(layer_index),
                 learning_rate,
                 input_errors,
-                self.weight_deltas.get_row_mut(layer_index),
+                self.buffers.weight_deltas.get_row_mut(layer_index),
             );
         }
 
-        self.net.first_layer().backprop(
+        backprop_layer(
+            self.net.first_layer(),
+            self.batch_norm_stats.first().and_then(Option::as_ref),
             self.net.get_weights().get_first_row(),
-            self.error_gradient_buffers.get_first_row(),
+            self.buffers.error_gradient_buffers.get_first_row(),
             inputs,
-            self.output_buffers.get_first_row(),
+            self.buffers.output_buffers.get_first_row(),
             learning_rate,
-            self.input_error_buffer.as_mut_slice(),
-            self.weight_deltas.get_first_row_mut(),
+            self.buffers.input_error_buffer.as_mut_slice(),
+            self.buffers.weight_deltas.get_first_row_mut(),
         );
     }
 
-    pub fn train_backprop_single_batch(
-        &mut self,
-        data_set: &PreparedDataSet,
-        learning_rate: f32,
-        error_fn: &ErrorFn,
-        mini_batch_size: Option<NonZeroU32>
-    ) {
+    /// Back-propagates through a net whose layers aren't a simple chain. Since the output of a
+    /// layer may feed several later layers, the errors of each layer are accumulated from all of
+    /// them before the layer itself is back-propagated.
+    fn backprop_graph(&mut self, inputs: &[Float], learning_rate: Float) {
 
-        let mut iter = data_set.iter();
+        // the errors of the output layers were set by compute_error
+        let last_layer_index = self.net.num_layers() - 1;
+        for layer_index in 0..=last_layer_index {
+            if !self.buffers.output_layers.contains(&layer_index) {
+                self.buffers.error_gradient_buffers.get_row_mut(layer_index).iter_mut().for_each(|e| *e = 0.0);
+            }
+        }
+        self.buffers.input_error_buffer.iter_mut().for_each(|e| *e = 0.0);
 
-        debug_assert!(iter.has_next());
+        for layer_index in (0..=last_layer_index).rev() {
+            let layer = self.net.layer(layer_index);
+            let layer_input = self.net.layer_input(layer_index);
+            let layer_inputs = match layer_input {
+                LayerInput::Previous if layer_index == 0 => inputs,
+                LayerInput::Previous => self.buffers.output_buffers.get_row(layer_index - 1),
+                _ => self.buffers.layer_input_buffers.get_row(layer_index),
+            };
+            let input_errors = &mut self.buffers.layer_input_error_buffer[..layer.input_size()];
+            backprop_layer(
+                layer,
+                self.batch_norm_stats.get(layer_index).and_then(Option::as_ref),
+                self.net.get_weights().get_row(layer_index),
+                self.buffers.error_gradient_buffers.get_row(layer_index),
+                layer_inputs,
+                self.buffers.output_buffers.get_row(layer_index),
+                learning_rate,
+                input_errors,
+                self.buffers.weight_deltas.get_row_mut(layer_index),
+            );
+            layer_input.scatter_errors(layer_index, input_errors, &mut self.buffers.input_error_buffer, &mut self.buffers.error_gradient_buffers);
+        }
+    }
 
-        while iter.has_next() {
+    /// Adds the weight deltas of the rows to the deltas of the mini-batch
+    fn backprop_rows(&mut self, rows: &[(&[Float], &[Float])], error_fn: &ErrorFn, learning_rate: Float) {
+        if self.can_backprop_batched() {
+            self.backprop_rows_batched(rows, error_fn, learning_rate);
+            return;
+        }
+        for &(inputs, expected_outputs) in rows {
 
-            //buffers.error_gradient_buffers.reset_to(0.0);
+            self.forward_pass_and_compute_error(
+                inputs,
+                expected_outputs,
+                error_fn,
+            );
+            self.check_outputs();
 
-            self.weight_deltas.reset_to(0.0);
+            self.backprop(inputs, learning_rate);
+        }
+    }
+
+    /// Records the first layer with NaN or infinite outputs for the last row, when checking
+    fn check_outputs(&mut self) {
+        let buffers = &mut *self.buffers;
+        if buffers.check_outputs && buffers.non_finite_outputs.is_none() {
+            buffers.non_finite_outputs = first_non_finite_row(&buffers.output_buffers);
+        }
+    }
+
+    /// Whether the rows of a mini-batch can be passed through the net at once, which so far
+    /// needs a chain of fully connected layers. Divergence checks need the outputs of every row.
+    fn can_backprop_batched(&self) -> bool {
+        !self.buffers.check_outputs
+            && self.net.is_chain()
+            && self.net.output_heads().is_empty()
+            && self.net.layer_iter().all(|layer| matches!(layer, NetLayer::FullyConnected(_)))
+    }
+
+    /// Like `backprop_rows`, with each layer passing all the rows at once as a matrix product,
+    /// see `FullyConnectedNetLayer::forward_pass_batch`. The deltas are the same as those of the
+    /// per-row passes.
+    fn backprop_rows_batched(&mut self, rows: &[(&[Float], &[Float])], error_fn: &ErrorFn, learning_rate: Float) {
+
+        let net = self.net;
+        let num_layers = net.num_layers();
+        let buffers = &mut self.buffers.batch_buffers;
+        let layer = |layer_index: usize| match net.layer(layer_index) {
+            NetLayer::FullyConnected(layer) => layer,
+            _ => unreachable!("only fully connected layers are batched"),
+        };
+
+        buffers.inputs.clear();
+        for &(inputs, _) in rows {
+            buffers.inputs.extend_from_slice(inputs);
+        }
+        buffers.outputs.resize_with(num_layers, Vec::new);
+        buffers.errors.resize_with(num_layers, Vec::new);
+
+        for layer_index in 0..num_layers {
+            let (previous_outputs, outputs) = buffers.outputs.split_at_mut(layer_index);
+            let inputs = previous_outputs.last().unwrap_or(&buffers.inputs);
+            outputs[0].resize(rows.len() * layer(layer_index).output_size(), 0.0);
+            layer(layer_index).forward_pass_batch(net.get_weights().get_row(layer_index), inputs, &mut outputs[0]);
+        }
+
+        let output_size = net.output_size();
+        let errors = &mut buffers.errors[num_layers - 1];
+        errors.resize(rows.len() * output_size, 0.0);
+        let outputs = buffers.outputs[num_layers - 1].chunks_exact(output_size);
+        for ((output, error_grads), &(_, expected_outputs)) in outputs.zip(errors.chunks_exact_mut(output_size)).zip(rows) {
+            let mut error_sum = 0.0;
+            for output_index in 0..output_size {
+                error_sum += error_fn.get_error(expected_outputs[output_index], output[output_index]);
+                error_grads[output_index] = error_fn.get_error_derivative(expected_outputs[output_index], output[output_index]);
+            }
+            self.buffers.error_stats.report(error_sum);
+        }
 
-            let mut remaining_epochs = match mini_batch_size {
-                None => -1,
-                Some(size) => size.get() as i64,
+        for layer_index in (0..num_layers).rev() {
+            let (previous_errors, errors) = buffers.errors.split_at_mut(layer_index);
+            let input_errors = previous_errors.last_mut().map(|input_errors| {
+                input_errors.resize(rows.len() * layer(layer_index).input_size(), 0.0);
+                input_errors.as_mut_slice()
+            });
+            let inputs = match layer_index {
+                0 => &buffers.inputs,
+                _ => &buffers.outputs[layer_index - 1],
             };
+            layer(layer_index).backprop_batch(
+                net.get_weights().get_row(layer_index),
+                &mut errors[0],
+                inputs,
+                &buffers.outputs[layer_index],
+                learning_rate,
+                input_errors,
+                self.buffers.weight_deltas.get_row_mut(layer_index),
+            );
+        }
 
-            while remaining_epochs != 0 && iter.has_next() {
+    }
 
-                let (inputs, expected_outputs) = iter.next_unchecked();
+}
 
-                self.forward_pass_and_compute_error(
-                    inputs,
-                    expected_outputs,
-                    error_fn,
-                );
+/// Overwrites the weights which aren't trained by back-propagation, such as the running statistics
+/// of batch norm layers, in the shared weights, which are otherwise only updated by weight deltas
+fn store_untrained_weights(net: &Net, shared: &SharedRowBuffer) {
+    let weights = net.get_weights();
+    for (layer_index, layer) in net.layer_iter().enumerate() {
+        let (offset, size) = weights.row_layout()[layer_index];
+        shared.store_range(weights, offset + layer.num_trainable_weights()..offset + size);
+    }
+}
 
-                self.backprop(inputs, learning_rate);
+/// The first row of the buffer with a NaN or infinite value, if any
+fn first_non_finite_row(buffer: &RowBuffer) -> Option<usize> {
+    (0..buffer.num_rows()).find(|&row| buffer.get_row(row).iter().any(|value| !value.is_finite()))
+}
+#[inline]
+fn forward_layer(layer: &NetLayer, weights: &[Float], batch_stats: Option<&BatchNormStats>, input: &[Float], output: &mut [Float]) {
+    match (layer, batch_stats) {
+        (NetLayer::BatchNorm(layer), Some(stats)) => layer.forward_pass_with_stats(weights, stats, input, output),
+        _ => layer.forward_pass(weights, input, output),
+    }
+}
 
-                if remaining_epochs > 0 {
-                    remaining_epochs -= 1;
-                }
+#[inline]
+#[allow(clippy::too_many_arguments)]
+fn backprop_layer(layer: &NetLayer, batch_stats: Option<&BatchNormStats>, weights: &[Float], output_errors: &[Float],
+                  inputs: &[Float], outputs: &[Float], learning_rate: Float, input_errors: &mut [Float], delta_target: &mut [Float]) {
+    match (layer, batch_stats) {
+        (NetLayer::BatchNorm(layer), Some(stats)) => {
+            layer.backprop_with_stats(weights, stats, output_errors, inputs, learning_rate, input_errors, delta_target)
+        },
+        _ => layer.backprop(weights, output_errors, inputs, outputs, learning_rate, input_errors, delta_target),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::lines_2x2;
+    use crate::net::NetConfig;
+    use crate::layer::NetLayerConfig;
+    use crate::func::ActivationFn;
+    use crate::initializer::RandomNetInitializer;
+    use crate::graph::LayerSource;
+    use crate::data::CsvDialect;
+
+    #[test]
+    fn test_single_step_matches_batch_training() {
+
+        let mut net = NetConfig::new_fully_connected(
+            2,
+            1,
+            [3],
+            ActivationFn::standard_logistic_sigmoid()
+        ).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("single step"));
+        let mut net2 = net.clone();
+
+        let data_set = PreparedDataSet::from_csv(
+            "data/2x2_lines_binary.csv",
+            ["0_0", "0_1"],
+            ["has_horizontal"]
+        ).unwrap();
+
+        net.get_training_context().train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, None, None);
+
+        let mut context = net2.get_training_context();
+        for (inputs, expected_outputs) in &data_set {
+            let outputs = context.forward_only(inputs);
+            assert_eq!(outputs.len(), 1);
+            context.backward_only(expected_outputs, &ErrorFn::SquaredError, 0.5);
+        }
+        context.apply_weight_deltas();
+        assert!(context.get_weight_deltas().get_buffer().iter().all(|&d| d == 0.0));
+
+        assert_eq!(net.get_weights().get_buffer(), net2.get_weights().get_buffer());
+
+    }
+
+    #[test]
+    fn test_batch_threads() {
+
+        let mut net = NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("helpers"));
+        let mut net2 = net.clone();
+
+        let data_set = lines_2x2();
+        let mini_batch_size = NonZeroU32::new(5);
+        let mixed_precision = MixedPrecision::new(crate::precision::HalfFormat::Bf16);
+
+        let mut context = net.get_training_context();
+        context.set_mixed_precision(mixed_precision);
+        context.set_batch_threads(3, &BufferPool::new());
+        for _ in 0..3 {
+            assert_eq!(context.train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, mini_batch_size, None), 3);
+        }
+        let mut context2 = net2.get_training_context();
+        context2.set_mixed_precision(mixed_precision);
+        for _ in 0..3 {
+            context2.train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, mini_batch_size, None);
+        }
+
+        // the helpers read the rounded weights of the net, and the deltas are only added up in a
+        // different order
+        assert_eq!(context.mixed_precision_state(), context2.mixed_precision_state());
+        assert!(net.get_weights().approx_eq(net2.get_weights(), 1e-5));
+
+    }
+
+    #[test]
+    fn test_shuffled_training() {
+
+        let mut net = NetConfig::new_fully_connected(
+            2,
+            1,
+            [3],
+            ActivationFn::standard_logistic_sigmoid()
+        ).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("shuffle"));
+        let mut net2 = net.clone();
+
+        let data_set = PreparedDataSet::from_csv(
+            "data/2x2_lines_binary.csv",
+            ["0_0", "0_1"],
+            ["has_horizontal"]
+        ).unwrap();
+
+        // the order of the rows within a single full batch only affects rounding
+        net.get_training_context().train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, None, None);
+        let mut context = net2.get_training_context();
+        context.train_backprop_single_batch_shuffled(&data_set, 0.5, &ErrorFn::SquaredError, None, None);
+        assert_eq!(context.row_order.len(), data_set.num_rows());
+        for (a, b) in net.get_weights().get_buffer().iter().zip(net2.get_weights().get_buffer()) {
+            assert!((a - b).abs() < 0.00001);
+        }
+
+    }
+
+    #[test]
+    fn test_batch_norm_training() {
+
+        let mut net = NetConfig::new(4, vec![
+            NetLayerConfig::FullyConnected(4, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::BatchNorm { momentum: 0.9 },
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid()),
+        ]).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("batch norm"));
+
+        let data_set = lines_2x2();
+
+        let mut context = net.get_training_context();
+        let initial_error = context.compute_error_for_batch(&data_set, &ErrorFn::SquaredError);
+        for _ in 0..300 {
+            context.train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, NonZeroU32::new(4), None);
+        }
+        assert!(context.batch_norm_stats.is_empty());
+        let error = context.compute_error_for_batch(&data_set, &ErrorFn::SquaredError);
+        assert!(error.mean() < initial_error.mean());
+
+        // the running mean converges towards the mean of the sigmoid outputs of the first layer
+        let running_mean = &net.get_weights().get_row(1)[8..12];
+        assert!(running_mean.iter().all(|&m| m > 0.0 && m < 1.0));
+
+        let prediction = net.predict(&[1.0, 1.0, 0.0, 0.0]);
+        assert!(prediction[0] > prediction[1]);
+
+    }
+
+    #[test]
+    fn test_batch_norm_backprop_matches_numerical_gradient() {
+
+        let mut net = NetConfig::new(3, vec![
+            NetLayerConfig::FullyConnected(4, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::BatchNorm { momentum: 0.9 },
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid()),
+        ]).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("batch norm gradient"));
+
+        let rows: [([Float; 3], [Float; 2]); 4] = [
+            ([0.3, -0.7, 0.9], [0.0, 1.0]),
+            ([-0.4, 0.2, 0.6], [1.0, 0.0]),
+            ([0.8, 0.5, -0.1], [1.0, 1.0]),
+            ([-0.9, -0.3, 0.4], [0.0, 0.0]),
+        ];
+        let batch: Vec<(&[Float], &[Float])> = rows.iter().map(|(inputs, expected)| (&inputs[..], &expected[..])).collect();
+
+        // the error of the mini-batch, normalized by its own statistics
+        let error = |net: &mut Net| {
+            let mut context = net.get_training_context();
+            context.compute_batch_norm_stats(&batch);
+            batch.iter().map(|&(inputs, expected_outputs)| {
+                let mut pass = context.pass();
+                pass.forward_pass(inputs);
+                pass.compute_error(expected_outputs, &ErrorFn::SquaredError)
+            }).sum::<Float>()
+        };
+
+        let mut net2 = net.clone();
+        let mut context = net2.get_training_context();
+        context.compute_batch_norm_stats(&batch);
+        context.compute_batch_norm_error_means(&batch, &ErrorFn::SquaredError, 1.0);
+        context.pass().backprop_rows(&batch, &ErrorFn::SquaredError, 1.0);
+        let deltas = context.get_weight_deltas().clone();
+
+        // the running statistics of the batch norm layer aren't trained
+        for layer_index in 0..net.num_layers() {
+            let trainable = net.layer(layer_index).num_trainable_weights();
+            let offset = net.get_weights().row_layout()[layer_index].0;
+            for (i, &delta) in deltas.get_row(layer_index)[..trainable].iter().enumerate() {
+                let mut plus = net.clone();
+                plus.get_weights_mut().get_buffer_mut()[offset + i] += 0.001;
+                let mut minus = net.clone();
+                minus.get_weights_mut().get_buffer_mut()[offset + i] -= 0.001;
+                let numerical = (error(&mut plus) - error(&mut minus)) / 0.002;
+                assert!((-delta - numerical).abs() < 0.05 * numerical.abs() + 0.0001, "layer {} weight {}: {} vs {}", layer_index, i, -delta, numerical);
             }
+            assert!(deltas.get_row(layer_index)[trainable..].iter().all(|&d| d == 0.0));
+        }
 
-            // apply weight updates
-            self.net.get_weights_mut().add(&self.weight_deltas);
+    }
+
+    #[test]
+    fn test_global_average_pooling_training() {
 
+        // 2 channels with 4 positions each, pooled for the head
+        let mut net = NetConfig::new(4, vec![
+            NetLayerConfig::FullyConnected(8, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::GlobalAveragePooling { channels: 2 },
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid()),
+        ]).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("pooling"));
+        assert_eq!(net.get_weights().get_row(1).len(), 0);
+
+        let data_set = lines_2x2();
+
+        let mut context = net.get_training_context();
+        let initial_error = context.compute_error_for_batch(&data_set, &ErrorFn::SquaredError);
+        for _ in 0..300 {
+            context.train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, None, None);
         }
+        let error = context.compute_error_for_batch(&data_set, &ErrorFn::SquaredError);
+        assert!(error.mean() < initial_error.mean());
+
     }
 
-    pub fn compute_error_for_batch(&mut self, data_set: &PreparedDataSet, error_fn: &ErrorFn) -> Stats {
-        self.error_stats.reset();
-        for (inputs, expected_outputs) in data_set {
-            self.forward_pass_and_compute_error(inputs, expected_outputs, error_fn);
+    #[test]
+    fn test_softmax_cross_entropy_training() {
+
+        let mut net = NetConfig::new_classifier(2, 2, [8], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("softmax"));
+
+        // XOR, one-hot encoded
+        let rows = [
+            ([0.0, 0.0], [1.0, 0.0]),
+            ([0.0, 1.0], [0.0, 1.0]),
+            ([1.0, 0.0], [0.0, 1.0]),
+            ([1.0, 1.0], [1.0, 0.0]),
+        ];
+
+        let mut context = net.get_training_context();
+        let mut errors = Vec::new();
+        for _ in 0..5000 {
+            let mut error = 0.0;
+            for (inputs, expected_outputs) in &rows {
+                context.forward_only(inputs);
+                // the configured error function is ignored in favour of the cross-entropy
+                error += context.backward_only(expected_outputs, &ErrorFn::SquaredError, 1.0);
+            }
+            context.apply_weight_deltas();
+            errors.push(error);
+        }
+        assert!(errors.last().unwrap() < &(0.01 * errors[0]));
+
+        for (inputs, expected_outputs) in &rows {
+            let outputs = net.predict(inputs);
+            assert!((outputs[0] + outputs[1] - 1.0).abs() < 0.0001);
+            assert_eq!(outputs[1] > outputs[0], expected_outputs[1] > expected_outputs[0]);
         }
-        self.error_stats.clone()
+
     }
 
-    #[inline]
-    pub fn get_net(&mut self) -> &Net {
-        &self.net
+    #[test]
+    fn test_graph_backprop_matches_numerical_gradient() {
+
+        let mut net = NetConfig::new(2, vec![
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid()),
+        ])
+            .with_input(1, LayerInput::Concat(vec![LayerSource::Layer(0), LayerSource::Input]))
+            .with_input(2, LayerInput::Sum(vec![LayerSource::Layer(0), LayerSource::Layer(1)]))
+            .create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("graph"));
+
+        let (inputs, expected_outputs) = ([0.3, -0.7], [0.0, 1.0]);
+        let error = |net: &mut Net| {
+            let mut context = net.get_training_context();
+            context.forward_only(&inputs);
+            context.backward_only(&expected_outputs, &ErrorFn::SquaredError, 1.0)
+        };
+
+        let mut net2 = net.clone();
+        let mut context = net2.get_training_context();
+        context.forward_only(&inputs);
+        context.backward_only(&expected_outputs, &ErrorFn::SquaredError, 1.0);
+        let deltas = context.get_weight_deltas().get_buffer().to_vec();
+
+        for (i, &delta) in deltas.iter().enumerate() {
+            let mut plus = net.clone();
+            plus.get_weights_mut().get_buffer_mut()[i] += 0.001;
+            let mut minus = net.clone();
+            minus.get_weights_mut().get_buffer_mut()[i] -= 0.001;
+            let numerical = (error(&mut plus) - error(&mut minus)) / 0.002;
+            // the deltas are the negated gradient scaled by the learning rate
+            assert!((-delta - numerical).abs() < 0.0005, "weight {}: {} vs {}", i, -delta, numerical);
+        }
+
     }
 
-    #[inline]
-    pub fn get_net_mut(&mut self) -> &mut Net {
-        &mut self.net
+    #[test]
+    fn test_output_heads_match_numerical_gradient() {
+
+        // head a is also the input of head b, so its errors are combined with those propagated back
+        let mut net = NetConfig::new(2, vec![
+            NetLayerConfig::FullyConnected(3, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid()),
+            NetLayerConfig::FullyConnected(1, ActivationFn::standard_logistic_sigmoid()),
+        ])
+            .with_output_head("a", 1, Some(ErrorFn::CrossEntropy))
+            .with_output_head("b", 2, None)
+            .create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("heads"));
+
+        let (inputs, expected_outputs) = ([0.3, -0.7], [0.0, 1.0, 0.25]);
+        let error = |net: &mut Net| {
+            let mut context = net.get_training_context();
+            context.forward_only(&inputs);
+            context.backward_only(&expected_outputs, &ErrorFn::SquaredError, 1.0)
+        };
+
+        let mut net2 = net.clone();
+        let mut context = net2.get_training_context();
+        assert_eq!(context.forward_only(&inputs).len(), 3);
+        let total_error = context.backward_only(&expected_outputs, &ErrorFn::SquaredError, 1.0);
+        let deltas = context.get_weight_deltas().get_buffer().to_vec();
+
+        let outputs = net.predict(&inputs);
+        let expected_error = ErrorFn::CrossEntropy.get_error(0.0, outputs[0])
+            + ErrorFn::CrossEntropy.get_error(1.0, outputs[1])
+            + ErrorFn::SquaredError.get_error(0.25, outputs[2]);
+        assert!((total_error - expected_error).abs() < 1e-5);
+
+        for (i, &delta) in deltas.iter().enumerate() {
+            let mut plus = net.clone();
+            plus.get_weights_mut().get_buffer_mut()[i] += 0.001;
+            let mut minus = net.clone();
+            minus.get_weights_mut().get_buffer_mut()[i] -= 0.001;
+            let numerical = (error(&mut plus) - error(&mut minus)) / 0.002;
+            assert!((-delta - numerical).abs() < 0.001, "weight {}: {} vs {}", i, -delta, numerical);
+        }
+
+    }
+
+    #[test]
+    fn test_shared_weight_gradients_accumulate() {
+
+        let mut net = NetConfig::new_fully_connected(2, 1, [3, 3, 3], ActivationFn::standard_logistic_sigmoid())
+            .with_shared_weights(2, 1)
+            .create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("tied"));
+
+        let (inputs, expected_outputs) = ([0.3, -0.7], [1.0]);
+        let error = |net: &mut Net| {
+            let mut context = net.get_training_context();
+            context.forward_only(&inputs);
+            context.backward_only(&expected_outputs, &ErrorFn::SquaredError, 1.0)
+        };
+
+        let mut net2 = net.clone();
+        let mut context = net2.get_training_context();
+        context.forward_only(&inputs);
+        context.backward_only(&expected_outputs, &ErrorFn::SquaredError, 1.0);
+        let deltas = context.get_weight_deltas().get_buffer().to_vec();
+        assert_eq!(deltas.len(), net.get_weights().buffer_len());
+
+        // perturbing a shared weight changes both layers, so matching the numerical gradient
+        // means the deltas of both layers were accumulated
+        for (i, &delta) in deltas.iter().enumerate() {
+            let mut plus = net.clone();
+            plus.get_weights_mut().get_buffer_mut()[i] += 0.001;
+            let mut minus = net.clone();
+            minus.get_weights_mut().get_buffer_mut()[i] -= 0.001;
+            let numerical = (error(&mut plus) - error(&mut minus)) / 0.002;
+            assert!((-delta - numerical).abs() < 0.0005, "weight {}: {} vs {}", i, -delta, numerical);
+        }
+
+    }
+
+    #[test]
+    fn test_fully_connected_backprop_matches_numerical_gradient() {
+
+        // a scale and offset other than the standard sigmoid's would expose a derivative computed
+        // at the output as if it were the input
+        let mut net = NetConfig::new(3, vec![
+            NetLayerConfig::FullyConnected(4, ActivationFn::LogisticSigmoid { steepness: 2.0, scale: 1.5, y_offset: -0.25 }),
+            NetLayerConfig::FullyConnected(2, ActivationFn::standard_logistic_sigmoid_neg()),
+        ]).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("fully connected"));
+
+        let (inputs, expected_outputs) = ([0.3, -0.7, 0.9], [-0.5, 0.8]);
+        let error = |net: &mut Net| {
+            let mut context = net.get_training_context();
+            context.forward_only(&inputs);
+            context.backward_only(&expected_outputs, &ErrorFn::SquaredError, 1.0)
+        };
+
+        let mut net2 = net.clone();
+        let mut context = net2.get_training_context();
+        context.forward_only(&inputs);
+        context.backward_only(&expected_outputs, &ErrorFn::SquaredError, 1.0);
+        let deltas = context.get_weight_deltas().get_buffer().to_vec();
+
+        for (i, &delta) in deltas.iter().enumerate() {
+            let mut plus = net.clone();
+            plus.get_weights_mut().get_buffer_mut()[i] += 0.01;
+            let mut minus = net.clone();
+            minus.get_weights_mut().get_buffer_mut()[i] -= 0.01;
+            let numerical = (error(&mut plus) - error(&mut minus)) / 0.02;
+            // the hidden layer gradients are small, so they are compared relative to their size
+            assert!((-delta - numerical).abs() < 0.05 * numerical.abs() + 0.0001, "weight {}: {} vs {}", i, -delta, numerical);
+        }
+
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "backward_only needs a forward_only pass first")]
+    fn test_backward_only_needs_forward_only() {
+
+        let mut net = NetConfig::new_fully_connected(2, 1, [], ActivationFn::standard_logistic_sigmoid()).create_net();
+        let mut context = net.get_training_context();
+        context.forward_only(&[0.0, 1.0]);
+        context.backward_only(&[1.0], &ErrorFn::SquaredError, 1.0);
+        // the outputs of the first pass were back-propagated already
+        context.backward_only(&[1.0], &ErrorFn::SquaredError, 1.0);
+
+    }
+
+    #[test]
+    fn test_gradient_noise_anneals_per_batch() {
+
+        let mut net = NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("noise"));
+        // a standard deviation of 100 for the first mini-batch and of 0.003 for the second
+        let noise_fn = GradientNoiseFn::Annealed { eta: 10000.0, gamma: 30.0 };
+        let max_delta = |context: &NetTrainingContext| context.get_weight_deltas().get_buffer().iter().fold(0.0, |max: Float, d| max.max(d.abs()));
+
+        // the deltas are those of the last mini-batch of the pass
+        let mut context = net.get_training_context();
+        context.set_seed("noise");
+        context.train_backprop_single_batch(&lines_2x2().select_rows(&[0]), 0.01, &ErrorFn::SquaredError, NonZeroU32::new(1), Some(noise_fn));
+        let first_batch = max_delta(&context);
+        let mut context = net.get_training_context();
+        context.set_seed("noise");
+        context.train_backprop_single_batch(&lines_2x2().select_rows(&[0, 1]), 0.01, &ErrorFn::SquaredError, NonZeroU32::new(1), Some(noise_fn));
+        let second_batch = max_delta(&context);
+        assert!(first_batch > 100.0 * second_batch, "{} vs {}", first_batch, second_batch);
+
+    }
+
+    #[test]
+    fn test_single_layer_training() {
+
+        // logistic regression on AND, which is linearly separable
+        let mut net = NetConfig::new_fully_connected(2, 1, [], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("single layer"));
+        let rows = [([0.0, 0.0], [0.0]), ([0.0, 1.0], [0.0]), ([1.0, 0.0], [0.0]), ([1.0, 1.0], [1.0])];
+
+        let mut context = net.get_training_context();
+        for _ in 0..2000 {
+            for (inputs, expected_outputs) in &rows {
+                context.forward_only(inputs);
+                context.backward_only(expected_outputs, &ErrorFn::SquaredError, 2.0);
+            }
+            context.apply_weight_deltas();
+        }
+
+        for (inputs, expected_outputs) in &rows {
+            let output = net.predict(inputs)[0];
+            assert!((output - expected_outputs[0]).abs() < 0.3, "{:?} -> {}", inputs, output);
+        }
+
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_streamed_training() {
+
+        let mut net = NetConfig::new_fully_connected(4, 2, [4], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("streamed"));
+        let stream = StreamingCsvDataSet::open(
+            "data/2x2_lines_binary.csv",
+            &CsvDialect::default(),
+            ["0_0", "0_1", "1_0", "1_1"],
+            ["has_horizontal", "has_vertical"],
+            4,
+        ).unwrap();
+        let data_set = lines_2x2();
+
+        let mut context = net.get_training_context();
+        let initial_error = context.compute_error_streamed(&stream, &ErrorFn::SquaredError).unwrap();
+        assert_eq!(initial_error.count() as usize, data_set.num_rows());
+        assert!((initial_error.mean() - context.compute_error_for_batch(&data_set, &ErrorFn::SquaredError).mean()).abs() < 1e-9);
+        for _ in 0..300 {
+            assert_eq!(context.train_backprop_streamed(&stream, 0.5, &ErrorFn::SquaredError, None, None).unwrap(), 11);
+        }
+        let error = context.compute_error_streamed(&stream, &ErrorFn::SquaredError).unwrap();
+        assert!(error.mean() < initial_error.mean());
+
+    }
+
+    #[test]
+    fn test_divergence_checks() {
+
+        let mut net = NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        net.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("divergence"));
+        let one_batch = NonZeroU32::new(1);
+
+        // an infinite input saturates the first layer, but its gradients aren't finite
+        let data_set = PreparedDataSet::from_rows(&[[0.0, 1.0], [Float::INFINITY, 1.0]], &[[1.0], [0.0]]);
+        let mut saturated = net.clone();
+        let mut context = saturated.get_training_context();
+        context.set_check_divergence(true);
+        assert_eq!(context.train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, one_batch, None), 2);
+        assert_eq!(context.divergence(), Some(DivergenceReport { batch: 1, layer: 0, source: DivergenceSource::Gradients }));
+        assert_eq!(context.batch_counter(), 2);
+
+        // a NaN weight of the last layer shows in its outputs first
+        let data_set = PreparedDataSet::from_rows(&[[0.0, 1.0]], &[[1.0]]);
+        net.get_weights_mut().get_row_mut(1)[0] = Float::NAN;
+        let mut unchecked = net.clone();
+        let mut context = unchecked.get_training_context();
+        context.train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, one_batch, None);
+        assert_eq!(context.divergence(), None);
+        let mut context = net.get_training_context();
+        context.set_check_divergence(true);
+        context.train_backprop_single_batch(&data_set, 0.5, &ErrorFn::SquaredError, one_batch, None);
+        assert_eq!(context.divergence(), Some(DivergenceReport { batch: 0, layer: 1, source: DivergenceSource::Outputs }));
+
+    }
+
+}
@@ -0,0 +1,136 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+use crossbeam::channel::{Receiver, Sender, TrySendError};
+
+/// A handle to pause, resume or cancel a trainer from another thread, see
+/// `NetTrainer::control`. Clones control the same trainer.
+#[derive(Clone, Default)]
+pub struct TrainingControl {
+    state: Arc<(Mutex<ControlState>, Condvar)>,
+}
+
+#[derive(Default)]
+struct ControlState {
+    paused: bool,
+    cancelled: bool,
+    /// Notified of every change, see `watch`
+    watchers: Vec<Sender<()>>,
+}
+
+impl ControlState {
+
+    fn notify_watchers(&mut self) {
+        self.watchers.retain(|watcher| !matches!(watcher.try_send(()), Err(TrySendError::Disconnected(_))));
+    }
+
+}
+
+impl TrainingControl {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// No new tasks are started, and running backprop tasks wait before their next epoch (or
+    /// sync, when multi-threaded) until `resume` is called
+    pub fn pause(&self) {
+        let mut state = self.state.0.lock().unwrap();
+        state.paused = true;
+        state.notify_watchers();
+    }
+
+    pub fn resume(&self) {
+        let (state, resumed) = &*self.state;
+        let mut state = state.lock().unwrap();
+        state.paused = false;
+        state.notify_watchers();
+        resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        let state = self.state.0.lock().unwrap();
+        state.paused && !state.cancelled
+    }
+
+    /// Stops training for good. Running backprop tasks stop at their next epoch (or sync) as if
+    /// they had completed, and the trainer returns the best result so far, or an error if there
+    /// is none. Also ends a pause.
+    pub fn cancel(&self) {
+        let (state, resumed) = &*self.state;
+        let mut state = state.lock().unwrap();
+        state.cancelled = true;
+        state.notify_watchers();
+        resumed.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.0.lock().unwrap().cancelled
+    }
+
+    /// A receiver which is sent a value when training is paused, resumed or cancelled, so the
+    /// trainer can wait on it. Changes it hasn't received yet are merged into one.
+    pub(crate) fn watch(&self) -> Receiver<()> {
+        let (sender, receiver) = crossbeam::channel::bounded(1);
+        self.state.0.lock().unwrap().watchers.push(sender);
+        receiver
+    }
+
+    /// Blocks the calling thread while training is paused
+    pub fn wait_while_paused(&self) {
+        let (state, resumed) = &*self.state;
+        let _guard = resumed.wait_while(state.lock().unwrap(), |state| state.paused && !state.cancelled).unwrap();
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_pause_and_resume() {
+
+        let control = TrainingControl::new();
+        control.wait_while_paused();
+        control.pause();
+        assert!(control.is_paused());
+
+        let (sender, receiver) = mpsc::channel();
+        let worker = {
+            let control = control.clone();
+            thread::spawn(move || {
+                control.wait_while_paused();
+                sender.send(()).unwrap();
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(receiver.try_recv().is_err());
+
+        control.resume();
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        worker.join().unwrap();
+        assert!(!control.is_paused());
+
+    }
+
+    #[test]
+    fn test_cancel_ends_pause() {
+
+        let control = TrainingControl::new();
+        control.pause();
+        let worker = {
+            let control = control.clone();
+            thread::spawn(move || control.wait_while_paused())
+        };
+        let changes = control.watch();
+        control.cancel();
+        worker.join().unwrap();
+        assert!(control.is_cancelled() && !control.is_paused());
+        assert_eq!(changes.try_iter().count(), 1);
+
+    }
+
+}
@@ -0,0 +1,113 @@
+use crate::Float;
+use crate::data::PreparedDataSet;
+use crate::net::Net;
+
+/// Trains the nets of a trainer to match the outputs of a teacher net, see
+/// `NetTrainerBuilder::distillation`. The teacher's outputs must be probabilities, either of
+/// classes (which should sum to 1) or, with a single output, of a binary outcome.
+#[derive(Clone, Debug)]
+pub struct Distillation {
+    teacher: Net,
+    temperature: Float,
+    hard_target_weight: Float,
+}
+
+impl Distillation {
+
+    /// Soft targets are the teacher's probabilities at the given temperature, where a
+    /// temperature above 1 spreads them out
+    pub fn new(teacher: Net, temperature: Float) -> Self {
+        assert!(temperature > 0.0, "temperature must be positive");
+        Distillation {
+            teacher,
+            temperature,
+            hard_target_weight: 0.0,
+        }
+    }
+
+    /// Mixes the data set's own targets into the soft targets with this weight, from 0 to 1
+    pub fn with_hard_target_weight(self, weight: Float) -> Self {
+        assert!((0.0..=1.0).contains(&weight), "hard target weight must be between 0 and 1");
+        let mut new = self;
+        new.hard_target_weight = weight;
+        new
+    }
+
+    #[inline]
+    pub fn teacher(&self) -> &Net {
+        &self.teacher
+    }
+
+    #[inline]
+    pub fn temperature(&self) -> Float {
+        self.temperature
+    }
+
+    /// Raises the probabilities to the power of 1 / temperature and renormalizes them, which for
+    /// a softmax is the same as dividing its logits by the temperature
+    pub fn soften(&self, probabilities: &mut [Float]) {
+        let exponent = 1.0 / self.temperature;
+        let soften = |p: Float| p.clamp(Float::MIN_POSITIVE, 1.0).powf(exponent);
+        if let [p] = probabilities {
+            let (positive, negative) = (soften(*p), soften(1.0 - *p));
+            *p = positive / (positive + negative);
+        } else {
+            for p in probabilities.iter_mut() {
+                *p = soften(*p);
+            }
+            let sum: Float = probabilities.iter().sum();
+            for p in probabilities.iter_mut() {
+                *p /= sum;
+            }
+        }
+    }
+
+    /// A copy of the data set whose targets are the soft targets of the teacher, mixed with the
+    /// original targets by the hard target weight
+    pub fn soft_targets(&self, data_set: &PreparedDataSet) -> PreparedDataSet {
+        assert_eq!(self.teacher.input_size(), data_set.independent_cols(), "teacher has the wrong input size");
+        assert_eq!(self.teacher.output_size(), data_set.dependent_cols(), "teacher has the wrong output size");
+        let mut targets = Vec::with_capacity(data_set.num_rows() * data_set.dependent_cols());
+        for (inputs, hard_targets) in data_set {
+            let mut soft_targets = self.teacher.predict(inputs);
+            self.soften(&mut soft_targets);
+            targets.extend(soft_targets.iter().zip(hard_targets)
+                .map(|(soft, hard)| (1.0 - self.hard_target_weight) * soft + self.hard_target_weight * hard));
+        }
+        PreparedDataSet::from_row_major(&data_set.inputs_row_major(), &targets, data_set.independent_cols(), data_set.dependent_cols())
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::NetConfig;
+    use crate::func::ActivationFn;
+
+    #[test]
+    fn test_soften() {
+
+        let teacher = NetConfig::new_fully_connected(1, 2, [2], ActivationFn::standard_logistic_sigmoid()).create_net();
+        let mut probabilities = [0.8, 0.2];
+        Distillation::new(teacher.clone(), 1.0).soften(&mut probabilities);
+        assert!((probabilities[0] - 0.8).abs() < 1e-5);
+        Distillation::new(teacher.clone(), 2.0).soften(&mut probabilities);
+        assert!((probabilities[0] - 2.0 / 3.0).abs() < 1e-5 && (probabilities[1] - 1.0 / 3.0).abs() < 1e-5, "{:?}", probabilities);
+        let mut probability = [0.9];
+        Distillation::new(teacher.clone(), 2.0).soften(&mut probability);
+        assert!((probability[0] - 0.75).abs() < 1e-5, "{:?}", probability);
+
+        let data_set = PreparedDataSet::from_rows(&[[0.0], [1.0]], &[[1.0, 0.0], [0.0, 1.0]]);
+        let soft = Distillation::new(teacher.clone(), 1.0).with_hard_target_weight(0.25).soft_targets(&data_set);
+        for ((inputs, soft_targets), (_, hard_targets)) in soft.iter().zip(&data_set) {
+            let predicted = teacher.predict(inputs);
+            let sum: Float = predicted.iter().sum();
+            for ((&soft, &hard), &p) in soft_targets.iter().zip(hard_targets).zip(&predicted) {
+                assert!((soft - (0.75 * p / sum + 0.25 * hard)).abs() < 1e-5);
+            }
+        }
+
+    }
+
+}
@@ -1,4 +1,5 @@
 use crate::{
+    buffer::BufferPool,
     train::{
         task::{
             TaskResult,
@@ -11,17 +12,13 @@ use std::{
     error::Error,
     net::IpAddr,
     thread,
+    time::Duration,
     sync::{
-        mpsc::{
-            Receiver,
-            Sender,
-            self,
-            TryIter,
-        },
         Arc,
         atomic::{AtomicBool, Ordering},
     }
 };
+use crossbeam::channel::{Receiver, Select, Sender, TryIter};
 use crossbeam::internal::SelectHandle;
 use crate::train::task::{TaskUpdate, TaskUpdateEmitter};
 
@@ -37,7 +34,6 @@ quick_error! {
 }
 
 
-#[allow(dead_code)]
 pub enum Executor {
     Local(usize),
     Distributed { discover_addr: IpAddr, discover_port: u16 },
@@ -52,6 +48,14 @@ impl Executor {
             &Executor::Local(num_workers) => Ok(Box::new(LocalExecutor::new(num_workers))),
         }
     }
+
+    /// The number of workers, if known before the executor starts
+    pub fn num_workers(&self) -> Option<usize> {
+        match self {
+            Executor::Local(num_workers) => Some(*num_workers),
+            Executor::Distributed { .. } => None,
+        }
+    }
 }
 
 pub trait ExecutorInstance {
@@ -84,6 +88,8 @@ impl ExecutorInstance for LocalExecutor {
             let ctrl_slave = ctrl_slave.clone();
             let stopped_flag = self.stopped.clone();
             thread::spawn(move || {
+                // the scratch buffers of one task are reused by the next of the same architecture
+                let pool = BufferPool::new();
                 // wrap logic in a function to allow error cascading with "?"
                 let inner_fn = || -> Result<(), Box<dyn Error>> {
                     while !stopped_flag.load(Ordering::Relaxed) {
@@ -95,7 +101,7 @@ impl ExecutorInstance for LocalExecutor {
                         ctrl_slave.accept_task(executor_id.clone(), task.task_id.clone())?;
 
                         // execute task
-                        match task.exec(&ctrl_slave) {
+                        match task.exec_with_pool(&ctrl_slave, &pool) {
                             Ok(result) => {
                                 ctrl_slave.send_result(result)?;
                             },
@@ -126,7 +132,7 @@ fn executor_control() -> (ExecutorControlMaster, ExecutorControlSlave) {
     // A zero-sized mpmc (though used as spmr) channel for sending tasks to executor workers
     let (task_sender, task_receiver) = crossbeam::channel::bounded(0);
     // An unbounded mpsc channel for sending results back to the
-    let (event_sender, event_receiver) = mpsc::channel();
+    let (event_sender, event_receiver) = crossbeam::channel::unbounded();
     let master = ExecutorControlMaster {
         task_sender,
         event_receiver,
@@ -143,7 +149,7 @@ pub enum ExecutorEvent {
         task_id: String,
         executor_id: String,
     },
-    TaskResult(TaskResult),
+    TaskResult(Box<TaskResult>),
     ExecutorError {
         task_id: String,
         executor_id: String,
@@ -174,6 +180,20 @@ impl ExecutorControlMaster {
         self.event_receiver.try_iter()
     }
 
+    /// Blocks until there is an event, an executor is waiting for a task (if `for_executor`),
+    /// `wake_up` receives or the timeout passes, whichever comes first
+    pub fn wait(&self, for_executor: bool, wake_up: &Receiver<()>, timeout: Duration) {
+        let mut select = Select::new();
+        select.recv(&self.event_receiver);
+        let wake_up_index = select.recv(wake_up);
+        if for_executor {
+            select.send(&self.task_sender);
+        }
+        if select.ready_timeout(timeout) == Ok(wake_up_index) {
+            wake_up.try_iter().for_each(drop);
+        }
+    }
+
 }
 
 #[derive(Clone)]
@@ -182,11 +202,10 @@ pub struct ExecutorControlSlave {
     event_sender: Sender<ExecutorEvent>,
 }
 
-#[allow(dead_code)]
 impl ExecutorControlSlave {
 
     fn send_result(&self, result: TaskResult) -> Result<(), Box<dyn Error>> {
-        self.event_sender.send(ExecutorEvent::TaskResult(result))?;
+        self.event_sender.send(ExecutorEvent::TaskResult(Box::new(result)))?;
         Ok(())
     }
 
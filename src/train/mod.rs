@@ -5,12 +5,21 @@ mod trainer;
 mod optimizer;
 mod context;
 mod observer;
+mod snapshot;
+mod retrain;
+mod control;
+mod distill;
 
 pub use self::{
     backprop::*,
+    task::*,
     executor::*,
     trainer::*,
     optimizer::*,
     context::*,
     observer::*,
+    snapshot::*,
+    retrain::*,
+    control::*,
+    distill::*,
 };
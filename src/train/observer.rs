@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::train::control::TrainingControl;
+use crate::train::task::{TaskResult, TaskUpdate};
+use crate::train::trainer::{TrainingEvent, TrainingResult};
+
+/// The result of a trainer executed by `NetTrainer::execute_async`
+pub type AsyncTrainingResult = Result<TrainingResult, Box<dyn Error + Send + Sync>>;
+
+/// A `TrainingEvent` which owns its data, so it can be sent to another thread
+#[derive(Clone)]
+pub enum OwnedTrainingEvent {
+    TaskSubmit {
+        task_id: String,
+    },
+    TaskAccepted {
+        task_id: String,
+        executor_id: String,
+    },
+    TaskResult(Box<TaskResult>),
+    TaskUpdate(TaskUpdate),
+}
+
+impl<'a> From<&TrainingEvent<'a>> for OwnedTrainingEvent {
+    fn from(event: &TrainingEvent<'a>) -> Self {
+        match event {
+            TrainingEvent::TaskSubmit(task) => OwnedTrainingEvent::TaskSubmit { task_id: task.task_id.clone() },
+            TrainingEvent::TaskAccepted { task_id, executor_id } => OwnedTrainingEvent::TaskAccepted {
+                task_id: task_id.clone(),
+                executor_id: executor_id.clone(),
+            },
+            TrainingEvent::TaskResult(result) => OwnedTrainingEvent::TaskResult(Box::new((*result).clone())),
+            TrainingEvent::TaskUpdate(update) => OwnedTrainingEvent::TaskUpdate(update.clone()),
+        }
+    }
+}
+
+/// The events of a trainer executed by `NetTrainer::execute_async`, in order. Iterating blocks
+/// until the next event and ends once the trainer finishes. With the `futures` feature, this is
+/// also a `Stream`, for async code.
+pub struct TrainingEvents {
+    queue: Arc<(Mutex<EventQueue>, Condvar)>,
+}
+
+#[derive(Default)]
+struct EventQueue {
+    events: VecDeque<OwnedTrainingEvent>,
+    /// The trainer finished, so no more events are sent
+    finished: bool,
+    /// The `TrainingEvents` were dropped, so events are no longer kept
+    dropped: bool,
+    waker: Option<Waker>,
+}
+
+/// Sends the events of a trainer to its `TrainingEvents`, which end once this is dropped
+pub(crate) struct EventSender {
+    queue: Arc<(Mutex<EventQueue>, Condvar)>,
+}
+
+pub(crate) fn event_channel() -> (EventSender, TrainingEvents) {
+    let queue: Arc<(Mutex<EventQueue>, Condvar)> = Arc::default();
+    (EventSender { queue: queue.clone() }, TrainingEvents { queue })
+}
+
+impl TrainingEvents {
+
+    /// The next event if there is one already, without blocking
+    pub fn try_next(&self) -> Option<OwnedTrainingEvent> {
+        self.queue.0.lock().unwrap().events.pop_front()
+    }
+
+}
+
+impl Iterator for TrainingEvents {
+    type Item = OwnedTrainingEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (queue, sent) = &*self.queue;
+        let mut queue = sent.wait_while(queue.lock().unwrap(), |queue| queue.events.is_empty() && !queue.finished).unwrap();
+        queue.events.pop_front()
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_core::Stream for TrainingEvents {
+    type Item = OwnedTrainingEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut queue = self.queue.0.lock().unwrap();
+        match queue.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if queue.finished => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl Drop for TrainingEvents {
+    fn drop(&mut self) {
+        let mut queue = self.queue.0.lock().unwrap();
+        queue.dropped = true;
+        queue.events.clear();
+    }
+}
+
+impl EventSender {
+
+    pub(crate) fn send(&self, event: OwnedTrainingEvent) {
+        self.update(|queue| {
+            if !queue.dropped {
+                queue.events.push_back(event);
+            }
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut EventQueue)) {
+        let (queue, sent) = &*self.queue;
+        let waker = {
+            let mut queue = queue.lock().unwrap();
+            f(&mut queue);
+            queue.waker.take()
+        };
+        sent.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        self.update(|queue| queue.finished = true);
+    }
+}
+
+/// Resolves to the result of a trainer running on its own thread. It can be awaited by any
+/// executor, or waited on with `wait`.
+pub struct TrainingFuture {
+    state: Arc<(Mutex<FutureState>, Condvar)>,
+    control: TrainingControl,
+}
+
+#[derive(Default)]
+struct FutureState {
+    finished: bool,
+    result: Option<AsyncTrainingResult>,
+    waker: Option<Waker>,
+}
+
+/// Completes a `TrainingFuture`, or fails it if dropped first, e.g. because the trainer panicked
+pub(crate) struct TrainingCompleter {
+    state: Arc<(Mutex<FutureState>, Condvar)>,
+}
+
+impl TrainingFuture {
+
+    pub(crate) fn new(control: TrainingControl) -> (Self, TrainingCompleter) {
+        let state: Arc<(Mutex<FutureState>, Condvar)> = Arc::default();
+        let completer = TrainingCompleter { state: state.clone() };
+        (TrainingFuture { state, control }, completer)
+    }
+
+    /// A handle to pause, resume or cancel the trainer
+    pub fn control(&self) -> TrainingControl {
+        self.control.clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.0.lock().unwrap().finished
+    }
+
+    /// Blocks the calling thread until the trainer finishes
+    pub fn wait(self) -> AsyncTrainingResult {
+        let (state, finished) = &*self.state;
+        let mut state = finished.wait_while(state.lock().unwrap(), |state| !state.finished).unwrap();
+        state.result.take().expect("training result was already taken")
+    }
+
+}
+
+impl Future for TrainingFuture {
+    type Output = AsyncTrainingResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.0.lock().unwrap();
+        if state.finished {
+            Poll::Ready(state.result.take().expect("training future polled after completion"))
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl TrainingCompleter {
+
+    pub(crate) fn complete(&self, result: AsyncTrainingResult) {
+        let (state, finished) = &*self.state;
+        let waker = {
+            let mut state = state.lock().unwrap();
+            if state.finished {
+                return;
+            }
+            state.finished = true;
+            state.result = Some(result);
+            state.waker.take()
+        };
+        finished.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+}
+
+impl Drop for TrainingCompleter {
+    fn drop(&mut self) {
+        self.complete(Err("the training thread stopped without a result".into()));
+    }
+}
@@ -1,17 +1,121 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use crate::Float;
 use crate::utils::stable_hash_seed;
 use crate::train::task::TaskResult;
 use rand::{Rng, FromEntropy, SeedableRng};
+use std::error::Error;
+use serde::{Serialize, Deserialize};
 
 pub trait ParamFactory {
     fn range_usize(&mut self, key: String, low: usize, high: usize) -> usize;
-    fn range_f32(&mut self, low: f32, high: f32) -> f32;
+    fn range_float(&mut self, low: Float, high: Float) -> Float;
+}
+
+/// A hyperparameter sampled for a task
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SampledParam {
+    /// The key it was sampled with, which float parameters don't have
+    pub key: Option<String>,
+    pub value: ParamValue,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ParamValue {
+    Usize(usize),
+    Float(Float),
+}
+
+/// Records the parameters sampled from another factory, in order
+pub(crate) struct RecordingParamFactory {
+    inner: Box<dyn ParamFactory>,
+    sampled: Vec<SampledParam>,
+}
+
+impl RecordingParamFactory {
+
+    pub(crate) fn new(inner: Box<dyn ParamFactory>) -> Self {
+        RecordingParamFactory { inner, sampled: Vec::new() }
+    }
+
+    pub(crate) fn into_sampled(self) -> Vec<SampledParam> {
+        self.sampled
+    }
+
+}
+
+impl ParamFactory for RecordingParamFactory {
+
+    fn range_usize(&mut self, key: String, low: usize, high: usize) -> usize {
+        let value = self.inner.range_usize(key.clone(), low, high);
+        self.sampled.push(SampledParam { key: Some(key), value: ParamValue::Usize(value) });
+        value
+    }
+
+    fn range_float(&mut self, low: Float, high: Float) -> Float {
+        let value = self.inner.range_float(low, high);
+        self.sampled.push(SampledParam { key: None, value: ParamValue::Float(value) });
+        value
+    }
+
+}
+
+/// Samples the same parameters as a previous task, in the same order, e.g. to train a
+/// configuration on each fold of a cross-validation
+pub(crate) struct ReplayOptimizer {
+    params: Vec<SampledParam>,
+}
+
+impl ReplayOptimizer {
+    pub(crate) fn new(params: Vec<SampledParam>) -> Self {
+        ReplayOptimizer { params }
+    }
+}
+
+impl Optimizer for ReplayOptimizer {
+
+    fn next_parameters(&mut self, _id: &str) -> Box<dyn ParamFactory> {
+        Box::new(ReplayParamFactory { params: self.params.clone().into_iter() })
+    }
+
+    fn report(&mut self, _results: &TaskResult) {
+        // no-op
+    }
+}
+
+struct ReplayParamFactory {
+    params: std::vec::IntoIter<SampledParam>,
+}
+
+impl ParamFactory for ReplayParamFactory {
+
+    fn range_usize(&mut self, key: String, _low: usize, _high: usize) -> usize {
+        match self.params.next() {
+            Some(SampledParam { key: Some(ref replayed), value: ParamValue::Usize(value) }) if *replayed == key => value,
+            param => panic!("parameter {} was not sampled in the same order, got {:?}", key, param),
+        }
+    }
+
+    fn range_float(&mut self, _low: Float, _high: Float) -> Float {
+        match self.params.next() {
+            Some(SampledParam { key: None, value: ParamValue::Float(value) }) => value,
+            param => panic!("float parameter was not sampled in the same order, got {:?}", param),
+        }
+    }
+
 }
 
 pub trait Optimizer {
     fn next_parameters(&mut self, id: &str) -> Box<dyn ParamFactory>;
     fn report(&mut self, results: &TaskResult);
+    /// Captures any internal state (e.g. RNG state) to be included in trainer snapshots
+    fn save_state(&self) -> Result<serde_json::Value, Box<dyn Error>> {
+        Ok(serde_json::Value::Null)
+    }
+    /// Restores state previously captured by `save_state`
+    fn restore_state(&mut self, _state: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -25,7 +129,6 @@ impl RandomOptimizer {
             rng: Rc::new(RefCell::new(rand_xorshift::XorShiftRng::from_entropy()))
         }
     }
-    #[allow(dead_code)]
     pub fn from_seed(seed: &str) -> Self {
         let seed_bytes = stable_hash_seed(seed);
         RandomOptimizer {
@@ -45,20 +148,28 @@ impl Optimizer for RandomOptimizer {
     fn report(&mut self, _results: &TaskResult) {
         // no-op
     }
+
+    fn save_state(&self) -> Result<serde_json::Value, Box<dyn Error>> {
+        Ok(serde_json::to_value(&*self.rng.borrow())?)
+    }
+
+    fn restore_state(&mut self, state: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        *self.rng.borrow_mut() = serde_json::from_value(state)?;
+        Ok(())
+    }
 }
 
 struct RandomParamFactory {
     rng: Rc<RefCell<rand_xorshift::XorShiftRng>>
 }
 
-#[allow(dead_code)]
 impl ParamFactory for RandomParamFactory {
 
     fn range_usize(&mut self, _key: String, low: usize, high: usize) -> usize {
         return (&*self.rng).borrow_mut().gen_range(low, high);
     }
 
-    fn range_f32(&mut self, low: f32, high: f32) -> f32 {
+    fn range_float(&mut self, low: Float, high: Float) -> Float {
         return (&*self.rng).borrow_mut().gen_range(low, high);
     }
 
@@ -0,0 +1,380 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::data::PreparedDataSet;
+use crate::net::FrozenNet;
+use crate::drift::{DriftMetric, DriftMonitor, DriftReference};
+use crate::stats::Stats;
+use crate::train::trainer::NetTrainer;
+
+/// Supplies the latest version of the data set to retrain on
+pub trait DataSetSource {
+    fn load(&mut self) -> Result<PreparedDataSet, Box<dyn Error>>;
+}
+
+impl<F> DataSetSource for F where F: FnMut() -> Result<PreparedDataSet, Box<dyn Error>> {
+    fn load(&mut self) -> Result<PreparedDataSet, Box<dyn Error>> {
+        self()
+    }
+}
+
+/// When a `RetrainService` retrains, in addition to the initial training
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetrainTrigger {
+    /// Retrain once the served net is older than the interval
+    Interval(Duration),
+    /// Retrain once the drift score of any input column of the data, compared to the data the
+    /// served net was trained on, reaches the threshold. The score is the shift of the column's
+    /// mean in standard deviations unless another metric is set, see
+    /// `RetrainService::with_drift_metric`.
+    Drift { threshold: f64 },
+    IntervalOrDrift { interval: Duration, threshold: f64 },
+}
+
+impl RetrainTrigger {
+
+    fn interval(&self) -> Option<Duration> {
+        match self {
+            &RetrainTrigger::Interval(interval) | &RetrainTrigger::IntervalOrDrift { interval, threshold: _ } => Some(interval),
+            RetrainTrigger::Drift { threshold: _ } => None,
+        }
+    }
+
+    fn drift_threshold(&self) -> Option<f64> {
+        match self {
+            &RetrainTrigger::Drift { threshold } | &RetrainTrigger::IntervalOrDrift { interval: _, threshold } => Some(threshold),
+            RetrainTrigger::Interval(_) => None,
+        }
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetrainReason {
+    Initial,
+    Scheduled,
+    Drift { score: f64 },
+}
+
+#[derive(Clone, Debug)]
+pub enum RetrainEvent {
+    DataLoaded { num_rows: usize },
+    DriftChecked { score: f64 },
+    TrainingStarted { reason: RetrainReason },
+    TrainingCompleted { error_stats: Stats, duration: Duration },
+    TrainingFailed { error: String },
+    /// A newly trained net replaced the served net
+    NetSwapped { version: usize },
+}
+
+type TrainerFactory = dyn FnMut(PreparedDataSet) -> Result<NetTrainer, Box<dyn Error>>;
+type RetrainObserver = dyn Fn(&RetrainEvent);
+type VersionedNet = (usize, Arc<FrozenNet>);
+
+/// A shared handle to the currently served net, which is replaced atomically on retraining.
+/// Readers keep using the net they obtained until they ask for the current one again.
+#[derive(Clone, Debug, Default)]
+pub struct ServingNet {
+    current: Arc<RwLock<Option<VersionedNet>>>,
+}
+
+impl ServingNet {
+
+    /// The currently served net, if one was trained yet
+    pub fn current(&self) -> Option<Arc<FrozenNet>> {
+        self.current.read().unwrap().as_ref().map(|(_, net)| net.clone())
+    }
+
+    /// The number of times the served net was swapped, 0 if none was trained yet
+    pub fn version(&self) -> usize {
+        self.current.read().unwrap().as_ref().map_or(0, |(version, _)| *version)
+    }
+
+    fn swap(&self, net: FrozenNet) -> usize {
+        let mut current = self.current.write().unwrap();
+        let version = current.as_ref().map_or(0, |(version, _)| *version) + 1;
+        *current = Some((version, Arc::new(net)));
+        version
+    }
+
+}
+
+/// Continuously retrains a net as new data becomes available, hot-swapping the served net.
+///
+/// Each check loads the data set from the source and retrains if there is no net yet, the
+/// served net is older than the trigger's interval, or the data drifted by more than the
+/// trigger's threshold. If a `DriftMonitor` of the inputs seen by the served net is attached, any
+/// alert it raises also triggers retraining, after which its reference is reset to the new data.
+pub struct RetrainService {
+    source: Box<dyn DataSetSource>,
+    trainer_factory: Box<TrainerFactory>,
+    trigger: RetrainTrigger,
+    check_interval: Duration,
+    observer: Option<Box<RetrainObserver>>,
+    serving: ServingNet,
+    last_trained: Option<SystemTime>,
+    reference: Option<DriftReference>,
+    drift_metric: DriftMetric,
+    drift_monitor: Option<Arc<Mutex<DriftMonitor>>>,
+}
+
+/// The number of quantile bins of the drift reference computed from the training data
+const DRIFT_REFERENCE_BINS: usize = 10;
+
+impl RetrainService {
+
+    pub fn new<S, F>(source: S, trainer_factory: F, trigger: RetrainTrigger) -> Self
+        where S: DataSetSource + 'static,
+              F: FnMut(PreparedDataSet) -> Result<NetTrainer, Box<dyn Error>> + 'static
+    {
+        RetrainService {
+            source: Box::new(source),
+            trainer_factory: Box::new(trainer_factory),
+            trigger,
+            check_interval: Duration::from_secs(60),
+            observer: None,
+            serving: ServingNet::default(),
+            last_trained: None,
+            reference: None,
+            drift_metric: DriftMetric::MeanShift,
+            drift_monitor: None,
+        }
+    }
+
+    /// How long `run` waits between checks. Defaults to one minute.
+    pub fn with_check_interval(self, check_interval: Duration) -> Self {
+        let mut new = self;
+        new.check_interval = check_interval;
+        new
+    }
+
+    pub fn with_observer(self, observer: impl Fn(&RetrainEvent) + 'static) -> Self {
+        let mut new = self;
+        new.observer = Some(Box::new(observer));
+        new
+    }
+
+    /// How the drift of the loaded data is scored against the threshold of the trigger. Defaults
+    /// to `DriftMetric::MeanShift`.
+    pub fn with_drift_metric(self, drift_metric: DriftMetric) -> Self {
+        let mut new = self;
+        new.drift_metric = drift_metric;
+        new
+    }
+
+    /// Retrains whenever the monitor, which should be fed the inputs of the served net, alerts
+    pub fn with_drift_monitor(self, drift_monitor: Arc<Mutex<DriftMonitor>>) -> Self {
+        let mut new = self;
+        new.drift_monitor = Some(drift_monitor);
+        new
+    }
+
+    /// A handle to the served net, to be shared with the threads doing inference
+    pub fn serving(&self) -> ServingNet {
+        self.serving.clone()
+    }
+
+    /// Performs a single check, retraining if necessary. Returns whether the served net was swapped.
+    /// A failed training is reported as an event and leaves the served net in place.
+    pub fn check(&mut self) -> Result<bool, Box<dyn Error>> {
+
+        let data_set = self.source.load()?;
+        self.omit_event(&RetrainEvent::DataLoaded { num_rows: data_set.num_rows() });
+
+        let reason = match self.last_trained {
+            None => Some(RetrainReason::Initial),
+            Some(last_trained) => {
+                let age = SystemTime::now().duration_since(last_trained).unwrap_or_default();
+                if self.trigger.interval().is_some_and(|interval| age >= interval) {
+                    Some(RetrainReason::Scheduled)
+                } else {
+                    self.check_drift(&data_set).map(|score| RetrainReason::Drift { score })
+                }
+            },
+        };
+
+        let reason = match reason {
+            Some(reason) => reason,
+            None => return Ok(false),
+        };
+
+        self.omit_event(&RetrainEvent::TrainingStarted { reason });
+        let reference = DriftReference::from_data_set(&data_set, DRIFT_REFERENCE_BINS);
+        let result = (self.trainer_factory)(data_set).and_then(|mut trainer| trainer.execute());
+        match result {
+            Ok(result) => {
+                self.omit_event(&RetrainEvent::TrainingCompleted {
+                    error_stats: result.error_stats.clone(),
+                    duration: result.duration,
+                });
+                let version = self.serving.swap(result.net.freeze());
+                self.last_trained = Some(SystemTime::now());
+                if let Some(drift_monitor) = &self.drift_monitor {
+                    drift_monitor.lock().unwrap().reset(reference.clone());
+                }
+                self.reference = Some(reference);
+                self.omit_event(&RetrainEvent::NetSwapped { version });
+                Ok(true)
+            },
+            Err(error) => {
+                self.omit_event(&RetrainEvent::TrainingFailed { error: error.to_string() });
+                Ok(false)
+            },
+        }
+    }
+
+    /// Checks every `check_interval` until `stop` is set
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<(), Box<dyn Error>> {
+        while !stop.load(Ordering::Relaxed) {
+            self.check()?;
+            let mut waited = Duration::from_secs(0);
+            while waited < self.check_interval && !stop.load(Ordering::Relaxed) {
+                let step = Duration::min(Duration::from_millis(50), self.check_interval - waited);
+                thread::sleep(step);
+                waited += step;
+            }
+        }
+        Ok(())
+    }
+
+    /// The drift score if it warrants retraining
+    fn check_drift(&self, data_set: &PreparedDataSet) -> Option<f64> {
+        if let Some(drift_monitor) = &self.drift_monitor {
+            let drift_monitor = drift_monitor.lock().unwrap();
+            if !drift_monitor.alerts().is_empty() {
+                let score = drift_monitor.max_score();
+                self.omit_event(&RetrainEvent::DriftChecked { score });
+                return Some(score);
+            }
+        }
+        let threshold = self.trigger.drift_threshold()?;
+        let reference = self.reference.as_ref()?;
+        let score = if reference.columns().len() == data_set.independent_cols() {
+            reference.compare_data_set(data_set, self.drift_metric).iter()
+                .map(|drift| drift.score)
+                .fold(0.0, f64::max)
+        } else {
+            f64::INFINITY
+        };
+        self.omit_event(&RetrainEvent::DriftChecked { score });
+        if score >= threshold {
+            Some(score)
+        } else {
+            None
+        }
+    }
+
+    fn omit_event(&self, event: &RetrainEvent) {
+        if let Some(observer) = &self.observer {
+            observer(event);
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::{backprop_options, lines_2x2};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use crate::func::{ActivationFn, CompletionFn};
+    use crate::net::NetConfig;
+    use crate::train::{BackpropOptions, NetTrainerBuilder};
+
+    fn load_data_set() -> Result<PreparedDataSet, Box<dyn Error>> {
+        Ok(lines_2x2())
+    }
+
+    fn build_trainer(data_set: PreparedDataSet) -> Result<NetTrainer, Box<dyn Error>> {
+        Ok(NetTrainerBuilder::default()
+            .data_set(data_set)
+            .net_config(NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid()))
+            .backprop_options(BackpropOptions {
+                completion_fn: CompletionFn::stop_after_epoch(5),
+                ..backprop_options()
+            })
+            .observer(Box::new(|_| {}))
+            .build()?)
+    }
+
+    #[test]
+    fn test_retrain_on_drift() {
+        for drift_metric in [DriftMetric::MeanShift, DriftMetric::PopulationStabilityIndex] {
+            check_retrain_on_drift(drift_metric);
+        }
+    }
+
+    fn check_retrain_on_drift(drift_metric: DriftMetric) {
+
+        // the third load only returns part of the rows, which shifts the column means
+        let loads = Rc::new(Cell::new(0));
+        let source_loads = loads.clone();
+        let source = move || -> Result<PreparedDataSet, Box<dyn Error>> {
+            source_loads.set(source_loads.get() + 1);
+            let data_set = load_data_set()?;
+            if source_loads.get() == 3 {
+                return Ok(data_set.partition(2).remove(0));
+            }
+            Ok(data_set)
+        };
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let observed_events = events.clone();
+        let mut service = RetrainService::new(source, build_trainer, RetrainTrigger::Drift { threshold: 0.1 })
+            .with_drift_metric(drift_metric)
+            .with_observer(move |event| observed_events.borrow_mut().push(event.clone()));
+        let serving = service.serving();
+
+        assert!(serving.current().is_none());
+        assert!(service.check().unwrap());
+        assert_eq!(serving.version(), 1);
+        let first = serving.current().unwrap();
+        assert_eq!(first.predict(&[0.0, 1.0, 0.0, 1.0]).len(), 2);
+
+        assert!(!service.check().unwrap());
+        assert_eq!(serving.version(), 1);
+
+        assert!(service.check().unwrap());
+        assert_eq!(serving.version(), 2);
+        assert!(!Arc::ptr_eq(&first, &serving.current().unwrap()));
+
+        let events = events.borrow();
+        assert!(matches!(events[1], RetrainEvent::TrainingStarted { reason: RetrainReason::Initial }));
+        assert!(events.iter().any(|e| matches!(e, RetrainEvent::DriftChecked { score } if *score == 0.0)));
+        assert!(events.iter().any(|e| matches!(e, RetrainEvent::TrainingStarted { reason: RetrainReason::Drift { .. } })));
+        assert_eq!(loads.get(), 3);
+        assert!(matches!(events.last().unwrap(), RetrainEvent::NetSwapped { version: 2 }));
+
+    }
+
+    #[test]
+    fn test_retrain_on_served_drift() {
+
+        let reference = DriftReference::from_data_set(&lines_2x2(), 10);
+        let drift_monitor = Arc::new(Mutex::new(
+            DriftMonitor::new(reference, DriftMetric::PopulationStabilityIndex, 0.25, 20).with_min_observations(10)));
+
+        let mut service = RetrainService::new(load_data_set, build_trainer, RetrainTrigger::Interval(Duration::from_secs(3600)))
+            .with_drift_monitor(drift_monitor.clone());
+
+        assert!(service.check().unwrap());
+        assert!(!service.check().unwrap());
+
+        let net = service.serving().current().unwrap();
+        for _ in 0..20 {
+            let inputs = [1.0, 1.0, 1.0, 1.0];
+            net.predict(&inputs);
+            drift_monitor.lock().unwrap().observe(&inputs);
+        }
+        assert!(service.check().unwrap());
+        assert_eq!(service.serving().version(), 2);
+        // the monitor starts over against the data of the new net
+        assert_eq!(drift_monitor.lock().unwrap().num_observed(), 0);
+
+    }
+
+}
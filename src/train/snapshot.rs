@@ -0,0 +1,154 @@
+use std::error::Error;
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use crate::initializer::RandomNetInitializer;
+use crate::train::task::TaskResult;
+use crate::train::trainer::TrialSummary;
+
+/// The full state of a `NetTrainer`, from which an interrupted training run can be resumed with
+/// `NetTrainerBuilder::restore`. Tasks which were in flight when the snapshot was taken are not
+/// included and will be re-generated.
+#[derive(Serialize, Deserialize)]
+pub struct TrainerSnapshot {
+    pub task_counter: usize,
+    pub elapsed: Duration,
+    pub optimizer_state: serde_json::Value,
+    pub initializer: RandomNetInitializer,
+    pub trials: Vec<TrialSummary>,
+    pub best: Option<TaskResult>,
+    /// The best results, best first, if the trainer keeps the top k
+    #[serde(default)]
+    pub top_results: Vec<TaskResult>,
+    /// The seed from which the RNGs of the tasks, bootstrap samples and folds are derived
+    #[serde(default)]
+    pub seed: Option<String>,
+}
+
+impl TrainerSnapshot {
+
+    /// Writes the snapshot as JSON. The snapshot is first written to a temporary file next to
+    /// `path` and then renamed, so an existing snapshot is never left partially overwritten.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut tmp_file_name = path.file_name()
+            .ok_or("Snapshot path must be a file")?
+            .to_os_string();
+        tmp_file_name.push(".tmp");
+        let tmp_path: PathBuf = path.with_file_name(tmp_file_name);
+        {
+            let mut writer = BufWriter::new(fs::File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, self)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<TrainerSnapshot, Box<dyn Error>> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::train::task::TaskOutcome;
+    use crate::net::NetConfig;
+    use crate::func::ActivationFn;
+    use crate::stats::Stats;
+    use crate::Float;
+    use std::error::Error;
+
+    #[test]
+    fn test_save_load_round_trip() -> Result<(), Box<dyn Error>> {
+
+        let mut net = NetConfig::new_fully_connected(
+            3,
+            2,
+            [4],
+            ActivationFn::standard_logistic_sigmoid()
+        ).create_net();
+        let mut initializer = RandomNetInitializer::new_standard_with_seed("snapshot");
+        net.initialize_weights(&mut initializer);
+
+        let mut error_stats = Stats::new();
+        error_stats.report(0.25);
+        error_stats.report(0.5);
+
+        let snapshot = TrainerSnapshot {
+            task_counter: 7,
+            elapsed: Duration::from_millis(1500),
+            optimizer_state: serde_json::Value::Null,
+            initializer: initializer.clone(),
+            trials: vec![TrialSummary {
+                task_id: "backprop_6".to_string(),
+                outcome: TaskOutcome::Completed,
+                error_stats: error_stats.clone(),
+                epoch: 12,
+                batch_count: 48,
+                elapsed: Duration::from_millis(100),
+                evaluations: Vec::new(),
+                params: Vec::new(),
+            }],
+            best: Some(TaskResult {
+                task_id: "backprop_6".to_string(),
+                outcome: TaskOutcome::Completed,
+                net: net.clone(),
+                error_stats: error_stats.clone(),
+                accuracy_stats: Some(Stats::new()),
+                accuracies: Vec::new(),
+                ranking_stats: None,
+                epoch: 12,
+                batch_count: 48,
+                elapsed: Duration::from_millis(100),
+                evaluations: Vec::new(),
+                throughput: Default::default(),
+                divergence: None,
+                history: Vec::new(),
+                params: Vec::new(),
+                ema_net: None,
+            }),
+            top_results: Vec::new(),
+            seed: Some("run".to_string()),
+        };
+        // the net of a diverged result
+        let mut diverged = snapshot.best.clone().unwrap();
+        diverged.outcome = TaskOutcome::DivergenceDetected;
+        diverged.net.get_weights_mut().get_buffer_mut()[..2].copy_from_slice(&[Float::NAN, Float::INFINITY]);
+        let snapshot = TrainerSnapshot { top_results: vec![diverged], ..snapshot };
+
+        let path = std::env::temp_dir().join(format!("rust_neural_net_snapshot_{}.json", std::process::id()));
+        snapshot.save(&path)?;
+        let mut loaded = TrainerSnapshot::load(&path)?;
+        fs::remove_file(&path)?;
+
+        assert_eq!(loaded.task_counter, 7);
+        assert_eq!(loaded.elapsed, Duration::from_millis(1500));
+        assert_eq!(loaded.trials.len(), 1);
+        assert_eq!(loaded.trials[0].error_stats.mean(), error_stats.mean());
+        assert_eq!(loaded.initializer.get_weight(), initializer.get_weight());
+
+        let best = loaded.best.unwrap();
+        assert_eq!(best.net.get_config(), net.get_config());
+        assert_eq!(best.net.get_weights().get_buffer(), net.get_weights().get_buffer());
+        assert_eq!(best.outcome, TaskOutcome::Completed);
+        assert_eq!(best.error_stats.count(), 2);
+        assert!(best.accuracy_stats.unwrap().min().is_nan());
+
+        let diverged = loaded.top_results[0].net.get_weights().get_buffer();
+        assert!(diverged[0].is_nan() && diverged[1] == Float::INFINITY);
+        assert_eq!(diverged[2..], net.get_weights().get_buffer()[2..]);
+        assert_eq!(loaded.seed.as_deref(), Some("run"));
+
+        Ok(())
+    }
+
+}
@@ -1,13 +1,21 @@
+use crate::Float;
 use crate::{
+    buffer::BufferPool,
     data::PreparedDataSet,
     net::Net,
     train::{
         BackpropOptions,
-        backprop::backprop_stage_task_impl
+        backprop::{pooled_backprop_stage_task_impl, curriculum_task_impl, CurriculumStage, EpochMetrics, LayerNorms},
+        DataSetEvaluation,
+        context::DivergenceReport,
+        control::TrainingControl,
+        optimizer::SampledParam,
     },
-    stats::Stats
+    stats::{RankingStats, Stats},
+    func::ErrorFn,
 };
 use std::time::{Duration, SystemTime};
+use serde::{Serialize, Deserialize};
 
 
 pub struct Task {
@@ -15,6 +23,15 @@ pub struct Task {
     pub data_set: PreparedDataSet,
     pub net: Net,
     pub op: TaskOp,
+    /// Named data sets the resulting net of a backprop task is evaluated against
+    pub evaluation_data_sets: Vec<(String, PreparedDataSet)>,
+    /// The control of the trainer, to pause backprop tasks
+    pub control: TrainingControl,
+    /// The hyperparameters sampled for the task, which are passed on to its result
+    pub params: Vec<SampledParam>,
+    /// The thresholds at which a backprop task also computes the accuracy of its final net,
+    /// besides those of its completion fn, e.g. those of the trainer's global completion fn
+    pub accuracy_thresholds: Vec<Float>,
 }
 
 quick_error! {
@@ -26,44 +43,213 @@ quick_error! {
     }
 }
 
+#[derive(Clone)]
 pub struct TaskUpdate {
     pub task_id: String,
     pub error_stats: Stats,
     pub epoch: usize,
     pub elapsed: Duration,
+    /// The throughput since the task started
+    pub throughput: Throughput,
+    /// The norms of every layer, if the task tracks them
+    pub layer_norms: Vec<LayerNorms>,
+}
+
+/// The training throughput of a task, to compare executor configurations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Throughput {
+    pub rows_per_second: f64,
+    pub batches_per_second: f64,
+}
+
+impl Throughput {
+
+    /// Zero if no time elapsed
+    pub fn new(row_count: usize, batch_count: usize, elapsed: Duration) -> Self {
+        let seconds = elapsed.as_secs_f64();
+        if seconds <= 0.0 {
+            return Throughput::default();
+        }
+        Throughput {
+            rows_per_second: row_count as f64 / seconds,
+            batches_per_second: batch_count as f64 / seconds,
+        }
+    }
+
 }
 
 pub trait TaskUpdateEmitter {
     fn emit_update(&self, update: TaskUpdate);
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TaskOutcome {
+    Completed,
+    /// The error became NaN or infinite, so the task was stopped early
+    DivergenceDetected,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TaskResult {
     pub task_id: String,
+    pub outcome: TaskOutcome,
     pub net: Net,
     pub error_stats: Stats,
+    /// The accuracy stats at the first accuracy threshold of the completion fn
+    pub accuracy_stats: Option<Stats>,
+    /// The accuracy stats at each accuracy threshold of the completion fn, then at each of the
+    /// task's `accuracy_thresholds`
+    #[serde(default)]
+    pub accuracies: Vec<(Float, Stats)>,
+    /// The number of passes over the data set
     pub epoch: usize,
+    /// The number of mini-batch weight updates
+    #[serde(default)]
+    pub batch_count: usize,
     pub elapsed: Duration,
+    /// The ranked scores of an evaluate task's `ranked_output`, if set
+    #[serde(default)]
+    pub ranking_stats: Option<RankingStats>,
+    /// The evaluation of the net against each of the task's evaluation data sets, in order
+    #[serde(default)]
+    pub evaluations: Vec<DataSetEvaluation>,
+    /// The training throughput of a backprop task, not counting its evaluations
+    #[serde(default)]
+    pub throughput: Throughput,
+    /// Where the training of a backprop task first diverged, if it checked for divergence
+    #[serde(default)]
+    pub divergence: Option<DivergenceReport>,
+    /// The metrics of every completion check of a backprop task
+    #[serde(default)]
+    pub history: Vec<EpochMetrics>,
+    #[serde(default)]
+    pub params: Vec<SampledParam>,
+    /// The net with the moving average of its weights, if the backprop options have an EMA decay
+    #[serde(default)]
+    pub ema_net: Option<Net>,
 }
 
 pub enum TaskOp {
-    Backprop(BackpropOptions)
+    Backprop(Box<BackpropOptions>),
+    /// Trains the net through each stage in turn, each on its own data set, see `CurriculumStage`
+    Curriculum(Vec<CurriculumStage>),
+    /// Computes error (and optionally accuracy) stats of the net over the data set without training
+    Evaluate(EvaluateOptions),
+}
+
+#[derive(Clone, Debug)]
+pub struct EvaluateOptions {
+    pub error_fn: ErrorFn,
+    pub accuracy_threshold: Option<Float>,
+    /// If set, the scores of this output are ranked against its expected outputs, which are
+    /// positive if at least the accuracy threshold, or 0.5 without one
+    pub ranked_output: Option<usize>,
 }
 
 impl Task {
-    pub fn exec(mut self, update_emitter: &dyn TaskUpdateEmitter) -> Result<TaskResult, TaskError> {
+    pub fn exec(self, update_emitter: &dyn TaskUpdateEmitter) -> Result<TaskResult, TaskError> {
+        self.exec_with_pool(update_emitter, &BufferPool::new())
+    }
+
+    /// Like `exec`, but the scratch buffers come from the pool and go back to it when the task is
+    /// done, so tasks of the same architecture run one after another allocate them only once
+    pub fn exec_with_pool(mut self, update_emitter: &dyn TaskUpdateEmitter, pool: &BufferPool) -> Result<TaskResult, TaskError> {
         let start_time = SystemTime::now();
-        match self.op {
+        let (mut stage_result, options, evaluation_set) = match self.op {
             TaskOp::Backprop(ref options) => {
-                let (error_stats, batch_count) = backprop_stage_task_impl(&mut self.net, &self.data_set, options);
-                Ok(TaskResult {
+                let stage_result = pooled_backprop_stage_task_impl(&mut self.net, &self.data_set, options, &self.task_id, update_emitter, &self.control, pool);
+                (stage_result, &**options, options.validation_set.as_ref().unwrap_or(&self.data_set))
+            },
+            TaskOp::Curriculum(ref stages) => {
+                let stage_result = curriculum_task_impl(&mut self.net, stages, &self.task_id, update_emitter, &self.control, pool);
+                let last_stage = stages.last().expect("a curriculum needs at least one stage");
+                (stage_result, &last_stage.options, last_stage.options.validation_set.as_ref().unwrap_or(&last_stage.data_set))
+            },
+            TaskOp::Evaluate(ref options) => {
+                let mut context = self.net.get_pooled_training_context(pool);
+                let thresholds: Vec<Float> = options.accuracy_threshold.into_iter().collect();
+                let (error_stats, accuracies) = context
+                    .compute_error_and_accuracies_for_batch(&self.data_set, &options.error_fn, &thresholds);
+                let ranking_stats = match options.ranked_output {
+                    Some(output_index) => Some(context.compute_ranking_for_batch(&self.data_set, output_index, options.accuracy_threshold.unwrap_or(0.5))),
+                    None => None,
+                };
+                context.release_buffers(pool);
+                return Ok(TaskResult {
                     task_id: self.task_id,
+                    outcome: TaskOutcome::Completed,
                     net: self.net,
                     error_stats,
-                    epoch: batch_count,
-                    elapsed: SystemTime::now().duration_since(start_time).unwrap()
-                })
+                    accuracy_stats: accuracies.first().map(|(_, stats)| stats.clone()),
+                    accuracies,
+                    ranking_stats,
+                    epoch: 0,
+                    batch_count: 0,
+                    elapsed: SystemTime::now().duration_since(start_time).unwrap(),
+                    evaluations: Vec::new(),
+                    throughput: Throughput::default(),
+                    divergence: None,
+                    history: Vec::new(),
+                    params: self.params,
+                    ema_net: None,
+                });
             },
+        };
+        let throughput = Throughput::new(
+            stage_result.row_count,
+            stage_result.batch_count,
+            SystemTime::now().duration_since(start_time).unwrap_or_default(),
+        );
+        let accuracy_threshold = options.completion_fn.accuracy_threshold();
+        let mut context = self.net.get_pooled_training_context(pool);
+        // the accuracy at thresholds the stage didn't check is computed on the same data set
+        let missing_thresholds: Vec<Float> = self.accuracy_thresholds.iter()
+            .copied()
+            .filter(|threshold| !stage_result.accuracies.iter().any(|(t, _)| t == threshold))
+            .collect();
+        if !missing_thresholds.is_empty() && !stage_result.diverged {
+            let (_, mut accuracies) = context
+                .compute_error_and_accuracies_for_batch(evaluation_set, &options.error_fn, &missing_thresholds);
+            stage_result.accuracies.append(&mut accuracies);
         }
+        let evaluations = self.evaluation_data_sets.iter()
+            .map(|(name, data_set)| {
+                let (error_stats, accuracy_stats) = context
+                    .compute_error_and_accuracy_for_batch(data_set, &options.error_fn, accuracy_threshold);
+                DataSetEvaluation {
+                    name: name.clone(),
+                    error_stats,
+                    accuracy_stats,
+                    ranking_stats: None,
+                }
+            })
+            .collect();
+        context.release_buffers(pool);
+        let ema_net = stage_result.ema_weights.map(|ema_weights| {
+            let mut ema_net = self.net.clone();
+            ema_weights.copy_into(ema_net.get_weights_mut());
+            ema_net
+        });
+        Ok(TaskResult {
+            task_id: self.task_id,
+            outcome: if stage_result.diverged { TaskOutcome::DivergenceDetected } else { TaskOutcome::Completed },
+            net: self.net,
+            error_stats: stage_result.error_stats,
+            accuracy_stats: stage_result.accuracies.first()
+                .filter(|(threshold, _)| Some(*threshold) == accuracy_threshold)
+                .map(|(_, stats)| stats.clone()),
+            accuracies: stage_result.accuracies,
+            ranking_stats: None,
+            epoch: stage_result.epoch_count,
+            batch_count: stage_result.batch_count,
+            elapsed: SystemTime::now().duration_since(start_time).unwrap(),
+            evaluations,
+            throughput,
+            divergence: stage_result.divergence,
+            history: stage_result.history,
+            params: self.params,
+            ema_net,
+        })
     }
 }
 
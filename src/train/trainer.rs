@@ -1,57 +1,173 @@
 use rand::{Rng, FromEntropy, SeedableRng};
 
-use crate::func::CompletionFn;
+use crate::Float;
+use crate::func::{CompletionFn, ErrorFn};
 use crate::net::{Net, NetConfig};
 use crate::data::PreparedDataSet;
-use crate::stats::Stats;
-use crate::train::backprop::BackpropOptions;
+use crate::stats::{RankingStats, Stats};
+use crate::train::backprop::{BackpropOptions, CurriculumStage, EpochMetrics};
 use crate::train::executor::Executor;
-use crate::train::task::{Task, TaskResult, TaskOp, TaskUpdate};
+use crate::train::task::{Task, TaskResult, TaskOp, TaskUpdate, TaskOutcome, EvaluateOptions};
 use crate::train::executor::ExecutorControlMaster;
-use crate::initializer::RandomNetInitializer;
+use crate::initializer::{FromPretrained, RandomNetInitializer};
 use crate::utils::stable_hash_seed;
-use crate::train::optimizer::{Optimizer, ParamFactory, RandomOptimizer};
+use crate::ensemble::Ensemble;
+use crate::train::optimizer::{Optimizer, ParamFactory, RandomOptimizer, RecordingParamFactory, ReplayOptimizer, SampledParam};
 use std::time::SystemTime;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::time::Duration;
 use std::thread;
 use std::error::Error;
+use std::path::PathBuf;
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
 use crate::train::executor::ExecutorEvent;
+use crate::train::snapshot::TrainerSnapshot;
+use crate::train::control::TrainingControl;
+use crate::train::distill::Distillation;
+use crate::train::observer::{self, EventSender, OwnedTrainingEvent, TrainingEvents, TrainingFuture};
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum TrainingError {
+        CancelledWithoutResult {
+            description("Training was cancelled before any task completed")
+            display("Training was cancelled before any task completed")
+        }
+    }
+}
 
-#[allow(dead_code)]
 #[derive(Clone, Copy, Debug)]
 pub enum NetTrainerMode {
     Standard,
-    Evolutionary { trials_per_generation: usize }
+    Evolutionary { trials_per_generation: usize },
+    /// Trains each sampled configuration on every one of `folds` folds of the data set, validating
+    /// on the rest, and picks the configuration with the lowest mean validation error, see
+    /// `TrainingResult::cross_validation`. Each fold is a trial of the global completion fn, and
+    /// the validation data set is ignored.
+    CrossValidation { folds: usize },
+    /// Trains `members` nets, each on a bootstrap sample of `sample_fraction` times as many rows
+    /// as the data set, and combines them into `TrainingResult::ensemble`. Members which diverge
+    /// are retrained on a new sample, up to `members` times in all, and left out of the ensemble.
+    /// The global completion fn and `keep_top_k` are ignored, and `top_results` holds every
+    /// trained net.
+    Ensemble { members: usize, sample_fraction: Float },
 }
 
 #[derive(Builder)]
 #[builder(pattern = "owned")]
 pub struct NetTrainer {
     data_set: PreparedDataSet,
+    /// If set, backprop tasks check completion and report errors on this data set, unless their
+    /// options already have a validation set
+    #[builder(default, setter(strip_option))]
+    validation_data_set: Option<PreparedDataSet>,
+    #[builder(default, setter(strip_option))]
+    test_data_set: Option<PreparedDataSet>,
+    /// Named data sets every trained net is evaluated against, see `evaluation_data_set`
+    #[builder(default, private)]
+    evaluation_data_sets: Vec<(String, PreparedDataSet)>,
+    #[builder(default = "ModelSelection::Training")]
+    model_selection: ModelSelection,
+    /// If set, the result keeps this many of the best task results, see `TrainingResult::top_results`
+    #[builder(default, setter(strip_option))]
+    keep_top_k: Option<usize>,
+    /// If set, the best net is evaluated against each data set in parallel once training completes
+    #[builder(default, setter(strip_option))]
+    final_evaluation: Option<FinalEvaluationOptions>,
     #[builder(default = "Executor::Local(1)")]
     executor: Executor,
+    /// The most tasks submitted at once. If unset, one per worker of a local executor, or as
+    /// many as there are waiting workers of a distributed one.
+    #[builder(default, setter(strip_option))]
+    max_concurrent_tasks: Option<usize>,
     #[builder(default = "Box::new(default_optimizer_factory)")]
-    optimizer_factory: Box<dyn Fn() -> Box<dyn Optimizer>>,
+    optimizer_factory: Box<dyn Fn() -> Box<dyn Optimizer> + Send>,
     #[builder(default = "NetTrainerMode::Standard")]
     mode: NetTrainerMode,
-    net_config_factory: Box<dyn Fn(&mut dyn ParamFactory) -> NetConfig>,
-    backprop_options_factory: Box<dyn Fn(&mut dyn ParamFactory) -> BackpropOptions>,
+    net_config_factory: Box<dyn Fn(&mut dyn ParamFactory) -> NetConfig + Send>,
+    backprop_options_factory: Box<dyn Fn(&mut dyn ParamFactory) -> BackpropOptions + Send>,
     #[builder(default = "CompletionFn::stop_after_epoch(1)")]
     global_completion_fn: CompletionFn,
-    #[builder(setter(skip))]
+    /// Initializes the weights of every generated net; seed it for reproducible training
     #[builder(default = "RandomNetInitializer::new_standard_from_entropy()")]
     initializer: RandomNetInitializer,
+    /// If set, every generated net starts from the pretrained weights instead, and `initializer`
+    /// is ignored
+    #[builder(default, setter(strip_option))]
+    pretrained: Option<FromPretrained>,
+    /// If set, every task trains through these stages instead, see `curriculum`
+    #[builder(default, private, setter(name = "curriculum_stages"))]
+    curriculum: Option<Vec<CurriculumStage>>,
+    /// If set, every task starts from a copy of this net, see `initial_net`
+    #[builder(default, private)]
+    initial_state: Option<Net>,
     #[builder(setter(strip_option))]
-    observer: Option<Box<dyn Fn(&TrainingEvent)>>
+    observer: Option<Box<dyn Fn(&TrainingEvent) + Send>>,
+    /// If set, a snapshot of the trainer state is written to this path every `snapshot_interval` results
+    #[builder(default, setter(strip_option, into))]
+    snapshot_path: Option<PathBuf>,
+    #[builder(default = "1")]
+    snapshot_interval: usize,
+    #[builder(default, private)]
+    restore_from: Option<PathBuf>,
+    #[builder(setter(skip))]
+    control: TrainingControl,
+    /// If set, the nets are trained on the soft targets of a teacher net instead of the targets
+    /// of the data set, minimizing binary cross-entropy whatever the error fn of their options.
+    /// The final evaluation of the training data set is against the soft targets too.
+    #[builder(default, setter(strip_option))]
+    distillation: Option<Distillation>,
+    /// Seeds the backprop tasks which aren't seeded by their options, see `seed`
+    #[builder(default, private, setter(name = "task_seed"))]
+    seed: Option<String>,
+    /// Receives every event when executed by `execute_async`
+    #[builder(setter(skip))]
+    event_sender: Option<EventSender>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FinalEvaluationOptions {
+    pub error_fn: ErrorFn,
+    pub accuracy_threshold: Option<Float>,
+    /// The number of partitions each data set is split into, to be evaluated by separate executor workers
+    pub partitions: usize,
+    /// If set, the scores of this output are ranked for the ROC and precision-recall curves of
+    /// each data set, see `EvaluateOptions::ranked_output`
+    pub ranked_output: Option<usize>,
+}
+
+/// Which error the trainer minimizes when picking the best net
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModelSelection {
+    /// The error on the training data set
+    Training,
+    /// The mean error across the evaluation data sets
+    EvaluationMean,
+    /// The largest error of any evaluation data set, favoring nets that are robust across segments
+    EvaluationWorstCase,
+}
+
+impl ModelSelection {
+
+    /// Falls back to the training error if the result has no evaluations
+    fn selection_error(&self, result: &TaskResult) -> f64 {
+        let evaluation_errors = result.evaluations.iter().map(|e| e.error_stats.mean());
+        match self {
+            _ if result.evaluations.is_empty() => result.error_stats.mean(),
+            ModelSelection::Training => result.error_stats.mean(),
+            ModelSelection::EvaluationMean => evaluation_errors.sum::<f64>() / result.evaluations.len() as f64,
+            ModelSelection::EvaluationWorstCase => evaluation_errors.fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+
 }
 
 fn default_optimizer_factory() -> Box<dyn Optimizer> {
     Box::new(RandomOptimizer::from_entropy())
 }
 
-#[allow(dead_code)]
 impl NetTrainerBuilder {
 
     pub fn net_config(self, net_config: NetConfig) -> Self {
@@ -66,12 +182,158 @@ impl NetTrainerBuilder {
         new
     }
 
+    /// Registers a named data set (e.g. "holdout-2023" or "edge-cases") which every trained net is
+    /// evaluated against. The evaluations are part of each task result and trial summary, can be
+    /// used for model selection and are included in the final evaluation.
+    pub fn evaluation_data_set(self, name: impl ToString, data_set: PreparedDataSet) -> Self {
+        let mut new = self;
+        new.evaluation_data_sets.get_or_insert_with(Vec::new).push((name.to_string(), data_set));
+        new
+    }
+
+    /// Starts every task from a copy of an existing net instead of a newly generated one, e.g. to
+    /// fine-tune a trained model. The net config is taken from the net, and `initializer` and
+    /// `pretrained` are ignored.
+    pub fn initial_net(self, net: Net) -> Self {
+        self.net_config(net.get_config()).initial_state(Some(net))
+    }
+
+    /// Makes training reproducible by seeding the initializer, the optimizer and the shuffling and
+    /// gradient noise of every task, each of which gets its own seed derived from this one. An
+    /// initializer, optimizer factory or task seed which is also set explicitly takes precedence.
+    /// Multi-threaded backprop is only reproducible up to the scheduling of its worker threads.
+    pub fn seed(self, seed: &str) -> Self {
+        let mut new = self;
+        if new.initializer.is_none() {
+            new.initializer = Some(RandomNetInitializer::new_standard_with_seed(&format!("{}/initializer", seed)));
+        }
+        if new.optimizer_factory.is_none() {
+            let optimizer_seed = format!("{}/optimizer", seed);
+            new.optimizer_factory = Some(Box::new(move || -> Box<dyn Optimizer> {
+                Box::new(RandomOptimizer::from_seed(&optimizer_seed))
+            }));
+        }
+        new.task_seed(Some(seed.to_string()))
+    }
+
+    /// Trains every net through the stages in turn, on the data set of each, instead of on the
+    /// data set of the trainer, which is still used for the final evaluation. The stages are
+    /// seeded and get the validation data set like the options of other tasks, whose factory
+    /// isn't needed and is otherwise unused.
+    pub fn curriculum(self, stages: Vec<CurriculumStage>) -> Self {
+        assert!(!stages.is_empty(), "a curriculum needs at least one stage");
+        let new = if self.backprop_options_factory.is_none() {
+            self.backprop_options(stages[stages.len() - 1].options.clone())
+        } else {
+            self
+        };
+        new.curriculum_stages(Some(stages))
+    }
+
+    /// Resumes training from a snapshot previously written to `snapshot_path` by another trainer.
+    /// The rest of the configuration must be supplied as usual and should match the original.
+    pub fn restore(self, path: impl Into<PathBuf>) -> Self {
+        self.restore_from(Some(path.into()))
+    }
+
 }
 
 pub struct TrainingResult {
     pub net: Net,
     pub error_stats: Stats,
+    pub accuracy_stats: Option<Stats>,
     pub duration: Duration,
+    pub trials: Vec<TrialSummary>,
+    /// The final evaluation of the net, if configured, in the order train, validation, test, then
+    /// the named evaluation data sets
+    pub evaluations: Vec<DataSetEvaluation>,
+    /// The metrics over the training of the best net, see `EpochMetrics`
+    pub history: Vec<EpochMetrics>,
+    /// The `keep_top_k` best task results, best first, ranked like the best net. Their error is
+    /// the validation error if there is a validation set.
+    pub top_results: Vec<TaskResult>,
+    /// The members averaged uniformly, when trained in `NetTrainerMode::Ensemble`
+    pub ensemble: Option<Ensemble>,
+    /// The best net with the moving average of its weights, if the backprop options have an EMA decay
+    pub ema_net: Option<Net>,
+    /// Every configuration trained on all folds in `NetTrainerMode::CrossValidation`, best first.
+    /// The net is then the best fold of the first.
+    pub cross_validation: Vec<CrossValidationSummary>,
+}
+
+/// The validation errors of a configuration over the folds of a cross-validation
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CrossValidationSummary {
+    pub params: Vec<SampledParam>,
+    /// The stats of the mean validation error of each fold, e.g. to report mean ± std dev
+    pub fold_error_stats: Stats,
+    /// The tasks which trained the folds, in fold order
+    pub task_ids: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DataSetEvaluation {
+    pub name: String,
+    pub error_stats: Stats,
+    pub accuracy_stats: Option<Stats>,
+    /// The ranked scores of the final evaluation's `ranked_output`, if set
+    #[serde(default)]
+    pub ranking_stats: Option<RankingStats>,
+}
+
+impl DataSetEvaluation {
+
+    fn new(name: impl ToString) -> Self {
+        DataSetEvaluation {
+            name: name.to_string(),
+            error_stats: Stats::new(),
+            accuracy_stats: None,
+            ranking_stats: None,
+        }
+    }
+
+    fn merge(&mut self, result: &TaskResult) {
+        self.error_stats.merge(&result.error_stats);
+        if let Some(accuracy_stats) = &result.accuracy_stats {
+            self.accuracy_stats.get_or_insert_with(Stats::new).merge(accuracy_stats);
+        }
+        if let Some(ranking_stats) = &result.ranking_stats {
+            self.ranking_stats.get_or_insert_with(RankingStats::new).merge(ranking_stats);
+        }
+    }
+
+}
+
+/// A summary of the result of a single task executed by the trainer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrialSummary {
+    pub task_id: String,
+    pub outcome: TaskOutcome,
+    pub error_stats: Stats,
+    pub epoch: usize,
+    #[serde(default)]
+    pub batch_count: usize,
+    pub elapsed: Duration,
+    #[serde(default)]
+    pub evaluations: Vec<DataSetEvaluation>,
+    /// The hyperparameters sampled for the task
+    #[serde(default)]
+    pub params: Vec<SampledParam>,
+}
+
+impl From<&TaskResult> for TrialSummary {
+    fn from(result: &TaskResult) -> Self {
+        TrialSummary {
+            task_id: result.task_id.clone(),
+            outcome: result.outcome,
+            error_stats: result.error_stats.clone(),
+            epoch: result.epoch,
+            batch_count: result.batch_count,
+            elapsed: result.elapsed,
+            evaluations: result.evaluations.clone(),
+            params: result.params.clone(),
+        }
+    }
 }
 
 pub enum TrainingEvent<'a> {
@@ -86,40 +348,124 @@ pub enum TrainingEvent<'a> {
 
 impl NetTrainer {
 
+    /// A handle to pause, resume or cancel training from another thread
+    pub fn control(&self) -> TrainingControl {
+        self.control.clone()
+    }
+
     pub fn execute(&mut self) -> Result<TrainingResult, Box<dyn Error>> {
 
         let executor = self.executor.get_instance()?;
 
+        let snapshot = match &self.restore_from {
+            Some(path) => {
+                let snapshot = TrainerSnapshot::load(path)?;
+                self.initializer = snapshot.initializer.clone();
+                Some(snapshot)
+            },
+            None => None,
+        };
+
         let ctrl_master = executor.start()?;
 
+        // train on the soft targets, and restore the original data set afterwards
+        let hard_targets = self.distillation.as_ref()
+            .map(|distillation| distillation.soft_targets(&self.data_set))
+            .map(|soft_targets| std::mem::replace(&mut self.data_set, soft_targets));
+
+        // the RNGs of the tasks, bootstrap samples and folds are derived from the seed, so a
+        // snapshot restores them by restoring the seed
+        let configured_seed = self.seed.clone();
+        match snapshot.as_ref().and_then(|snapshot| snapshot.seed.clone()) {
+            Some(seed) => self.seed = Some(seed),
+            None if self.seed.is_none() => self.seed = Some(rand::random::<u64>().to_string()),
+            None => {},
+        }
+
         let result = match self.mode {
-            NetTrainerMode::Standard => StandardTrainerImpl::new(self).train(ctrl_master),
+            NetTrainerMode::Standard => StandardTrainerImpl::new(self).train(ctrl_master, snapshot),
             NetTrainerMode::Evolutionary { trials_per_generation: _ } => { unimplemented!(); },
+            NetTrainerMode::CrossValidation { folds } => CrossValidationTrainerImpl::new(self, folds).train(ctrl_master, snapshot),
+            NetTrainerMode::Ensemble { members, sample_fraction } => EnsembleTrainerImpl::new(self, members, sample_fraction).train(ctrl_master, snapshot),
         };
 
         executor.stop();
 
+        if let Some(data_set) = hard_targets {
+            self.data_set = data_set;
+        }
+        self.seed = configured_seed;
+
         result
     }
 
+    /// Executes the trainer on a new thread, so the calling thread isn't blocked. Its events are
+    /// sent to the returned `TrainingEvents` as well as to its observer, and end once it
+    /// finishes.
+    pub fn execute_async(mut self) -> (TrainingFuture, TrainingEvents) {
+        let (sender, events) = observer::event_channel();
+        let (future, completer) = TrainingFuture::new(self.control.clone());
+        thread::spawn(move || {
+            self.event_sender = Some(sender);
+            let result = self.execute();
+            completer.complete(result.map_err(|err| err.to_string().into()));
+        });
+        (future, events)
+    }
+
 }
 
+/// The longest the trainer waits for an event before checking the global completion fn again,
+/// which may depend on the time
+const COMPLETION_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
 trait TrainerImpl {
 
     fn get_config(&self) -> &NetTrainer;
     fn handle_result(&mut self, result: &TaskResult);
     fn next_task(&mut self, task_id: usize) -> Task;
+    fn save_optimizer_state(&self) -> Result<serde_json::Value, Box<dyn Error>>;
+    fn restore_optimizer_state(&mut self, state: serde_json::Value) -> Result<(), Box<dyn Error>>;
+
+    /// If set, exactly this many tasks are trained, rather than until the global completion fn stops
+    fn max_tasks(&self) -> Option<usize> {
+        None
+    }
+
+    fn keep_top_k(&self) -> Option<usize> {
+        self.get_config().keep_top_k
+    }
+
+    /// Picks the result the trainer returns, the best by the model selection unless overridden
+    fn select_best(&self, best: TaskResult) -> TaskResult {
+        best
+    }
+
+    /// Adds anything specific to the mode to the training result
+    fn finish(&self, result: TrainingResult) -> TrainingResult {
+        result
+    }
 
     fn omit_event(&self, event: &TrainingEvent) {
         // TODO: logging?
         if let Some(observer) = self.get_config().observer.as_ref() {
             observer.as_ref()(event);
         }
+        if let Some(sender) = self.get_config().event_sender.as_ref() {
+            sender.send(OwnedTrainingEvent::from(event));
+        }
     }
 
     fn gen_net(&self, params: &mut dyn ParamFactory) -> Net {
         let mut net: Net = self.get_config().net_config_factory.as_ref()(params).create_net();
-        net.initialize_weights(&mut self.get_config().initializer.clone());
+        match &self.get_config().pretrained {
+            Some(pretrained) => {
+                if let Err(err) = pretrained.initialize(&mut net) {
+                    panic!("can't initialize generated net: {}", err);
+                }
+            },
+            None => net.initialize_weights(&mut self.get_config().initializer.clone()),
+        }
         net
     }
 
@@ -127,26 +473,74 @@ trait TrainerImpl {
 
         let task_id = format!("backprop_{}", task_id);
 
-        let mut params = optimizer.next_parameters(task_id.as_str());
+        let mut params = RecordingParamFactory::new(optimizer.next_parameters(task_id.as_str()));
 
-        let net = initial_state.unwrap_or_else(|| self.gen_net(params.as_mut()));
+        let net = initial_state.unwrap_or_else(|| self.gen_net(&mut params));
 
-        let backprop_options: BackpropOptions = self.get_config().backprop_options_factory.as_ref()(params.as_mut());
+        let fill_options = |options: &mut BackpropOptions, seed_id: &str| {
+            if options.validation_set.is_none() {
+                options.validation_set = self.get_config().validation_data_set.clone();
+            }
+            if self.get_config().distillation.is_some() {
+                options.error_fn = ErrorFn::BinaryCrossEntropy;
+            }
+            if options.seed.is_none() {
+                options.seed = self.get_config().seed.as_ref().map(|seed| format!("{}/{}", seed, seed_id));
+            }
+        };
+        let op = match &self.get_config().curriculum {
+            Some(stages) => TaskOp::Curriculum(stages.iter().enumerate()
+                .map(|(index, stage)| {
+                    let mut stage = stage.clone();
+                    fill_options(&mut stage.options, &format!("{}/stage_{}", task_id, index));
+                    stage
+                })
+                .collect()),
+            None => {
+                let mut backprop_options: BackpropOptions = self.get_config().backprop_options_factory.as_ref()(&mut params);
+                fill_options(&mut backprop_options, &task_id);
+                TaskOp::Backprop(Box::new(backprop_options))
+            },
+        };
 
         Task {
             task_id,
             data_set,
             net,
-            op: TaskOp::Backprop(backprop_options)
+            op,
+            evaluation_data_sets: self.get_config().evaluation_data_sets.clone(),
+            control: self.get_config().control.clone(),
+            params: params.into_sampled(),
+            accuracy_thresholds: self.get_config().global_completion_fn.accuracy_thresholds(),
         }
 
     }
 
-    fn train(&mut self, ctrl_master: ExecutorControlMaster) -> Result<TrainingResult, Box<dyn Error>> {
+    fn train(&mut self, ctrl_master: ExecutorControlMaster, snapshot: Option<TrainerSnapshot>) -> Result<TrainingResult, Box<dyn Error>> {
 
-        let start_time = SystemTime::now();
-        let epoch: usize = 0;
+        let mut start_time = SystemTime::now();
+        let mut task_counter: usize = 0;
         let mut best: Option<TaskResult> = None;
+        let mut top_results: Vec<TaskResult> = Vec::new();
+        let mut trials: Vec<TrialSummary> = Vec::new();
+        let mut results_since_snapshot: usize = 0;
+        // the number of submitted tasks without a result
+        let mut in_flight: usize = 0;
+        let max_in_flight = self.get_config().max_concurrent_tasks
+            .or_else(|| self.get_config().executor.num_workers())
+            .unwrap_or(usize::MAX)
+            .max(1);
+
+        if let Some(snapshot) = snapshot {
+            start_time -= snapshot.elapsed;
+            task_counter = snapshot.task_counter;
+            best = snapshot.best;
+            top_results = snapshot.top_results;
+            trials = snapshot.trials;
+            self.restore_optimizer_state(snapshot.optimizer_state)?;
+        }
+
+        let wake_up = self.get_config().control.watch();
 
         'train: loop {
 
@@ -163,12 +557,19 @@ trait TrainerImpl {
                             });
                         },
                         ExecutorEvent::TaskResult(result) => {
+                            in_flight -= 1;
+                            let result = *result;
                             self.handle_result(&result);
                             self.omit_event(&TrainingEvent::TaskResult(&result));
+                            trials.push(TrialSummary::from(&result));
+                            results_since_snapshot += 1;
+                            if let Some(k) = self.keep_top_k() {
+                                insert_ranked(&mut top_results, &result, k, self.get_config().model_selection);
+                            }
                             best = Some(match best {
                                 None => result,
                                 Some(best) => {
-                                    if result.error_stats.mean() < best.error_stats.mean() {
+                                    if is_better_result(&result, &best, self.get_config().model_selection) {
                                         result
                                     } else {
                                         best
@@ -177,6 +578,7 @@ trait TrainerImpl {
                             });
                         },
                         ExecutorEvent::ExecutorError { task_id, executor_id, error} => {
+                            in_flight -= 1;
                             // TODO?
                             eprintln!("Error: {:?}", error);
                         }
@@ -186,38 +588,204 @@ trait TrainerImpl {
                     }
                 }
 
+                if let Some(snapshot_path) = &self.get_config().snapshot_path {
+                    if results_since_snapshot > 0 && results_since_snapshot >= self.get_config().snapshot_interval {
+                        TrainerSnapshot {
+                            task_counter,
+                            elapsed: SystemTime::now().duration_since(start_time)?,
+                            optimizer_state: self.save_optimizer_state()?,
+                            initializer: self.get_config().initializer.clone(),
+                            trials: trials.clone(),
+                            best: best.clone(),
+                            top_results: top_results.clone(),
+                            seed: self.get_config().seed.clone(),
+                        }.save(snapshot_path)?;
+                        results_since_snapshot = 0;
+                    }
+                }
+
+                // once cancelled, wait for the running tasks to stop and keep the best result
+                if self.get_config().control.is_cancelled() {
+                    if in_flight == 0 {
+                        break 'train;
+                    }
+                    ctrl_master.wait(false, &wake_up, COMPLETION_CHECK_INTERVAL);
+                    continue 'wait;
+                }
+
                 // check if we should stop training
-                if let Some(best) = &best {
-                    if self.get_config().global_completion_fn.should_stop_training(epoch, start_time, &best.error_stats) {
+                if let Some(max_tasks) = self.max_tasks() {
+                    if task_counter >= max_tasks && in_flight == 0 {
+                        break 'train;
+                    }
+                } else if let Some(best) = &best {
+                    // to the trainer, each trial is an epoch
+                    let epoch = trials.len().saturating_sub(1);
+                    let batch_count = trials.iter().map(|t| t.batch_count).sum();
+                    if self.get_config().global_completion_fn.should_stop_training(epoch, batch_count, start_time, &best.error_stats, &best.accuracies) {
                         break 'train;
                     }
                 }
 
-                // check if an executor is waiting
-                if ctrl_master.has_waiting_executor() {
+                // check if an executor is waiting, unless paused or enough tasks are running
+                let tasks_left = self.max_tasks().is_none_or(|max_tasks| task_counter < max_tasks);
+                let can_submit = !self.get_config().control.is_paused() && tasks_left && in_flight < max_in_flight;
+                if can_submit && ctrl_master.has_waiting_executor() {
                     break 'wait;
-                } else {
-                    thread::sleep(Duration::from_millis(50));
                 }
+                // wakes up for the next event, executor or control change
+                ctrl_master.wait(can_submit, &wake_up, COMPLETION_CHECK_INTERVAL);
 
             }
 
             // send next task to execute
-            let task = self.next_task(epoch);
+            let task = self.next_task(task_counter);
+            task_counter += 1;
             self.omit_event(&TrainingEvent::TaskSubmit(&task));
             ctrl_master.send_task(task)?;
+            in_flight += 1;
 
         }
 
-        let best = best.unwrap();
+        let best = self.select_best(best.ok_or(TrainingError::CancelledWithoutResult)?);
+        let mut net = best.net;
+        net.set_schema(self.get_config().data_set.schema().cloned());
+        for result in &mut top_results {
+            result.net.set_schema(self.get_config().data_set.schema().cloned());
+        }
+        let ema_net = best.ema_net.map(|mut ema_net| {
+            ema_net.set_schema(self.get_config().data_set.schema().cloned());
+            ema_net
+        });
+
+        // a cancelled trainer returns right away
+        let evaluations = match &self.get_config().final_evaluation {
+            Some(options) if !self.get_config().control.is_cancelled() => self.evaluate(&ctrl_master, &net, options)?,
+            _ => Vec::new(),
+        };
 
-        Ok(TrainingResult {
-            net: best.net,
+        Ok(self.finish(TrainingResult {
+            net,
             error_stats: best.error_stats,
+            accuracy_stats: best.accuracy_stats,
             duration: SystemTime::now().duration_since(start_time)?,
-        })
+            trials,
+            evaluations,
+            history: best.history,
+            top_results,
+            ensemble: None,
+            ema_net,
+            cross_validation: Vec::new(),
+        }))
 
     }
+
+    /// Evaluates the net against the train, validation and test data sets by partitioning each
+    /// and distributing the partitions across executor workers, then merging the stats.
+    fn evaluate(&self, ctrl_master: &ExecutorControlMaster, net: &Net, options: &FinalEvaluationOptions) -> Result<Vec<DataSetEvaluation>, Box<dyn Error>> {
+
+        let config = self.get_config();
+        let mut data_sets = vec![("train", &config.data_set)];
+        if let Some(data_set) = &config.validation_data_set {
+            data_sets.push(("validation", data_set));
+        }
+        if let Some(data_set) = &config.test_data_set {
+            data_sets.push(("test", data_set));
+        }
+        for (name, data_set) in &config.evaluation_data_sets {
+            data_sets.push((name.as_str(), data_set));
+        }
+
+        let mut evaluations: Vec<DataSetEvaluation> = data_sets.iter()
+            .map(|(name, _)| DataSetEvaluation::new(name))
+            .collect();
+
+        // maps the ids of pending tasks to the index of their evaluation
+        let mut pending: HashMap<String, usize> = HashMap::new();
+
+        let evaluate_options = EvaluateOptions {
+            error_fn: options.error_fn,
+            accuracy_threshold: options.accuracy_threshold,
+            ranked_output: options.ranked_output,
+        };
+
+        for (index, (name, data_set)) in data_sets.iter().enumerate() {
+            let partitions = if options.partitions > 1 && options.partitions < data_set.num_rows() {
+                data_set.partition(options.partitions)
+            } else {
+                vec![(*data_set).clone()]
+            };
+            for (partition_index, partition) in partitions.into_iter().enumerate() {
+                let task = Task {
+                    task_id: format!("evaluate_{}_{}", name, partition_index),
+                    data_set: partition,
+                    net: net.clone(),
+                    op: TaskOp::Evaluate(evaluate_options.clone()),
+                    evaluation_data_sets: Vec::new(),
+                    control: config.control.clone(),
+                    params: Vec::new(),
+                    accuracy_thresholds: Vec::new(),
+                };
+                pending.insert(task.task_id.clone(), index);
+                self.omit_event(&TrainingEvent::TaskSubmit(&task));
+                ctrl_master.send_task(task)?;
+            }
+        }
+
+        while !pending.is_empty() {
+            for event in ctrl_master.try_get_events() {
+                match event {
+                    ExecutorEvent::TaskAccepted { task_id, executor_id } => {
+                        self.omit_event(&TrainingEvent::TaskAccepted {
+                            task_id,
+                            executor_id,
+                        });
+                    },
+                    ExecutorEvent::TaskResult(result) => {
+                        // results of training tasks still in flight are ignored
+                        if let Some(index) = pending.remove(&result.task_id) {
+                            evaluations[index].merge(&result);
+                        }
+                        self.omit_event(&TrainingEvent::TaskResult(&result));
+                    },
+                    ExecutorEvent::ExecutorError { task_id, executor_id: _, error } => {
+                        if pending.contains_key(&task_id) {
+                            return Err(Box::new(error));
+                        }
+                        eprintln!("Error: {:?}", error);
+                    },
+                    ExecutorEvent::TaskUpdate(update) => {
+                        self.omit_event(&TrainingEvent::TaskUpdate(update));
+                    },
+                }
+            }
+            if !pending.is_empty() {
+                ctrl_master.wait(false, &crossbeam::channel::never(), COMPLETION_CHECK_INTERVAL);
+            }
+        }
+
+        Ok(evaluations)
+    }
+}
+
+/// Inserts a clone of the result into the ranked results if it's among the best k
+fn insert_ranked(ranked: &mut Vec<TaskResult>, result: &TaskResult, k: usize, selection: ModelSelection) {
+    let position = ranked.iter()
+        .position(|other| is_better_result(result, other, selection))
+        .unwrap_or(ranked.len());
+    if position < k {
+        ranked.insert(position, result.clone());
+        ranked.truncate(k);
+    }
+}
+
+/// Results of diverged tasks are only ever better than other diverged results
+fn is_better_result(result: &TaskResult, best: &TaskResult, selection: ModelSelection) -> bool {
+    match (result.outcome, best.outcome) {
+        (TaskOutcome::Completed, TaskOutcome::DivergenceDetected) => true,
+        (TaskOutcome::DivergenceDetected, TaskOutcome::Completed) => false,
+        _ => selection.selection_error(result) < selection.selection_error(best),
+    }
 }
 
 impl TrainerImpl for StandardTrainerImpl<'_> {
@@ -231,7 +799,15 @@ impl TrainerImpl for StandardTrainerImpl<'_> {
     }
 
     fn next_task(&mut self, task_id: usize) -> Task {
-        self.gen_backprop_task(task_id, self.optimizer.borrow_mut().as_mut(), self.config.data_set.clone(), None)
+        self.gen_backprop_task(task_id, self.optimizer.borrow_mut().as_mut(), self.config.data_set.clone(), self.config.initial_state.clone())
+    }
+
+    fn save_optimizer_state(&self) -> Result<serde_json::Value, Box<dyn Error>> {
+        self.optimizer.borrow().save_state()
+    }
+
+    fn restore_optimizer_state(&mut self, state: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        self.optimizer.borrow_mut().restore_state(state)
     }
 }
 
@@ -248,4 +824,748 @@ impl<'a> StandardTrainerImpl<'a> {
             optimizer
         }
     }
-}
\ No newline at end of file
+}
+
+impl TrainerImpl for EnsembleTrainerImpl<'_> {
+
+    fn get_config(&self) -> &NetTrainer {
+        self.config
+    }
+
+    fn handle_result(&mut self, result: &TaskResult) {
+        self.optimizer.borrow_mut().report(result);
+        if result.outcome == TaskOutcome::DivergenceDetected {
+            self.diverged += 1;
+        }
+    }
+
+    fn next_task(&mut self, task_id: usize) -> Task {
+        let sample = self.config.data_set.bootstrap_sample(self.sample_fraction, &format!("{}/bootstrap_{}", self.sample_seed, task_id));
+        self.gen_backprop_task(task_id, self.optimizer.borrow_mut().as_mut(), sample, self.config.initial_state.clone())
+    }
+
+    fn save_optimizer_state(&self) -> Result<serde_json::Value, Box<dyn Error>> {
+        self.optimizer.borrow().save_state()
+    }
+
+    fn restore_optimizer_state(&mut self, state: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        self.optimizer.borrow_mut().restore_state(state)
+    }
+
+    fn max_tasks(&self) -> Option<usize> {
+        Some(self.members + self.diverged.min(self.members))
+    }
+
+    fn keep_top_k(&self) -> Option<usize> {
+        Some(2 * self.members)
+    }
+
+    /// The ensemble of the members which didn't diverge, if any
+    fn finish(&self, result: TrainingResult) -> TrainingResult {
+        let nets: Vec<Net> = result.top_results.iter()
+            .filter(|result| result.outcome == TaskOutcome::Completed)
+            .take(self.members)
+            .map(|result| result.net.clone())
+            .collect();
+        let ensemble = if nets.is_empty() { None } else { Some(Ensemble::new(nets)) };
+        TrainingResult { ensemble, ..result }
+    }
+}
+
+struct EnsembleTrainerImpl<'a> {
+    config: &'a NetTrainer,
+    optimizer: RefCell<Box<dyn Optimizer>>,
+    members: usize,
+    sample_fraction: Float,
+    /// Seeds the bootstrap samples, from the trainer seed if it has one
+    sample_seed: String,
+    /// The number of members which diverged, each of which is retrained
+    diverged: usize,
+}
+
+impl<'a> EnsembleTrainerImpl<'a> {
+    fn new(config: &'a NetTrainer, members: usize, sample_fraction: Float) -> Self {
+        assert!(members > 0, "an ensemble needs at least one member");
+        EnsembleTrainerImpl {
+            config,
+            optimizer: RefCell::new(config.optimizer_factory.as_ref()()),
+            members,
+            sample_fraction,
+            sample_seed: config.seed.clone().unwrap_or_else(|| rand::random::<u64>().to_string()),
+            diverged: 0,
+        }
+    }
+}
+
+impl TrainerImpl for CrossValidationTrainerImpl<'_> {
+
+    fn get_config(&self) -> &NetTrainer {
+        self.config
+    }
+
+    fn handle_result(&mut self, result: &TaskResult) {
+        self.optimizer.borrow_mut().report(result);
+        let selection = self.config.model_selection;
+        if let Some(&(index, fold)) = self.task_folds.get(&result.task_id) {
+            let configuration = &mut self.configurations[index];
+            configuration.fold_errors[fold] = Some((result.task_id.clone(), selection.selection_error(result)));
+            if configuration.best_fold.as_ref().is_none_or(|best| is_better_result(result, best, selection)) {
+                configuration.best_fold = Some(result.clone());
+            }
+        }
+    }
+
+    fn next_task(&mut self, task_id: usize) -> Task {
+        let (index, fold) = (task_id / self.folds.len(), task_id % self.folds.len());
+        let (train, validation) = self.folds[fold].clone();
+        let mut task = if fold == 0 {
+            self.gen_backprop_task(task_id, self.optimizer.borrow_mut().as_mut(), train, self.config.initial_state.clone())
+        } else {
+            let params = self.configurations[index].params.clone();
+            self.gen_backprop_task(task_id, &mut ReplayOptimizer::new(params), train, self.config.initial_state.clone())
+        };
+        if let TaskOp::Backprop(options) = &mut task.op {
+            options.validation_set = Some(validation);
+        }
+        if fold == 0 {
+            self.configurations.push(FoldedConfiguration {
+                params: task.params.clone(),
+                fold_errors: vec![None; self.folds.len()],
+                best_fold: None,
+            });
+        }
+        self.task_folds.insert(task.task_id.clone(), (index, fold));
+        task
+    }
+
+    fn save_optimizer_state(&self) -> Result<serde_json::Value, Box<dyn Error>> {
+        self.optimizer.borrow().save_state()
+    }
+
+    fn restore_optimizer_state(&mut self, state: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        self.optimizer.borrow_mut().restore_state(state)
+    }
+
+    fn select_best(&self, best: TaskResult) -> TaskResult {
+        self.summaries().into_iter().next()
+            .and_then(|(index, _)| self.configurations[index].best_fold.clone())
+            .unwrap_or(best)
+    }
+
+    fn finish(&self, result: TrainingResult) -> TrainingResult {
+        let cross_validation = self.summaries().into_iter().map(|(_, summary)| summary).collect();
+        TrainingResult { cross_validation, ..result }
+    }
+}
+
+/// Picks configurations by their validation error over the folds of the data set. Fold results
+/// aren't included in trainer snapshots.
+struct CrossValidationTrainerImpl<'a> {
+    config: &'a NetTrainer,
+    optimizer: RefCell<Box<dyn Optimizer>>,
+    /// The training and validation data set of each fold
+    folds: Vec<(PreparedDataSet, PreparedDataSet)>,
+    configurations: Vec<FoldedConfiguration>,
+    /// The configuration and fold of each task
+    task_folds: HashMap<String, (usize, usize)>,
+}
+
+struct FoldedConfiguration {
+    params: Vec<SampledParam>,
+    /// The task and validation error of each fold, once it has a result
+    fold_errors: Vec<Option<(String, f64)>>,
+    best_fold: Option<TaskResult>,
+}
+
+impl<'a> CrossValidationTrainerImpl<'a> {
+
+    fn new(config: &'a NetTrainer, folds: usize) -> Self {
+        assert!(folds >= 2, "cross-validation needs at least two folds");
+        let seed = config.seed.clone().unwrap_or_else(|| rand::random::<u64>().to_string());
+        CrossValidationTrainerImpl {
+            config,
+            optimizer: RefCell::new(config.optimizer_factory.as_ref()()),
+            folds: config.data_set.k_folds(folds, &format!("{}/folds", seed)),
+            configurations: Vec::new(),
+            task_folds: HashMap::new(),
+        }
+    }
+
+    /// The summaries of the configurations with a result for every fold, best first, with the
+    /// index of each configuration
+    fn summaries(&self) -> Vec<(usize, CrossValidationSummary)> {
+        let mut summaries: Vec<(usize, CrossValidationSummary)> = self.configurations.iter().enumerate()
+            .filter(|(_, configuration)| configuration.fold_errors.iter().all(Option::is_some))
+            .map(|(index, configuration)| {
+                let mut fold_error_stats = Stats::new();
+                let mut task_ids = Vec::new();
+                for (task_id, error) in configuration.fold_errors.iter().flatten() {
+                    fold_error_stats.report(*error as Float);
+                    task_ids.push(task_id.clone());
+                }
+                (index, CrossValidationSummary { params: configuration.params.clone(), fold_error_stats, task_ids })
+            })
+            .collect();
+        summaries.sort_by(|(_, a), (_, b)| a.fold_error_stats.mean().total_cmp(&b.fold_error_stats.mean()));
+        summaries
+    }
+
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::{backprop_options, lines_2x2};
+    use crate::func::{ActivationFn, MiniBatchSize, LearningRateFn, GradientNoiseFn};
+    use std::future::Future;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use crate::layer::NetLayerConfig;
+    use crate::train::optimizer::ParamValue;
+
+    fn test_trainer_builder() -> NetTrainerBuilder {
+        let data_set = lines_2x2();
+        NetTrainerBuilder::default()
+            .data_set(data_set)
+            .net_config(NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid()))
+            .backprop_options(test_backprop_options())
+            .observer(Box::new(|_| {}))
+    }
+
+    fn test_backprop_options() -> BackpropOptions {
+        BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(10),
+            ..backprop_options()
+        }
+    }
+
+    #[test]
+    fn test_final_evaluation() {
+
+        let validation_data_set = lines_2x2();
+
+        let mut trainer = test_trainer_builder()
+            .executor(Executor::Local(2))
+            .validation_data_set(validation_data_set)
+            .final_evaluation(FinalEvaluationOptions {
+                error_fn: ErrorFn::SquaredError,
+                accuracy_threshold: Some(0.5),
+                partitions: 3,
+                ranked_output: Some(1),
+            })
+            .build()
+            .unwrap();
+
+        let result = trainer.execute().unwrap();
+
+        assert_eq!(result.evaluations.len(), 2);
+        assert_eq!(result.evaluations[0].name, "train");
+        assert_eq!(result.evaluations[1].name, "validation");
+        for evaluation in &result.evaluations {
+            assert_eq!(evaluation.error_stats.count(), 11);
+            assert_eq!(evaluation.accuracy_stats.as_ref().unwrap().count(), 11);
+            // the partitions are ranked together
+            let ranking_stats = evaluation.ranking_stats.as_ref().unwrap();
+            assert_eq!(ranking_stats.count(), 11);
+            assert!(ranking_stats.roc_auc().is_some_and(|auc| (0.0..=1.0).contains(&auc)));
+        }
+        assert!((result.evaluations[0].error_stats.mean() - result.evaluations[1].error_stats.mean()).abs() < 0.00001);
+
+    }
+
+    #[test]
+    fn test_global_accuracy_completion() {
+
+        // the stage completion fn doesn't depend on accuracy, the global one does
+        let mut trainer = test_trainer_builder()
+            .global_completion_fn(CompletionFn::any(vec![
+                CompletionFn::stop_after_epoch(20),
+                CompletionFn::stop_at_accuracy(0.0, 0.5),
+            ]))
+            .keep_top_k(1)
+            .build()
+            .unwrap();
+
+        let result = trainer.execute().unwrap();
+        assert!(result.trials.len() < 20);
+        let accuracies = &result.top_results[0].accuracies;
+        assert_eq!(accuracies.len(), 1);
+        assert_eq!((accuracies[0].0, accuracies[0].1.count()), (0.5, 11));
+        assert!(result.top_results[0].accuracy_stats.is_none());
+
+    }
+
+    #[test]
+    fn test_pretrained() {
+
+        let mut pretrained = NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid()).create_net();
+        pretrained.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("pretrained"));
+
+        // with a negligible learning rate, the trained net keeps the pretrained weights
+        let mut trainer = test_trainer_builder()
+            .backprop_options(BackpropOptions {
+                completion_fn: CompletionFn::stop_after_epoch(1),
+                learning_rate_fn: LearningRateFn::Constant(1e-6),
+                ..backprop_options()
+            })
+            .pretrained(FromPretrained::new(pretrained.clone()))
+            .build()
+            .unwrap();
+
+        let result = trainer.execute().unwrap();
+        assert!(result.net.approx_eq(&pretrained, 1e-5));
+
+    }
+
+    #[test]
+    fn test_evaluation_data_sets() {
+
+        let data_set = lines_2x2();
+        let mut partitions = data_set.partition(2);
+
+        let mut trainer = test_trainer_builder()
+            .global_completion_fn(CompletionFn::stop_after_epoch(3))
+            .evaluation_data_set("holdout", partitions.remove(0))
+            .evaluation_data_set("edge-cases", partitions.remove(0))
+            .model_selection(ModelSelection::EvaluationWorstCase)
+            .final_evaluation(FinalEvaluationOptions {
+                error_fn: ErrorFn::SquaredError,
+                accuracy_threshold: None,
+                partitions: 1,
+                ranked_output: None,
+            })
+            .build()
+            .unwrap();
+
+        let result = trainer.execute().unwrap();
+
+        assert!(result.trials.len() >= 3);
+        for trial in &result.trials {
+            let names: Vec<&str> = trial.evaluations.iter().map(|e| e.name.as_str()).collect();
+            assert_eq!(names, ["holdout", "edge-cases"]);
+            assert_eq!(trial.evaluations[0].error_stats.count() + trial.evaluations[1].error_stats.count(), 11);
+        }
+
+        let names: Vec<&str> = result.evaluations.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["train", "holdout", "edge-cases"]);
+
+        // the best net has the lowest worst-case error of all trials
+        let worst_error = |evaluations: &[DataSetEvaluation]| evaluations.iter()
+            .map(|e| e.error_stats.mean())
+            .fold(f64::NEG_INFINITY, f64::max);
+        let best_worst_error = worst_error(&result.evaluations[1..]);
+        assert!(result.trials.iter().all(|t| worst_error(&t.evaluations) >= best_worst_error - 0.00001));
+
+    }
+
+    #[test]
+    fn test_validation_set() {
+
+        let data_set = lines_2x2();
+        let mut partitions = data_set.partition(2);
+        let (train, validation) = (partitions.remove(0), partitions.remove(0));
+        let validation_rows = validation.num_rows();
+
+        let mut trainer = test_trainer_builder()
+            .data_set(train)
+            .validation_data_set(validation)
+            .build()
+            .unwrap();
+
+        // the errors of each trial are those of the held-out rows
+        let result = trainer.execute().unwrap();
+        assert!(!result.trials.is_empty());
+        assert!(result.trials.iter().all(|t| t.error_stats.count() as usize == validation_rows));
+        assert_eq!(result.error_stats.count() as usize, validation_rows);
+        assert!(result.history.iter().all(|m| m.validation_error.is_some()));
+
+    }
+
+    #[test]
+    fn test_history() {
+
+        let mut trainer = test_trainer_builder().build().unwrap();
+        let result = trainer.execute().unwrap();
+
+        // the test options stop each stage after 10 epochs
+        let epochs: Vec<usize> = result.history.iter().map(|m| m.epoch).collect();
+        assert_eq!(epochs, (1..=10).collect::<Vec<_>>());
+        assert!(result.history.iter().all(|m| m.validation_error.is_none() && m.learning_rate == 0.5));
+        assert!(result.history.windows(2).all(|w| w[0].elapsed <= w[1].elapsed));
+        assert_eq!(result.history.last().unwrap().train_error, result.error_stats.mean());
+
+    }
+
+    #[test]
+    fn test_initial_net() {
+
+        let mut initial = NetConfig::new_fully_connected(4, 2, [5], ActivationFn::standard_logistic_sigmoid()).create_net();
+        initial.initialize_weights(&mut RandomNetInitializer::new_standard_with_seed("initial"));
+
+        // the initial net replaces the configured architecture, and barely changes with a
+        // negligible learning rate
+        let mut trainer = test_trainer_builder()
+            .backprop_options(BackpropOptions {
+                completion_fn: CompletionFn::stop_after_epoch(1),
+                learning_rate_fn: LearningRateFn::Constant(1e-6),
+                ..backprop_options()
+            })
+            .initial_net(initial.clone())
+            .build()
+            .unwrap();
+
+        let result = trainer.execute().unwrap();
+        assert_eq!(result.net.get_config(), initial.get_config());
+        assert!(result.net.approx_eq(&initial, 1e-5));
+
+    }
+
+    #[test]
+    fn test_seed() {
+
+        let train = |seed: &str| {
+            let mut trainer = NetTrainerBuilder::default()
+                .data_set(lines_2x2())
+                .net_config(NetConfig::new_fully_connected(4, 2, [3], ActivationFn::standard_logistic_sigmoid()))
+                .backprop_options(BackpropOptions {
+                    completion_fn: CompletionFn::stop_after_epoch(20),
+                    mini_batch_size_fn: MiniBatchSize::Constant(std::num::NonZeroU32::new(3).unwrap()),
+                    gradient_noise_fn: Some(GradientNoiseFn::Annealed { eta: 0.1, gamma: 0.55 }),
+                    shuffle: true,
+                    ..backprop_options()
+                })
+                .seed(seed)
+                .observer(Box::new(|_| {}))
+                .build()
+                .unwrap();
+            // the number of trials depends on timing, but the first is always the same task
+            trainer.execute().unwrap().trials[0].error_stats.mean()
+        };
+
+        assert_eq!(train("reproducible"), train("reproducible"));
+        assert_ne!(train("reproducible"), train("other"));
+
+    }
+
+    #[test]
+    fn test_snapshot_seed() {
+
+        // an unseeded trainer still records the seed its tasks were seeded from
+        let path = std::env::temp_dir().join(format!("rust_neural_net_trainer_snapshot_{}.json", std::process::id()));
+        let mut trainer = test_trainer_builder().snapshot_path(&path).build().unwrap();
+        trainer.execute().unwrap();
+        let seed = TrainerSnapshot::load(&path).unwrap().seed.unwrap();
+
+        let mut restored = test_trainer_builder().snapshot_path(&path).restore(&path).build().unwrap();
+        restored.execute().unwrap();
+        assert_eq!(TrainerSnapshot::load(&path).unwrap().seed, Some(seed));
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+    #[test]
+    fn test_pause() {
+
+        let mut trainer = test_trainer_builder().build().unwrap();
+        let control = trainer.control();
+        control.pause();
+
+        let start_time = SystemTime::now();
+        let resumer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            control.resume();
+        });
+        trainer.execute().unwrap();
+        assert!(SystemTime::now().duration_since(start_time).unwrap() >= Duration::from_millis(300));
+        resumer.join().unwrap();
+
+    }
+
+    #[test]
+    fn test_cancel() {
+
+        let mut trainer = test_trainer_builder()
+            .backprop_options(BackpropOptions {
+                completion_fn: CompletionFn::stop_after_epoch(usize::MAX),
+                ..backprop_options()
+            })
+            .build()
+            .unwrap();
+
+        // the endless task stops once cancelled, and is the best so far
+        let control = trainer.control();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            control.cancel();
+        });
+        let result = trainer.execute().unwrap();
+        canceller.join().unwrap();
+        assert_eq!(result.trials.len(), 1);
+        assert!(result.trials[0].epoch > 0);
+        assert_eq!(result.error_stats.count(), 11);
+
+        let mut trainer = test_trainer_builder().build().unwrap();
+        trainer.control().cancel();
+        assert!(trainer.execute().is_err());
+
+    }
+
+    #[test]
+    fn test_execute_async() {
+
+        struct ThreadWaker(thread::Thread);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+            let mut context = Context::from_waker(&waker);
+            let mut future = Box::pin(future);
+            loop {
+                match future.as_mut().poll(&mut context) {
+                    Poll::Ready(output) => return output,
+                    Poll::Pending => thread::park(),
+                }
+            }
+        }
+
+        let (future, events) = test_trainer_builder().build().unwrap().execute_async();
+        let result = block_on(future).unwrap();
+        let results = events
+            .filter(|event| matches!(event, OwnedTrainingEvent::TaskResult(_)))
+            .count();
+        assert_eq!(results, result.trials.len());
+
+        // the events are a stream too, which ends with the trainer
+        #[cfg(feature = "futures")]
+        {
+            use futures_core::Stream;
+            use std::pin::Pin;
+            let (future, mut events) = test_trainer_builder().build().unwrap().execute_async();
+            let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+            let mut context = Context::from_waker(&waker);
+            let mut submitted = 0;
+            loop {
+                match Pin::new(&mut events).poll_next(&mut context) {
+                    Poll::Ready(Some(OwnedTrainingEvent::TaskSubmit { .. })) => submitted += 1,
+                    Poll::Ready(Some(_)) => {},
+                    Poll::Ready(None) => break,
+                    Poll::Pending => thread::park(),
+                }
+            }
+            assert_eq!(submitted, future.wait().unwrap().trials.len());
+        }
+
+        let trainer = test_trainer_builder().build().unwrap();
+        trainer.control().cancel();
+        let (future, events) = trainer.execute_async();
+        assert_eq!(future.wait().err().unwrap().to_string(), TrainingError::CancelledWithoutResult.to_string());
+        assert_eq!(events.count(), 0);
+
+    }
+
+    #[test]
+    fn test_max_concurrent_tasks() {
+
+        let in_flight = Arc::new(std::sync::Mutex::new((0, 0)));
+        let observed_in_flight = in_flight.clone();
+        let mut trainer = test_trainer_builder()
+            .executor(Executor::Local(4))
+            .max_concurrent_tasks(2)
+            .global_completion_fn(CompletionFn::stop_after_epoch(6))
+            .backprop_options(BackpropOptions {
+                completion_fn: CompletionFn::stop_after_duration(Duration::from_millis(150)),
+                ..backprop_options()
+            })
+            .observer(Box::new(move |event| {
+                let (current, max) = &mut *observed_in_flight.lock().unwrap();
+                match event {
+                    TrainingEvent::TaskSubmit(_) => *current += 1,
+                    TrainingEvent::TaskResult(_) => *current -= 1,
+                    _ => {},
+                }
+                *max = (*max).max(*current);
+            }))
+            .build()
+            .unwrap();
+
+        let result = trainer.execute().unwrap();
+        assert!(result.trials.len() >= 6);
+        assert_eq!(in_flight.lock().unwrap().1, 2);
+
+    }
+
+    #[test]
+    fn test_keep_top_k() {
+
+        let mut trainer = test_trainer_builder()
+            .net_config_factory(Box::new(|params: &mut dyn ParamFactory| {
+                let hidden = params.range_usize("hidden".to_string(), 2, 6);
+                NetConfig::new_fully_connected(4, 2, [hidden], ActivationFn::standard_logistic_sigmoid())
+            }))
+            .global_completion_fn(CompletionFn::stop_after_epoch(5))
+            .keep_top_k(3)
+            .seed("top_k")
+            .build()
+            .unwrap();
+        let result = trainer.execute().unwrap();
+
+        assert_eq!(result.top_results.len(), 3);
+        assert_eq!(result.top_results[0].error_stats.mean(), result.error_stats.mean());
+        assert!(result.top_results.windows(2).all(|w| w[0].error_stats.mean() <= w[1].error_stats.mean()));
+        for top in &result.top_results {
+            let hidden = match top.net.get_config().layers()[0] {
+                NetLayerConfig::FullyConnected(size, _) => size,
+                _ => unreachable!(),
+            };
+            assert_eq!(top.params, [SampledParam { key: Some("hidden".to_string()), value: ParamValue::Usize(hidden) }]);
+        }
+        assert_eq!(result.trials[0].params.len(), 1);
+
+    }
+
+    #[test]
+    fn test_ensemble_mode() {
+
+        let data_set = test_trainer_builder().data_set.unwrap();
+        let mut trainer = test_trainer_builder()
+            .mode(NetTrainerMode::Ensemble { members: 3, sample_fraction: 0.5 })
+            .seed("ensemble")
+            .build()
+            .unwrap();
+        let result = trainer.execute().unwrap();
+
+        assert_eq!(result.trials.len(), 3);
+        assert_eq!(result.top_results.len(), 3);
+        let ensemble = result.ensemble.unwrap();
+        assert_eq!(ensemble.members().len(), 3);
+        assert_eq!(ensemble.weights(), [1.0 / 3.0; 3]);
+        let (inputs, _) = data_set.row(0);
+        let average: Vec<Float> = (0..2)
+            .map(|output| ensemble.members().iter().map(|net| net.predict(inputs)[output]).sum::<Float>() / 3.0)
+            .collect();
+        let predicted = ensemble.predict(inputs);
+        assert!(predicted.iter().zip(&average).all(|(a, b)| (a - b).abs() < 1e-5));
+
+    }
+
+    #[test]
+    fn test_ensemble_retrains_diverged_members() {
+
+        // the first member diverges with infinite gradient noise
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut trainer = test_trainer_builder()
+            .mode(NetTrainerMode::Ensemble { members: 3, sample_fraction: 0.5 })
+            .seed("ensemble")
+            .backprop_options_factory(Box::new(move |_| {
+                let mut options = test_backprop_options();
+                if calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed) == 0 {
+                    options.gradient_noise_fn = Some(GradientNoiseFn::Annealed { eta: Float::INFINITY, gamma: 0.0 });
+                }
+                options
+            }))
+            .build()
+            .unwrap();
+        let result = trainer.execute().unwrap();
+
+        assert_eq!(result.trials.len(), 4);
+        assert_eq!(result.trials.iter().filter(|trial| trial.outcome == TaskOutcome::DivergenceDetected).count(), 1);
+        let ensemble = result.ensemble.unwrap();
+        assert_eq!(ensemble.members().len(), 3);
+        assert!(ensemble.members().iter().all(|net| net.get_weights().get_buffer().iter().all(|w| w.is_finite())));
+
+    }
+
+    #[test]
+    fn test_cross_validation_mode() {
+
+        let mut trainer = test_trainer_builder()
+            .net_config_factory(Box::new(|params: &mut dyn ParamFactory| {
+                let hidden = params.range_usize("hidden".to_string(), 2, 6);
+                NetConfig::new_fully_connected(4, 2, [hidden], ActivationFn::standard_logistic_sigmoid())
+            }))
+            .mode(NetTrainerMode::CrossValidation { folds: 3 })
+            .global_completion_fn(CompletionFn::stop_after_epoch(9))
+            .seed("cross_validation")
+            .build()
+            .unwrap();
+        let result = trainer.execute().unwrap();
+
+        assert_eq!(result.trials.len(), 9);
+        assert_eq!(result.cross_validation.len(), 3);
+        assert!(result.cross_validation.windows(2).all(|w| w[0].fold_error_stats.mean() <= w[1].fold_error_stats.mean()));
+        let best = &result.cross_validation[0];
+        assert_eq!(best.fold_error_stats.count(), 3);
+        // every fold of a configuration has the same params
+        for task_id in &best.task_ids {
+            let trial = result.trials.iter().find(|trial| &trial.task_id == task_id).unwrap();
+            assert_eq!(trial.params, best.params);
+        }
+        let best_fold = best.task_ids.iter()
+            .map(|task_id| result.trials.iter().find(|trial| &trial.task_id == task_id).unwrap())
+            .min_by(|a, b| a.error_stats.mean().total_cmp(&b.error_stats.mean()))
+            .unwrap();
+        assert_eq!(result.error_stats.mean(), best_fold.error_stats.mean());
+
+    }
+
+    #[test]
+    fn test_distillation() {
+
+        let teacher = test_trainer_builder()
+            .net_config(NetConfig::new_fully_connected(4, 2, [6], ActivationFn::standard_logistic_sigmoid()))
+            .seed("teacher")
+            .build()
+            .unwrap()
+            .execute()
+            .unwrap()
+            .net;
+        let distillation = Distillation::new(teacher, 2.0);
+        let data_set = test_trainer_builder().data_set.unwrap();
+        let soft_targets = distillation.soft_targets(&data_set);
+
+        let mut trainer = test_trainer_builder()
+            .net_config(NetConfig::new_fully_connected(4, 2, [2], ActivationFn::standard_logistic_sigmoid()))
+            .distillation(distillation)
+            .seed("student")
+            .build()
+            .unwrap();
+        let mut result = trainer.execute().unwrap();
+
+        // the student is trained, and reports its error, against the soft targets
+        let student_error = result.net.get_training_context().compute_error_for_batch(&soft_targets, &ErrorFn::BinaryCrossEntropy);
+        assert_eq!(student_error.mean(), result.error_stats.mean());
+        assert_eq!(trainer.data_set.iter().collect::<Vec<_>>(), data_set.iter().collect::<Vec<_>>());
+
+    }
+
+    #[test]
+    fn test_curriculum() {
+
+        let data_set = test_trainer_builder().data_set.unwrap();
+        let options = BackpropOptions {
+            completion_fn: CompletionFn::stop_after_epoch(1),
+            ..backprop_options()
+        };
+        let mut trainer = test_trainer_builder()
+            .curriculum(vec![
+                CurriculumStage::new(data_set.take_n(4, "easy"), options.clone(), CompletionFn::stop_after_epoch(3)),
+                CurriculumStage::new(data_set.clone(), options, CompletionFn::stop_after_epoch(5)),
+            ])
+            .build()
+            .unwrap();
+        let mut result = trainer.execute().unwrap();
+
+        // the history runs on across the stages, with a batch per epoch
+        assert_eq!(result.trials[0].epoch, 8);
+        assert_eq!(result.history.iter().map(|metrics| metrics.epoch).collect::<Vec<_>>(), (1..=8).collect::<Vec<_>>());
+        assert_eq!(result.history.iter().map(|metrics| metrics.batch).collect::<Vec<_>>(), (1..=8).collect::<Vec<_>>());
+        let expected = result.net.get_training_context().compute_error_for_batch(&data_set, &ErrorFn::SquaredError);
+        assert_eq!(result.error_stats.mean(), expected.mean());
+
+    }
+
+}
@@ -2,9 +2,10 @@ use std::u128;
 use std::collections::HashSet;
 use std::hash::Hash;
 use std::slice;
+use crate::Float;
 
 #[inline(always)]
-pub fn square_f32(n: f32) -> f32 {
+pub fn square(n: Float) -> Float {
     n * n
 }
 
@@ -58,6 +59,95 @@ pub fn split_slice<T>(slice: &[T], left: usize, right: usize) -> (&[T], &[T]) {
 }
 
 
+/// Serde helpers for floats which may be NaN, which formats like JSON cannot represent.
+/// NaN values are written as `None` and read back as NaN.
+pub mod serde_nan {
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use num::Float;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where T: Float + Serialize, S: Serializer
+    {
+        if value.is_nan() {
+            None::<T>.serialize(serializer)
+        } else {
+            Some(*value).serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+        where T: Float + Deserialize<'de>, D: Deserializer<'de>
+    {
+        Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_else(T::nan))
+    }
+}
+
+/// Serde helpers for slices of floats which may be NaN or infinite, e.g. the weights of a
+/// diverged net. Finite values are written as numbers, the others as the strings "NaN", "inf"
+/// and "-inf", so they read back exactly.
+pub mod serde_non_finite {
+    use std::fmt;
+    use serde::{Serializer, Deserialize, Deserializer};
+    use serde::de::{self, Visitor};
+    use crate::Float;
+
+    pub fn serialize<S: Serializer>(values: &[Float], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(|&value| MaybeFinite(value)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Box<[Float]>, D::Error> {
+        let values = Vec::<MaybeFinite>::deserialize(deserializer)?;
+        Ok(values.into_iter().map(|value| value.0).collect())
+    }
+
+    struct MaybeFinite(Float);
+
+    impl serde::Serialize for MaybeFinite {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            if self.0.is_finite() {
+                self.0.serialize(serializer)
+            } else {
+                serializer.serialize_str(&self.0.to_string())
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for MaybeFinite {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(MaybeFiniteVisitor)
+        }
+    }
+
+    struct MaybeFiniteVisitor;
+
+    impl<'de> Visitor<'de> for MaybeFiniteVisitor {
+        type Value = MaybeFinite;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a number, \"NaN\", \"inf\" or \"-inf\"")
+        }
+
+        fn visit_f64<E: de::Error>(self, value: f64) -> Result<MaybeFinite, E> {
+            Ok(MaybeFinite(value as Float))
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<MaybeFinite, E> {
+            Ok(MaybeFinite(value as Float))
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<MaybeFinite, E> {
+            Ok(MaybeFinite(value as Float))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<MaybeFinite, E> {
+            match value {
+                "NaN" | "inf" | "-inf" => Ok(MaybeFinite(value.parse().unwrap())),
+                _ => Err(E::invalid_value(de::Unexpected::Str(value), &self)),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,126 @@
+//! Trains tiny nets on the example data sets with fixed seeds and asserts that they converge.
+//! The examples in `examples/` walk through the same tasks.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_neural_net::Float;
+use rust_neural_net::data::PreparedDataSet;
+use rust_neural_net::func::{ActivationFn, CompletionFn, ErrorFn, LearningRateFn, MiniBatchSize};
+use rust_neural_net::initializer::RandomNetInitializer;
+use rust_neural_net::net::NetConfig;
+use rust_neural_net::train::{
+    BackpropOptions, Executor, NetTrainerBuilder, ParamFactory, RandomOptimizer, TrainingEvent,
+};
+
+fn backprop_options(max_epoch: usize, learning_rate: Float) -> BackpropOptions {
+    BackpropOptions {
+        completion_fn: CompletionFn::stop_after_epoch(max_epoch),
+        mini_batch_size_fn: MiniBatchSize::Full,
+        learning_rate_fn: LearningRateFn::Constant(learning_rate),
+        error_fn: ErrorFn::SquaredError,
+        gradient_noise_fn: None,
+        gradient_noise_layer_scales: Vec::new(),
+        shuffle: false,
+        multi_threading: None,
+        validation_set: None,
+        update_interval: None,
+        check_divergence: false,
+        seed: None,
+        track_norms: false,
+        ema_decay: None,
+        mixed_precision: None,
+        batch_threads: None,
+    }
+}
+
+#[test]
+fn xor_converges() {
+
+    let data_set = PreparedDataSet::from_csv("data/xor.csv", ["x_0", "x_1"], ["xor"]).unwrap();
+
+    let results = Arc::new(AtomicUsize::new(0));
+    let observed_results = results.clone();
+
+    let mut trainer = NetTrainerBuilder::default()
+        .data_set(data_set)
+        .net_config(NetConfig::new_fully_connected(2, 1, [3], ActivationFn::standard_logistic_sigmoid()))
+        .backprop_options(backprop_options(5000, 5.0))
+        .initializer(RandomNetInitializer::new_standard_with_seed("xor"))
+        .observer(Box::new(move |event| {
+            if let TrainingEvent::TaskResult(_) = event {
+                observed_results.fetch_add(1, Ordering::Relaxed);
+            }
+        }))
+        .build()
+        .unwrap();
+
+    let result = trainer.execute().unwrap();
+
+    assert_eq!(results.load(Ordering::Relaxed), result.trials.len());
+    assert!(result.error_stats.mean() < 0.01, "error {:?}", result.error_stats);
+    for &(input, expected) in &[([0.0, 0.0], 0.0), ([0.0, 1.0], 1.0), ([1.0, 0.0], 1.0), ([1.0, 1.0], 0.0)] {
+        let output = result.net.predict(&input)[0];
+        assert!((output - expected).abs() < 0.2, "{:?} -> {}", input, output);
+    }
+
+}
+
+#[test]
+fn lines_2x2_converges_with_sampled_hyper_parameters() {
+
+    let data_set = PreparedDataSet::from_csv(
+        "data/2x2_lines_binary.csv",
+        ["0_0", "0_1", "1_0", "1_1"],
+        ["has_horizontal", "has_vertical"]
+    ).unwrap();
+
+    let mut trainer = NetTrainerBuilder::default()
+        .data_set(data_set)
+        .executor(Executor::Local(2))
+        .optimizer_factory(Box::new(|| Box::new(RandomOptimizer::from_seed("lines"))))
+        .net_config_factory(Box::new(|params: &mut dyn ParamFactory| {
+            let hidden_size = params.range_usize("hidden_size".to_string(), 3, 6);
+            NetConfig::new_fully_connected(4, 2, [hidden_size], ActivationFn::standard_logistic_sigmoid())
+        }))
+        .backprop_options_factory(Box::new(|params: &mut dyn ParamFactory| {
+            backprop_options(2000, params.range_float(1.0, 3.0))
+        }))
+        .global_completion_fn(CompletionFn::stop_after_epoch(4))
+        .initializer(RandomNetInitializer::new_standard_with_seed("lines"))
+        .observer(Box::new(|_| {}))
+        .build()
+        .unwrap();
+
+    let result = trainer.execute().unwrap();
+
+    assert!(result.trials.len() >= 4);
+    assert!(result.error_stats.mean() < 0.05, "error {:?}", result.error_stats);
+
+}
+
+#[test]
+fn sine_regression_converges() {
+
+    let data_set = PreparedDataSet::from_csv("data/sine_regression.csv", ["x"], ["y"]).unwrap();
+
+    let mut trainer = NetTrainerBuilder::default()
+        .data_set(data_set)
+        .net_config(NetConfig::new_fully_connected(1, 1, [8], ActivationFn::standard_logistic_sigmoid()))
+        .backprop_options(BackpropOptions {
+            mini_batch_size_fn: MiniBatchSize::Constant(std::num::NonZeroU32::new(4).unwrap()),
+            ..backprop_options(5000, 1.0)
+        })
+        .initializer(RandomNetInitializer::new_standard_with_seed("sine"))
+        .observer(Box::new(|_| {}))
+        .build()
+        .unwrap();
+
+    let result = trainer.execute().unwrap();
+
+    assert!(result.error_stats.mean() < 0.005, "error {:?}", result.error_stats);
+    let peak = result.net.predict(&[0.25])[0];
+    let trough = result.net.predict(&[0.75])[0];
+    assert!(peak > 0.75 && trough < 0.25, "peak {}, trough {}", peak, trough);
+
+}